@@ -0,0 +1,635 @@
+//! Allocation tracking preloaded into targets of `memory_tracker run --alloc-trace`.
+//!
+//! The malloc family is interposed and every block gets a 16-byte header naming the
+//! call site bucket it was allocated from. A background thread periodically streams the
+//! live count and bytes of each bucket to the tracker over the socket whose descriptor
+//! is in `MEMORY_TRACKER_ALLOC_FD`. Without that variable, blocks are passed straight
+//! through to the system allocator.
+//!
+//! Nothing on the allocation path may allocate itself: call sites are hashed into a
+//! fixed table of atomics, and anything the hooks need from libc (dlsym, backtrace)
+//! runs with the per-thread reentrancy guard set so nested calls go untracked.
+
+// The exported functions are the C allocator entry points with their C contracts
+#![allow(clippy::missing_safety_doc)]
+// Built on glibc internals (errno location, ELF program headers); elsewhere the library
+// is empty and `--alloc-trace` is unavailable
+#![cfg(target_os = "linux")]
+
+use libc::{c_char, c_int, c_void, size_t};
+use std::cell::{Cell, UnsafeCell};
+use std::ffi::CStr;
+use std::fmt::Write as _;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::Duration;
+
+const FD_ENV: &CStr = c"MEMORY_TRACKER_ALLOC_FD";
+const INTERVAL_ENV: &CStr = c"MEMORY_TRACKER_ALLOC_INTERVAL_MS";
+
+const HEADER: usize = 16;
+const MAGIC: u32 = 0xa11c_0000;
+const MAGIC_MASK: u32 = 0xffff_0000;
+/// Header slot of blocks that are not accounted, e.g. the reporter's own
+const UNTRACKED: u32 = 0xffff;
+
+/// Call site buckets; slot 0 collects allocations once the table is full
+const SLOTS: usize = 4096;
+/// Frames outside this library that make up a call site
+const DEPTH: usize = 3;
+/// Frames captured per allocation, enough to get past the hooks themselves
+const CAPTURE: usize = DEPTH + 8;
+const MAX_PROBES: usize = 64;
+
+#[repr(C)]
+struct Header {
+    tag: u32,
+    /// Distance from the block the system allocator returned to the user pointer
+    offset: u32,
+    size: u64,
+}
+
+struct Slot {
+    key: AtomicU64,
+    frames: [AtomicUsize; DEPTH],
+    live_count: AtomicI64,
+    live_bytes: AtomicI64,
+    allocs: AtomicU64,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            key: AtomicU64::new(0),
+            frames: [const { AtomicUsize::new(0) }; DEPTH],
+            live_count: AtomicI64::new(0),
+            live_bytes: AtomicI64::new(0),
+            allocs: AtomicU64::new(0),
+        }
+    }
+}
+
+static TABLE: [Slot; SLOTS] = [const { Slot::new() }; SLOTS];
+
+/// Serves the allocations dlsym makes while the real allocator is being looked up.
+#[repr(C, align(16))]
+struct Bootstrap(UnsafeCell<[u8; 64 * 1024]>);
+
+unsafe impl Sync for Bootstrap {}
+
+static BOOTSTRAP: Bootstrap = Bootstrap(UnsafeCell::new([0; 64 * 1024]));
+static BOOTSTRAP_USED: AtomicUsize = AtomicUsize::new(0);
+
+const UNINITIALIZED: u8 = 0;
+const INITIALIZING: u8 = 1;
+const READY: u8 = 2;
+static STATE: AtomicU8 = AtomicU8::new(UNINITIALIZED);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REPORT_FD: AtomicUsize = AtomicUsize::new(0);
+
+static REAL_MALLOC: AtomicUsize = AtomicUsize::new(0);
+static REAL_CALLOC: AtomicUsize = AtomicUsize::new(0);
+static REAL_REALLOC: AtomicUsize = AtomicUsize::new(0);
+static REAL_FREE: AtomicUsize = AtomicUsize::new(0);
+static REAL_USABLE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Address range of this library, so its own frames are skipped in call sites
+static SELF_START: AtomicUsize = AtomicUsize::new(0);
+static SELF_END: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the current thread as inside the hooks until dropped.
+struct Guard(bool);
+
+impl Guard {
+    fn enter() -> Self {
+        Guard(IN_HOOK.with(|h| h.replace(true)))
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        IN_HOOK.with(|h| h.set(self.0));
+    }
+}
+
+fn tracking() -> bool {
+    ENABLED.load(Ordering::Relaxed) && !IN_HOOK.with(|h| h.get())
+}
+
+/// Resolve the real allocator on first use. Returns false while another call is
+/// still resolving it, in which case the bootstrap buffer has to be used.
+unsafe fn ensure_init() -> bool {
+    let claimed = STATE.compare_exchange(
+        UNINITIALIZED,
+        INITIALIZING,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    match claimed {
+        Ok(_) => {}
+        Err(READY) => return true,
+        Err(_) => return false,
+    }
+    let resolve = |name: &CStr, slot: &AtomicUsize| {
+        slot.store(
+            libc::dlsym(libc::RTLD_NEXT, name.as_ptr()) as usize,
+            Ordering::Release,
+        )
+    };
+    resolve(c"malloc", &REAL_MALLOC);
+    resolve(c"calloc", &REAL_CALLOC);
+    resolve(c"realloc", &REAL_REALLOC);
+    resolve(c"free", &REAL_FREE);
+    resolve(c"malloc_usable_size", &REAL_USABLE_SIZE);
+
+    let fd = libc::getenv(FD_ENV.as_ptr());
+    if let Some(fd) = parse_number(fd) {
+        REPORT_FD.store(fd, Ordering::Relaxed);
+        find_self_range();
+        ENABLED.store(true, Ordering::Release);
+    }
+    STATE.store(READY, Ordering::Release);
+    true
+}
+
+unsafe fn parse_number(value: *const c_char) -> Option<usize> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok()?.parse().ok()
+}
+
+unsafe fn find_self_range() {
+    let mut info: libc::Dl_info = std::mem::zeroed();
+    if libc::dladdr(find_self_range as *const c_void, &mut info) == 0 {
+        return;
+    }
+    unsafe extern "C" fn visit(
+        info: *mut libc::dl_phdr_info,
+        _size: size_t,
+        base: *mut c_void,
+    ) -> c_int {
+        let info = &*info;
+        if info.dlpi_addr as usize != base as usize {
+            return 0;
+        }
+        let headers = std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+        let end = headers
+            .iter()
+            .filter(|h| h.p_type == libc::PT_LOAD)
+            .map(|h| h.p_vaddr as usize + h.p_memsz as usize)
+            .max()
+            .unwrap_or(0);
+        SELF_START.store(base as usize, Ordering::Relaxed);
+        SELF_END.store(base as usize + end, Ordering::Relaxed);
+        1
+    }
+    libc::dl_iterate_phdr(Some(visit), info.dli_fbase);
+}
+
+unsafe fn real_malloc(size: size_t) -> *mut c_void {
+    let f: unsafe extern "C" fn(size_t) -> *mut c_void =
+        std::mem::transmute(REAL_MALLOC.load(Ordering::Acquire));
+    f(size)
+}
+
+unsafe fn real_calloc(count: size_t, size: size_t) -> *mut c_void {
+    let f: unsafe extern "C" fn(size_t, size_t) -> *mut c_void =
+        std::mem::transmute(REAL_CALLOC.load(Ordering::Acquire));
+    f(count, size)
+}
+
+unsafe fn real_realloc(block: *mut c_void, size: size_t) -> *mut c_void {
+    let f: unsafe extern "C" fn(*mut c_void, size_t) -> *mut c_void =
+        std::mem::transmute(REAL_REALLOC.load(Ordering::Acquire));
+    f(block, size)
+}
+
+unsafe fn real_free(block: *mut c_void) {
+    let f: unsafe extern "C" fn(*mut c_void) =
+        std::mem::transmute(REAL_FREE.load(Ordering::Acquire));
+    f(block)
+}
+
+unsafe fn real_usable_size(block: *mut c_void) -> size_t {
+    let f: unsafe extern "C" fn(*mut c_void) -> size_t =
+        std::mem::transmute(REAL_USABLE_SIZE.load(Ordering::Acquire));
+    f(block)
+}
+
+fn bootstrap_alloc(size: usize) -> *mut c_void {
+    let size = (size + 15) & !15;
+    let offset = BOOTSTRAP_USED.fetch_add(size, Ordering::Relaxed);
+    if offset + size > std::mem::size_of::<Bootstrap>() {
+        return ptr::null_mut();
+    }
+    // Never reused, so still zeroed for calloc
+    unsafe { (BOOTSTRAP.0.get() as *mut u8).add(offset) as *mut c_void }
+}
+
+fn in_bootstrap(block: *mut c_void) -> bool {
+    let start = BOOTSTRAP.0.get() as usize;
+    (start..start + std::mem::size_of::<Bootstrap>()).contains(&(block as usize))
+}
+
+fn set_errno(value: c_int) {
+    unsafe { *libc::__errno_location() = value };
+}
+
+/// Header of a block allocated by these hooks, or `None` for a foreign pointer.
+unsafe fn header_of(block: *mut c_void) -> Option<*mut Header> {
+    let header = (block as *mut u8).sub(HEADER) as *mut Header;
+    ((*header).tag & MAGIC_MASK == MAGIC).then_some(header)
+}
+
+fn account(slot: u32, bytes: i64, count: i64) {
+    let Some(slot) = TABLE.get(slot as usize) else {
+        return;
+    };
+    slot.live_bytes.fetch_add(bytes, Ordering::Relaxed);
+    slot.live_count.fetch_add(count, Ordering::Relaxed);
+    if count > 0 {
+        slot.allocs.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Bucket of the current call site: the first `DEPTH` frames outside this library.
+unsafe fn site_slot() -> u32 {
+    let _guard = Guard::enter();
+    let mut frames = [ptr::null_mut::<c_void>(); CAPTURE];
+    let captured = libc::backtrace(frames.as_mut_ptr(), CAPTURE as c_int).max(0) as usize;
+    let own = SELF_START.load(Ordering::Relaxed)..SELF_END.load(Ordering::Relaxed);
+    let mut site = [0usize; DEPTH];
+    let foreign = frames[..captured]
+        .iter()
+        .map(|f| *f as usize)
+        .filter(|f| !own.contains(f));
+    for (dst, frame) in site.iter_mut().zip(foreign) {
+        *dst = frame;
+    }
+    if site[0] == 0 {
+        return 0;
+    }
+
+    // FNV-1a over the frame addresses; zero marks an empty slot
+    let key = site
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325u64, |h, f| {
+            (h ^ *f as u64).wrapping_mul(0x100_0000_01b3)
+        })
+        .max(1);
+    for probe in 0..MAX_PROBES {
+        let idx = 1 + (key as usize + probe) % (SLOTS - 1);
+        let slot = &TABLE[idx];
+        match slot
+            .key
+            .compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                for (dst, frame) in slot.frames.iter().zip(site) {
+                    dst.store(frame, Ordering::Release);
+                }
+                return idx as u32;
+            }
+            Err(existing) if existing == key => return idx as u32,
+            Err(_) => {}
+        }
+    }
+    0
+}
+
+/// Allocate `size` bytes aligned to `align` behind a header, from the system allocator.
+unsafe fn allocate(size: usize, align: usize, zeroed: bool) -> *mut c_void {
+    let tracked = tracking();
+    if !tracked && align <= HEADER {
+        return if zeroed {
+            real_calloc(1, size)
+        } else {
+            real_malloc(size)
+        };
+    }
+    let pad = if align <= HEADER {
+        HEADER
+    } else {
+        HEADER + align
+    };
+    let Some(total) = size.checked_add(pad) else {
+        set_errno(libc::ENOMEM);
+        return ptr::null_mut();
+    };
+    let base = if zeroed {
+        real_calloc(1, total)
+    } else {
+        real_malloc(total)
+    };
+    if base.is_null() {
+        return base;
+    }
+    let block = if align <= HEADER {
+        base as usize + HEADER
+    } else {
+        (base as usize + HEADER + align - 1) & !(align - 1)
+    };
+    let slot = if tracked { site_slot() } else { UNTRACKED };
+    (block as *mut Header).sub(1).write(Header {
+        tag: MAGIC | slot,
+        offset: (block - base as usize) as u32,
+        size: size as u64,
+    });
+    account(slot, size as i64, 1);
+    block as *mut c_void
+}
+
+unsafe fn allocate_aligned(align: usize, size: usize) -> *mut c_void {
+    if !ensure_init() {
+        return ptr::null_mut();
+    }
+    allocate(size, align, false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn malloc(size: size_t) -> *mut c_void {
+    if !ensure_init() {
+        return bootstrap_alloc(size);
+    }
+    allocate(size, 1, false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn calloc(count: size_t, size: size_t) -> *mut c_void {
+    let Some(total) = count.checked_mul(size) else {
+        set_errno(libc::ENOMEM);
+        return ptr::null_mut();
+    };
+    if !ensure_init() {
+        return bootstrap_alloc(total);
+    }
+    allocate(total, 1, true)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn free(block: *mut c_void) {
+    if block.is_null() || in_bootstrap(block) || !ensure_init() {
+        return;
+    }
+    match header_of(block) {
+        Some(header) => {
+            let Header { tag, offset, size } = header.read();
+            (*header).tag = 0;
+            account(tag & !MAGIC_MASK, -(size as i64), -1);
+            real_free((block as *mut u8).sub(offset as usize) as *mut c_void);
+        }
+        None => real_free(block),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn realloc(block: *mut c_void, size: size_t) -> *mut c_void {
+    if block.is_null() {
+        return malloc(size);
+    }
+    if in_bootstrap(block) || !ensure_init() {
+        let new = malloc(size);
+        if !new.is_null() {
+            let end = BOOTSTRAP.0.get() as usize + std::mem::size_of::<Bootstrap>();
+            let len = size.min(end - block as usize);
+            ptr::copy_nonoverlapping(block as *const u8, new as *mut u8, len);
+        }
+        return new;
+    }
+    let Some(header) = header_of(block) else {
+        return real_realloc(block, size);
+    };
+    if size == 0 {
+        free(block);
+        return ptr::null_mut();
+    }
+    let Header {
+        tag,
+        offset,
+        size: old_size,
+    } = header.read();
+    if offset as usize != HEADER {
+        // Over-aligned blocks can't be resized in place without losing the alignment
+        let new = malloc(size);
+        if !new.is_null() {
+            let len = size.min(old_size as usize);
+            ptr::copy_nonoverlapping(block as *const u8, new as *mut u8, len);
+            free(block);
+        }
+        return new;
+    }
+    let Some(total) = size.checked_add(HEADER) else {
+        set_errno(libc::ENOMEM);
+        return ptr::null_mut();
+    };
+    let old_slot = tag & !MAGIC_MASK;
+    let slot = if tracking() { site_slot() } else { old_slot };
+    let base = real_realloc(header as *mut c_void, total);
+    if base.is_null() {
+        return base;
+    }
+    (base as *mut Header).write(Header {
+        tag: MAGIC | slot,
+        offset,
+        size: size as u64,
+    });
+    account(old_slot, -(old_size as i64), -1);
+    account(slot, size as i64, 1);
+    (base as *mut u8).add(HEADER) as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn malloc_usable_size(block: *mut c_void) -> size_t {
+    if block.is_null() || in_bootstrap(block) || !ensure_init() {
+        return 0;
+    }
+    match header_of(block) {
+        Some(header) => {
+            let offset = (*header).offset as usize;
+            real_usable_size((block as *mut u8).sub(offset) as *mut c_void) - offset
+        }
+        None => real_usable_size(block),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn posix_memalign(
+    out: *mut *mut c_void,
+    align: size_t,
+    size: size_t,
+) -> c_int {
+    if !align.is_power_of_two() || !align.is_multiple_of(std::mem::size_of::<*mut c_void>()) {
+        return libc::EINVAL;
+    }
+    let block = allocate_aligned(align, size);
+    if block.is_null() {
+        return libc::ENOMEM;
+    }
+    *out = block;
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn aligned_alloc(align: size_t, size: size_t) -> *mut c_void {
+    if !align.is_power_of_two() {
+        set_errno(libc::EINVAL);
+        return ptr::null_mut();
+    }
+    allocate_aligned(align, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn memalign(align: size_t, size: size_t) -> *mut c_void {
+    aligned_alloc(align.next_power_of_two(), size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn valloc(size: size_t) -> *mut c_void {
+    allocate_aligned(page_size(), size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn pvalloc(size: size_t) -> *mut c_void {
+    let page = page_size();
+    allocate_aligned(page, size.div_ceil(page).max(1) * page)
+}
+
+fn page_size() -> usize {
+    (unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).max(4096) as usize
+}
+
+#[used]
+#[link_section = ".init_array"]
+static CONSTRUCTOR: extern "C" fn() = start;
+
+/// Starts the reporter once the library is loaded, if the tracker asked for reports.
+extern "C" fn start() {
+    unsafe {
+        if !ensure_init() || !ENABLED.load(Ordering::Acquire) {
+            return;
+        }
+        let fd = REPORT_FD.load(Ordering::Relaxed) as c_int;
+        // Programs this one executes must neither inherit the socket nor write to
+        // whatever ends up with the same descriptor number
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::unsetenv(FD_ENV.as_ptr());
+        {
+            // The first backtrace loads the unwinder, which allocates
+            let _guard = Guard::enter();
+            let mut frame = [ptr::null_mut::<c_void>(); 1];
+            libc::backtrace(frame.as_mut_ptr(), 1);
+        }
+        let mut thread: libc::pthread_t = 0;
+        if libc::pthread_create(&mut thread, ptr::null(), report, ptr::null_mut()) == 0 {
+            libc::pthread_detach(thread);
+        }
+    }
+}
+
+/// Writes every bucket seen so far as tab-separated `slot live_count live_bytes allocs
+/// site` lines followed by `end`, until the tracker closes its end of the socket.
+extern "C" fn report(_: *mut c_void) -> *mut c_void {
+    IN_HOOK.with(|h| h.set(true));
+    let fd = REPORT_FD.load(Ordering::Relaxed) as c_int;
+    let interval = unsafe { parse_number(libc::getenv(INTERVAL_ENV.as_ptr())) }.unwrap_or(1000);
+    let mut labels: Vec<Option<String>> = vec![None; SLOTS];
+    let mut out = String::new();
+    loop {
+        std::thread::sleep(Duration::from_millis(interval as u64));
+        out.clear();
+        for (idx, slot) in TABLE.iter().enumerate() {
+            let allocs = slot.allocs.load(Ordering::Relaxed);
+            if allocs == 0 {
+                continue;
+            }
+            if labels[idx].is_none() {
+                labels[idx] = site_label(idx, slot);
+            }
+            let Some(label) = &labels[idx] else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                idx,
+                slot.live_count.load(Ordering::Relaxed),
+                slot.live_bytes.load(Ordering::Relaxed),
+                allocs,
+                label
+            );
+        }
+        out.push_str("end\n");
+        if !send_all(fd, out.as_bytes()) {
+            return ptr::null_mut();
+        }
+    }
+}
+
+fn send_all(fd: c_int, mut bytes: &[u8]) -> bool {
+    while !bytes.is_empty() {
+        // MSG_NOSIGNAL: a tracker that went away must not kill the target with SIGPIPE
+        let sent = unsafe {
+            libc::send(
+                fd,
+                bytes.as_ptr() as *const c_void,
+                bytes.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+        if sent < 0 {
+            if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return false;
+        }
+        bytes = &bytes[sent as usize..];
+    }
+    true
+}
+
+/// `symbol+0x1f (libfoo.so) <- caller ...`, or `None` while the slot is being filled.
+fn site_label(idx: usize, slot: &Slot) -> Option<String> {
+    if idx == 0 {
+        return Some("[other call sites]".to_string());
+    }
+    let frames: Vec<usize> = slot
+        .frames
+        .iter()
+        .map(|f| f.load(Ordering::Acquire))
+        .take_while(|f| *f != 0)
+        .collect();
+    if frames.is_empty() {
+        return None;
+    }
+    let names: Vec<String> = frames.iter().map(|f| frame_label(*f)).collect();
+    Some(names.join(" <- "))
+}
+
+fn frame_label(address: usize) -> String {
+    let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+    if unsafe { libc::dladdr(address as *const c_void, &mut info) } == 0 {
+        return format!("{:#x}", address);
+    }
+    let object = if info.dli_fname.is_null() {
+        "?".to_string()
+    } else {
+        let path = unsafe { CStr::from_ptr(info.dli_fname) }.to_string_lossy();
+        path.rsplit('/').next().unwrap_or_default().to_string()
+    };
+    if info.dli_sname.is_null() {
+        format!("{}+{:#x}", object, address - info.dli_fbase as usize)
+    } else {
+        let symbol = unsafe { CStr::from_ptr(info.dli_sname) }.to_string_lossy();
+        format!(
+            "{}+{:#x} ({})",
+            symbol,
+            address - info.dli_saddr as usize,
+            object
+        )
+    }
+}
@@ -0,0 +1,268 @@
+//! `cargo memtrack`: build a crate's binary, tests or benchmarks with cargo and run each
+//! of them under `memory_tracker run`, leaving the charts and summaries in
+//! `target/memtrack/`.
+//!
+//! Cargo runs `cargo-<name>` binaries on the PATH as `cargo <name>`, passing the name as
+//! the first argument. `memory_tracker` is looked up next to this binary, where the
+//! workspace build and `cargo install` put it, and otherwise on the PATH.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+
+const USAGE: &str = "\
+Build with cargo and record the memory usage of the result with memory_tracker
+
+Usage: cargo memtrack [TRACKER OPTIONS] <run|test|bench> [CARGO OPTIONS] [-- <ARGS>...]
+
+  run    Build the binary (`--bin`, `--example`) and record it
+  test   Build the test targets and record each test binary in turn
+  bench  Build the benchmark targets and record each one in turn
+
+Tracker options go before the subcommand and are passed to `memory_tracker run`, e.g.
+`cargo memtrack --interval 50ms --max-peak 1GB test --release`. Cargo options such as
+`--release`, `-p`, `--features` or `--test` go after it, and arguments after `--` are
+passed to every binary run. The chart, JSON summary and CSV of each binary are written
+to target/memtrack/ unless the tracker options say otherwise.";
+
+/// Subcommands that build something to run.
+const MODES: [&str; 3] = ["run", "test", "bench"];
+
+/// A compiled binary to record.
+struct Executable {
+    /// Cargo target name, e.g. the binary or integration test file name
+    name: String,
+    /// Cargo target kind: `bin`, `example`, `lib`, `test` or `bench`
+    kind: String,
+    path: PathBuf,
+    /// Directory of the package's Cargo.toml, where `cargo test` runs test binaries
+    package_dir: PathBuf,
+}
+
+impl Executable {
+    /// File name stem of the outputs: the name alone for `run`, with the kind for tests,
+    /// where a library and a binary of the same name both have unit tests.
+    fn output_stem(&self, mode: &str) -> String {
+        match mode {
+            "run" => self.name.clone(),
+            _ => format!("{}-{}", self.kind, self.name),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            // Cargo's own code for a failed command
+            ExitCode::from(101)
+        }
+    }
+}
+
+fn run() -> Result<ExitCode> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("memtrack") {
+        args.remove(0);
+    }
+    let Some(mode_idx) = args.iter().position(|arg| MODES.contains(&arg.as_str())) else {
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            println!("{}", USAGE);
+            return Ok(ExitCode::SUCCESS);
+        }
+        eprintln!("{}", USAGE);
+        return Ok(ExitCode::from(2));
+    };
+    let tracker_options = &args[..mode_idx];
+    let mode = args[mode_idx].as_str();
+    let rest = &args[mode_idx + 1..];
+    let (cargo_args, target_args) = match rest.iter().position(|arg| arg == "--") {
+        Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+        None => (rest, &[][..]),
+    };
+
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let out_dir = target_dir(&cargo, cargo_args)?.join("memtrack");
+    let executables = build(&cargo, mode, cargo_args)?;
+    let executables = match mode {
+        "run" => vec![only_binary(executables)?],
+        _ if executables.is_empty() => bail!("cargo {} built nothing to run", mode),
+        _ => executables,
+    };
+    std::fs::create_dir_all(&out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let tracker = tracker();
+    for executable in &executables {
+        eprintln!(
+            "     Running {} under {}",
+            executable.path.display(),
+            tracker.display()
+        );
+        let stem = out_dir.join(executable.output_stem(mode));
+        let mut command = Command::new(&tracker);
+        command.arg("run").args(tracker_options);
+        // Each output goes to target/memtrack unless the tracker options place it
+        let outputs: [(&[&str], &str); 3] = [
+            (&["-o", "--output"], "png"),
+            (&["--json"], "json"),
+            (&["-c", "--csv-output"], "csv"),
+        ];
+        for (flags, extension) in outputs {
+            if !given(tracker_options, flags) {
+                command
+                    .arg(flags[flags.len() - 1])
+                    .arg(stem.with_extension(extension));
+            }
+        }
+        command.arg("--").arg(&executable.path).args(target_args);
+        // As `cargo bench` does, so the harness runs benchmarks rather than tests
+        if mode == "bench" {
+            command.arg("--bench");
+        }
+        if mode != "run" {
+            command
+                .current_dir(&executable.package_dir)
+                .env("CARGO_MANIFEST_DIR", &executable.package_dir);
+        }
+        let status = command.status().with_context(|| {
+            format!(
+                "Failed to start {}; is memory_tracker installed?",
+                tracker.display()
+            )
+        })?;
+        if !status.success() {
+            let code = status.code().unwrap_or(101);
+            eprintln!(
+                "error: {} exited with code {}",
+                executable.path.display(),
+                code
+            );
+            return Ok(ExitCode::from(u8::try_from(code).unwrap_or(101)));
+        }
+    }
+    eprintln!("Charts and summaries are in {}", out_dir.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Build with `cargo build` (for `run`) or `cargo test/bench --no-run` and collect the
+/// executables cargo reports.
+fn build(cargo: &OsString, mode: &str, cargo_args: &[String]) -> Result<Vec<Executable>> {
+    let mut command = Command::new(cargo);
+    match mode {
+        "run" => command.arg("build"),
+        _ => command.args([mode, "--no-run"]),
+    };
+    let mut child = command
+        .args(cargo_args)
+        // Diagnostics still reach the terminal, rendered, on stderr
+        .arg("--message-format=json-render-diagnostics")
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to start cargo")?;
+
+    let mut executables = Vec::new();
+    let stdout = child.stdout.take().context("cargo has no stdout")?;
+    for line in BufReader::new(stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(&line?) else {
+            continue;
+        };
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        let Some(path) = message["executable"].as_str() else {
+            continue;
+        };
+        let kind = message["target"]["kind"][0].as_str().unwrap_or_default();
+        let wanted = match mode {
+            "run" => matches!(kind, "bin" | "example"),
+            _ => message["profile"]["test"].as_bool().unwrap_or(false),
+        };
+        if !wanted {
+            continue;
+        }
+        let manifest = Path::new(message["manifest_path"].as_str().unwrap_or_default());
+        executables.push(Executable {
+            name: message["target"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            kind: kind.to_string(),
+            path: PathBuf::from(path),
+            package_dir: manifest.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        });
+    }
+    if !child.wait()?.success() {
+        bail!("cargo could not build the project");
+    }
+    Ok(executables)
+}
+
+/// The one binary `run` records; like `cargo run`, several need choosing between.
+fn only_binary(mut executables: Vec<Executable>) -> Result<Executable> {
+    match executables.len() {
+        0 => bail!("no binary was built; pass --bin or --example"),
+        1 => Ok(executables.remove(0)),
+        _ => {
+            let names: Vec<_> = executables.iter().map(|e| e.name.as_str()).collect();
+            bail!(
+                "several binaries were built ({}); pick one with --bin or --example",
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// The workspace's target directory as `cargo metadata` reports it, honouring
+/// `--manifest-path` and `CARGO_TARGET_DIR`.
+fn target_dir(cargo: &OsString, cargo_args: &[String]) -> Result<PathBuf> {
+    let mut command = Command::new(cargo);
+    command.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(idx) = cargo_args.iter().position(|arg| arg == "--manifest-path") {
+        command.args(&cargo_args[idx..(idx + 2).min(cargo_args.len())]);
+    } else if let Some(arg) = cargo_args
+        .iter()
+        .find(|arg| arg.starts_with("--manifest-path="))
+    {
+        command.arg(arg);
+    }
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run cargo metadata")?;
+    if !output.status.success() {
+        bail!("cargo metadata failed");
+    }
+    let metadata: Value =
+        serde_json::from_slice(&output.stdout).context("Invalid cargo metadata output")?;
+    match metadata["target_directory"].as_str() {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => bail!("cargo metadata reported no target directory"),
+    }
+}
+
+/// `memory_tracker` next to this binary, or else the one on the PATH.
+fn tracker() -> PathBuf {
+    let name = format!("memory_tracker{}", std::env::consts::EXE_SUFFIX);
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(&name)))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Whether `options` set one of `flags`, as `--flag value` or `--flag=value`.
+fn given(options: &[String], flags: &[&str]) -> bool {
+    options.iter().any(|option| {
+        flags.iter().any(|flag| {
+            option == flag
+                || option
+                    .strip_prefix(flag)
+                    .is_some_and(|rest| rest.starts_with('='))
+        })
+    })
+}
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::metadata::RunMetadata;
+
+/// Minimum time between two reconnection attempts after the collector went away.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Streams samples to a `memory_tracker collector` as JSON lines over TCP.
+///
+/// Every connection starts with a `hello` line naming the agent and carrying the run
+/// metadata, followed by `sample` and `marker` lines and a final `end`. Lines are sent
+/// from a separate thread so a slow network never delays sampling; while the collector
+/// is unreachable lines are dropped and the connection is retried every few seconds.
+pub struct AgentStream {
+    tx: Sender<Value>,
+    handle: JoinHandle<()>,
+}
+
+impl AgentStream {
+    /// Connect to the collector at `addr`, failing right away when it is not listening.
+    pub fn connect(addr: &str, name: &str, metadata: &RunMetadata) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .with_context(|| format!("Failed to connect to the collector at {}", addr))?;
+        let mut hello = metadata.to_json();
+        hello["type"] = json!("hello");
+        hello["agent"] = json!(name);
+        let (tx, rx) = mpsc::channel();
+        let addr = addr.to_string();
+        let handle = thread::spawn(move || send_lines(&addr, stream, &hello, rx));
+        Ok(Self { tx, handle })
+    }
+
+    pub fn sample(&self, time: f64, memory_kb: u64) {
+        let _ = self
+            .tx
+            .send(json!({ "type": "sample", "time": time, "memory_kb": memory_kb }));
+    }
+
+    pub fn marker(&self, time: f64, label: &str) {
+        let _ = self
+            .tx
+            .send(json!({ "type": "marker", "time": time, "label": label }));
+    }
+
+    /// Tell the collector the run is over and wait until everything queued is sent.
+    pub fn finish(self) -> Result<()> {
+        let _ = self.tx.send(json!({ "type": "end" }));
+        drop(self.tx);
+        self.handle
+            .join()
+            .map_err(|_| anyhow!("Collector sender thread panicked"))
+    }
+}
+
+fn send_lines(addr: &str, stream: TcpStream, hello: &Value, rx: Receiver<Value>) {
+    let mut writer = Some(BufWriter::new(stream));
+    let mut last_attempt = Instant::now();
+    if let Some(w) = &mut writer {
+        if writeln!(w, "{}", hello).is_err() {
+            writer = None;
+        }
+    }
+    while let Ok(first) = rx.recv() {
+        if writer.is_none() && last_attempt.elapsed() >= RECONNECT_INTERVAL {
+            last_attempt = Instant::now();
+            writer = TcpStream::connect(addr).ok().map(BufWriter::new);
+            if let Some(w) = &mut writer {
+                match writeln!(w, "{}", hello) {
+                    Ok(()) => info!("Reconnected to the collector at {}", addr),
+                    Err(_) => writer = None,
+                }
+            }
+        }
+        let Some(w) = &mut writer else {
+            continue;
+        };
+        let result = std::iter::once(first)
+            .chain(rx.try_iter())
+            .try_for_each(|line| writeln!(w, "{}", line))
+            .and_then(|()| w.flush());
+        if let Err(e) = result {
+            warn!("lost the connection to the collector at {}: {}", addr, e);
+            writer = None;
+            last_attempt = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Fires once when RSS first reaches the threshold, then stays quiet until RSS has
+/// dropped below the re-arm level, so a value hovering at the line doesn't alert on
+/// every sample.
+#[derive(Debug, Clone)]
+pub struct ThresholdAlert {
+    pub threshold_kb: u64,
+    rearm_kb: u64,
+    armed: bool,
+}
+
+impl ThresholdAlert {
+    /// `hysteresis_percent` of the threshold is how far RSS must fall to re-arm.
+    pub fn new(threshold_kb: u64, hysteresis_percent: f64) -> Self {
+        let rearm_kb = threshold_kb as f64 * (1.0 - hysteresis_percent / 100.0);
+        Self {
+            threshold_kb,
+            rearm_kb: rearm_kb.max(0.0) as u64,
+            armed: true,
+        }
+    }
+
+    /// Feed one sample; returns true when the alert fires.
+    pub fn check(&mut self, rss_kb: u64) -> bool {
+        if self.armed && rss_kb >= self.threshold_kb {
+            self.armed = false;
+            return true;
+        }
+        if !self.armed && rss_kb < self.rearm_kb {
+            self.armed = true;
+        }
+        false
+    }
+}
+
+/// `memory_chart.png` -> `memory_chart-alert-20240101-130000.png`.
+pub fn snapshot_path(output: &str, stamp: chrono::DateTime<chrono::Local>) -> String {
+    let path = std::path::Path::new(output);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("memory_chart");
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}-alert-{}.{}", stem, stamp.format("%Y%m%d-%H%M%S"), ext),
+        None => format!("{}-alert-{}", stem, stamp.format("%Y%m%d-%H%M%S")),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// POST `payload` as JSON from a background thread so a slow endpoint never delays
+/// sampling. Failures are reported but never end the run.
+pub fn post_json(url: &str, payload: serde_json::Value) -> thread::JoinHandle<()> {
+    let url = url.to_string();
+    thread::spawn(move || {
+        let result = ureq::post(&url)
+            .set("Content-Type", "application/json")
+            .send_string(&payload.to_string())
+            .with_context(|| format!("Failed to send alert to {}", url));
+        if let Err(e) = result {
+            warn!("{:#}", e);
+        }
+    })
+}
+
+/// Wait for in-flight notifications so the process doesn't exit mid-request.
+pub fn wait(pending: Vec<thread::JoinHandle<()>>) -> Result<()> {
+    for handle in pending {
+        handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("alert notification thread panicked"))?;
+    }
+    Ok(())
+}
+
+/// Outcome of one `--on-threshold-exec` run.
+#[derive(Debug, Clone)]
+pub struct ExecRecord {
+    pub time: f64,
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub duration_s: f64,
+    pub error: Option<String>,
+}
+
+impl ExecRecord {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "time_s": self.time,
+            "command": self.command,
+            "exit_code": self.exit_code,
+            "duration_s": self.duration_s,
+            "error": self.error,
+        })
+    }
+}
+
+/// Runs a shell command when a threshold alert fires, at most once per `min_interval`
+/// and never while the previous run is still going.
+pub struct ThresholdCommand {
+    template: String,
+    min_interval: Duration,
+    last_start: Option<Instant>,
+    runs: Vec<thread::JoinHandle<ExecRecord>>,
+}
+
+impl ThresholdCommand {
+    pub fn new(template: &str, min_interval: Duration) -> Self {
+        Self {
+            template: template.to_string(),
+            min_interval,
+            last_start: None,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Start the command with `{name}` placeholders replaced from `vars`. Returns the
+    /// expanded command, or `None` when rate limiting skipped it.
+    pub fn trigger(&mut self, time: f64, vars: &[(&str, String)]) -> Option<String> {
+        let busy = self.runs.last().is_some_and(|run| !run.is_finished());
+        let too_soon = self
+            .last_start
+            .is_some_and(|t| t.elapsed() < self.min_interval);
+        if busy || too_soon {
+            return None;
+        }
+
+        let mut command = self.template.clone();
+        for (name, value) in vars {
+            command = command.replace(&format!("{{{}}}", name), value);
+        }
+        self.last_start = Some(Instant::now());
+        let expanded = command.clone();
+        self.runs.push(thread::spawn(move || {
+            let started = Instant::now();
+            let result = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .status();
+            ExecRecord {
+                time,
+                exit_code: result.as_ref().ok().and_then(|status| status.code()),
+                error: result.err().map(|e| e.to_string()),
+                duration_s: started.elapsed().as_secs_f64(),
+                command,
+            }
+        }));
+        Some(expanded)
+    }
+
+    /// Wait for every started run and return their outcomes in start order.
+    pub fn finish(self) -> Vec<ExecRecord> {
+        self.runs
+            .into_iter()
+            .filter_map(|run| run.join().ok())
+            .collect()
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// File name of the preload library built from the `alloc_trace` workspace member.
+pub const LIBRARY_NAME: &str = "libmemory_tracker_alloc_trace.so";
+/// Descriptor of the socket the preload library reports on.
+const FD_ENV: &str = "MEMORY_TRACKER_ALLOC_FD";
+/// Milliseconds between two reports.
+const INTERVAL_ENV: &str = "MEMORY_TRACKER_ALLOC_INTERVAL_MS";
+
+/// The explicit library path, or the library next to the running executable.
+pub fn find_library(explicit: Option<&str>) -> Result<PathBuf> {
+    if let Some(path) = explicit {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            bail!("Allocation trace library not found: {}", path.display());
+        }
+        return Ok(path);
+    }
+    let exe = std::env::current_exe().context("Failed to locate the memory_tracker binary")?;
+    let path = exe.with_file_name(LIBRARY_NAME);
+    if !path.is_file() {
+        bail!(
+            "{} not found next to {}; build it with `cargo build -p memory_tracker_alloc_trace` \
+             or pass --alloc-trace-lib",
+            LIBRARY_NAME,
+            exe.display()
+        );
+    }
+    Ok(path)
+}
+
+/// Live allocations attributed to one call site.
+#[derive(Debug, Clone)]
+pub struct SiteReport {
+    /// Up to three frames, innermost first
+    pub site: String,
+    pub live_count: i64,
+    pub live_bytes: i64,
+    /// Allocations made from this site over the whole run
+    pub allocs: u64,
+    /// Change in live bytes since the site was first reported
+    pub growth_bytes: i64,
+}
+
+impl SiteReport {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "site": self.site,
+            "live_count": self.live_count,
+            "live_bytes": self.live_bytes,
+            "allocs": self.allocs,
+            "growth_bytes": self.growth_bytes,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct TraceState {
+    reports: u64,
+    latest: Vec<(u32, SiteReport)>,
+    first_bytes: HashMap<u32, i64>,
+}
+
+/// Receives per-call-site allocation reports from the preload library in a run-mode
+/// child.
+pub struct AllocTrace {
+    state: Arc<Mutex<TraceState>>,
+}
+
+impl AllocTrace {
+    /// Preload `library` into `command` and start listening for its reports, sent
+    /// every `interval_ms`.
+    pub fn prepare(command: &mut Command, library: &Path, interval_ms: u64) -> Result<Self> {
+        let (ours, theirs) =
+            UnixStream::pair().context("Failed to create the allocation trace socket")?;
+        let preload = match std::env::var("LD_PRELOAD") {
+            Ok(existing) if !existing.is_empty() => format!("{} {}", library.display(), existing),
+            _ => library.display().to_string(),
+        };
+        command
+            .env("LD_PRELOAD", preload)
+            .env(FD_ENV, theirs.as_raw_fd().to_string())
+            .env(INTERVAL_ENV, interval_ms.to_string());
+        // SAFETY: fcntl is async-signal-safe. The closure owns `theirs`, so the
+        // descriptor stays open until the command is dropped after spawning.
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(theirs.as_raw_fd(), libc::F_SETFD, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let state = Arc::new(Mutex::new(TraceState::default()));
+        let reader_state = Arc::clone(&state);
+        thread::spawn(move || read_reports(ours, &reader_state));
+        Ok(Self { state })
+    }
+
+    /// Number of reports received so far.
+    pub fn reports(&self) -> u64 {
+        self.state.lock().unwrap().reports
+    }
+
+    /// The `top` sites with the most live bytes gained since first reported, then the
+    /// most live bytes.
+    pub fn top_sites(&self, top: usize) -> Vec<SiteReport> {
+        let state = self.state.lock().unwrap();
+        let mut sites: Vec<SiteReport> = state.latest.iter().map(|(_, s)| s.clone()).collect();
+        sites.sort_by_key(|s| std::cmp::Reverse((s.growth_bytes, s.live_bytes)));
+        sites.truncate(top);
+        sites
+    }
+}
+
+fn read_reports(stream: UnixStream, state: &Mutex<TraceState>) {
+    let mut current: Vec<(u32, SiteReport)> = Vec::new();
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        if line == "end" {
+            let mut state = state.lock().unwrap();
+            for (slot, site) in &mut current {
+                let first = *state.first_bytes.entry(*slot).or_insert(site.live_bytes);
+                site.growth_bytes = site.live_bytes - first;
+            }
+            state.latest = std::mem::take(&mut current);
+            state.reports += 1;
+            continue;
+        }
+        // slot, live count, live bytes, allocations, site
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        let [slot, live_count, live_bytes, allocs, site] = fields[..] else {
+            continue;
+        };
+        let (Ok(slot), Ok(live_count), Ok(live_bytes), Ok(allocs)) = (
+            slot.parse(),
+            live_count.parse(),
+            live_bytes.parse(),
+            allocs.parse(),
+        ) else {
+            continue;
+        };
+        current.push((
+            slot,
+            SiteReport {
+                site: site.to_string(),
+                live_count,
+                live_bytes,
+                allocs,
+                growth_bytes: 0,
+            },
+        ));
+    }
+}
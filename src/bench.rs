@@ -0,0 +1,226 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::chart;
+use crate::csv_writer::{Columns, CsvWriter};
+use crate::stats::MemoryStats;
+use crate::{read_memory_usage, shutdown, units};
+
+#[derive(clap::Args)]
+#[command(mut_arg("title", |arg| arg.default_value("Memory Usage Across Runs")))]
+pub struct BenchArgs {
+    /// Command to run repeatedly; its output is passed through
+    #[arg(last = true, required = true, value_name = "COMMAND")]
+    command: Vec<String>,
+
+    /// Number of measured runs
+    #[arg(short = 'n', long, default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+
+    /// Runs made before the measured ones and left out of the results, e.g. to warm caches
+    #[arg(long, value_name = "N", default_value = "0")]
+    warmup: u32,
+
+    /// Sampling interval, e.g. 50ms (a bare number is milliseconds)
+    #[arg(short, long, value_name = "INTERVAL", default_value = "100", value_parser = units::parse_millis)]
+    interval: u64,
+
+    /// Output path for the chart overlaying every measured run
+    #[arg(short, long, default_value = "memory_bench.png")]
+    output: String,
+
+    /// Skip chart rendering
+    #[arg(long)]
+    no_chart: bool,
+
+    #[command(flatten)]
+    chart: chart::ChartArgs,
+
+    /// Save each measured run as run-<N>.csv in this directory
+    #[arg(long, value_name = "DIR")]
+    csv_dir: Option<String>,
+
+    /// Write the per-run peaks and their statistics as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+}
+
+/// One measured run of the command.
+struct BenchRun {
+    stats: MemoryStats,
+    /// Highest of the sampled RSS and the kernel's VmHWM, which also catches peaks
+    /// between samples
+    peak_kb: u64,
+    duration: f64,
+    success: bool,
+}
+
+/// Run the command repeatedly and report how its peak memory varies across runs.
+pub fn run(args: &BenchArgs) -> Result<()> {
+    shutdown::install();
+    if let Some(dir) = &args.csv_dir {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir))?;
+    }
+    let interval = Duration::from_millis(args.interval);
+
+    for n in 1..=args.warmup {
+        info!(
+            "Warm-up run {}/{}: {}",
+            n,
+            args.warmup,
+            args.command.join(" ")
+        );
+        measure(&args.command, interval)?;
+        if shutdown::requested() {
+            bail!("Interrupted during warm-up");
+        }
+    }
+
+    let mut runs = Vec::new();
+    for n in 1..=args.runs {
+        info!("Run {}/{}: {}", n, args.runs, args.command.join(" "));
+        let run = measure(&args.command, interval)?;
+        info!(
+            "  peak {:.2} MB in {:.2}s ({} samples){}",
+            run.peak_kb as f64 / 1024.0,
+            run.duration,
+            run.stats.samples.len(),
+            if run.success { "" } else { ", command failed" }
+        );
+        if let Some(dir) = &args.csv_dir {
+            let path = format!("{}/run-{}.csv", dir, n);
+            let writer = CsvWriter::create(&path, None, &[], Columns::kb())?;
+            let written = run
+                .stats
+                .samples
+                .iter()
+                .try_for_each(|(time, memory_kb)| writer.sample(*time, *memory_kb));
+            match written {
+                Ok(()) => writer.finish()?,
+                Err(e) => return Err(writer.stopped(e)),
+            }
+        }
+        runs.push(run);
+        if shutdown::requested() {
+            info!("Stopping after {} of {} runs", runs.len(), args.runs);
+            break;
+        }
+    }
+
+    // Peaks and durations as one-sample-per-run series, to reuse the recording statistics
+    let mut peaks = MemoryStats::new();
+    let mut durations = Vec::new();
+    for (idx, run) in runs.iter().enumerate() {
+        peaks.add_sample(idx as f64, run.peak_kb);
+        durations.push(run.duration);
+    }
+    let failed = runs.iter().filter(|r| !r.success).count();
+    let mean_duration = durations.iter().sum::<f64>() / durations.len() as f64;
+
+    println!("\n=== Peak memory over {} runs ===", runs.len());
+    println!("Mean:     {:>10.2} MB", peaks.mean() / 1024.0);
+    println!(
+        "Std dev:  {:>10.2} MB ({:.1}%)",
+        peaks.std_dev() / 1024.0,
+        cv_percent(&peaks)
+    );
+    println!("Median:   {:>10.2} MB", peaks.median() / 1024.0);
+    println!("Min:      {:>10.2} MB", peaks.min() as f64 / 1024.0);
+    println!("Max:      {:>10.2} MB", peaks.max() as f64 / 1024.0);
+    println!("Duration: {:>10.2} s mean", mean_duration);
+    if failed > 0 {
+        warn!("the command failed in {} of {} runs", failed, runs.len());
+    }
+
+    if !args.no_chart {
+        info!("Generating overlay chart: {}", args.output);
+        let labels: Vec<String> = (1..=runs.len()).map(|n| format!("Run {}", n)).collect();
+        let overlay: Vec<(String, &MemoryStats)> = labels
+            .into_iter()
+            .zip(runs.iter().map(|r| &r.stats))
+            .filter(|(_, stats)| !stats.samples.is_empty())
+            .collect();
+        let options = args.chart.options();
+        chart::generate_overlay(
+            &overlay,
+            chart::OverlayAxis::Elapsed("Time"),
+            &args.output,
+            &options,
+        )?;
+        info!("Chart saved successfully!");
+    }
+
+    if let Some(json_path) = &args.json {
+        let summary = serde_json::json!({
+            "command": args.command,
+            "runs": runs
+                .iter()
+                .map(|r| serde_json::json!({
+                    "peak_kb": r.peak_kb,
+                    "duration_s": r.duration,
+                    "samples": r.stats.samples.len(),
+                    "success": r.success,
+                }))
+                .collect::<Vec<_>>(),
+            "peak": {
+                "mean_kb": peaks.mean(),
+                "std_dev_kb": peaks.std_dev(),
+                "cv_percent": cv_percent(&peaks),
+                "median_kb": peaks.median(),
+                "min_kb": peaks.min(),
+                "max_kb": peaks.max(),
+            },
+            "mean_duration_s": mean_duration,
+            "failed_runs": failed,
+        });
+        fs::write(json_path, serde_json::to_string_pretty(&summary)?)
+            .with_context(|| format!("Failed to write JSON summary: {}", json_path))?;
+        info!("JSON summary saved to {}", json_path);
+    }
+    Ok(())
+}
+
+/// Launch the command once and sample its RSS until it exits.
+fn measure(command: &[String], interval: Duration) -> Result<BenchRun> {
+    let start = Instant::now();
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .spawn()
+        .with_context(|| format!("Failed to launch {}", command[0]))?;
+    let mut stats = MemoryStats::new();
+    let mut hwm_kb = 0;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        // The process may exit between try_wait and the read; the next try_wait reaps it
+        if let Ok(reading) = read_memory_usage(child.id()) {
+            stats.add_sample(start.elapsed().as_secs_f64(), reading.rss_kb);
+            hwm_kb = hwm_kb.max(reading.hwm_kb);
+        }
+        shutdown::sleep_until(Instant::now() + interval);
+        if shutdown::requested() {
+            let _ = child.kill();
+            break child.wait()?;
+        }
+    };
+    Ok(BenchRun {
+        peak_kb: stats.max().max(hwm_kb),
+        stats,
+        duration: start.elapsed().as_secs_f64(),
+        success: status.success(),
+    })
+}
+
+/// Standard deviation relative to the mean: how noisy a single run is.
+fn cv_percent(peaks: &MemoryStats) -> f64 {
+    let mean = peaks.mean();
+    if mean == 0.0 {
+        0.0
+    } else {
+        peaks.std_dev() / mean * 100.0
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{bail, Context, Result};
+use std::ffi::CStr;
+use std::{io, mem, ptr};
+
+use crate::MemoryReading;
+
+/// Read the resident set size and its high-water mark. The BSDs report neither swap
+/// use per process nor an anonymous/file/shared breakdown, so those stay zero.
+pub fn read_memory_usage(pid: u32) -> Result<MemoryReading> {
+    let info = kinfo_proc(pid)?;
+    // Like a Linux zombie without VmRSS, an exited but unreaped process ends the run
+    #[cfg(target_os = "freebsd")]
+    let zombie = info.ki_stat == libc::SZOMB;
+    #[cfg(target_os = "openbsd")]
+    let zombie = info.p_stat == SZOMB || info.p_stat == SDEAD;
+    if zombie {
+        return Err(gone()).with_context(|| format!("Process {} has exited", pid));
+    }
+    let page_kb = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64 / 1024;
+    #[cfg(target_os = "freebsd")]
+    let (rss_pages, hwm_kb) = (info.ki_rssize as u64, info.ki_rusage.ru_maxrss as u64);
+    #[cfg(target_os = "openbsd")]
+    let (rss_pages, hwm_kb) = (info.p_vm_rssize as u64, info.p_uru_maxrss);
+    Ok(MemoryReading {
+        rss_kb: rss_pages * page_kb,
+        hwm_kb,
+        ..Default::default()
+    })
+}
+
+/// The command line of `pid` with arguments joined by spaces, or empty when it cannot
+/// be read.
+pub fn read_cmdline(pid: u32) -> String {
+    let args = command_args(pid).unwrap_or_default();
+    if !args.is_empty() {
+        return args.join(" ");
+    }
+    // Kernel processes and zombies have no arguments, only a name
+    kinfo_proc(pid)
+        .map(|info| process_name(&info))
+        .unwrap_or_default()
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) } != 0 {
+        return "unknown".to_string();
+    }
+    // SAFETY: gethostname NUL-terminates names shorter than the buffer
+    unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Process states from OpenBSD's <sys/proc.h>, which the libc crate does not define
+#[cfg(target_os = "openbsd")]
+const SZOMB: i8 = 5;
+#[cfg(target_os = "openbsd")]
+const SDEAD: i8 = 6;
+
+fn kinfo_proc(pid: u32) -> Result<libc::kinfo_proc> {
+    #[cfg(target_os = "freebsd")]
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_PID,
+        pid as libc::c_int,
+    ];
+    // OpenBSD takes the record size and count as part of the name
+    #[cfg(target_os = "openbsd")]
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_PID,
+        pid as libc::c_int,
+        mem::size_of::<libc::kinfo_proc>() as libc::c_int,
+        1,
+    ];
+    // SAFETY: kinfo_proc is plain data, valid when zeroed
+    let mut info: libc::kinfo_proc = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::kinfo_proc>();
+    let result = unsafe {
+        libc::sysctl(
+            mib.as_ptr(),
+            mib.len() as libc::c_uint,
+            &mut info as *mut libc::kinfo_proc as *mut libc::c_void,
+            &mut len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if result != 0 {
+        bail!(
+            "Failed to read process {}: {}",
+            pid,
+            io::Error::last_os_error()
+        );
+    }
+    // An exited pid is not an error to sysctl, it just returns nothing
+    if len == 0 {
+        return Err(gone()).with_context(|| format!("Process {} not found", pid));
+    }
+    Ok(info)
+}
+
+/// The error of a process that no longer exists, which ends a run rather than being retried.
+fn gone() -> io::Error {
+    io::Error::from_raw_os_error(libc::ESRCH)
+}
+
+fn process_name(info: &libc::kinfo_proc) -> String {
+    #[cfg(target_os = "freebsd")]
+    let name = &info.ki_comm;
+    #[cfg(target_os = "openbsd")]
+    let name = &info.p_comm;
+    // SAFETY: the kernel NUL-terminates the name within the array
+    unsafe { CStr::from_ptr(name.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The arguments of `pid` through `KERN_PROC_ARGS`: NUL-separated strings on FreeBSD,
+/// an argv array followed by its strings on OpenBSD.
+fn command_args(pid: u32) -> io::Result<Vec<String>> {
+    #[cfg(target_os = "freebsd")]
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC,
+        libc::KERN_PROC_ARGS,
+        pid as libc::c_int,
+    ];
+    #[cfg(target_os = "openbsd")]
+    let mib = [
+        libc::CTL_KERN,
+        libc::KERN_PROC_ARGS,
+        pid as libc::c_int,
+        libc::KERN_PROC_ARGV,
+    ];
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let mut len = buf.len();
+        let result = unsafe {
+            libc::sysctl(
+                mib.as_ptr(),
+                mib.len() as libc::c_uint,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if result == 0 {
+            buf.truncate(len);
+            break;
+        }
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ENOMEM) || buf.len() >= 16 * 1024 * 1024 {
+            return Err(error);
+        }
+        buf.resize(buf.len() * 4, 0);
+    }
+
+    #[cfg(target_os = "freebsd")]
+    let args = buf
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    #[cfg(target_os = "openbsd")]
+    let args = {
+        // The kernel rewrites the argv pointers to point into `buf`
+        let argv = buf.as_ptr() as *const *const libc::c_char;
+        let mut args = Vec::new();
+        for idx in 0..buf.len() / mem::size_of::<*const libc::c_char>() {
+            // SAFETY: the array is NULL-terminated and its entries point into `buf`
+            let arg = unsafe { *argv.add(idx) };
+            if arg.is_null() {
+                break;
+            }
+            args.push(
+                unsafe { CStr::from_ptr(arg) }
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        args
+    };
+    Ok(args)
+}
@@ -0,0 +1,182 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Limits at or above this are the kernel's way of saying "unlimited" on cgroup v1.
+const V1_UNLIMITED: u64 = 1 << 62;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Version {
+    V1,
+    V2,
+}
+
+/// The memory cgroup a process belongs to.
+#[derive(Debug, Clone)]
+pub struct MemoryCgroup {
+    dir: PathBuf,
+    version: Version,
+}
+
+impl MemoryCgroup {
+    /// Resolve the memory cgroup of `pid` from `/proc/<pid>/cgroup`.
+    pub fn of_pid(pid: u32) -> Option<Self> {
+        let content = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+
+        // Prefer a v1 memory controller when present; hybrid systems expose both
+        for line in content.lines() {
+            let mut parts = line.splitn(3, ':');
+            let (_, controllers, path) = (parts.next()?, parts.next()?, parts.next()?);
+            if controllers.split(',').any(|c| c == "memory") {
+                return Some(Self {
+                    dir: resolve("/sys/fs/cgroup/memory", path),
+                    version: Version::V1,
+                });
+            }
+        }
+        for line in content.lines() {
+            if let Some(path) = line.strip_prefix("0::") {
+                return Some(Self {
+                    dir: resolve("/sys/fs/cgroup", path),
+                    version: Version::V2,
+                });
+            }
+        }
+        None
+    }
+
+    /// The memory cgroup at `path` inside the hierarchy, as systemd reports it for a unit.
+    pub fn at_path(path: &str) -> Option<Self> {
+        let v1 = Path::new("/sys/fs/cgroup/memory").join(path.trim_start_matches('/'));
+        if v1.is_dir() {
+            return Some(Self {
+                dir: v1,
+                version: Version::V1,
+            });
+        }
+        // Hybrid systems mount the unified hierarchy, without memory controller, separately
+        ["/sys/fs/cgroup", "/sys/fs/cgroup/unified"]
+            .into_iter()
+            .filter(|mount| Path::new(mount).join("cgroup.controllers").exists())
+            .map(|mount| Path::new(mount).join(path.trim_start_matches('/')))
+            .find(|dir| dir.is_dir())
+            .map(|dir| Self {
+                dir,
+                version: Version::V2,
+            })
+    }
+
+    /// Whether the cgroup still exists; systemd removes a unit's cgroup once it stops.
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    /// Every process in this cgroup and its descendants.
+    pub fn pids(&self) -> Vec<u32> {
+        let mut pids = Vec::new();
+        let mut pending = vec![self.dir.clone()];
+        while let Some(dir) = pending.pop() {
+            if let Ok(procs) = fs::read_to_string(dir.join("cgroup.procs")) {
+                pids.extend(
+                    procs
+                        .lines()
+                        .filter_map(|line| line.trim().parse::<u32>().ok()),
+                );
+            }
+            if let Ok(entries) = fs::read_dir(&dir) {
+                pending.extend(
+                    entries
+                        .flatten()
+                        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+                        .map(|entry| entry.path()),
+                );
+            }
+        }
+        pids
+    }
+
+    /// Effective memory limit in KB: the tightest limit on this cgroup or any ancestor.
+    pub fn limit_kb(&self) -> Option<u64> {
+        self.limiting().map(|(_, kb)| kb)
+    }
+
+    /// The cgroup holding the effective limit (this one or an ancestor), with the limit
+    /// in KB. Its usage is what the limit is enforced against.
+    pub fn limiting(&self) -> Option<(MemoryCgroup, u64)> {
+        let file = match self.version {
+            Version::V1 => "memory.limit_in_bytes",
+            Version::V2 => "memory.max",
+        };
+        let root = match self.version {
+            Version::V1 => Path::new("/sys/fs/cgroup/memory"),
+            Version::V2 => Path::new("/sys/fs/cgroup"),
+        };
+
+        let mut limit: Option<(&Path, u64)> = None;
+        let mut dir = Some(self.dir.as_path());
+        while let Some(current) = dir {
+            if let Some(bytes) = read_limit(&current.join(file)) {
+                if limit.is_none_or(|(_, l)| bytes < l) {
+                    limit = Some((current, bytes));
+                }
+            }
+            if current == root {
+                break;
+            }
+            dir = current.parent();
+        }
+        limit.map(|(dir, bytes)| {
+            let cgroup = MemoryCgroup {
+                dir: dir.to_path_buf(),
+                version: self.version,
+            };
+            (cgroup, bytes / 1024)
+        })
+    }
+
+    /// Memory charged to the cgroup in KB, page cache and kernel memory included
+    /// (`memory.current`, or `memory.usage_in_bytes` on v1).
+    pub fn usage_kb(&self) -> Option<u64> {
+        let file = match self.version {
+            Version::V1 => "memory.usage_in_bytes",
+            Version::V2 => "memory.current",
+        };
+        let bytes: u64 = fs::read_to_string(self.dir.join(file))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        Some(bytes / 1024)
+    }
+
+    /// The cgroup's memory pressure stall information (`memory.pressure`), which only
+    /// cgroup v2 has.
+    pub fn pressure(&self) -> Option<String> {
+        match self.version {
+            Version::V1 => None,
+            Version::V2 => fs::read_to_string(self.dir.join("memory.pressure")).ok(),
+        }
+    }
+}
+
+/// Map a path from `/proc/<pid>/cgroup` onto the mounted hierarchy. Inside a cgroup
+/// namespace the mount is rooted at the namespace, so fall back to the mount root.
+fn resolve(mount: &str, path: &str) -> PathBuf {
+    let candidate = Path::new(mount).join(path.trim_start_matches('/'));
+    if candidate.exists() {
+        candidate
+    } else {
+        PathBuf::from(mount)
+    }
+}
+
+fn read_limit(path: &Path) -> Option<u64> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+    if value == "max" {
+        return None;
+    }
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|bytes| *bytes < V1_UNLIMITED)
+}
@@ -0,0 +1,1668 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::coord::ranged1d::{
+    AsRangedCoord, KeyPointHint, NoDefaultFormatting, Ranged, ValueFormatter,
+};
+use plotters::coord::types::RangedCoordf64;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::segments::{Segment, SegmentKind};
+use crate::stats::MemoryStats;
+use crate::units::{self, Unit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+struct ThemeColors {
+    background: RGBColor,
+    foreground: RGBColor,
+    grid: RGBColor,
+    series: RGBColor,
+    secondary: RGBColor,
+    threshold: RGBColor,
+    marker: RGBColor,
+    /// Plateau, growth and decline segment fits
+    segments: [RGBColor; 3],
+}
+
+impl Theme {
+    fn palette(self) -> ThemeColors {
+        match self {
+            Theme::Light => ThemeColors {
+                background: WHITE,
+                foreground: BLACK,
+                grid: RGBColor(220, 220, 220),
+                series: BLUE,
+                secondary: RGBColor(214, 96, 24),
+                threshold: RGBColor(200, 30, 30),
+                marker: RGBColor(90, 90, 90),
+                segments: [
+                    RGBColor(30, 140, 60),
+                    RGBColor(200, 30, 30),
+                    RGBColor(120, 60, 180),
+                ],
+            },
+            Theme::Dark => ThemeColors {
+                background: RGBColor(24, 26, 31),
+                foreground: RGBColor(215, 218, 224),
+                grid: RGBColor(60, 64, 72),
+                series: RGBColor(100, 170, 255),
+                secondary: RGBColor(255, 160, 80),
+                threshold: RGBColor(255, 90, 90),
+                marker: RGBColor(170, 170, 170),
+                segments: [
+                    RGBColor(90, 200, 120),
+                    RGBColor(255, 90, 90),
+                    RGBColor(190, 140, 255),
+                ],
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChartStyle {
+    /// A single RSS line
+    Line,
+    /// RSS broken down into anonymous, file-backed and shared memory as stacked areas
+    Stacked,
+}
+
+/// Corner of the plot the series legend is drawn in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LegendPosition {
+    UpperLeft,
+    UpperRight,
+    LowerLeft,
+    LowerRight,
+    /// No legend
+    None,
+}
+
+impl LegendPosition {
+    fn series_label_position(self) -> Option<SeriesLabelPosition> {
+        match self {
+            LegendPosition::UpperLeft => Some(SeriesLabelPosition::UpperLeft),
+            LegendPosition::UpperRight => Some(SeriesLabelPosition::UpperRight),
+            LegendPosition::LowerLeft => Some(SeriesLabelPosition::LowerLeft),
+            LegendPosition::LowerRight => Some(SeriesLabelPosition::LowerRight),
+            LegendPosition::None => None,
+        }
+    }
+}
+
+/// How the RSS series is reduced before drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Downsample {
+    /// Use a min/max band when there are more samples than pixels
+    Auto,
+    /// Always draw a per-pixel min/max band with a mean line
+    Band,
+    /// Reduce to `downsample_points` with Largest-Triangle-Three-Buckets, preserving spikes
+    Lttb,
+    /// Always draw every sample
+    None,
+}
+
+/// The chart options of every command that draws one, flattened into its arguments. A
+/// command with a title of its own sets it with `mut_arg("title", ...)`.
+#[derive(clap::Args)]
+pub struct ChartArgs {
+    /// Chart title
+    #[arg(long, default_value = "Memory Usage Over Time")]
+    pub title: String,
+
+    /// Chart size in pixels as WIDTHxHEIGHT
+    #[arg(long, value_name = "WxH", default_value = "1024x768", value_parser = parse_size)]
+    pub chart_size: (u32, u32),
+
+    /// Chart color theme
+    #[arg(long, value_enum, default_value = "light")]
+    pub theme: Theme,
+
+    /// Target display DPI; text, margins and line widths scale relative to 96
+    #[arg(long, default_value = "96", value_parser = clap::value_parser!(u32).range(1..))]
+    pub dpi: u32,
+}
+
+impl ChartArgs {
+    /// Chart options with these arguments and the defaults for everything else.
+    pub fn options(&self) -> ChartOptions {
+        ChartOptions {
+            size: self.chart_size,
+            title: self.title.clone(),
+            theme: self.theme,
+            dpi: self.dpi,
+            ..Default::default()
+        }
+    }
+}
+
+/// Rendering options for the memory chart.
+#[derive(Debug, Clone)]
+pub struct ChartOptions {
+    pub size: (u32, u32),
+    pub title: String,
+    pub theme: Theme,
+    /// Target display DPI; text, margins and strokes scale relative to 96 DPI
+    pub dpi: u32,
+    pub log_scale: bool,
+    pub style: ChartStyle,
+    pub downsample: Downsample,
+    /// Target point count for LTTB downsampling
+    pub downsample_points: usize,
+    /// Window (in samples) of the moving-average overlay
+    pub smooth: Option<usize>,
+    /// Draw the allocation rate, averaged over this many sample intervals, in a panel
+    /// below the memory chart
+    pub rate_window: Option<usize>,
+    /// Unit of the memory axis; `Auto` is resolved from the largest value drawn
+    pub unit: Unit,
+    /// Labeled horizontal reference lines, in KB
+    pub thresholds: Vec<(String, f64)>,
+    /// Labeled vertical event markers, in seconds
+    pub markers: Vec<(f64, String)>,
+    /// Label the time axis with wall-clock times relative to this start instead of seconds
+    pub wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+    /// Most ticks on the time axis; by default one per ~100 pixels of width
+    pub x_ticks: Option<u32>,
+    /// strftime format of wall-clock tick labels; by default the time of day, with the
+    /// date once the axis spans more than one day
+    pub date_format: Option<String>,
+    /// Plateau/growth segments to draw as colored fit lines over the series
+    pub segments: Vec<Segment>,
+    /// Flagged spikes as (time, KB, label), drawn as circled points
+    pub spikes: Vec<(f64, f64, String)>,
+    /// Smaller line under the title identifying the run
+    pub subtitle: Option<String>,
+    /// Corner of the legend, which is drawn once there is more than the RSS series
+    pub legend: LegendPosition,
+    /// Draw an inset box with peak, mean, p95 and duration in the upper corner the legend
+    /// does not use
+    pub stats_box: bool,
+    /// Monitored process, e.g. `./app (pid 1234)`, heading the stats box
+    pub target: Option<String>,
+}
+
+impl Default for ChartOptions {
+    fn default() -> Self {
+        Self {
+            size: (1024, 768),
+            title: "Memory Usage Over Time".to_string(),
+            theme: Theme::Light,
+            dpi: 96,
+            log_scale: false,
+            style: ChartStyle::Line,
+            downsample: Downsample::Auto,
+            downsample_points: 2000,
+            smooth: None,
+            rate_window: None,
+            unit: Unit::Mb,
+            thresholds: Vec::new(),
+            markers: Vec::new(),
+            wallclock_start: None,
+            x_ticks: None,
+            date_format: None,
+            segments: Vec::new(),
+            spikes: Vec::new(),
+            subtitle: None,
+            legend: LegendPosition::UpperLeft,
+            stats_box: false,
+            target: None,
+        }
+    }
+}
+
+impl ChartOptions {
+    /// These options with `Auto` units replaced by the unit suiting values up to `max_kb`.
+    fn resolved(&self, max_kb: f64) -> ChartOptions {
+        ChartOptions {
+            unit: self.unit.resolve(max_kb),
+            ..self.clone()
+        }
+    }
+
+    fn memory_desc(&self, name: &str) -> String {
+        format!("{} ({})", name, self.unit.label())
+    }
+
+    fn scale(&self, value: u32) -> u32 {
+        (value * self.dpi).div_ceil(96)
+    }
+}
+
+/// Parse a `WIDTHxHEIGHT` chart size argument.
+pub fn parse_size(arg: &str) -> Result<(u32, u32), String> {
+    let (w, h) = arg
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got '{}'", arg))?;
+    let w: u32 = w.parse().map_err(|_| format!("invalid width '{}'", w))?;
+    let h: u32 = h.parse().map_err(|_| format!("invalid height '{}'", h))?;
+    if w == 0 || h == 0 {
+        return Err("chart dimensions must be non-zero".to_string());
+    }
+    Ok((w, h))
+}
+
+pub fn generate_chart(
+    stats: &MemoryStats,
+    output_path: &str,
+    options: &ChartOptions,
+) -> Result<()> {
+    let options = &options.resolved(stats.max() as f64);
+    let is_svg = Path::new(output_path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        draw_chart(
+            SVGBackend::new(output_path, options.size).into_drawing_area(),
+            stats,
+            options,
+        )
+    } else {
+        draw_chart(
+            BitMapBackend::new(output_path, options.size).into_drawing_area(),
+            stats,
+            options,
+        )
+    }
+}
+
+/// The chart [`generate_chart`] draws, encoded as a PNG in memory.
+pub fn render_png(stats: &MemoryStats, options: &ChartOptions) -> Result<Vec<u8>> {
+    let options = &options.resolved(stats.max() as f64);
+    let (width, height) = options.size;
+    let mut rgb = vec![0; width as usize * height as usize * 3];
+    draw_chart(
+        BitMapBackend::with_buffer(&mut rgb, options.size).into_drawing_area(),
+        stats,
+        options,
+    )?;
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&rgb, width, height, ColorType::Rgb8)?;
+    Ok(png)
+}
+
+/// Parse a `--date-format` strftime string, rejecting specifiers chrono cannot format.
+pub fn parse_date_format(arg: &str) -> Result<String, String> {
+    if chrono::format::StrftimeItems::new(arg).any(|item| item == chrono::format::Item::Error) {
+        return Err(format!(
+            "invalid strftime format '{}', e.g. '%H:%M' or '%d %b %H:%M'",
+            arg
+        ));
+    }
+    Ok(arg.to_string())
+}
+
+/// What the x axis of an overlay chart measures.
+#[derive(Debug, Clone, Copy)]
+pub enum OverlayAxis<'a> {
+    /// Seconds since each run (or the first) started, labelled like a single run's time
+    /// axis; the name is completed with the label format, e.g. "Time (m:ss)"
+    Elapsed(&'a str),
+    /// Any other quantity, with its full description, e.g. "Run progress (%)"
+    Other(&'a str),
+}
+
+/// Render several recordings as lines on one chart with a legend entry per run.
+pub fn generate_overlay(
+    runs: &[(String, &MemoryStats)],
+    x_axis: OverlayAxis,
+    output_path: &str,
+    options: &ChartOptions,
+) -> Result<()> {
+    let max_kb = runs.iter().map(|(_, s)| s.max()).max().unwrap_or(0);
+    let options = &options.resolved(max_kb as f64);
+    let is_svg = Path::new(output_path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        draw_overlay(
+            SVGBackend::new(output_path, options.size).into_drawing_area(),
+            runs,
+            x_axis,
+            options,
+        )
+    } else {
+        draw_overlay(
+            BitMapBackend::new(output_path, options.size).into_drawing_area(),
+            runs,
+            x_axis,
+            options,
+        )
+    }
+}
+
+fn draw_overlay<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    runs: &[(String, &MemoryStats)],
+    x_axis: OverlayAxis,
+    options: &ChartOptions,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let palette = options.theme.palette();
+    root.fill(&palette.background)?;
+
+    let max_time = runs
+        .iter()
+        .filter_map(|(_, stats)| stats.samples.last().map(|(t, _)| *t))
+        .fold(0.0, f64::max);
+    let per_unit = options.unit.kb();
+    let max_y = runs.iter().map(|(_, s)| s.max()).max().unwrap_or(0) as f64 / per_unit;
+    let min_y = runs.iter().map(|(_, s)| s.min()).min().unwrap_or(0) as f64 / per_unit;
+    let y_margin = match (max_y - min_y) / 10.0 {
+        m if m > 0.0 => m,
+        _ => (max_y / 10.0).max(1.0),
+    };
+    let max_time = max_time.max(f64::MIN_POSITIVE);
+    // Runs are lined up by elapsed time, never by the clock
+    let time_axis = match x_axis {
+        OverlayAxis::Elapsed(_) => Some(TimeAxis::new(
+            &ChartOptions {
+                wallclock_start: None,
+                ..options.clone()
+            },
+            0.0,
+            max_time,
+        )),
+        OverlayAxis::Other(_) => None,
+    };
+    let (ticks, x_desc) = match (&time_axis, x_axis) {
+        (Some(axis), OverlayAxis::Elapsed(name)) => (axis.ticks.clone(), axis.desc(name)),
+        (_, OverlayAxis::Elapsed(desc) | OverlayAxis::Other(desc)) => {
+            let count = tick_count(options) as f64;
+            let magnitude = 10f64.powi((max_time / count).log10().floor() as i32);
+            let step = NICE_STEPS
+                .iter()
+                .map(|step| step * magnitude)
+                .find(|step| max_time / step <= count)
+                .unwrap_or(max_time);
+            (ticks_every(0.0, max_time, step, 0.0), desc.to_string())
+        }
+    };
+    let x_label = |v: &f64| match &time_axis {
+        Some(axis) => axis.label(*v),
+        None => v.to_string(),
+    };
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(
+            &options.title,
+            ("sans-serif", options.scale(40))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .margin(options.scale(10))
+        .x_label_area_size(options.scale(40))
+        .y_label_area_size(options.scale(60))
+        .build_cartesian_2d(
+            TickedCoord::new(0f64..max_time, ticks),
+            (min_y - y_margin).max(0.0)..max_y + y_margin,
+        )?;
+
+    chart
+        .configure_mesh()
+        .x_desc(x_desc)
+        .x_label_formatter(&x_label)
+        .y_desc(options.memory_desc("Memory"))
+        .axis_style(palette.foreground)
+        .bold_line_style(palette.grid)
+        .light_line_style(palette.grid.mix(0.4))
+        .label_style(
+            ("sans-serif", options.scale(12))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .axis_desc_style(
+            ("sans-serif", options.scale(16))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .draw()?;
+
+    for (idx, (label, stats)) in runs.iter().enumerate() {
+        let style = Palette99::pick(idx).stroke_width(options.scale(2));
+        let points: Vec<(f64, f64)> = stats
+            .samples
+            .iter()
+            .map(|(t, kb)| (*t, *kb as f64 / per_unit))
+            .collect();
+        // Band rendering cannot tell overlapping runs apart, so reduce with LTTB instead
+        let points = match options.downsample {
+            Downsample::None => points,
+            _ => lttb(&points, options.downsample_points),
+        };
+        chart
+            .draw_series(LineSeries::new(points, style))?
+            .label(label.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+    }
+
+    if let Some(position) = options.legend.series_label_position() {
+        chart
+            .configure_series_labels()
+            .position(position)
+            .background_style(palette.background.mix(0.8))
+            .border_style(palette.grid)
+            .label_font(
+                ("sans-serif", options.scale(14))
+                    .into_font()
+                    .color(&palette.foreground),
+            )
+            .draw()?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+/// One metric drawn on its own axis by [`generate_panels`].
+pub struct Panel {
+    pub name: String,
+    /// Axis label, including the unit
+    pub y_desc: String,
+    /// (time, value) in the unit of `y_desc`
+    pub points: Vec<(f64, f64)>,
+    /// Labeled horizontal reference lines, in the unit of `y_desc`
+    pub thresholds: Vec<(String, f64)>,
+}
+
+impl Panel {
+    /// A panel of a KB series shown in `unit`, resolving `Auto` from the series itself.
+    pub fn memory(
+        name: &str,
+        series: &[(f64, u64)],
+        unit: Unit,
+        thresholds_kb: &[(String, f64)],
+    ) -> Self {
+        let unit = unit.resolve(series.iter().map(|(_, kb)| *kb).max().unwrap_or(0) as f64);
+        Self {
+            name: name.to_string(),
+            y_desc: format!("{} ({})", name, unit.label()),
+            points: series
+                .iter()
+                .map(|(t, kb)| (*t, *kb as f64 / unit.kb()))
+                .collect(),
+            thresholds: thresholds_kb
+                .iter()
+                .map(|(label, kb)| (label.clone(), kb / unit.kb()))
+                .collect(),
+        }
+    }
+}
+
+/// Render `panels` stacked on a shared time axis, each with its own y-axis, under
+/// `title`. Markers of `options` are drawn across every panel.
+pub fn generate_panels(
+    panels: &[Panel],
+    title: &str,
+    output_path: &str,
+    options: &ChartOptions,
+) -> Result<()> {
+    let is_svg = Path::new(output_path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        draw_panels(
+            SVGBackend::new(output_path, options.size).into_drawing_area(),
+            panels,
+            title,
+            options,
+        )
+    } else {
+        draw_panels(
+            BitMapBackend::new(output_path, options.size).into_drawing_area(),
+            panels,
+            title,
+            options,
+        )
+    }
+}
+
+fn draw_panels<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    panels: &[Panel],
+    title: &str,
+    options: &ChartOptions,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let palette = options.theme.palette();
+    root.fill(&palette.background)?;
+    let area = titled_area(&root, title, options, &palette)?;
+
+    let times = panels.iter().flat_map(|p| p.points.iter().map(|(t, _)| *t));
+    let min_time = times.clone().fold(f64::INFINITY, f64::min);
+    let max_time = times.fold(f64::NEG_INFINITY, f64::max);
+    let time_range = if min_time < max_time {
+        (min_time, max_time)
+    } else {
+        (0.0, 1.0)
+    };
+    for (idx, (panel, panel_area)) in panels
+        .iter()
+        .zip(area.split_evenly((panels.len().max(1), 1)))
+        .enumerate()
+    {
+        plot_panel(&panel_area, panel, idx, options, &palette, time_range)?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+/// Draw one metric of a panel figure. Event markers are labeled on the first panel only.
+fn plot_panel<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    panel: &Panel,
+    idx: usize,
+    options: &ChartOptions,
+    palette: &ThemeColors,
+    (min_time, max_time): (f64, f64),
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    // Keep zero in range so values of different panels are read against the same baseline
+    let (lo, hi) = panel
+        .points
+        .iter()
+        .map(|(_, v)| *v)
+        .chain(panel.thresholds.iter().map(|(_, v)| *v))
+        .fold((0f64, 0f64), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let margin = match (hi - lo) / 10.0 {
+        m if m > 0.0 => m,
+        _ => 1.0,
+    };
+    let y_min = if lo < 0.0 { lo - margin } else { 0.0 };
+    let axis = TimeAxis::new(options, min_time, max_time);
+    let mut chart = ChartBuilder::on(root)
+        .margin(options.scale(10))
+        .x_label_area_size(options.scale(40))
+        .y_label_area_size(options.scale(60))
+        .build_cartesian_2d(
+            TickedCoord::new(min_time..max_time, axis.ticks.clone()),
+            y_min..hi + margin,
+        )?;
+
+    let x_label = |t: &f64| axis.label(*t);
+    chart
+        .configure_mesh()
+        .x_desc(axis.desc("Time"))
+        .x_label_formatter(&x_label)
+        .y_desc(panel.y_desc.as_str())
+        .y_label_formatter(&|v: &f64| compact_label(*v))
+        .axis_style(palette.foreground)
+        .bold_line_style(palette.grid)
+        .light_line_style(palette.grid.mix(0.4))
+        .label_style(
+            ("sans-serif", options.scale(12))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .axis_desc_style(
+            ("sans-serif", options.scale(16))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .draw()?;
+
+    if lo < 0.0 {
+        chart.draw_series(LineSeries::new(
+            [(min_time, 0.0), (max_time, 0.0)],
+            palette.foreground.mix(0.5).stroke_width(options.scale(1)),
+        ))?;
+    }
+    chart.draw_series(LineSeries::new(
+        downsampled(panel.points.clone(), options),
+        Palette99::pick(idx).stroke_width(options.scale(2)),
+    ))?;
+
+    for (label, y) in &panel.thresholds {
+        chart.draw_series(DashedLineSeries::new(
+            [(min_time, *y), (max_time, *y)],
+            options.scale(10),
+            options.scale(6),
+            palette.threshold.stroke_width(options.scale(2)),
+        ))?;
+        chart.draw_series(std::iter::once(
+            EmptyElement::at((min_time, *y))
+                + Text::new(
+                    label.clone(),
+                    (options.scale(6) as i32, -(options.scale(18) as i32)),
+                    ("sans-serif", options.scale(14))
+                        .into_font()
+                        .color(&palette.threshold),
+                ),
+        ))?;
+    }
+
+    let (y_low, y_high) = (y_min, hi + margin);
+    for (time, label) in &options.markers {
+        chart.draw_series(DashedLineSeries::new(
+            [(*time, y_low), (*time, y_high)],
+            options.scale(6),
+            options.scale(4),
+            palette.marker.stroke_width(options.scale(1)),
+        ))?;
+        if idx == 0 {
+            chart.draw_series(std::iter::once(
+                EmptyElement::at((*time, y_high))
+                    + Text::new(
+                        label.clone(),
+                        (options.scale(16) as i32, options.scale(4) as i32),
+                        ("sans-serif", options.scale(13))
+                            .into_font()
+                            .transform(FontTransform::Rotate90)
+                            .color(&palette.marker),
+                    ),
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Short tick label for axes whose values span several orders of magnitude.
+fn compact_label(value: f64) -> String {
+    match value.abs() {
+        v if v >= 1e6 => format!("{:.1}M", value / 1e6),
+        v if v >= 1e4 => format!("{:.0}k", value / 1e3),
+        v if v >= 100.0 || value.fract() == 0.0 => format!("{:.0}", value),
+        _ => format!("{:.2}", value),
+    }
+}
+
+/// Render a histogram of `buckets` (lower_kb, upper_kb, count) using the theme, size
+/// and DPI of `options`.
+pub fn generate_histogram(
+    buckets: &[(f64, f64, usize)],
+    output_path: &str,
+    options: &ChartOptions,
+) -> Result<()> {
+    let options = &options.resolved(buckets.last().map(|b| b.1).unwrap_or(0.0));
+    let is_svg = Path::new(output_path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        draw_histogram(
+            SVGBackend::new(output_path, options.size).into_drawing_area(),
+            buckets,
+            options,
+        )
+    } else {
+        draw_histogram(
+            BitMapBackend::new(output_path, options.size).into_drawing_area(),
+            buckets,
+            options,
+        )
+    }
+}
+
+fn draw_histogram<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    buckets: &[(f64, f64, usize)],
+    options: &ChartOptions,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let palette = options.theme.palette();
+    root.fill(&palette.background)?;
+
+    let per_unit = options.unit.kb();
+    let x_min = buckets.first().map(|b| b.0 / per_unit).unwrap_or(0.0);
+    let x_max = buckets.last().map(|b| b.1 / per_unit).unwrap_or(1.0);
+    let max_count = buckets.iter().map(|b| b.2).max().unwrap_or(0);
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(
+            "Memory Usage Distribution",
+            ("sans-serif", options.scale(40))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .margin(options.scale(10))
+        .x_label_area_size(options.scale(40))
+        .y_label_area_size(options.scale(60))
+        .build_cartesian_2d(x_min..x_max, 0usize..(max_count + max_count / 10).max(1))?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_desc(options.memory_desc("Memory"))
+        .y_desc("Samples")
+        .axis_style(palette.foreground)
+        .bold_line_style(palette.grid)
+        .light_line_style(palette.grid.mix(0.4))
+        .label_style(
+            ("sans-serif", options.scale(12))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .axis_desc_style(
+            ("sans-serif", options.scale(16))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .draw()?;
+
+    chart.draw_series(buckets.iter().map(|(lower, upper, count)| {
+        Rectangle::new(
+            [(lower / per_unit, *count), (upper / per_unit, 0)],
+            palette.series.mix(0.7).filled(),
+        )
+    }))?;
+    chart.draw_series(buckets.iter().map(|(lower, upper, count)| {
+        Rectangle::new(
+            [(lower / per_unit, *count), (upper / per_unit, 0)],
+            palette.background.stroke_width(1),
+        )
+    }))?;
+
+    root.present()?;
+    Ok(())
+}
+
+fn draw_chart<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    stats: &MemoryStats,
+    options: &ChartOptions,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let palette = options.theme.palette();
+    root.fill(&palette.background)?;
+
+    // Cropped recordings start wherever the crop does rather than at zero
+    let min_time = stats.samples.first().map(|(t, _)| *t).unwrap_or(0.0);
+    let max_time = stats.samples.last().map(|(t, _)| *t).unwrap_or(0.0);
+    // Reference lines must stay visible even when the run never got close to them
+    let per_unit = options.unit.kb();
+    let max_memory = options
+        .thresholds
+        .iter()
+        .map(|(_, kb)| *kb / per_unit)
+        .chain(
+            stats
+                .extra_series
+                .iter()
+                .flat_map(|(_, series)| series.iter().map(|(_, kb)| *kb as f64 / per_unit)),
+        )
+        .fold(stats.max() as f64 / per_unit, f64::max);
+    let min_memory = stats
+        .extra_series
+        .iter()
+        .flat_map(|(_, series)| series.iter().map(|(_, kb)| *kb as f64 / per_unit))
+        .fold(stats.min() as f64 / per_unit, f64::min);
+
+    // With a subtitle the title is drawn here, above it, instead of as the chart caption
+    let area = match &options.subtitle {
+        Some(_) => titled_area(&root, &options.title, options, &palette)?,
+        None => root.clone(),
+    };
+    let (area, rate_area) = match options.rate_window {
+        Some(window) => {
+            let (memory, rate) = area.split_vertically(area.dim_in_pixel().1 * 2 / 3);
+            (memory, Some((rate, window)))
+        }
+        None => (area, None),
+    };
+
+    if options.log_scale {
+        // A log axis cannot reach zero, and margins must be multiplicative to look even
+        let y_min = (min_memory / 1.25).max(0.01);
+        let y_max = (max_memory * 1.25).max(y_min * 10.0);
+        plot(
+            &area,
+            stats,
+            options,
+            &palette,
+            (min_time, max_time),
+            (y_min..y_max).log_scale(),
+            y_min,
+        )?;
+    } else {
+        // A perfectly flat run would otherwise collapse the axis to a single value
+        let y_margin = match (max_memory - min_memory) / 10.0 {
+            m if m > 0.0 => m,
+            _ => (max_memory / 10.0).max(1.0),
+        };
+        // Stacked areas are only meaningful against a zero baseline
+        let y_min = match options.style {
+            ChartStyle::Stacked => 0.0,
+            ChartStyle::Line => (min_memory - y_margin).max(0.0),
+        };
+        let y_max = max_memory + y_margin;
+        plot(
+            &area,
+            stats,
+            options,
+            &palette,
+            (min_time, max_time),
+            y_min..y_max,
+            y_min,
+        )?;
+    }
+    if let Some((rate_area, window)) = rate_area {
+        plot_rate(
+            &rate_area,
+            stats,
+            window,
+            options,
+            &palette,
+            (min_time, max_time),
+        )?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+/// Draw `title` and the subtitle of `options` at the top of `root`, returning the area
+/// below them.
+fn titled_area<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    title: &str,
+    options: &ChartOptions,
+    palette: &ThemeColors,
+) -> Result<DrawingArea<DB, Shift>>
+where
+    DB::ErrorType: 'static,
+{
+    let area = root.margin(options.scale(10), 0, 0, 0).titled(
+        title,
+        ("sans-serif", options.scale(40))
+            .into_font()
+            .color(&palette.foreground),
+    )?;
+    let Some(subtitle) = &options.subtitle else {
+        return Ok(area);
+    };
+    area.draw(&Text::new(
+        subtitle.as_str(),
+        (area.dim_in_pixel().0 as i32 / 2, 0),
+        ("sans-serif", options.scale(14))
+            .into_font()
+            .color(&palette.foreground.mix(0.7))
+            .pos(Pos::new(HPos::Center, VPos::Top)),
+    ))?;
+    Ok(area.margin(options.scale(18), 0, 0, 0))
+}
+
+/// Steps between time ticks in seconds, from a hundredth of a second to a day; longer
+/// axes step by whole days.
+const TIME_STEPS: [f64; 24] = [
+    0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+    900.0, 1800.0, 3600.0, 7200.0, 10800.0, 21600.0, 43200.0, 86400.0,
+];
+
+/// Steps of a plain axis, times a power of ten.
+const NICE_STEPS: [f64; 4] = [1.0, 2.0, 5.0, 10.0];
+
+/// Most ticks on the x axis: `--x-ticks`, or one per ~100 pixels so labels do not touch.
+fn tick_count(options: &ChartOptions) -> u32 {
+    options
+        .x_ticks
+        .unwrap_or_else(|| options.size.0 / options.scale(100))
+        .max(2)
+}
+
+/// Multiples of `step` within `min..=max`, counted from `offset` seconds before zero.
+fn ticks_every(min: f64, max: f64, step: f64, offset: f64) -> Vec<f64> {
+    let first = ((min + offset) / step - 1e-9).ceil();
+    let ticks: Vec<f64> = (0..)
+        .map(|idx| (first + idx as f64) * step - offset)
+        .take_while(|t| *t <= max + step * 1e-9)
+        .collect();
+    if ticks.is_empty() {
+        return vec![min];
+    }
+    ticks
+}
+
+/// A linear x coordinate with grid lines and labels only at the given ticks.
+struct TickedCoord {
+    inner: RangedCoordf64,
+    ticks: Vec<f64>,
+}
+
+impl TickedCoord {
+    fn new(range: Range<f64>, ticks: Vec<f64>) -> TickedCoord {
+        TickedCoord {
+            inner: range.into(),
+            ticks,
+        }
+    }
+}
+
+impl Ranged for TickedCoord {
+    type FormatOption = NoDefaultFormatting;
+    type ValueType = f64;
+
+    fn map(&self, value: &f64, limit: (i32, i32)) -> i32 {
+        self.inner.map(value, limit)
+    }
+
+    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<f64> {
+        // No light lines between the ticks
+        if hint.weight().allow_light_points() {
+            return Vec::new();
+        }
+        self.ticks.clone()
+    }
+
+    fn range(&self) -> Range<f64> {
+        self.inner.range()
+    }
+}
+
+impl ValueFormatter<f64> for TickedCoord {
+    fn format(value: &f64) -> String {
+        value.to_string()
+    }
+}
+
+/// How tick labels of the time axis are written.
+#[derive(Clone)]
+enum TimeFormat {
+    /// `12.5`, with this many decimals
+    Seconds(usize),
+    /// `4:05`
+    Minutes(usize),
+    /// `2:03:00`
+    Hours(usize),
+    /// strftime format of wall-clock times from the start
+    Clock(chrono::DateTime<chrono::Local>, String),
+}
+
+/// Ticks and labels of the time axis over `min..max` seconds since the start: round
+/// steps (5s, 1m, 15m, 1h, ...) written as seconds, m:ss or h:mm:ss depending on how
+/// far the axis reaches, or wall-clock times falling on round times of day.
+#[derive(Clone)]
+struct TimeAxis {
+    ticks: Vec<f64>,
+    format: TimeFormat,
+}
+
+impl TimeAxis {
+    fn new(options: &ChartOptions, min: f64, max: f64) -> TimeAxis {
+        let count = tick_count(options) as f64;
+        let span = (max - min).max(0.0);
+        let step = TIME_STEPS
+            .iter()
+            .copied()
+            // Clock labels stop at seconds
+            .filter(|step| options.wallclock_start.is_none() || *step >= 1.0)
+            .find(|step| span / step <= count)
+            .unwrap_or_else(|| (span / count / 86400.0).ceil() * 86400.0);
+        let decimals = match step {
+            s if s < 0.1 => 2,
+            s if s < 1.0 => 1,
+            _ => 0,
+        };
+        let reach = min.abs().max(max.abs());
+        let (format, offset) = match options.wallclock_start {
+            Some(start) => {
+                use chrono::Timelike;
+                let at = |t: f64| start + chrono::TimeDelta::milliseconds((t * 1000.0) as i64);
+                let format = options.date_format.clone().unwrap_or_else(|| {
+                    if at(min).date_naive() != at(max).date_naive() {
+                        "%m-%d %H:%M".to_string()
+                    } else if step >= 60.0 {
+                        "%H:%M".to_string()
+                    } else {
+                        "%H:%M:%S".to_string()
+                    }
+                });
+                // Seconds since midnight at the start, so ticks land on round times
+                let since_midnight = start.num_seconds_from_midnight() as f64
+                    + start.timestamp_subsec_millis() as f64 / 1000.0;
+                (TimeFormat::Clock(start, format), since_midnight)
+            }
+            None if reach < 60.0 => (TimeFormat::Seconds(decimals), 0.0),
+            None if reach < 3600.0 => (TimeFormat::Minutes(decimals), 0.0),
+            None => (TimeFormat::Hours(decimals), 0.0),
+        };
+        TimeAxis {
+            ticks: ticks_every(min, max, step, offset),
+            format,
+        }
+    }
+
+    fn label(&self, t: f64) -> String {
+        let (decimals, hours) = match &self.format {
+            TimeFormat::Seconds(decimals) => return format!("{:.*}", decimals, t),
+            TimeFormat::Clock(start, format) => {
+                return (*start + chrono::TimeDelta::milliseconds((t * 1000.0) as i64))
+                    .format(format)
+                    .to_string()
+            }
+            TimeFormat::Minutes(decimals) => (*decimals, false),
+            TimeFormat::Hours(decimals) => (*decimals, true),
+        };
+        // Round first so 59.96s at one decimal reads 1:00.0 rather than 0:60.0
+        let scale = 10f64.powi(decimals as i32);
+        let rounded = (t.abs() * scale).round() / scale;
+        let whole = rounded.trunc() as u64;
+        let seconds = rounded - (whole - whole % 60) as f64;
+        let width = if decimals > 0 { decimals + 3 } else { 2 };
+        let sign = if t < 0.0 && rounded > 0.0 { "-" } else { "" };
+        if hours {
+            format!(
+                "{}{}:{:02}:{:0w$.d$}",
+                sign,
+                whole / 3600,
+                whole % 3600 / 60,
+                seconds,
+                w = width,
+                d = decimals
+            )
+        } else {
+            format!(
+                "{}{}:{:0w$.d$}",
+                sign,
+                whole / 60,
+                seconds,
+                w = width,
+                d = decimals
+            )
+        }
+    }
+
+    /// Axis description: `name` with how the labels read, e.g. `Time (h:mm:ss)`.
+    fn desc(&self, name: &str) -> String {
+        match &self.format {
+            TimeFormat::Seconds(_) => format!("{} (seconds)", name),
+            TimeFormat::Minutes(_) => format!("{} (m:ss)", name),
+            TimeFormat::Hours(_) => format!("{} (h:mm:ss)", name),
+            TimeFormat::Clock(start, _) => format!("{} ({})", name, start.format("%Y-%m-%d %Z")),
+        }
+    }
+}
+
+/// Draw the allocation rate of `stats` under the memory chart, on the same time axis.
+fn plot_rate<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    stats: &MemoryStats,
+    window: usize,
+    options: &ChartOptions,
+    palette: &ThemeColors,
+    (min_time, max_time): (f64, f64),
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let rate = stats.rate_series(window);
+    // Keep zero in range so growth and release read as above and below the line
+    let (lo, hi) = rate.iter().fold((0f64, 0f64), |(lo, hi), (_, kb_s)| {
+        (lo.min(*kb_s), hi.max(*kb_s))
+    });
+    let margin = ((hi - lo) / 10.0).max(1.0);
+    let axis = TimeAxis::new(options, min_time, max_time);
+    let mut chart = ChartBuilder::on(root)
+        .margin(options.scale(10))
+        .x_label_area_size(options.scale(40))
+        .y_label_area_size(options.scale(60))
+        // Line up with the memory chart, which reserves the right side for swap
+        .right_y_label_area_size(if stats.swap_samples.is_empty() {
+            0
+        } else {
+            options.scale(60)
+        })
+        .build_cartesian_2d(
+            TickedCoord::new(min_time..max_time, axis.ticks.clone()),
+            lo - margin..hi + margin,
+        )?;
+
+    let x_label = |t: &f64| axis.label(*t);
+    chart
+        .configure_mesh()
+        .x_desc(axis.desc("Time"))
+        .x_label_formatter(&x_label)
+        .y_desc("Rate (KB/s)")
+        .y_label_formatter(&|kb_s: &f64| match kb_s.abs() {
+            r if r >= 1e6 => format!("{:.1}M", kb_s / 1e6),
+            r if r >= 1e3 => format!("{:.0}k", kb_s / 1e3),
+            _ => format!("{:.0}", kb_s),
+        })
+        .axis_style(palette.foreground)
+        .bold_line_style(palette.grid)
+        .light_line_style(palette.grid.mix(0.4))
+        .label_style(
+            ("sans-serif", options.scale(12))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .axis_desc_style(
+            ("sans-serif", options.scale(16))
+                .into_font()
+                .color(&palette.foreground),
+        )
+        .draw()?;
+
+    chart.draw_series(LineSeries::new(
+        [(min_time, 0.0), (max_time, 0.0)],
+        palette.foreground.mix(0.5).stroke_width(options.scale(1)),
+    ))?;
+    chart.draw_series(LineSeries::new(
+        rate,
+        palette.series.stroke_width(options.scale(1)),
+    ))?;
+    Ok(())
+}
+
+fn plot<DB: DrawingBackend, Y>(
+    root: &DrawingArea<DB, Shift>,
+    stats: &MemoryStats,
+    options: &ChartOptions,
+    palette: &ThemeColors,
+    (min_time, max_time): (f64, f64),
+    y_range: Y,
+    y_floor: f64,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+    Y: AsRangedCoord<Value = f64>,
+    Y::CoordDescType: ValueFormatter<f64>,
+{
+    let axis = TimeAxis::new(options, min_time, max_time);
+    let mut builder = ChartBuilder::on(root);
+    if options.subtitle.is_none() {
+        builder.caption(
+            &options.title,
+            ("sans-serif", options.scale(40))
+                .into_font()
+                .color(&palette.foreground),
+        );
+    }
+    let mut chart = builder
+        .margin(options.scale(10))
+        .x_label_area_size(options.scale(40))
+        .y_label_area_size(options.scale(60))
+        .right_y_label_area_size(if stats.swap_samples.is_empty() {
+            0
+        } else {
+            options.scale(60)
+        })
+        .build_cartesian_2d(
+            TickedCoord::new(min_time..max_time, axis.ticks.clone()),
+            y_range,
+        )?
+        // Swap is typically orders of magnitude smaller than RSS, so it gets its own scale.
+        // The secondary coord must be set before drawing the primary mesh so the right
+        // label area belongs to it.
+        .set_secondary_coord(
+            min_time..max_time,
+            0f64..(stats.max_swap() as f64 / options.unit.kb() * 1.1).max(1.0),
+        );
+
+    let label_font = ("sans-serif", options.scale(12))
+        .into_font()
+        .color(&palette.foreground);
+    let desc_font = ("sans-serif", options.scale(16))
+        .into_font()
+        .color(&palette.foreground);
+    let x_label = |t: &f64| axis.label(*t);
+    chart
+        .configure_mesh()
+        .x_desc(axis.desc("Time"))
+        .x_label_formatter(&x_label)
+        .y_desc(if options.log_scale {
+            format!("Memory ({}, log scale)", options.unit.label())
+        } else {
+            options.memory_desc("Memory")
+        })
+        .axis_style(palette.foreground)
+        .bold_line_style(palette.grid)
+        .light_line_style(palette.grid.mix(0.4))
+        .label_style(label_font.clone())
+        .axis_desc_style(desc_font.clone())
+        .draw()?;
+
+    let mut legend = false;
+    match options.style {
+        ChartStyle::Line => {
+            // With smoothing the raw data becomes dimmed context for the bold average
+            let rss_style = match options.smooth {
+                Some(_) => palette.series.mix(0.35).stroke_width(options.scale(1)),
+                None => palette.series.stroke_width(options.scale(1)),
+            };
+            let plot_width = chart.plotting_area().dim_in_pixel().0 as usize;
+            let use_band = match options.downsample {
+                Downsample::Band => true,
+                Downsample::Auto => stats.samples.len() > plot_width * 2,
+                Downsample::Lttb | Downsample::None => false,
+            };
+
+            let rss = if use_band {
+                let buckets = min_max_buckets(
+                    &stats.samples,
+                    plot_width.max(1),
+                    (min_time, max_time),
+                    options.unit.kb(),
+                );
+                let band = buckets
+                    .iter()
+                    .map(|b| (b.time, b.max))
+                    .chain(buckets.iter().rev().map(|b| (b.time, b.min)))
+                    .collect::<Vec<_>>();
+                chart.draw_series(std::iter::once(Polygon::new(
+                    band,
+                    palette.series.mix(0.3).filled(),
+                )))?;
+                chart.draw_series(LineSeries::new(
+                    buckets.iter().map(|b| (b.time, b.mean)),
+                    rss_style,
+                ))?
+            } else {
+                chart.draw_series(LineSeries::new(
+                    series_points(&stats.samples, options),
+                    rss_style,
+                ))?
+            };
+            rss.label(if use_band {
+                "RSS (mean, min/max band)"
+            } else {
+                "RSS"
+            })
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], rss_style));
+
+            if let Some(window) = options.smooth {
+                let smooth_style = palette.series.stroke_width(options.scale(3));
+                let smoothed = stats
+                    .rolling_mean(window)
+                    .into_iter()
+                    .map(|(t, kb)| (t, kb / options.unit.kb()))
+                    .collect();
+                chart
+                    .draw_series(LineSeries::new(
+                        downsampled(smoothed, options),
+                        smooth_style,
+                    ))?
+                    .label(format!("RSS ({}-sample moving average)", window))
+                    .legend(move |(x, y)| {
+                        PathElement::new(vec![(x, y), (x + 20, y)], smooth_style)
+                    });
+                legend = true;
+            }
+        }
+        ChartStyle::Stacked => {
+            // Draw cumulative layers from the top down so each lower layer covers the
+            // part of the one above it that belongs to itself
+            let mut totals: Vec<f64> = vec![0.0; stats.breakdown.len()];
+            let mut layers = Vec::new();
+            for idx in 0..stats.breakdown_labels.len() {
+                for (total, (_, values)) in totals.iter_mut().zip(&stats.breakdown) {
+                    *total += values[idx] as f64 / options.unit.kb();
+                }
+                layers.push(totals.clone());
+            }
+            for (idx, layer) in layers.iter().enumerate().rev() {
+                let color = Palette99::pick(idx).to_rgba();
+                chart
+                    .draw_series(AreaSeries::new(
+                        stats
+                            .breakdown
+                            .iter()
+                            .map(|(t, _)| *t)
+                            .zip(layer.iter().copied()),
+                        y_floor,
+                        color.filled(),
+                    ))?
+                    .label(stats.breakdown_labels[idx].as_str())
+                    .legend(move |(x, y)| {
+                        Rectangle::new([(x, y - 5), (x + 20, y + 5)], color.filled())
+                    });
+            }
+            legend = true;
+        }
+    }
+
+    for (idx, (name, series)) in stats.extra_series.iter().enumerate() {
+        let style = Palette99::pick(idx + 1).stroke_width(options.scale(2));
+        chart
+            .draw_series(LineSeries::new(
+                series_points(series, options)
+                    .into_iter()
+                    .map(|(t, y)| (t, y.max(y_floor))),
+                style,
+            ))?
+            .label(name.as_str())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+        legend = true;
+    }
+
+    let mut labeled = Vec::new();
+    for segment in &options.segments {
+        let color = palette.segments[segment.kind as usize];
+        let style = color.stroke_width(options.scale(3));
+        let series = chart.draw_series(LineSeries::new(
+            [
+                (
+                    segment.start,
+                    (segment.start_kb / options.unit.kb()).max(y_floor),
+                ),
+                (
+                    segment.end,
+                    (segment.end_kb / options.unit.kb()).max(y_floor),
+                ),
+            ],
+            style,
+        ))?;
+        if !labeled.contains(&segment.kind) {
+            labeled.push(segment.kind);
+            series
+                .label(match segment.kind {
+                    SegmentKind::Plateau => "Plateau",
+                    SegmentKind::Growth => "Growth phase",
+                    SegmentKind::Decline => "Decline phase",
+                })
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style));
+            legend = true;
+        }
+    }
+
+    if !stats.swap_samples.is_empty() {
+        chart
+            .configure_secondary_axes()
+            .y_desc(options.memory_desc("Swap"))
+            .axis_style(palette.foreground)
+            .label_style(label_font)
+            .axis_desc_style(desc_font)
+            .draw()?;
+
+        let swap_style = palette.secondary.stroke_width(options.scale(1));
+        chart
+            .draw_secondary_series(LineSeries::new(
+                series_points(&stats.swap_samples, options),
+                swap_style,
+            ))?
+            .label("Swap")
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], swap_style));
+        legend = true;
+    }
+
+    for (label, kb) in &options.thresholds {
+        let y = kb / options.unit.kb();
+        let style = palette.threshold.stroke_width(options.scale(2));
+        chart.draw_series(DashedLineSeries::new(
+            [(min_time, y), (max_time, y)],
+            options.scale(10),
+            options.scale(6),
+            style,
+        ))?;
+        chart.draw_series(std::iter::once(
+            EmptyElement::at((min_time, y))
+                + Text::new(
+                    label.clone(),
+                    (options.scale(6) as i32, -(options.scale(18) as i32)),
+                    ("sans-serif", options.scale(14))
+                        .into_font()
+                        .color(&palette.threshold),
+                ),
+        ))?;
+    }
+
+    for (time, label) in &options.markers {
+        let style = palette.marker.stroke_width(options.scale(1));
+        let y_span = chart.y_range();
+        let (y_low, y_high) = (y_span.start, y_span.end);
+        chart.draw_series(DashedLineSeries::new(
+            [(*time, y_low), (*time, y_high)],
+            options.scale(6),
+            options.scale(4),
+            style,
+        ))?;
+        chart.draw_series(std::iter::once(
+            EmptyElement::at((*time, y_high))
+                + Text::new(
+                    label.clone(),
+                    (options.scale(16) as i32, options.scale(4) as i32),
+                    ("sans-serif", options.scale(13))
+                        .into_font()
+                        .transform(FontTransform::Rotate90)
+                        .color(&palette.marker),
+                ),
+        ))?;
+    }
+
+    for (idx, (time, kb, label)) in options.spikes.iter().enumerate() {
+        let color = palette.threshold;
+        let circle_style = color.stroke_width(options.scale(2));
+        let spike = chart.draw_series(std::iter::once(
+            EmptyElement::at((*time, kb / options.unit.kb()))
+                + Circle::new((0, 0), options.scale(6), circle_style)
+                + Text::new(
+                    label.clone(),
+                    (options.scale(8) as i32, -(options.scale(18) as i32)),
+                    ("sans-serif", options.scale(13)).into_font().color(&color),
+                ),
+        ))?;
+        if idx == 0 {
+            spike
+                .label("Spike")
+                .legend(move |(x, y)| Circle::new((x + 10, y), 5, circle_style));
+            legend = true;
+        }
+    }
+
+    if let (true, Some(position)) = (legend, options.legend.series_label_position()) {
+        chart
+            .configure_series_labels()
+            .position(position)
+            .background_style(palette.background.mix(0.8))
+            .border_style(palette.grid)
+            .label_font(
+                ("sans-serif", options.scale(14))
+                    .into_font()
+                    .color(&palette.foreground),
+            )
+            .draw()?;
+    }
+    if options.stats_box {
+        let right = options.legend != LegendPosition::UpperRight;
+        draw_stats_box(
+            &chart.plotting_area().strip_coord_spec(),
+            stats,
+            right,
+            options,
+            palette,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Draw the inset summary of the run in the upper right (or left) corner of `area`:
+/// the target, then peak, mean, p95 and duration.
+fn draw_stats_box<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    stats: &MemoryStats,
+    right: bool,
+    options: &ChartOptions,
+    palette: &ThemeColors,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let duration = match (stats.samples.first(), stats.samples.last()) {
+        (Some((start, _)), Some((end, _))) => end - start,
+        _ => 0.0,
+    };
+    let mut lines = Vec::new();
+    if let Some(target) = &options.target {
+        lines.push(target.clone());
+    }
+    lines.push(format!("Peak: {}", options.unit.format(stats.max() as f64)));
+    lines.push(format!("Mean: {}", options.unit.format(stats.mean())));
+    lines.push(format!(
+        "p95: {}",
+        options.unit.format(stats.percentile(95.0))
+    ));
+    lines.push(format!("Duration: {}", units::format_duration(duration)));
+
+    let font = ("sans-serif", options.scale(14))
+        .into_font()
+        .color(&palette.foreground);
+    let padding = options.scale(8) as i32;
+    let line_height = options.scale(18) as i32;
+    let width = lines
+        .iter()
+        .map(|line| area.estimate_text_size(line, &font).map(|(w, _)| w as i32))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .max()
+        .unwrap_or(0)
+        + 2 * padding;
+    let height = line_height * lines.len() as i32 + 2 * padding;
+    let margin = options.scale(10) as i32;
+    let left = if right {
+        area.dim_in_pixel().0 as i32 - margin - width
+    } else {
+        margin
+    };
+    let top = margin;
+
+    area.draw(&Rectangle::new(
+        [(left, top), (left + width, top + height)],
+        palette.background.mix(0.8).filled(),
+    ))?;
+    area.draw(&Rectangle::new(
+        [(left, top), (left + width, top + height)],
+        palette.grid.stroke_width(1),
+    ))?;
+    for (idx, line) in lines.iter().enumerate() {
+        area.draw(&Text::new(
+            line.as_str(),
+            (left + padding, top + padding + line_height * idx as i32),
+            font.clone(),
+        ))?;
+    }
+    Ok(())
+}
+
+/// One pixel-wide slice of the run, in the chart's unit.
+struct Bucket {
+    time: f64,
+    min: f64,
+    max: f64,
+    mean: f64,
+}
+
+/// Split the time axis into `count` equal buckets and reduce each to min/max/mean.
+/// Empty buckets are skipped so gaps in sampling stay visible as straight segments.
+fn min_max_buckets(
+    samples: &[(f64, u64)],
+    count: usize,
+    (min_time, max_time): (f64, f64),
+    per_unit: f64,
+) -> Vec<Bucket> {
+    let width = ((max_time - min_time) / count as f64).max(f64::MIN_POSITIVE);
+    let mut buckets: Vec<Bucket> = Vec::with_capacity(count);
+    let mut current: Option<(usize, f64, f64, f64, usize)> = None; // (index, min, max, sum, n)
+
+    let mut flush = |(index, min, max, sum, n): (usize, f64, f64, f64, usize)| {
+        buckets.push(Bucket {
+            time: min_time + (index as f64 + 0.5) * width,
+            min,
+            max,
+            mean: sum / n as f64,
+        });
+    };
+
+    for &(t, kb) in samples {
+        let y = kb as f64 / per_unit;
+        let index = (((t - min_time) / width) as usize).min(count - 1);
+        current = match current {
+            Some((i, min, max, sum, n)) if i == index => {
+                Some((i, min.min(y), max.max(y), sum + y, n + 1))
+            }
+            Some(done) => {
+                flush(done);
+                Some((index, y, y, y, 1))
+            }
+            None => Some((index, y, y, y, 1)),
+        };
+    }
+    if let Some(done) = current {
+        flush(done);
+    }
+    buckets
+}
+
+/// Convert a KB series to points in the chart's unit, applying LTTB when requested.
+fn series_points(samples: &[(f64, u64)], options: &ChartOptions) -> Vec<(f64, f64)> {
+    downsampled(
+        samples
+            .iter()
+            .map(|(t, kb)| (*t, *kb as f64 / options.unit.kb()))
+            .collect(),
+        options,
+    )
+}
+
+fn downsampled(points: Vec<(f64, f64)>, options: &ChartOptions) -> Vec<(f64, f64)> {
+    if options.downsample == Downsample::Lttb {
+        lttb(&points, options.downsample_points)
+    } else {
+        points
+    }
+}
+
+/// Largest-Triangle-Three-Buckets downsampling (Steinarsson, 2013).
+///
+/// Keeps the first and last points and, from each of `threshold - 2` buckets, the
+/// point forming the largest triangle with the previously kept point and the
+/// average of the next bucket. Unlike averaging, this keeps isolated spikes.
+fn lttb(points: &[(f64, f64)], threshold: usize) -> Vec<(f64, f64)> {
+    if threshold >= points.len() || threshold < 3 {
+        return points.to_vec();
+    }
+
+    let mut sampled = Vec::with_capacity(threshold);
+    sampled.push(points[0]);
+
+    let every = (points.len() - 2) as f64 / (threshold - 2) as f64;
+    let mut a = 0;
+    for i in 0..threshold - 2 {
+        // Average of the next bucket is the third triangle vertex
+        let next_start = ((i + 1) as f64 * every) as usize + 1;
+        let next_end = (((i + 2) as f64 * every) as usize + 1).min(points.len());
+        let next = &points[next_start..next_end];
+        let (avg_x, avg_y) = next
+            .iter()
+            .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        let (avg_x, avg_y) = (avg_x / next.len() as f64, avg_y / next.len() as f64);
+
+        let start = (i as f64 * every) as usize + 1;
+        let end = next_start;
+        let (ax, ay) = points[a];
+        let mut best = start;
+        let mut best_area = -1.0;
+        for (j, (x, y)) in points.iter().enumerate().take(end).skip(start) {
+            let area = ((ax - avg_x) * (y - ay) - (ax - x) * (avg_y - ay)).abs();
+            if area > best_area {
+                best_area = area;
+                best = j;
+            }
+        }
+        sampled.push(points[best]);
+        a = best;
+    }
+
+    sampled.push(points[points.len() - 1]);
+    sampled
+}
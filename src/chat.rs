@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::fs;
+use std::thread;
+use tracing::warn;
+
+/// Chat services that accept incoming-webhook messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    Slack,
+    Discord,
+}
+
+impl Service {
+    fn name(self) -> &'static str {
+        match self {
+            Service::Slack => "Slack",
+            Service::Discord => "Discord",
+        }
+    }
+}
+
+/// Posts alert and run-summary messages to a Slack or Discord incoming webhook.
+#[derive(Debug, Clone)]
+pub struct ChatNotifier {
+    service: Service,
+    url: String,
+}
+
+impl ChatNotifier {
+    pub fn new(service: Service, url: &str) -> Self {
+        Self {
+            service,
+            url: url.to_string(),
+        }
+    }
+
+    /// Send `text` (Markdown) from a background thread. Discord gets `image` attached;
+    /// Slack incoming webhooks cannot take uploads, so only the path is mentioned.
+    /// Failures are reported but never end the run.
+    pub fn send(&self, text: String, image: Option<String>) -> thread::JoinHandle<()> {
+        let notifier = self.clone();
+        thread::spawn(move || {
+            if let Err(e) = notifier.post(&text, image.as_deref()) {
+                warn!("{:#}", e);
+            }
+        })
+    }
+
+    fn post(&self, text: &str, image: Option<&str>) -> Result<()> {
+        let context = || format!("Failed to send {} notification", self.service.name());
+        match (self.service, image) {
+            (Service::Slack, image) => {
+                let text = match image {
+                    Some(path) => format!("{}\nChart: `{}`", text, path),
+                    None => text.to_string(),
+                };
+                ureq::post(&self.url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "text": text }).to_string())
+                    .with_context(context)?;
+            }
+            (Service::Discord, None) => {
+                ureq::post(&self.url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&json!({ "content": discord_content(text) }).to_string())
+                    .with_context(context)?;
+            }
+            (Service::Discord, Some(path)) => {
+                let bytes =
+                    fs::read(path).with_context(|| format!("Failed to read chart: {}", path))?;
+                let payload = json!({ "content": discord_content(text) }).to_string();
+                let (content_type, body) = multipart(&payload, path, &bytes);
+                ureq::post(&self.url)
+                    .set("Content-Type", &content_type)
+                    .send_bytes(&body)
+                    .with_context(context)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Discord rejects messages over 2000 characters.
+fn discord_content(text: &str) -> String {
+    const LIMIT: usize = 2000;
+    if text.chars().count() <= LIMIT {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(LIMIT - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// A multipart/form-data body with the message JSON and one attached file, as Discord
+/// expects for uploads.
+fn multipart(payload_json: &str, path: &str, bytes: &[u8]) -> (String, Vec<u8>) {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    let boundary = format!("memory-tracker-{:x}-{:x}", nanos, std::process::id());
+    let file_name = std::path::Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("chart.png")
+        .replace('"', "");
+    let mut body = Vec::with_capacity(bytes.len() + payload_json.len() + 512);
+    body.extend_from_slice(
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"payload_json\"\r\n\
+             Content-Type: application/json\r\n\r\n{payload_json}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"files[0]\"; filename=\"{file_name}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            b = boundary
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
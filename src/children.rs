@@ -0,0 +1,301 @@
+use anyhow::Result;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::metrics_plugins::{Collector, Metric};
+use crate::stats::{BoundedSeries, MemoryStats, SeriesLimit};
+use crate::systemd::{self, Unit};
+use crate::units::Unit as MemoryUnit;
+use crate::{read_cmdline, read_memory_usage, MemoryReading};
+
+/// Command lines are shown cut to this many characters to keep the table and legend
+/// readable.
+const MAX_KEY_CHARS: usize = 50;
+
+/// Processes beyond this many are drawn as one "other" layer of the stacked chart.
+const MAX_LAYERS: usize = 8;
+
+/// Per-process memory of a process tree, or of a `--unit`'s cgroup, for `--children`.
+///
+/// Processes are keyed by their command line (the process title for workers that set
+/// one), or their name when it is empty, so a process restarted under a new pid stays
+/// one entry. Processes sharing a key are summed.
+pub struct ChildBreakdown {
+    unit: Option<Unit>,
+    keys: Vec<String>,
+    /// KB per key at each sample, bounded like the samples of the stats; a row only
+    /// covers the keys known at its time
+    rows: BoundedSeries<(f64, Vec<u64>)>,
+    /// Time, total and row of the sample where the processes together used the most
+    peak: Option<(f64, u64, Vec<u64>)>,
+    /// Highest KB per key at any sample
+    own_peaks: Vec<u64>,
+    /// Processes of the reading not yet assigned to a sample
+    pending: Vec<(u32, u64)>,
+}
+
+/// One process's share of the unit at its peak.
+pub struct Contribution<'a> {
+    pub key: &'a str,
+    pub at_peak_kb: u64,
+    /// The process's own highest RSS during the run, whenever it was
+    pub own_peak_kb: u64,
+}
+
+impl ChildBreakdown {
+    pub fn new(unit: Option<Unit>, limit: SeriesLimit) -> Self {
+        Self {
+            unit,
+            keys: Vec::new(),
+            rows: BoundedSeries::new(limit),
+            peak: None,
+            own_peaks: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Read every process of the unit, or `pid` and its descendants without one,
+    /// returning their sum as the sample.
+    pub fn read(&mut self, pid: u32) -> Result<MemoryReading> {
+        let processes = match &self.unit {
+            Some(unit) => unit.read_processes()?,
+            None => read_tree(pid)?,
+        };
+        self.pending = processes
+            .iter()
+            .map(|(pid, reading)| (*pid, reading.rss_kb))
+            .collect();
+        Ok(systemd::total(&processes))
+    }
+
+    /// Keep the last reading as the sample at `time`.
+    pub fn on_sample(&mut self, time: f64) {
+        let mut row = vec![0; self.keys.len()];
+        for (pid, kb) in std::mem::take(&mut self.pending) {
+            let key = process_key(pid);
+            let idx = match self.keys.iter().position(|k| *k == key) {
+                Some(idx) => idx,
+                None => {
+                    self.keys.push(key);
+                    self.own_peaks.push(0);
+                    row.push(0);
+                    self.keys.len() - 1
+                }
+            };
+            row[idx] += kb;
+        }
+        for (own_peak, kb) in self.own_peaks.iter_mut().zip(&row) {
+            *own_peak = (*own_peak).max(*kb);
+        }
+        let total = row.iter().sum::<u64>();
+        if self.peak.as_ref().is_none_or(|(_, peak, _)| total > *peak) {
+            self.peak = Some((time, total, row.clone()));
+        }
+        self.rows.push((time, row));
+    }
+
+    /// Time and total of the sample where the processes together used the most.
+    pub fn peak(&self) -> Option<(f64, u64)> {
+        self.peak.as_ref().map(|(time, total, _)| (*time, *total))
+    }
+
+    /// Every process's share at the peak, largest first; processes that had exited or
+    /// not yet started by then are listed last with their own peak.
+    pub fn contributions(&self) -> Vec<Contribution<'_>> {
+        let peak_row = self
+            .peak
+            .as_ref()
+            .map(|(_, _, row)| row.as_slice())
+            .unwrap_or_default();
+        let mut contributions: Vec<_> = self
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(idx, key)| Contribution {
+                key,
+                at_peak_kb: peak_row.get(idx).copied().unwrap_or(0),
+                own_peak_kb: self.own_peaks[idx],
+            })
+            .collect();
+        contributions.sort_by_key(|c| std::cmp::Reverse((c.at_peak_kb, c.own_peak_kb)));
+        contributions
+    }
+
+    /// The run as a stacked recording with one layer per process, the largest at their
+    /// own peak first and the rest summed as "other".
+    pub fn stacked(&self) -> MemoryStats {
+        let mut order: Vec<usize> = (0..self.keys.len()).collect();
+        order.sort_by_key(|idx| std::cmp::Reverse(self.own_peaks[*idx]));
+        let (shown, rest) = order.split_at(order.len().min(MAX_LAYERS));
+
+        let mut stats = MemoryStats::new();
+        stats.breakdown_labels = shown
+            .iter()
+            .map(|idx| shortened(&self.keys[*idx]))
+            .collect();
+        if !rest.is_empty() {
+            stats
+                .breakdown_labels
+                .push(format!("{} other processes", rest.len()));
+        }
+        for (time, row) in self.rows.iter() {
+            let kb = |idx: &usize| row.get(*idx).copied().unwrap_or(0);
+            let mut values: Vec<u64> = shown.iter().map(kb).collect();
+            if !rest.is_empty() {
+                values.push(rest.iter().map(kb).sum());
+            }
+            stats.add_sample(*time, row.iter().sum());
+            stats.add_breakdown(*time, values);
+        }
+        stats
+    }
+
+    /// Print which processes made up the peak.
+    pub fn print(&self, units: Option<MemoryUnit>) {
+        let Some((time, total_kb)) = self.peak() else {
+            return;
+        };
+        let unit = units.unwrap_or_default().resolve(total_kb as f64);
+        println!(
+            "\nProcesses at the peak ({} at {:.1}s):",
+            unit.format(total_kb as f64),
+            time
+        );
+        println!(
+            "  {:<50} {:>14} {:>7} {:>14}",
+            "Process",
+            format!("At peak {}", unit.label()),
+            "Share",
+            format!("Own peak {}", unit.label())
+        );
+        let contributions = self.contributions();
+        for contribution in contributions.iter().take(10) {
+            println!(
+                "  {:<50} {:>14.2} {:>6.1}% {:>14.2}",
+                shortened(contribution.key),
+                contribution.at_peak_kb as f64 / unit.kb(),
+                contribution.at_peak_kb as f64 / total_kb.max(1) as f64 * 100.0,
+                contribution.own_peak_kb as f64 / unit.kb()
+            );
+        }
+        if contributions.len() > 10 {
+            let rest: u64 = contributions[10..].iter().map(|c| c.at_peak_kb).sum();
+            println!(
+                "  {:<50} {:>14.2} {:>6.1}%",
+                format!("({} more)", contributions.len() - 10),
+                rest as f64 / unit.kb(),
+                rest as f64 / total_kb.max(1) as f64 * 100.0
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let (time, total_kb) = self.peak().unwrap_or_default();
+        json!({
+            "peak_time_s": time,
+            "peak_kb": total_kb,
+            "processes": self
+                .contributions()
+                .iter()
+                .map(|c| json!({
+                    "process": c.key,
+                    "at_peak_kb": c.at_peak_kb,
+                    "own_peak_kb": c.own_peak_kb,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Collector for ChildBreakdown {
+    /// The summed reading, whose processes are kept as the sample at `time`.
+    fn collect(&mut self, pid: u32, time: f64) -> Result<Vec<Metric>> {
+        let reading = self.read(pid)?;
+        self.on_sample(time);
+        Ok(reading.to_metrics())
+    }
+}
+
+/// Memory of `pid` and each of its descendants. A descendant exiting while it is read is
+/// skipped; failing to read `pid` itself fails the reading.
+fn read_tree(pid: u32) -> Result<Vec<(u32, MemoryReading)>> {
+    let mut processes = vec![(pid, read_memory_usage(pid)?)];
+    processes.extend(
+        descendants(pid)
+            .into_iter()
+            .filter_map(|pid| Some((pid, read_memory_usage(pid).ok()?))),
+    );
+    Ok(processes)
+}
+
+/// Every live descendant of `root`, from the parent pid in each `/proc/<pid>/stat`.
+fn descendants(root: u32) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    for entry in fs::read_dir("/proc").into_iter().flatten().flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if let Some(ppid) = parent_pid(pid) {
+            children.entry(ppid).or_default().push(pid);
+        }
+    }
+    let mut found = Vec::new();
+    let mut queue = vec![root];
+    while let Some(parent) = queue.pop() {
+        for child in children.remove(&parent).unwrap_or_default() {
+            found.push(child);
+            queue.push(child);
+        }
+    }
+    found
+}
+
+/// Field 4 of `/proc/<pid>/stat`, after the command name that may itself hold spaces
+/// and parentheses.
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// The command line of `pid` on one line, or its name when it has none.
+fn process_key(pid: u32) -> String {
+    let cmdline = read_cmdline(pid)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !cmdline.is_empty() {
+        return cmdline;
+    }
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_else(|_| format!("pid {}", pid))
+}
+
+fn shortened(key: &str) -> String {
+    if key.chars().count() <= MAX_KEY_CHARS {
+        return key.to_string();
+    }
+    let mut short: String = key.chars().take(MAX_KEY_CHARS - 3).collect();
+    short.push_str("...");
+    short
+}
+
+/// `memory_usage.png` -> `memory_usage-children.png`.
+pub fn chart_path(output: &str) -> String {
+    let path = std::path::Path::new(output);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("memory_usage");
+    let name = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}-children.{}", stem, ext),
+        None => format!("{}-children", stem),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
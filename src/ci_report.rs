@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use std::fs;
+
+/// One pass/fail gate of a run: a budget or the leak verdict.
+pub struct Check {
+    pub name: String,
+    /// Why the check failed; `None` when it passed
+    pub failure: Option<String>,
+    /// The measured values, kept as the test case's output
+    pub output: String,
+}
+
+/// Write `checks` as a JUnit XML report with one test case per check, so CI systems
+/// list a violated budget or a suspected leak as a failed test.
+pub fn write_junit(
+    path: &str,
+    suite: &str,
+    checks: &[Check],
+    started_at: chrono::DateTime<chrono::Local>,
+    duration: f64,
+) -> Result<()> {
+    let failures = checks.iter().filter(|c| c.failure.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"memory_tracker\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        checks.len(),
+        failures,
+        duration
+    ));
+    xml.push_str(&format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"0\" time=\"{:.3}\" timestamp=\"{}\">\n",
+        escape(suite),
+        checks.len(),
+        failures,
+        duration,
+        started_at.format("%Y-%m-%dT%H:%M:%S")
+    ));
+    for check in checks {
+        xml.push_str(&format!(
+            "    <testcase classname=\"memory_tracker\" name=\"{}\" time=\"{:.3}\">\n",
+            escape(&check.name),
+            duration
+        ));
+        if let Some(failure) = &check.failure {
+            xml.push_str(&format!(
+                "      <failure message=\"{}\" type=\"{}\">{}</failure>\n",
+                escape(failure),
+                escape(&check.name),
+                escape(failure)
+            ));
+        }
+        xml.push_str(&format!(
+            "      <system-out>{}</system-out>\n",
+            escape(&check.output)
+        ));
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    fs::write(path, xml).with_context(|| format!("Failed to write JUnit report: {}", path))
+}
+
+/// Print GitHub Actions workflow commands: an error annotation per failed check and a
+/// warning annotation per run warning. The runner turns them into inline annotations
+/// on the job and the pull request.
+pub fn print_annotations(checks: &[Check], warnings: &[String]) {
+    for check in checks {
+        if let Some(failure) = &check.failure {
+            println!(
+                "::error title={}::{}",
+                escape_property(&format!("Memory check failed: {}", check.name)),
+                escape_data(failure)
+            );
+        }
+    }
+    for warning in warnings {
+        println!("::warning title=Memory tracker::{}", escape_data(warning));
+    }
+}
+
+/// XML-escape `text`, dropping control characters XML 1.0 cannot represent.
+fn escape(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Message of a workflow command; `%` and line breaks must be percent-encoded.
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Property of a workflow command, which additionally cannot contain `:` or `,`.
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
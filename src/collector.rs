@@ -0,0 +1,329 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::csv_writer::{Columns, CsvWriter};
+use crate::stats::MemoryStats;
+use crate::{chart, recording, shutdown, units};
+
+#[derive(clap::Args)]
+#[command(mut_arg("title", |arg| arg.default_value("Memory Usage Across Agents")))]
+pub struct CollectorArgs {
+    /// Address to accept agent connections on
+    #[arg(long, value_name = "ADDR", default_value = "0.0.0.0:9500")]
+    listen: String,
+
+    /// Stop once this many agents have connected and finished (default: run until interrupted)
+    #[arg(long, value_name = "N")]
+    agents: Option<usize>,
+
+    /// How to line up the agents' time axes
+    #[arg(long, value_enum, default_value = "wallclock")]
+    align: Align,
+
+    /// Output path for the chart with one line per agent
+    #[arg(short, long, default_value = "memory_fleet.png")]
+    output: String,
+
+    /// Skip chart rendering
+    #[arg(long)]
+    no_chart: bool,
+
+    #[command(flatten)]
+    chart: chart::ChartArgs,
+
+    /// Write every agent's samples and markers to this CSV (agent,time,memory_kb,marker)
+    #[arg(long, value_name = "FILE")]
+    csv_output: Option<String>,
+
+    /// Write each agent's metadata and statistics as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+
+    /// Trailing window for the recent growth-rate estimate in the JSON summary
+    #[arg(long, value_name = "DURATION", default_value = "300", value_parser = units::parse_whole_seconds)]
+    trend_window: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Align {
+    /// Place each agent by the wall-clock time its run started (needs synchronized clocks)
+    Wallclock,
+    /// Start every agent's series at zero
+    Start,
+}
+
+/// Everything received from one agent, across reconnections.
+#[derive(Debug, Default)]
+struct AgentSeries {
+    metadata: Value,
+    stats: MemoryStats,
+    finished: bool,
+}
+
+type Agents = Arc<Mutex<BTreeMap<String, AgentSeries>>>;
+
+/// How long an agent connection may stay silent, at least, before it is closed. An agent
+/// sampling less often gets ten of its intervals; one that was cut off reconnects and
+/// continues its series.
+const AGENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Collect samples from agents until they are done, then write the combined outputs.
+pub fn run(args: &CollectorArgs) -> Result<()> {
+    shutdown::install();
+    let listener = TcpListener::bind(&args.listen)
+        .with_context(|| format!("Failed to bind collector on {}", args.listen))?;
+    info!("Collecting from agents on {}", args.listen);
+    let agents: Agents = Arc::default();
+    let accept_agents = Arc::clone(&agents);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let agents = Arc::clone(&accept_agents);
+            thread::spawn(move || receive(stream, &agents));
+        }
+    });
+
+    let mut last_report = Instant::now();
+    loop {
+        let (connected, finished) = {
+            let agents = agents.lock().unwrap();
+            (agents.len(), agents.values().filter(|a| a.finished).count())
+        };
+        if args.agents.is_some_and(|n| finished >= n) {
+            info!("All {} agents finished", finished);
+            break;
+        }
+        if shutdown::requested() {
+            info!(
+                "Interrupted, stopping with {} of {} agents finished",
+                finished, connected
+            );
+            break;
+        }
+        if last_report.elapsed() >= Duration::from_secs(10) {
+            info!("{} agents connected, {} finished", connected, finished);
+            last_report = Instant::now();
+        }
+        shutdown::sleep_until(Instant::now() + Duration::from_millis(200));
+    }
+
+    let agents = std::mem::take(&mut *agents.lock().unwrap());
+    let agents: Vec<(String, AgentSeries)> = agents
+        .into_iter()
+        .filter(|(_, series)| !series.stats.samples.is_empty())
+        .collect();
+    if agents.is_empty() {
+        bail!("No samples were received from any agent");
+    }
+    let agents = aligned(agents, args.align);
+
+    println!(
+        "\n{:<24} {:>8} {:>12} {:>12} {:>12}",
+        "Agent", "Samples", "Peak MB", "Mean MB", "p95 MB"
+    );
+    for (name, series) in &agents {
+        println!(
+            "{:<24} {:>8} {:>12.2} {:>12.2} {:>12.2}",
+            name,
+            series.stats.samples.len(),
+            series.stats.max() as f64 / 1024.0,
+            series.stats.mean() / 1024.0,
+            series.stats.percentile(95.0) / 1024.0
+        );
+    }
+    let peaks: Vec<u64> = agents.iter().map(|(_, s)| s.stats.max()).collect();
+    let (highest, peak) = agents
+        .iter()
+        .map(|(name, s)| (name, s.stats.max()))
+        .max_by_key(|(_, peak)| *peak)
+        .expect("at least one agent has samples");
+    println!(
+        "\nHighest peak: {:.2} MB on {}; mean peak across agents: {:.2} MB",
+        peak as f64 / 1024.0,
+        highest,
+        peaks.iter().sum::<u64>() as f64 / peaks.len() as f64 / 1024.0
+    );
+
+    if !args.no_chart {
+        info!("Generating chart: {}", args.output);
+        let runs: Vec<(String, &MemoryStats)> = agents
+            .iter()
+            .map(|(name, s)| (name.clone(), &s.stats))
+            .collect();
+        let options = args.chart.options();
+        let x_axis = match args.align {
+            Align::Wallclock => chart::OverlayAxis::Elapsed("Time since the first agent started"),
+            Align::Start => chart::OverlayAxis::Elapsed("Time"),
+        };
+        chart::generate_overlay(&runs, x_axis, &args.output, &options)?;
+        info!("Chart saved successfully!");
+    }
+
+    if let Some(csv_path) = &args.csv_output {
+        write_csv(csv_path, &agents)?;
+        info!("Combined recording saved to CSV: {}", csv_path);
+    }
+
+    if let Some(json_path) = &args.json {
+        let summary: serde_json::Map<String, Value> = agents
+            .iter()
+            .map(|(name, series)| {
+                let entry = json!({
+                    "metadata": series.metadata,
+                    "finished": series.finished,
+                    "statistics": series.stats.summary_json(args.trend_window as f64),
+                });
+                (name.clone(), entry)
+            })
+            .collect();
+        fs::write(
+            json_path,
+            serde_json::to_string_pretty(&json!({ "agents": summary }))?,
+        )
+        .with_context(|| format!("Failed to write JSON summary: {}", json_path))?;
+        info!("JSON summary saved to {}", json_path);
+    }
+    Ok(())
+}
+
+/// Stream every agent's samples and markers to `path` through the CSV writer.
+fn write_csv(path: &str, agents: &[(String, AgentSeries)]) -> Result<()> {
+    let columns = Columns {
+        agents: true,
+        ..Columns::kb()
+    };
+    let writer = CsvWriter::create(path, None, &[], columns)?;
+    let written = agents.iter().try_for_each(|(name, series)| {
+        writer.agent(name)?;
+        for (time, memory_kb) in &series.stats.samples {
+            writer.sample(*time, *memory_kb)?;
+        }
+        for (time, label) in &series.stats.markers {
+            writer.marker(*time, label)?;
+        }
+        Ok(())
+    });
+    match written {
+        Ok(()) => writer.finish(),
+        Err(e) => Err(writer.stopped(e)),
+    }
+}
+
+/// Read one agent connection until it closes or goes silent. A reconnecting agent
+/// continues its series.
+fn receive(stream: TcpStream, agents: &Mutex<BTreeMap<String, AgentSeries>>) {
+    let peer = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+    let mut name = None;
+    let mut timeout = AGENT_TIMEOUT;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if let Err(e) = reader.get_ref().set_read_timeout(Some(timeout)) {
+            warn!("Failed to set a read timeout for {}: {}", peer, e);
+            break;
+        }
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                info!(
+                    "Agent {} sent nothing for {}s, closing its connection",
+                    name.as_deref().unwrap_or(peer.as_str()),
+                    timeout.as_secs()
+                );
+                break;
+            }
+            Err(_) => break,
+        }
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let mut agents = agents.lock().unwrap();
+        match (message["type"].as_str(), &name) {
+            (Some("hello"), _) => {
+                let agent = message["agent"].as_str().unwrap_or(&peer).to_string();
+                info!("Agent {} connected from {}", agent, peer);
+                if let Some(interval_ms) = message["interval_ms"].as_u64() {
+                    timeout =
+                        AGENT_TIMEOUT.max(Duration::from_millis(interval_ms.saturating_mul(10)));
+                }
+                let series = agents.entry(agent.clone()).or_default();
+                series.metadata = message;
+                series.finished = false;
+                name = Some(agent);
+            }
+            (Some("sample"), Some(agent)) => {
+                let (Some(time), Some(memory_kb)) =
+                    (message["time"].as_f64(), message["memory_kb"].as_u64())
+                else {
+                    continue;
+                };
+                if let Some(series) = agents.get_mut(agent) {
+                    series.stats.add_sample(time, memory_kb);
+                }
+            }
+            (Some("marker"), Some(agent)) => {
+                let (Some(time), Some(label)) =
+                    (message["time"].as_f64(), message["label"].as_str())
+                else {
+                    continue;
+                };
+                if let Some(series) = agents.get_mut(agent) {
+                    series.stats.markers.push((time, label.to_string()));
+                }
+            }
+            (Some("end"), Some(agent)) => {
+                if let Some(series) = agents.get_mut(agent) {
+                    info!(
+                        "Agent {} finished ({} samples)",
+                        agent,
+                        series.stats.samples.len()
+                    );
+                    series.finished = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Shift every series onto the common time axis chosen with `--align`.
+fn aligned(agents: Vec<(String, AgentSeries)>, align: Align) -> Vec<(String, AgentSeries)> {
+    let started = |series: &AgentSeries| {
+        series.metadata["started"]
+            .as_str()
+            .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+    };
+    let earliest = agents.iter().filter_map(|(_, s)| started(s)).min();
+    agents
+        .into_iter()
+        .map(|(name, mut series)| {
+            series.stats = match (align, earliest, started(&series)) {
+                (Align::Wallclock, Some(earliest), Some(start)) => {
+                    let offset = (start - earliest).num_milliseconds() as f64 / 1000.0;
+                    let mut stats = series.stats;
+                    for (t, _) in &mut stats.samples {
+                        *t += offset;
+                    }
+                    for (t, _) in &mut stats.markers {
+                        *t += offset;
+                    }
+                    stats
+                }
+                _ => recording::aligned(series.stats),
+            };
+            (name, series)
+        })
+        .collect()
+}
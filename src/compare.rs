@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use std::fs;
+use tracing::info;
+
+use crate::chart;
+use crate::recording;
+use crate::stats::MemoryStats;
+
+type Metric = fn(&MemoryStats) -> f64;
+
+#[derive(clap::Args)]
+#[command(mut_arg("title", |arg| arg.default_value("Memory Usage Comparison")))]
+pub struct CompareArgs {
+    /// Baseline recording (CSV written with --csv-output)
+    baseline: String,
+
+    /// Candidate recording to compare against the baseline
+    candidate: String,
+
+    /// Fail with exit code 6 when peak or mean memory grows by more than this percentage
+    #[arg(long, value_name = "PERCENT", default_value = "10")]
+    max_regression: f64,
+
+    /// Output path for the overlay chart
+    #[arg(short, long, default_value = "memory_compare.png")]
+    output: String,
+
+    #[command(flatten)]
+    chart: chart::ChartArgs,
+
+    /// Write the comparison as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+
+    // Applied to both recordings, each measured from its own start
+    #[command(flatten)]
+    range: recording::TimeRange,
+}
+
+/// Compare two recordings; returns whether the candidate regressed beyond the limit.
+pub fn run(args: &CompareArgs) -> Result<bool> {
+    let baseline = recording::aligned(args.range.load(&args.baseline)?);
+    let candidate = recording::aligned(args.range.load(&args.candidate)?);
+
+    let metrics: [(&str, Metric); 7] = [
+        ("Peak", |s| s.max() as f64),
+        ("Mean", MemoryStats::mean),
+        ("Median", MemoryStats::median),
+        ("p90", |s| s.percentile(90.0)),
+        ("p95", |s| s.percentile(95.0)),
+        ("p99", |s| s.percentile(99.0)),
+        ("Min", |s| s.min() as f64),
+    ];
+
+    println!(
+        "Baseline:  {} ({} samples)",
+        args.baseline,
+        baseline.samples.len()
+    );
+    println!(
+        "Candidate: {} ({} samples)",
+        args.candidate,
+        candidate.samples.len()
+    );
+    if let Some(range) = args.range.describe() {
+        println!("Compared {} into each recording", range);
+    }
+    println!(
+        "\n{:<8} {:>14} {:>14} {:>14} {:>9}",
+        "Metric", "Baseline MB", "Candidate MB", "Delta MB", "Delta %"
+    );
+    let mut rows = serde_json::Map::new();
+    let mut regressions = Vec::new();
+    for (name, metric) in metrics {
+        let (before, after) = (metric(&baseline), metric(&candidate));
+        let percent = delta_percent(before, after);
+        println!(
+            "{:<8} {:>14.2} {:>14.2} {:>+14.2} {:>+8.1}%",
+            name,
+            before / 1024.0,
+            after / 1024.0,
+            (after - before) / 1024.0,
+            percent
+        );
+        if matches!(name, "Peak" | "Mean") && percent > args.max_regression {
+            regressions.push(format!(
+                "{} grew by {:.1}% (limit {}%)",
+                name.to_lowercase(),
+                percent,
+                args.max_regression
+            ));
+        }
+        rows.insert(
+            name.to_lowercase(),
+            serde_json::json!({
+                "baseline_kb": before,
+                "candidate_kb": after,
+                "delta_kb": after - before,
+                "delta_percent": percent,
+            }),
+        );
+    }
+
+    info!("Generating overlay chart: {}", args.output);
+    let options = args.chart.options();
+    chart::generate_overlay(
+        &[
+            (format!("Baseline ({})", args.baseline), &baseline),
+            (format!("Candidate ({})", args.candidate), &candidate),
+        ],
+        chart::OverlayAxis::Elapsed("Time"),
+        &args.output,
+        &options,
+    )?;
+    info!("Chart saved successfully!");
+
+    if let Some(json_path) = &args.json {
+        let summary = serde_json::json!({
+            "baseline": args.baseline,
+            "candidate": args.candidate,
+            "max_regression_percent": args.max_regression,
+            "range": args.range.to_json(),
+            "metrics": rows,
+            "regressions": regressions,
+        });
+        fs::write(json_path, serde_json::to_string_pretty(&summary)?)
+            .with_context(|| format!("Failed to write JSON comparison: {}", json_path))?;
+        info!("JSON comparison saved to {}", json_path);
+    }
+
+    for regression in &regressions {
+        eprintln!("\nREGRESSION: {}", regression);
+    }
+    Ok(!regressions.is_empty())
+}
+
+fn delta_percent(before: f64, after: f64) -> f64 {
+    if before == 0.0 {
+        0.0
+    } else {
+        (after - before) / before * 100.0
+    }
+}
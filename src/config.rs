@@ -0,0 +1,247 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+
+/// Environment variable naming a config file used when `--config` is not given.
+pub const CONFIG_ENV: &str = "MEMORY_TRACKER_CONFIG";
+
+/// Subcommands whose options can come from a config file.
+const RECORDING_SUBCOMMANDS: [&str; 2] = ["record", "run"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Number(String),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+/// A parsed config file: option name to value, per table (`""` for top-level keys).
+type Document = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// Expand a config file into command-line flags placed before the user's own arguments,
+/// so anything given on the command line overrides it.
+///
+/// Keys are the long option names (`csv-output` or `csv_output`); tables only group keys
+/// and do not change their meaning, except `[profile.NAME]` tables, which only apply when
+/// selected with `--profile NAME`. `valid_options` lists the accepted long names.
+pub fn expand_args(args: Vec<OsString>, valid_options: &[String]) -> Result<Vec<OsString>> {
+    // Options for analysis subcommands never come from the config file
+    let insert_at = match args.get(1).and_then(|a| a.to_str()) {
+        Some(sub) if RECORDING_SUBCOMMANDS.contains(&sub) => 2,
+        Some(sub) if !sub.starts_with('-') => return Ok(args),
+        _ => 1,
+    };
+
+    let profile = flag_value(&args, "--profile");
+    let Some(path) = flag_value(&args, "--config")
+        .or_else(|| std::env::var(CONFIG_ENV).ok().filter(|p| !p.is_empty()))
+    else {
+        if profile.is_some() {
+            bail!("--profile needs a config file (--config or {})", CONFIG_ENV);
+        }
+        return Ok(args);
+    };
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file: {}", path))?;
+    let document = parse(&content).with_context(|| format!("Invalid config file: {}", path))?;
+
+    // Profile values replace top-level ones key by key, lists included
+    let mut options: BTreeMap<String, &Value> = BTreeMap::new();
+    let mut profiles: Vec<&str> = Vec::new();
+    for (table, entries) in &document {
+        if let Some(name) = profile_name(table) {
+            if !profiles.contains(&name) {
+                profiles.push(name);
+            }
+            continue;
+        }
+        for (key, value) in entries {
+            options.insert(key.replace('_', "-"), value);
+        }
+    }
+    if let Some(selected) = &profile {
+        if !profiles.contains(&selected.as_str()) {
+            bail!(
+                "{}: no profile '{}' (available: {})",
+                path,
+                selected,
+                if profiles.is_empty() {
+                    "none".to_string()
+                } else {
+                    profiles.join(", ")
+                }
+            );
+        }
+        for (table, entries) in &document {
+            if profile_name(table) == Some(selected.as_str()) {
+                for (key, value) in entries {
+                    options.insert(key.replace('_', "-"), value);
+                }
+            }
+        }
+    }
+
+    let mut flags = Vec::new();
+    for (name, value) in options {
+        if !valid_options.contains(&name) {
+            bail!("{}: unknown option '{}'", path, name);
+        }
+        push_flags(&mut flags, &name, value);
+    }
+
+    let mut expanded = args;
+    expanded.splice(insert_at..insert_at, flags);
+    Ok(expanded)
+}
+
+/// Value of a `--name VALUE` or `--name=VALUE` argument before any `--`.
+fn flag_value(args: &[OsString], name: &str) -> Option<String> {
+    let mut iter = args.iter().skip(1).map(|a| a.to_string_lossy());
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == name {
+            return iter.next().map(|v| v.into_owned());
+        }
+        if let Some(value) = arg
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// The profile a `[profile.NAME]` or `[profile.NAME.group]` table belongs to.
+fn profile_name(table: &str) -> Option<&str> {
+    table
+        .strip_prefix("profile.")
+        .map(|rest| rest.split('.').next().unwrap_or(rest))
+}
+
+fn push_flags(flags: &mut Vec<OsString>, name: &str, value: &Value) {
+    match value {
+        Value::Bool(true) => flags.push(format!("--{}", name).into()),
+        Value::Bool(false) => {}
+        Value::String(s) | Value::Number(s) => flags.push(format!("--{}={}", name, s).into()),
+        Value::Array(items) => {
+            for item in items {
+                push_flags(flags, name, item);
+            }
+        }
+    }
+}
+
+/// Parse the subset of TOML the config needs: `[table]` headers, `key = value` pairs with
+/// strings, numbers, booleans and single-line arrays, and `#` comments.
+fn parse(content: &str) -> Result<Document> {
+    let mut document = Document::new();
+    let mut table = String::new();
+    for (idx, raw) in content.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .with_context(|| format!("line {}: unterminated table header", line_no))?;
+            table = name.trim().to_string();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {}: expected key = value", line_no))?;
+        let key = key.trim().trim_matches('"').to_string();
+        let (value, rest) = parse_value(value.trim())
+            .with_context(|| format!("line {}: invalid value for '{}'", line_no, key))?;
+        if !rest.trim().is_empty() {
+            bail!(
+                "line {}: unexpected trailing characters '{}'",
+                line_no,
+                rest.trim()
+            );
+        }
+        document
+            .entry(table.clone())
+            .or_default()
+            .insert(key, value);
+    }
+    Ok(document)
+}
+
+/// Parse one value from the start of `input`, returning it and the unparsed remainder.
+fn parse_value(input: &str) -> Result<(Value, &str)> {
+    if let Some(rest) = input.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(out), &rest[idx + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some(c @ ('"' | '\\')) => out.push(c),
+                    other => bail!("unsupported escape sequence \\{}", other.unwrap_or(' ')),
+                },
+                c => out.push(c),
+            }
+        }
+        bail!("unterminated string");
+    }
+    if let Some(rest) = input.strip_prefix('\'') {
+        let end = rest.find('\'').context("unterminated string")?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+    if let Some(mut rest) = input.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+    }
+
+    let end = input.find([',', ']']).unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+    let token = token.trim();
+    match token {
+        "true" => Ok((Value::Bool(true), rest)),
+        "false" => Ok((Value::Bool(false), rest)),
+        _ if token.replace('_', "").parse::<f64>().is_ok() => {
+            Ok((Value::Number(token.replace('_', "")), rest))
+        }
+        _ => bail!(
+            "expected a string, number, boolean or array, got '{}'",
+            token
+        ),
+    }
+}
+
+/// Drop a trailing `#` comment, ignoring `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match (c, quote) {
+            _ if escaped => escaped = false,
+            ('\\', Some('"')) => escaped = true,
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('#', None) => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
@@ -0,0 +1,637 @@
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::write::GzEncoder;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use rusqlite::{params, Connection};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::recording::ParquetRow;
+use crate::stats::RateEstimator;
+use crate::units::Unit;
+use crate::{daemon, recording};
+
+/// Which columns a recording has besides time and marker.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns {
+    /// Unit of the memory column, which its name records
+    pub unit: Unit,
+    /// Add a `rate_kb_s` column with the allocation rate over this many samples
+    pub rate_window: Option<usize>,
+    /// Lead with an `agent` column naming whose row it is, as set with
+    /// [`CsvWriter::agent`]; CSV only
+    pub agents: bool,
+}
+
+impl Columns {
+    /// The plain `time,memory_kb,marker` layout.
+    pub fn kb() -> Self {
+        Self {
+            unit: Unit::Kb,
+            rate_window: None,
+            agents: false,
+        }
+    }
+
+    /// The header line; the rate goes before the free-text marker.
+    fn header(&self) -> String {
+        let agent = if self.agents { "agent," } else { "" };
+        let rate = if self.rate_window.is_some() {
+            "rate_kb_s,"
+        } else {
+            ""
+        };
+        format!("{}time,{},{}marker\n", agent, self.unit.csv_column(), rate)
+    }
+}
+
+/// How often buffered rows are pushed to disk. Each gzip or zstd flush ends a compressed
+/// block and each SQLite flush a transaction, so flushing every sample would cost most of the
+/// compression or a sync per row.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The file being written, gzip-compressed when its name ends in `.gz` and
+/// zstd-compressed when it ends in `.zst`.
+enum Output {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Output {
+    /// Open `path`, writing `comments` as `# ` lines and the header unless rows are
+    /// appended to an existing file. Appending to a `.gz` or `.zst` file adds a gzip
+    /// member or zstd frame, which readers concatenate.
+    fn open(path: &str, append: bool, comments: &[String], header: &str) -> Result<Self> {
+        let file = if append {
+            fs::OpenOptions::new().append(true).open(path)
+        } else {
+            File::create(path)
+        }
+        .with_context(|| format!("Failed to open CSV file: {}", path))?;
+        let file = BufWriter::new(file);
+        let mut output = if path.ends_with(".gz") {
+            Output::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+        } else if path.ends_with(".zst") {
+            let encoder = zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .with_context(|| format!("Failed to start zstd compression: {}", path))?;
+            Output::Zstd(encoder)
+        } else {
+            Output::Plain(file)
+        };
+        if !append {
+            for comment in comments {
+                writeln!(output, "# {}", comment)?;
+            }
+            output.write_all(header.as_bytes())?;
+        }
+        Ok(output)
+    }
+
+    /// Flush and, for gzip and zstd, write the trailer that makes the file complete.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(mut writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+            Output::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// An SQLite recording (see [`recording::SQLITE_SCHEMA`]). Rows are inserted in one
+/// transaction per flush, so readers see them about as soon as a CSV's.
+struct Database {
+    db: Connection,
+}
+
+impl Database {
+    /// Open `path`, replacing any existing file unless rows are appended to it, and
+    /// store `comments` as the run's metadata.
+    fn open(path: &str, append: bool, comments: &[String]) -> Result<Self> {
+        if !append {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e).with_context(|| format!("Failed to replace {}", path));
+                }
+                _ => {}
+            }
+        }
+        let context = || format!("Failed to open SQLite recording: {}", path);
+        let db = Connection::open(path).with_context(context)?;
+        db.execute_batch(recording::SQLITE_SCHEMA)
+            .with_context(context)?;
+        for comment in comments {
+            let (key, value) = comment.split_once(": ").unwrap_or((comment, ""));
+            db.execute(
+                "INSERT INTO metadata (key, value) VALUES (?1, ?2)",
+                params![key, value],
+            )
+            .with_context(context)?;
+        }
+        db.execute_batch("BEGIN").with_context(context)?;
+        Ok(Self { db })
+    }
+
+    fn sample(
+        &self,
+        time: f64,
+        timestamp: Option<String>,
+        memory_kb: u64,
+        rate: Option<f64>,
+    ) -> rusqlite::Result<()> {
+        self.db
+            .prepare_cached("INSERT INTO samples (time, timestamp, memory_kb, rate_kb_s) VALUES (?1, ?2, ?3, ?4)")?
+            .execute(params![time, timestamp, memory_kb as i64, rate])?;
+        Ok(())
+    }
+
+    fn marker(&self, time: f64, timestamp: Option<String>, label: &str) -> rusqlite::Result<()> {
+        self.db
+            .prepare_cached("INSERT INTO markers (time, timestamp, label) VALUES (?1, ?2, ?3)")?
+            .execute(params![time, timestamp, label])?;
+        Ok(())
+    }
+
+    /// Commit the rows so far and start the next transaction.
+    fn flush(&self) -> rusqlite::Result<()> {
+        self.db.execute_batch("COMMIT; BEGIN")
+    }
+
+    /// Copy the recording so far to `rotated` and empty it for the next period. The
+    /// metadata stays, so it heads every rotated file as well.
+    fn rotate(&self, rotated: &str) -> rusqlite::Result<()> {
+        self.db.execute_batch("COMMIT")?;
+        self.db.execute("VACUUM INTO ?1", [rotated])?;
+        self.db
+            .execute_batch("DELETE FROM samples; DELETE FROM markers; BEGIN")
+    }
+
+    fn finish(self) -> rusqlite::Result<()> {
+        self.db.execute_batch("COMMIT")?;
+        self.db.close().map_err(|(_, e)| e)
+    }
+}
+
+/// Rows per Parquet row group. Each row group is written as it fills, which bounds the
+/// rows held in memory; flushing one a second would leave mostly tiny row groups.
+const ROW_GROUP_ROWS: usize = 10_000;
+
+/// A Parquet recording (see [`recording::PARQUET_SCHEMA`]), zstd-compressed. Parquet's
+/// footer is only written when the file is closed, so the file is written next to
+/// `path` as `<path>.tmp` and only renamed over `path` once complete: an interrupted run
+/// leaves no readable recording behind, but never destroys the one it appends to.
+struct ParquetFile {
+    writer: SerializedFileWriter<File>,
+    rows: Vec<ParquetRow>,
+    path: String,
+    tmp_path: String,
+}
+
+impl ParquetFile {
+    /// Create `path` with `comments` as the run's metadata. A Parquet file cannot grow in
+    /// place, so appending copies the existing rows and metadata into the new file.
+    fn open(path: &str, append: bool, comments: &[String]) -> Result<Self> {
+        let read_context = || format!("Failed to read recording: {}", path);
+        let (metadata, existing) = if append {
+            let (metadata, rows) = recording::open_parquet(path).with_context(read_context)?;
+            (metadata, Some(rows))
+        } else {
+            let metadata = comments
+                .iter()
+                .map(|comment| comment.split_once(": ").unwrap_or((comment, "")))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            (metadata, None)
+        };
+        let context = || format!("Failed to open Parquet recording: {}", path);
+        let schema = parquet::schema::parser::parse_message_type(recording::PARQUET_SCHEMA)
+            .with_context(context)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .set_key_value_metadata(Some(
+                metadata
+                    .into_iter()
+                    .map(|(key, value)| KeyValue::new(key, value))
+                    .collect(),
+            ))
+            .build();
+        let tmp_path = format!("{}.tmp", path);
+        let file = File::create(&tmp_path).with_context(context)?;
+        let writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))
+            .with_context(context)?;
+        let mut parquet = Self {
+            writer,
+            rows: Vec::new(),
+            path: path.to_string(),
+            tmp_path,
+        };
+        for row in existing.into_iter().flatten() {
+            parquet
+                .push(row.with_context(read_context)?)
+                .with_context(context)?;
+        }
+        Ok(parquet)
+    }
+
+    fn push(&mut self, row: ParquetRow) -> parquet::errors::Result<()> {
+        self.rows.push(row);
+        if self.rows.len() >= ROW_GROUP_ROWS {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered rows as a row group, its columns in schema order.
+    fn write_row_group(&mut self) -> parquet::errors::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let mut group = self.writer.next_row_group()?;
+        write_column::<DoubleType>(&mut group, rows.iter().map(|row| Some(row.time)))?;
+        write_column::<ByteArrayType>(
+            &mut group,
+            rows.iter()
+                .map(|row| row.timestamp.as_deref().map(ByteArray::from)),
+        )?;
+        write_column::<Int64Type>(
+            &mut group,
+            rows.iter().map(|row| row.memory_kb.map(|kb| kb as i64)),
+        )?;
+        write_column::<DoubleType>(&mut group, rows.iter().map(|row| row.rate_kb_s))?;
+        write_column::<ByteArrayType>(
+            &mut group,
+            rows.iter()
+                .map(|row| row.marker.as_deref().map(ByteArray::from)),
+        )?;
+        group.close()?;
+        Ok(())
+    }
+
+    /// Write the remaining rows and the footer that makes the file readable, and move it
+    /// over `path`.
+    fn finish(&mut self) -> Result<()> {
+        self.write_row_group()?;
+        self.writer.finish()?;
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Failed to move {} to {}", self.tmp_path, self.path))?;
+        Ok(())
+    }
+}
+
+/// Write the next column of a row group; `None` values are nulls.
+fn write_column<T: DataType>(
+    group: &mut SerializedRowGroupWriter<'_, File>,
+    values: impl Iterator<Item = Option<T::T>>,
+) -> parquet::errors::Result<()> {
+    let mut column = group
+        .next_column()?
+        .ok_or_else(|| ParquetError::General("more columns than the schema has".to_string()))?;
+    let mut levels = Vec::new();
+    let mut present = Vec::new();
+    for value in values {
+        levels.push(i16::from(value.is_some()));
+        present.extend(value);
+    }
+    column
+        .typed::<T>()
+        .write_batch(&present, Some(&levels), None)?;
+    column.close()
+}
+
+/// Where the writer thread puts rows: a CSV file, an SQLite database or a Parquet file.
+enum Sink {
+    Text(Output),
+    Sqlite(Database),
+    Parquet(ParquetFile),
+}
+
+impl Sink {
+    /// Open `path` as an SQLite recording when its name ends in `.sqlite` or `.db`, as
+    /// Parquet when it ends in `.parquet` and as CSV otherwise.
+    fn open(path: &str, append: bool, comments: &[String], columns: &Columns) -> Result<Self> {
+        if columns.agents && (recording::is_sqlite(path) || recording::is_parquet(path)) {
+            bail!("{} must be a CSV file to hold an agent column", path);
+        }
+        if recording::is_sqlite(path) {
+            Ok(Sink::Sqlite(Database::open(path, append, comments)?))
+        } else if recording::is_parquet(path) {
+            Ok(Sink::Parquet(ParquetFile::open(path, append, comments)?))
+        } else {
+            Ok(Sink::Text(Output::open(
+                path,
+                append,
+                comments,
+                &columns.header(),
+            )?))
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Text(output) => Ok(output.flush()?),
+            Sink::Sqlite(database) => Ok(database.flush()?),
+            // Rows only become readable once the footer is written
+            Sink::Parquet(_) => Ok(()),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Sink::Text(output) => Ok(output.finish()?),
+            Sink::Sqlite(database) => Ok(database.finish()?),
+            Sink::Parquet(mut parquet) => parquet.finish(),
+        }
+    }
+}
+
+enum Message {
+    Sample(f64, u64),
+    Marker(f64, String),
+    /// Attribute the rows that follow to this agent
+    Agent(String),
+    /// Move the file written so far to the given path, prune old ones and start afresh
+    Rotate(String, usize),
+}
+
+/// Streams `time,memory_kb,marker` rows to disk from a writer thread, so sampling never
+/// waits on the disk and a crash loses at most about a second of rows. `Columns` choose
+/// the memory unit and an optional `rate_kb_s` allocation rate column. A `.sqlite` or
+/// `.db` path gets the same rows as an SQLite database and a `.parquet` path as a Parquet
+/// file, both with memory always in KB.
+pub struct CsvWriter {
+    path: String,
+    tx: Sender<Message>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl CsvWriter {
+    /// Create `path` and start the writer thread. Times are written as seconds since
+    /// start, or as RFC3339 timestamps relative to `wallclock_start` when given.
+    /// `comments` describe the run and head every file, including rotated ones.
+    pub fn create(
+        path: &str,
+        wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+        comments: &[String],
+        columns: Columns,
+    ) -> Result<Self> {
+        let writer = Sink::open(path, false, comments, &columns)?;
+        Self::start(path, writer, wallclock_start, comments.to_vec(), columns)
+    }
+
+    /// Like [`CsvWriter::create`], but add rows to the end of an existing recording,
+    /// whose `columns` the caller must match.
+    pub fn append(
+        path: &str,
+        wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+        columns: Columns,
+    ) -> Result<Self> {
+        let writer = Sink::open(path, true, &[], &columns)?;
+        Self::start(path, writer, wallclock_start, Vec::new(), columns)
+    }
+
+    fn start(
+        path: &str,
+        writer: Sink,
+        wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+        comments: Vec<String>,
+        columns: Columns,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let thread_path = path.to_string();
+        let handle = thread::spawn(move || {
+            let mut rows = Rows {
+                path: thread_path,
+                wallclock_start,
+                comments,
+                columns,
+                rate: columns.rate_window.map(RateEstimator::new),
+                agent: String::new(),
+            };
+            rows.write_all(writer, rx)
+        });
+        Ok(Self {
+            path: path.to_string(),
+            tx,
+            handle,
+        })
+    }
+
+    /// Queue a row. Fails once the writer thread has stopped on an error, which
+    /// [`CsvWriter::stopped`] then returns.
+    pub fn sample(&self, time: f64, memory_kb: u64) -> Result<()> {
+        self.send(Message::Sample(time, memory_kb))
+    }
+
+    pub fn marker(&self, time: f64, label: &str) -> Result<()> {
+        self.send(Message::Marker(time, label.to_string()))
+    }
+
+    /// Close the current file under `rotated`, keeping the newest `keep` rotated files.
+    pub fn rotate(&self, rotated: String, keep: usize) -> Result<()> {
+        self.send(Message::Rotate(rotated, keep))
+    }
+
+    /// Name `agent` in the `agent` column of the rows that follow.
+    pub fn agent(&self, agent: &str) -> Result<()> {
+        self.send(Message::Agent(agent.to_string()))
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|_| anyhow!("The writer of {} has stopped", self.path))
+    }
+
+    /// The error the writer thread stopped with, after `failed` was returned by a send.
+    pub fn stopped(self, failed: anyhow::Error) -> anyhow::Error {
+        self.finish().err().unwrap_or(failed)
+    }
+
+    /// Write the remaining rows and report the first error the writer hit, if any.
+    pub fn finish(self) -> Result<()> {
+        drop(self.tx);
+        self.handle
+            .join()
+            .map_err(|_| anyhow!("CSV writer thread panicked"))?
+    }
+}
+
+/// State of the writer thread.
+struct Rows {
+    path: String,
+    wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+    comments: Vec<String>,
+    columns: Columns,
+    rate: Option<RateEstimator>,
+    /// The `agent` column of the next rows, with its separator
+    agent: String,
+}
+
+impl Rows {
+    fn write_all(&mut self, mut writer: Sink, rx: Receiver<Message>) -> Result<()> {
+        let path = self.path.clone();
+        let context = || format!("Failed to write CSV file: {}", path);
+        let mut last_flush = Instant::now();
+        while let Ok(first) = rx.recv() {
+            for message in std::iter::once(first).chain(rx.try_iter()) {
+                self.write_message(&mut writer, message)?;
+            }
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                writer.flush().with_context(context)?;
+                last_flush = Instant::now();
+            }
+        }
+        writer.finish().with_context(context)
+    }
+
+    fn write_message(&mut self, writer: &mut Sink, message: Message) -> Result<()> {
+        let path = self.path.as_str();
+        let timestamp = |t: f64| {
+            self.wallclock_start.map(|start| {
+                (start + chrono::TimeDelta::milliseconds((t * 1000.0) as i64))
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, false)
+            })
+        };
+        let format_time = |t: f64| timestamp(t).unwrap_or_else(|| format!("{:.3}", t));
+        let context = || format!("Failed to write CSV file: {}", path);
+        match (message, writer) {
+            (Message::Sample(time, memory_kb), Sink::Text(writer)) => {
+                let memory = self.columns.unit.csv_value(memory_kb);
+                match &mut self.rate {
+                    Some(rate) => {
+                        let rate = rate
+                            .push(time, memory_kb)
+                            .map(|kb_s| format!("{:.1}", kb_s))
+                            .unwrap_or_default();
+                        writeln!(
+                            writer,
+                            "{}{},{},{},",
+                            self.agent,
+                            format_time(time),
+                            memory,
+                            rate
+                        )
+                        .with_context(context)
+                    }
+                    None => writeln!(writer, "{}{},{},", self.agent, format_time(time), memory)
+                        .with_context(context),
+                }
+            }
+            (Message::Sample(time, memory_kb), Sink::Sqlite(database)) => {
+                let rate = self
+                    .rate
+                    .as_mut()
+                    .and_then(|rate| rate.push(time, memory_kb));
+                database
+                    .sample(time, timestamp(time), memory_kb, rate)
+                    .with_context(context)
+            }
+            (Message::Sample(time, memory_kb), Sink::Parquet(parquet)) => {
+                let row = ParquetRow {
+                    time,
+                    timestamp: timestamp(time),
+                    memory_kb: Some(memory_kb),
+                    rate_kb_s: self
+                        .rate
+                        .as_mut()
+                        .and_then(|rate| rate.push(time, memory_kb)),
+                    marker: None,
+                };
+                parquet.push(row).with_context(context)
+            }
+            (Message::Marker(time, label), Sink::Text(writer)) => {
+                let empty = if self.rate.is_some() { ",," } else { "," };
+                writeln!(
+                    writer,
+                    "{}{},{}{}",
+                    self.agent,
+                    format_time(time),
+                    empty,
+                    csv_field(&label)
+                )
+                .with_context(context)
+            }
+            (Message::Marker(time, label), Sink::Sqlite(database)) => database
+                .marker(time, timestamp(time), &label)
+                .with_context(context),
+            (Message::Marker(time, label), Sink::Parquet(parquet)) => {
+                let row = ParquetRow {
+                    time,
+                    timestamp: timestamp(time),
+                    marker: Some(label),
+                    ..Default::default()
+                };
+                parquet.push(row).with_context(context)
+            }
+            (Message::Agent(agent), _) => {
+                self.agent = format!("{},", csv_field(&agent));
+                Ok(())
+            }
+            (Message::Rotate(rotated, keep), writer) => {
+                match writer {
+                    Sink::Text(writer) => {
+                        // The open handle follows the renamed file, so it can be finished afterwards
+                        fs::rename(path, &rotated)
+                            .with_context(|| format!("Failed to rotate {} to {}", path, rotated))?;
+                        let fresh =
+                            Output::open(path, false, &self.comments, &self.columns.header())?;
+                        let finished = std::mem::replace(writer, fresh);
+                        finished.finish().with_context(context)?;
+                    }
+                    Sink::Sqlite(database) => database
+                        .rotate(&rotated)
+                        .with_context(|| format!("Failed to rotate {} to {}", path, rotated))?,
+                    Sink::Parquet(parquet) => {
+                        // The rows only reach `path` once the file is finished
+                        parquet.finish().with_context(context)?;
+                        fs::rename(path, &rotated)
+                            .with_context(|| format!("Failed to rotate {} to {}", path, rotated))?;
+                        *parquet = ParquetFile::open(path, false, &self.comments)?;
+                    }
+                }
+                match daemon::prune_rotated(path, keep) {
+                    Ok(()) => info!("Rotated CSV to {}", rotated),
+                    Err(e) => warn!("{:#}", e),
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Quote a CSV field when it contains a separator, quote or newline.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
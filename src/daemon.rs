@@ -0,0 +1,138 @@
+use anyhow::{bail, Context, Result};
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// Removes the pidfile when the daemon exits normally.
+pub struct PidFile {
+    path: String,
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Detach from the terminal: fork, let the parent exit, start a new session and point
+/// stdio at `log_path` (or /dev/null). Must run before any threads are started.
+///
+/// The working directory is kept so relative output paths still resolve.
+pub fn daemonize(pidfile: Option<&str>, log_path: Option<&str>) -> Result<Option<PidFile>> {
+    // Open everything that can fail while errors still reach the terminal
+    let log_target = CString::new(log_path.unwrap_or("/dev/null"))?;
+    let log_fd = unsafe {
+        libc::open(
+            log_target.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND | libc::O_CLOEXEC,
+            0o644,
+        )
+    };
+    if log_fd < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to open daemon log {:?}", log_target));
+    }
+    let null = CString::new("/dev/null")?;
+    let null_fd = unsafe { libc::open(null.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+    if null_fd < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to open /dev/null");
+    }
+
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()).context("Failed to fork daemon"),
+        0 => {}
+        child => {
+            info!("Started daemon with pid {}", child);
+            std::process::exit(0);
+        }
+    }
+
+    unsafe {
+        if libc::setsid() < 0 {
+            bail!(
+                "Failed to start a new session: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        libc::dup2(null_fd, libc::STDIN_FILENO);
+        libc::dup2(log_fd, libc::STDOUT_FILENO);
+        libc::dup2(log_fd, libc::STDERR_FILENO);
+        libc::close(null_fd);
+        libc::close(log_fd);
+    }
+
+    match pidfile {
+        Some(path) => {
+            fs::write(path, format!("{}\n", std::process::id()))
+                .with_context(|| format!("Failed to write pidfile: {}", path))?;
+            Ok(Some(PidFile {
+                path: path.to_string(),
+            }))
+        }
+        None => Ok(None),
+    }
+}
+
+/// `data.csv` -> `data-20240101-130000.csv` for the period starting at `stamp`.
+pub fn rotated_path(path: &str, stamp: chrono::DateTime<chrono::Local>) -> String {
+    let path = Path::new(path);
+    let (stem, suffix) = split_name(
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("output"),
+    );
+    let name = format!("{}-{}{}", stem, stamp.format("%Y%m%d-%H%M%S"), suffix);
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Delete all but the newest `keep` rotated files of `path`.
+pub fn prune_rotated(path: &str, keep: usize) -> Result<()> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let (stem, suffix) = split_name(
+        path.file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("output"),
+    );
+    let prefix = format!("{}-", stem);
+
+    // Timestamps sort lexicographically, so the file names order the periods
+    let mut rotated: Vec<_> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to list {}", dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| {
+            let stamp = p
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(&prefix))
+                .and_then(|n| n.strip_suffix(suffix))
+                .unwrap_or("");
+            chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%d-%H%M%S").is_ok()
+        })
+        .collect();
+    rotated.sort();
+    let excess = rotated.len().saturating_sub(keep);
+    for old in &rotated[..excess] {
+        fs::remove_file(old).with_context(|| format!("Failed to remove {}", old.display()))?;
+    }
+    Ok(())
+}
+
+/// Split a file name into stem and extension, keeping a compression suffix together
+/// with the extension before it: `data.csv.gz` -> (`data`, `.csv.gz`).
+fn split_name(name: &str) -> (&str, &str) {
+    let Some(dot) = name.rfind('.').filter(|&i| i > 0) else {
+        return (name, "");
+    };
+    if matches!(&name[dot..], ".gz" | ".zst") {
+        if let Some(inner) = name[..dot].rfind('.').filter(|&i| i > 0) {
+            return name.split_at(inner);
+        }
+    }
+    name.split_at(dot)
+}
@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What the eBPF collector attributes to stack traces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EbpfProbe {
+    /// Live heap bytes per allocating stack, from uprobes on libc malloc/free
+    Malloc,
+    /// Bytes of pages allocated per stack, from the kmem:mm_page_alloc tracepoint
+    Pages,
+}
+
+impl EbpfProbe {
+    pub fn name(self) -> &'static str {
+        match self {
+            EbpfProbe::Malloc => "malloc",
+            EbpfProbe::Pages => "pages",
+        }
+    }
+
+    /// bpftrace program printing the cumulative bytes per user stack every interval.
+    fn script(self, pid: u32, interval_ms: u64, depth: u32) -> String {
+        let probes = match self {
+            EbpfProbe::Malloc => format!(
+                "uprobe:libc:malloc /pid == {pid}/ {{ @size[tid] = arg0; }}\n\
+                 uretprobe:libc:malloc /pid == {pid} && @size[tid]/ {{\n\
+                 \x20 @live[retval] = @size[tid];\n\
+                 \x20 @stack[retval] = ustack({depth});\n\
+                 \x20 @bytes[ustack({depth})] = sum((int64)@size[tid]);\n\
+                 \x20 delete(@size[tid]);\n\
+                 }}\n\
+                 uprobe:libc:free /pid == {pid} && @live[arg0]/ {{\n\
+                 \x20 @bytes[@stack[arg0]] = sum(-(int64)@live[arg0]);\n\
+                 \x20 delete(@live[arg0]);\n\
+                 \x20 delete(@stack[arg0]);\n\
+                 }}\n"
+            ),
+            EbpfProbe::Pages => format!(
+                "tracepoint:kmem:mm_page_alloc /pid == {pid}/ {{\n\
+                 \x20 @bytes[ustack({depth})] = sum(4096 << args->order);\n\
+                 }}\n"
+            ),
+        };
+        format!(
+            "{probes}interval:ms:{interval_ms} {{ print(@bytes); }}\n\
+             END {{ clear(@bytes); clear(@size); clear(@live); clear(@stack); }}\n"
+        )
+    }
+}
+
+/// Bytes attributed to one stack.
+#[derive(Debug, Clone)]
+pub struct StackBytes {
+    /// Innermost frame first, frames joined with " <- "
+    pub stack: String,
+    pub bytes: i64,
+}
+
+impl StackBytes {
+    fn to_json(&self) -> Value {
+        json!({ "stack": self.stack, "bytes": self.bytes })
+    }
+}
+
+/// Growth per stack during one collection interval.
+#[derive(Debug, Clone)]
+pub struct Interval {
+    pub time: f64,
+    pub stacks: Vec<StackBytes>,
+}
+
+#[derive(Debug, Default)]
+struct CollectorState {
+    totals: HashMap<String, i64>,
+    intervals: Vec<Interval>,
+    error: Option<String>,
+}
+
+/// What the collector saw over the run.
+#[derive(Debug, Clone)]
+pub struct EbpfReport {
+    pub probe: EbpfProbe,
+    /// Stacks with the largest totals since attaching
+    pub top: Vec<StackBytes>,
+    pub intervals: Vec<Interval>,
+    pub error: Option<String>,
+}
+
+impl EbpfReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "probe": self.probe.name(),
+            "top": self.top.iter().map(StackBytes::to_json).collect::<Vec<_>>(),
+            "intervals": self
+                .intervals
+                .iter()
+                .map(|i| json!({
+                    "time_s": i.time,
+                    "stacks": i.stacks.iter().map(StackBytes::to_json).collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<_>>(),
+            "error": self.error,
+        })
+    }
+}
+
+/// Runs bpftrace against the target and aggregates its per-interval map dumps.
+///
+/// There is no BPF loader among the dependencies, so the programs are compiled and
+/// attached by bpftrace, which has to be installed and run with CAP_BPF/CAP_PERFMON
+/// (usually as root).
+pub struct EbpfCollector {
+    probe: EbpfProbe,
+    top: usize,
+    child: Child,
+    reader: thread::JoinHandle<()>,
+    state: Arc<Mutex<CollectorState>>,
+}
+
+impl EbpfCollector {
+    pub fn start(
+        probe: EbpfProbe,
+        pid: u32,
+        interval_ms: u64,
+        depth: u32,
+        top: usize,
+        start: Instant,
+        time_offset: f64,
+    ) -> Result<Self> {
+        let mut child = Command::new("bpftrace")
+            .args(["-f", "json", "-e", &probe.script(pid, interval_ms, depth)])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("Failed to launch bpftrace for --ebpf; is it installed and in PATH?")?;
+        let stdout = child.stdout.take().context("bpftrace has no stdout")?;
+        let state = Arc::new(Mutex::new(CollectorState::default()));
+        let reader_state = Arc::clone(&state);
+        let reader = thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                let time = time_offset + start.elapsed().as_secs_f64();
+                handle_line(&line, time, top, &reader_state);
+            }
+        });
+        Ok(Self {
+            probe,
+            top,
+            child,
+            reader,
+            state,
+        })
+    }
+
+    /// Detach the probes and return what was collected.
+    pub fn finish(mut self) -> Result<EbpfReport> {
+        // SIGINT makes bpftrace detach cleanly; it exits on its own if the target is gone
+        unsafe { libc::kill(self.child.id() as i32, libc::SIGINT) };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut status = self.child.try_wait()?;
+        while status.is_none() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+            status = self.child.try_wait()?;
+        }
+        if status.is_none() {
+            self.child.kill()?;
+            status = Some(self.child.wait()?);
+        }
+        let _ = self.reader.join();
+
+        let state = self.state.lock().unwrap();
+        let mut error = state.error.clone();
+        if error.is_none() && state.intervals.is_empty() {
+            error = status
+                .filter(|s| !s.success())
+                .map(|s| format!("bpftrace exited with {} before reporting", s));
+        }
+        Ok(EbpfReport {
+            probe: self.probe,
+            top: largest(state.totals.iter().map(|(s, b)| (s.clone(), *b)), self.top),
+            intervals: state.intervals.clone(),
+            error,
+        })
+    }
+}
+
+/// Handle one line of bpftrace JSON output: `map` dumps of `@bytes` become intervals.
+fn handle_line(line: &str, time: f64, top: usize, state: &Mutex<CollectorState>) {
+    let Ok(message) = serde_json::from_str::<Value>(line) else {
+        return;
+    };
+    let mut state = state.lock().unwrap();
+    match message["type"].as_str() {
+        Some("map") => {
+            let Some(bytes) = message["data"]["@bytes"].as_object() else {
+                return;
+            };
+            let mut growth = Vec::new();
+            for (stack, total) in bytes {
+                let Some(total) = total.as_i64() else {
+                    continue;
+                };
+                let stack = stack_label(stack);
+                let previous = state.totals.insert(stack.clone(), total).unwrap_or(0);
+                if total != previous {
+                    growth.push((stack, total - previous));
+                }
+            }
+            state.intervals.push(Interval {
+                time,
+                stacks: largest(growth.into_iter(), top),
+            });
+        }
+        Some("error") => {
+            let text = message["msg"].as_str().unwrap_or(line).to_string();
+            state.error.get_or_insert(text);
+        }
+        _ => {}
+    }
+}
+
+/// bpftrace prints stacks one indented frame per line, innermost first.
+fn stack_label(stack: &str) -> String {
+    let frames: Vec<&str> = stack
+        .lines()
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .collect();
+    if frames.is_empty() {
+        "[unknown]".to_string()
+    } else {
+        frames.join(" <- ")
+    }
+}
+
+fn largest(stacks: impl Iterator<Item = (String, i64)>, top: usize) -> Vec<StackBytes> {
+    let mut stacks: Vec<StackBytes> = stacks
+        .map(|(stack, bytes)| StackBytes { stack, bytes })
+        .collect();
+    stacks.sort_by_key(|s| std::cmp::Reverse(s.bytes));
+    stacks.truncate(top);
+    stacks
+}
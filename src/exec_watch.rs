@@ -0,0 +1,86 @@
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::read_cmdline;
+
+/// The process image was replaced: the pid stayed, the program changed.
+pub struct Exec {
+    pub time: f64,
+    pub from: String,
+    pub to: String,
+    /// Resolved executable after the exec, when it could be read
+    pub exe: Option<String>,
+}
+
+impl Exec {
+    /// Short name of the program exec'd into, for chart markers.
+    pub fn program(&self) -> &str {
+        let program = self.to.split_whitespace().next().unwrap_or_default();
+        program.rsplit('/').next().unwrap_or(program)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "time_s": self.time,
+            "from": self.from,
+            "to": self.to,
+            "exe": self.exe,
+        })
+    }
+}
+
+/// Watches `/proc/<pid>/exe` and `/proc/<pid>/cmdline` for exec().
+///
+/// Wrappers (shell scripts, launchers, `env`) often exec into the real program under
+/// the same pid, so samples before the exec belong to a different program.
+pub struct ExecWatch {
+    pid: u32,
+    exe: Option<PathBuf>,
+    cmdline: String,
+    /// The tracker's own executable: a spawned command is a fork of the tracker until it
+    /// execs, which is not worth reporting
+    own_exe: Option<PathBuf>,
+    pub execs: Vec<Exec>,
+}
+
+impl ExecWatch {
+    pub fn start(pid: u32) -> Self {
+        Self {
+            pid,
+            exe: read_exe(pid),
+            cmdline: read_cmdline(pid),
+            own_exe: std::env::current_exe().ok(),
+            execs: Vec::new(),
+        }
+    }
+
+    /// Compare the image with the previous sample's; returns the exec when it changed.
+    pub fn on_sample(&mut self, time: f64) -> Option<&Exec> {
+        let exe = read_exe(self.pid);
+        let cmdline = read_cmdline(self.pid);
+        // Both read empty once the process is gone, which is not an exec
+        if exe.is_none() && cmdline.is_empty() {
+            return None;
+        }
+        if exe == self.exe && cmdline == self.cmdline {
+            return None;
+        }
+        let from = std::mem::replace(&mut self.cmdline, cmdline);
+        let previous_exe = std::mem::replace(&mut self.exe, exe);
+        if previous_exe.is_some() && previous_exe == self.own_exe {
+            return None;
+        }
+        self.execs.push(Exec {
+            time,
+            from,
+            to: self.cmdline.clone(),
+            exe: self.exe.as_ref().map(|path| path.display().to_string()),
+        });
+        self.execs.last()
+    }
+}
+
+fn read_exe(pid: u32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{}/exe", pid)).ok()
+}
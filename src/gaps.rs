@@ -0,0 +1,102 @@
+use serde_json::json;
+use std::fs;
+use std::io;
+
+/// A stretch of consecutive samples that could not be taken although the process
+/// was still there.
+pub struct Gap {
+    pub start: f64,
+    /// Time of the first successful sample after the gap, or of the last failed one
+    /// when the run ended inside it
+    pub end: f64,
+    pub missed: u64,
+    /// The last read error
+    pub error: String,
+}
+
+impl Gap {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "start_s": self.start,
+            "end_s": self.end,
+            "missed_samples": self.missed,
+            "error": self.error,
+        })
+    }
+}
+
+/// Collects the gaps of a `--tolerate-gaps` run.
+#[derive(Default)]
+pub struct GapTracker {
+    pub gaps: Vec<Gap>,
+    open: bool,
+}
+
+impl GapTracker {
+    /// Count a failed sample; returns true when it starts a new gap.
+    pub fn on_failure(&mut self, time: f64, error: &anyhow::Error) -> bool {
+        let error = format!("{:#}", error);
+        if self.open {
+            let gap = self.gaps.last_mut().expect("an open gap exists");
+            gap.end = time;
+            gap.missed += 1;
+            gap.error = error;
+            return false;
+        }
+        self.open = true;
+        self.gaps.push(Gap {
+            start: time,
+            end: time,
+            missed: 1,
+            error,
+        });
+        true
+    }
+
+    /// Close the open gap at a successful sample; returns it when there was one.
+    pub fn on_sample(&mut self, time: f64) -> Option<&Gap> {
+        if !std::mem::take(&mut self.open) {
+            return None;
+        }
+        let gap = self.gaps.last_mut()?;
+        gap.end = time;
+        Some(gap)
+    }
+
+    pub fn missed(&self) -> u64 {
+        self.gaps.iter().map(|gap| gap.missed).sum()
+    }
+}
+
+/// Whether `error`, from sampling `pid`, means the process is gone rather than that
+/// this one read failed.
+///
+/// A missing file or `ESRCH` is conclusive. Anything else (`EACCES` while the process
+/// changes credentials, `EIO`, a truncated read) only ends the run once the process
+/// no longer exists or has become a zombie, which keeps its pid but no memory.
+pub fn process_gone(pid: u32, error: &anyhow::Error) -> bool {
+    let conclusive = error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::ESRCH));
+    if conclusive {
+        return true;
+    }
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } != 0
+        && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+    {
+        return true;
+    }
+    is_zombie(pid)
+}
+
+/// State `Z` in `/proc/<pid>/stat`.
+fn is_zombie(pid: u32) -> bool {
+    let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+        return false;
+    };
+    // The command name may contain spaces and parentheses, so look after the last ')'
+    stat.rfind(')')
+        .and_then(|end| stat[end + 1..].split_whitespace().next())
+        .is_some_and(|state| state == "Z")
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use serde_json::json;
+
+use crate::cgroup::MemoryCgroup;
+use crate::metrics_plugins::{Collector, Metric};
+use crate::units::{self, Unit};
+
+/// Name of the `--headroom` chart series.
+pub const SERIES: &str = "Headroom";
+
+/// Room left under the target's cgroup memory limit, as the limit minus the cgroup's
+/// usage. The usage is what the kernel enforces the limit against, so it also counts page
+/// cache and the cgroup's other processes, unlike the sampled RSS.
+pub struct Headroom {
+    cgroup: MemoryCgroup,
+    limit_kb: u64,
+    /// Whether the headroom is charted (--headroom) or only summarized
+    charted: bool,
+    /// (time, headroom KB, usage KB) of the sample closest to the limit
+    min: Option<(f64, u64, u64)>,
+}
+
+impl Headroom {
+    /// The headroom of `pid`'s cgroup, None when no limit applies to it.
+    pub fn detect(pid: u32, charted: bool) -> Option<Self> {
+        let (cgroup, limit_kb) = MemoryCgroup::of_pid(pid)?.limiting()?;
+        Some(Self {
+            cgroup,
+            limit_kb,
+            charted,
+            min: None,
+        })
+    }
+
+    pub fn limit_kb(&self) -> u64 {
+        self.limit_kb
+    }
+
+    /// Read the cgroup's usage and return the headroom at `time`; None when the usage
+    /// cannot be read, e.g. once the cgroup is gone.
+    pub fn on_sample(&mut self, time: f64) -> Option<u64> {
+        let usage_kb = self.cgroup.usage_kb()?;
+        let headroom_kb = self.limit_kb.saturating_sub(usage_kb);
+        if self.min.is_none_or(|(_, min, _)| headroom_kb < min) {
+            self.min = Some((time, headroom_kb, usage_kb));
+        }
+        Some(headroom_kb)
+    }
+
+    /// Print how close the cgroup came to its limit.
+    pub fn print(&self, units: Option<Unit>) {
+        let Some((time, headroom_kb, usage_kb)) = self.min else {
+            return;
+        };
+        println!(
+            "Minimum headroom: {} below the cgroup limit of {} at {:.1}s ({:.1}% used)",
+            units::describe(headroom_kb as f64, units),
+            units::describe(self.limit_kb as f64, units),
+            time,
+            usage_kb as f64 / self.limit_kb.max(1) as f64 * 100.0
+        );
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "limit_kb": self.limit_kb,
+            "min_headroom_kb": self.min.map(|(_, headroom_kb, _)| headroom_kb),
+            "min_headroom_at_s": self.min.map(|(time, _, _)| time),
+            "usage_at_min_kb": self.min.map(|(_, _, usage_kb)| usage_kb),
+        })
+    }
+}
+
+impl Collector for Headroom {
+    /// The headroom as the chart series, nothing when it is not charted or the usage
+    /// cannot be read.
+    fn collect(&mut self, _pid: u32, time: f64) -> Result<Vec<Metric>> {
+        Ok(match self.on_sample(time) {
+            Some(kb) if self.charted => vec![Metric::kb(SERIES, kb)],
+            _ => Vec::new(),
+        })
+    }
+}
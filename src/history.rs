@@ -0,0 +1,289 @@
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection};
+use std::process::Command;
+use tracing::info;
+
+use crate::recording;
+use crate::stats::MemoryStats;
+
+#[derive(clap::Args)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    command: HistoryCommand,
+}
+
+#[derive(clap::Subcommand)]
+enum HistoryCommand {
+    /// Store the peak and mean of a recording in the history database
+    Record(RecordRun),
+    /// Compare a recording against the rolling median of the previous runs
+    Check(CheckRun),
+    /// List the stored runs, most recent last
+    List(ListRuns),
+}
+
+#[derive(clap::Args)]
+struct Database {
+    /// SQLite database holding the run history, created when missing
+    #[arg(long, value_name = "FILE", default_value = "memory_history.db")]
+    db: String,
+
+    /// Name of the tracked job, so several jobs can share one database
+    #[arg(long, default_value = "default")]
+    name: String,
+}
+
+#[derive(clap::Args)]
+struct RecordRun {
+    #[command(flatten)]
+    database: Database,
+
+    /// Recording of the run (CSV written with --csv-output)
+    recording: String,
+
+    /// Git commit the run was made on (default: $GITHUB_SHA, then `git rev-parse HEAD`)
+    #[arg(long)]
+    commit: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct CheckRun {
+    #[command(flatten)]
+    database: Database,
+
+    /// Recording of the new run (CSV written with --csv-output)
+    recording: String,
+
+    /// Number of most recent runs the rolling median is taken over
+    #[arg(long, value_name = "N", default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    last: u32,
+
+    /// Fail with exit code 7 when peak or mean exceeds the rolling median by more than this
+    /// percentage
+    #[arg(long, value_name = "PERCENT", default_value = "10")]
+    max_drift: f64,
+
+    /// Store the run in the history after checking it
+    #[arg(long)]
+    record: bool,
+
+    /// Git commit the run was made on, stored with --record (default: $GITHUB_SHA, then
+    /// `git rev-parse HEAD`)
+    #[arg(long)]
+    commit: Option<String>,
+}
+
+#[derive(clap::Args)]
+struct ListRuns {
+    #[command(flatten)]
+    database: Database,
+
+    /// Only list this many most recent runs
+    #[arg(long, value_name = "N")]
+    last: Option<u32>,
+}
+
+/// One stored run.
+struct Run {
+    recorded_at: String,
+    commit: Option<String>,
+    peak_kb: f64,
+    mean_kb: f64,
+}
+
+/// Run a `history` subcommand; returns whether `check` found drift beyond the limit.
+pub fn run(args: &HistoryArgs) -> Result<bool> {
+    match &args.command {
+        HistoryCommand::Record(args) => {
+            let stats = recording::load_csv(&args.recording)?;
+            let db = open(&args.database.db)?;
+            let commit = args.commit.clone().or_else(current_commit);
+            insert(&db, &args.database.name, &stats, commit.as_deref())?;
+            info!(
+                "Recorded {} in {} (job {}, commit {})",
+                args.recording,
+                args.database.db,
+                args.database.name,
+                commit.as_deref().unwrap_or("unknown")
+            );
+            Ok(false)
+        }
+        HistoryCommand::Check(args) => check(args),
+        HistoryCommand::List(args) => {
+            let db = open(&args.database.db)?;
+            let runs = recent(&db, &args.database.name, args.last.unwrap_or(u32::MAX))?;
+            println!(
+                "{:<26} {:<12} {:>12} {:>12}",
+                "Recorded", "Commit", "Peak MB", "Mean MB"
+            );
+            for run in runs.iter().rev() {
+                println!(
+                    "{:<26} {:<12} {:>12.2} {:>12.2}",
+                    run.recorded_at,
+                    run.commit.as_deref().map_or("-", |c| &c[..c.len().min(12)]),
+                    run.peak_kb / 1024.0,
+                    run.mean_kb / 1024.0
+                );
+            }
+            Ok(false)
+        }
+    }
+}
+
+fn check(args: &CheckRun) -> Result<bool> {
+    let stats = recording::load_csv(&args.recording)?;
+    let db = open(&args.database.db)?;
+    let history = recent(&db, &args.database.name, args.last)?;
+
+    let mut drifts = Vec::new();
+    if history.is_empty() {
+        println!(
+            "No earlier runs of {} in {}, nothing to compare against",
+            args.database.name, args.database.db
+        );
+    } else {
+        println!(
+            "Comparing {} against the median of the last {} runs of {}",
+            args.recording,
+            history.len(),
+            args.database.name
+        );
+        println!(
+            "\n{:<8} {:>14} {:>14} {:>9}",
+            "Metric", "Median MB", "This run MB", "Drift %"
+        );
+        let metrics: [(&str, f64, Vec<f64>); 2] = [
+            (
+                "Peak",
+                stats.max() as f64,
+                history.iter().map(|r| r.peak_kb).collect(),
+            ),
+            (
+                "Mean",
+                stats.mean(),
+                history.iter().map(|r| r.mean_kb).collect(),
+            ),
+        ];
+        for (name, value, past) in metrics {
+            let median = median(past);
+            let drift = if median == 0.0 {
+                0.0
+            } else {
+                (value - median) / median * 100.0
+            };
+            println!(
+                "{:<8} {:>14.2} {:>14.2} {:>+8.1}%",
+                name,
+                median / 1024.0,
+                value / 1024.0,
+                drift
+            );
+            if drift > args.max_drift {
+                drifts.push(format!(
+                    "{} is {:.1}% above the median of the last {} runs (limit {}%)",
+                    name.to_lowercase(),
+                    drift,
+                    history.len(),
+                    args.max_drift
+                ));
+            }
+        }
+    }
+
+    if args.record {
+        let commit = args.commit.clone().or_else(current_commit);
+        insert(&db, &args.database.name, &stats, commit.as_deref())?;
+        info!("Recorded {} in {}", args.recording, args.database.db);
+    }
+    for drift in &drifts {
+        eprintln!("\nDRIFT: {}", drift);
+    }
+    Ok(!drifts.is_empty())
+}
+
+fn open(path: &str) -> Result<Connection> {
+    let db = Connection::open(path)
+        .with_context(|| format!("Failed to open history database: {}", path))?;
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS runs (
+             id INTEGER PRIMARY KEY,
+             name TEXT NOT NULL,
+             recorded_at TEXT NOT NULL,
+             git_commit TEXT,
+             peak_kb INTEGER NOT NULL,
+             mean_kb REAL NOT NULL,
+             samples INTEGER NOT NULL,
+             duration_s REAL NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS runs_by_name ON runs (name, id);",
+    )
+    .with_context(|| format!("Failed to initialize history database: {}", path))?;
+    Ok(db)
+}
+
+fn insert(db: &Connection, name: &str, stats: &MemoryStats, commit: Option<&str>) -> Result<()> {
+    if stats.samples.is_empty() {
+        bail!("the recording has no samples");
+    }
+    let duration = stats.samples.last().map_or(0.0, |(t, _)| *t) - stats.samples[0].0;
+    db.execute(
+        "INSERT INTO runs (name, recorded_at, git_commit, peak_kb, mean_kb, samples, duration_s)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            name,
+            chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            commit,
+            stats.max() as i64,
+            stats.mean(),
+            stats.sample_count() as i64,
+            duration
+        ],
+    )
+    .context("Failed to store the run")?;
+    Ok(())
+}
+
+/// The `last` most recent runs of job `name`, newest first.
+fn recent(db: &Connection, name: &str, last: u32) -> Result<Vec<Run>> {
+    let mut statement = db.prepare(
+        "SELECT recorded_at, git_commit, peak_kb, mean_kb FROM runs
+         WHERE name = ?1 ORDER BY id DESC LIMIT ?2",
+    )?;
+    let runs = statement
+        .query_map(params![name, last as i64], |row| {
+            Ok(Run {
+                recorded_at: row.get(0)?,
+                commit: row.get(1)?,
+                peak_kb: row.get::<_, i64>(2)? as f64,
+                mean_kb: row.get(3)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .context("Failed to read the run history")?;
+    Ok(runs)
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// The commit being built: `$GITHUB_SHA` in GitHub Actions, otherwise the checkout's HEAD.
+fn current_commit() -> Option<String> {
+    if let Ok(sha) = std::env::var("GITHUB_SHA") {
+        return Some(sha).filter(|sha| !sha.is_empty());
+    }
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
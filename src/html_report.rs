@@ -0,0 +1,206 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+
+use crate::stats::MemoryStats;
+
+/// Write a single self-contained HTML file with an interactive chart, the
+/// statistics table, warnings about the run and the run metadata.
+pub fn write(path: &str, stats: &MemoryStats, metadata: &Value, warnings: &[String]) -> Result<()> {
+    let report = json!({
+        "samples": stats.samples.iter().map(|(t, m)| json!([t, m])).collect::<Vec<_>>(),
+        "stats": {
+            "Samples": stats.sample_count(),
+            "Mean (MB)": stats.mean() / 1024.0,
+            "Median (MB)": stats.median() / 1024.0,
+            "Max (MB)": stats.max() as f64 / 1024.0,
+            "Min (MB)": stats.min() as f64 / 1024.0,
+            "p90 (MB)": stats.percentile(90.0) / 1024.0,
+            "p95 (MB)": stats.percentile(95.0) / 1024.0,
+            "p99 (MB)": stats.percentile(99.0) / 1024.0,
+            "Std dev (MB)": stats.std_dev() / 1024.0,
+            "IQR (MB)": stats.iqr() / 1024.0,
+            "Time-weighted mean (MB)": stats.time_weighted_mean() / 1024.0,
+            "Time-weighted p95 (MB)": stats.time_weighted_percentile(95.0) / 1024.0,
+            "Growth rate (MB/hour)": stats.linear_fit(f64::NEG_INFINITY).map(|f| f.mb_per_hour()),
+            "Growth fit R²": stats.linear_fit(f64::NEG_INFINITY).map(|f| f.r_squared),
+        },
+        "metadata": metadata,
+        "warnings": warnings,
+    });
+
+    // "</" inside the inline script would terminate it early
+    let data = report.to_string().replace("</", "<\\/");
+    let html = TEMPLATE.replace("__REPORT_DATA__", &data);
+    fs::write(path, html).with_context(|| format!("Failed to write HTML report: {}", path))?;
+    Ok(())
+}
+
+const TEMPLATE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Memory Usage Report</title>
+<style>
+  body { font-family: sans-serif; margin: 2em; color: #222; }
+  h1 { font-size: 1.6em; }
+  h2 { font-size: 1.2em; margin-top: 1.5em; }
+  table { border-collapse: collapse; }
+  td, th { border: 1px solid #ccc; padding: 4px 10px; text-align: left; }
+  td.num { text-align: right; font-variant-numeric: tabular-nums; }
+  #chart { position: relative; user-select: none; }
+  #chart svg { border: 1px solid #ddd; }
+  #tooltip { position: absolute; pointer-events: none; background: rgba(0,0,0,0.8); color: #fff;
+             padding: 3px 6px; border-radius: 3px; font-size: 12px; display: none; white-space: nowrap; }
+  .hint { color: #777; font-size: 0.9em; }
+  #warnings li { color: #a40; font-weight: bold; }
+</style>
+</head>
+<body>
+<h1>Memory Usage Report</h1>
+<ul id="warnings"></ul>
+<div id="chart"><div id="tooltip"></div></div>
+<p class="hint">Drag to zoom into a time range, double-click to reset.</p>
+<h2>Statistics</h2>
+<table id="stats"></table>
+<h2>Run metadata</h2>
+<table id="metadata"></table>
+<script>
+const REPORT = __REPORT_DATA__;
+(function () {
+  const NS = "http://www.w3.org/2000/svg";
+  const W = 1000, H = 420, M = { left: 70, right: 20, top: 20, bottom: 45 };
+  const samples = REPORT.samples.map(([t, kb]) => [t, kb / 1024]);
+  const container = document.getElementById("chart");
+  const tooltip = document.getElementById("tooltip");
+  const svg = document.createElementNS(NS, "svg");
+  svg.setAttribute("width", W);
+  svg.setAttribute("height", H);
+  container.insertBefore(svg, tooltip);
+
+  const fullRange = samples.length ? [samples[0][0], samples[samples.length - 1][0]] : [0, 1];
+  let view = fullRange.slice();
+  let dragStart = null;
+
+  function el(name, attrs, parent) {
+    const e = document.createElementNS(NS, name);
+    for (const k in attrs) e.setAttribute(k, attrs[k]);
+    (parent || svg).appendChild(e);
+    return e;
+  }
+
+  function ticks(lo, hi, n) {
+    const span = hi - lo || 1, raw = span / n;
+    const mag = Math.pow(10, Math.floor(Math.log10(raw)));
+    const step = [1, 2, 5, 10].map(f => f * mag).find(s => s >= raw);
+    const out = [];
+    for (let v = Math.ceil(lo / step) * step; v <= hi + 1e-9; v += step) out.push(v);
+    return out;
+  }
+
+  function visible() {
+    return samples.filter(([t]) => t >= view[0] && t <= view[1]);
+  }
+
+  let sx, sy, shown;
+  function draw() {
+    while (svg.firstChild) svg.removeChild(svg.firstChild);
+    shown = visible();
+    const ys = shown.map(s => s[1]);
+    let lo = Math.min(...ys), hi = Math.max(...ys);
+    if (!isFinite(lo)) { lo = 0; hi = 1; }
+    const pad = (hi - lo) / 10 || 1;
+    lo = Math.max(0, lo - pad); hi += pad;
+    const x0 = view[0], x1 = view[1] > view[0] ? view[1] : view[0] + 1;
+    sx = t => M.left + (t - x0) / (x1 - x0) * (W - M.left - M.right);
+    sy = v => H - M.bottom - (v - lo) / (hi - lo) * (H - M.top - M.bottom);
+
+    for (const v of ticks(lo, hi, 6)) {
+      el("line", { x1: M.left, x2: W - M.right, y1: sy(v), y2: sy(v), stroke: "#eee" });
+      el("text", { x: M.left - 6, y: sy(v) + 4, "text-anchor": "end", "font-size": 11 }).textContent = v.toFixed(1);
+    }
+    for (const t of ticks(x0, x1, 8)) {
+      el("line", { x1: sx(t), x2: sx(t), y1: M.top, y2: H - M.bottom, stroke: "#eee" });
+      el("text", { x: sx(t), y: H - M.bottom + 16, "text-anchor": "middle", "font-size": 11 }).textContent = t.toFixed(1);
+    }
+    el("text", { x: (W + M.left) / 2, y: H - 6, "text-anchor": "middle", "font-size": 13 }).textContent = "Time (seconds)";
+    el("text", { x: 16, y: H / 2, "text-anchor": "middle", "font-size": 13, transform: `rotate(-90 16 ${H / 2})` }).textContent = "Memory (MB)";
+    el("rect", { x: M.left, y: M.top, width: W - M.left - M.right, height: H - M.top - M.bottom, fill: "none", stroke: "#999" });
+
+    el("polyline", {
+      points: shown.map(([t, v]) => `${sx(t).toFixed(1)},${sy(v).toFixed(1)}`).join(" "),
+      fill: "none", stroke: "#1f5fd1", "stroke-width": 1.5
+    });
+  }
+
+  function timeAt(evt) {
+    const x = evt.clientX - svg.getBoundingClientRect().left;
+    return view[0] + (x - M.left) / (W - M.left - M.right) * (view[1] - view[0]);
+  }
+
+  function nearest(t) {
+    let best = null;
+    for (const s of shown) if (!best || Math.abs(s[0] - t) < Math.abs(best[0] - t)) best = s;
+    return best;
+  }
+
+  svg.addEventListener("mousedown", e => { dragStart = timeAt(e); });
+  svg.addEventListener("mouseup", e => {
+    const end = timeAt(e);
+    if (dragStart !== null && Math.abs(end - dragStart) > (view[1] - view[0]) / 200) {
+      view = [Math.min(dragStart, end), Math.max(dragStart, end)];
+      draw();
+    }
+    dragStart = null;
+    const sel = svg.querySelector(".selection");
+    if (sel) sel.remove();
+  });
+  svg.addEventListener("dblclick", () => { view = fullRange.slice(); draw(); });
+  svg.addEventListener("mouseleave", () => { tooltip.style.display = "none"; });
+  svg.addEventListener("mousemove", e => {
+    const t = timeAt(e);
+    if (dragStart !== null) {
+      let sel = svg.querySelector(".selection");
+      if (!sel) sel = el("rect", { class: "selection", y: M.top, height: H - M.top - M.bottom, fill: "rgba(31,95,209,0.15)" });
+      sel.setAttribute("x", sx(Math.min(dragStart, t)));
+      sel.setAttribute("width", Math.abs(sx(t) - sx(dragStart)));
+    }
+    const s = nearest(t);
+    if (!s) return;
+    let dot = svg.querySelector(".hover");
+    if (!dot) dot = el("circle", { class: "hover", r: 4, fill: "#d1361f" });
+    dot.setAttribute("cx", sx(s[0]));
+    dot.setAttribute("cy", sy(s[1]));
+    tooltip.textContent = `${s[0].toFixed(2)} s: ${s[1].toFixed(2)} MB`;
+    tooltip.style.left = (sx(s[0]) + 10) + "px";
+    tooltip.style.top = (sy(s[1]) - 28) + "px";
+    tooltip.style.display = "block";
+  });
+
+  function fillTable(id, obj) {
+    const table = document.getElementById(id);
+    for (const k in obj) {
+      const row = table.insertRow();
+      row.insertCell().textContent = k;
+      const cell = row.insertCell();
+      const v = obj[k];
+      if (typeof v === "number") {
+        cell.className = "num";
+        cell.textContent = Number.isInteger(v) ? v : v.toFixed(2);
+      } else {
+        cell.textContent = v;
+      }
+    }
+  }
+
+  for (const warning of REPORT.warnings) {
+    document.getElementById("warnings").appendChild(document.createElement("li")).textContent = warning;
+  }
+  draw();
+  fillTable("stats", REPORT.stats);
+  fillTable("metadata", REPORT.metadata);
+})();
+</script>
+</body>
+</html>
+"##;
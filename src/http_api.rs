@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::chart;
+use crate::prometheus::CLIENT_TIMEOUT;
+use crate::stats::MemoryStats;
+use crate::MemoryReading;
+
+/// The recording of the run, which the sampling loop adds to and the live API reads.
+pub type SharedStats = Arc<Mutex<MemoryStats>>;
+
+/// What the live API answers from. Only one of its locks is held at a time.
+pub struct LiveState {
+    pub pid: u32,
+    /// The last reading, set by the sampling loop
+    pub latest: Mutex<Option<(f64, MemoryReading)>>,
+    pub stats: SharedStats,
+    /// Chart options of the run; markers are taken from `stats` at render time
+    pub chart_options: chart::ChartOptions,
+    pub trend_window: f64,
+}
+
+pub type SharedState = Arc<LiveState>;
+
+/// Bind `addr` and answer live queries from a task on the current tokio runtime.
+pub fn serve(addr: &str, state: SharedState) -> Result<()> {
+    let listener = std::net::TcpListener::bind(addr)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .with_context(|| format!("Failed to bind HTTP API listener on {}", addr))?;
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                let _ =
+                    tokio::time::timeout(CLIENT_TIMEOUT, handle_connection(stream, state)).await;
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_connection(mut stream: TcpStream, state: SharedState) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&mut stream)
+        .read_line(&mut request_line)
+        .await?;
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or("")
+        .to_string();
+    // Rendering a chart takes a while, so answers are put together off the runtime's workers
+    let (status, content_type, body) =
+        tokio::task::spawn_blocking(move || respond(&target, &state)).await?;
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    Ok(())
+}
+
+/// Status, content type and body of the answer to a request for `target`.
+fn respond(target: &str, state: &SharedState) -> (&'static str, &'static str, Vec<u8>) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    match path {
+        "/current" => ("200 OK", "application/json", json_body(&current(state))),
+        "/stats" => {
+            let stats = state.stats.lock().unwrap().summary_json(state.trend_window);
+            ("200 OK", "application/json", json_body(&stats))
+        }
+        "/samples" => match query_param(query, "since").map(str::parse::<f64>) {
+            Some(Err(_)) => (
+                "400 Bad Request",
+                "text/plain",
+                b"since must be a number of seconds\n".to_vec(),
+            ),
+            since => {
+                let since = since.and_then(Result::ok).unwrap_or(f64::NEG_INFINITY);
+                let samples = samples_since(&state.stats.lock().unwrap(), since);
+                ("200 OK", "application/json", json_body(&samples))
+            }
+        },
+        "/chart.png" => match render_chart(state) {
+            Ok(png) => ("200 OK", "image/png", png),
+            Err(e) => (
+                "500 Internal Server Error",
+                "text/plain",
+                format!("{:#}\n", e).into_bytes(),
+            ),
+        },
+        _ => ("404 Not Found", "text/plain", b"Not Found\n".to_vec()),
+    }
+}
+
+fn json_body(value: &Value) -> Vec<u8> {
+    let mut body = value.to_string().into_bytes();
+    body.push(b'\n');
+    body
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn current(state: &LiveState) -> Value {
+    let latest = *state.latest.lock().unwrap();
+    match latest {
+        Some((time, reading)) => json!({
+            "pid": state.pid,
+            "time_s": time,
+            "rss_kb": reading.rss_kb,
+            "swap_kb": reading.swap_kb,
+            "hwm_kb": reading.hwm_kb,
+            "samples": state.stats.lock().unwrap().sample_count(),
+        }),
+        None => json!({ "pid": state.pid, "samples": 0 }),
+    }
+}
+
+/// Retained samples and markers taken after `since` seconds.
+fn samples_since(stats: &MemoryStats, since: f64) -> Value {
+    json!({
+        "samples": stats
+            .samples
+            .iter()
+            .filter(|(t, _)| *t > since)
+            .map(|(t, kb)| json!([t, kb]))
+            .collect::<Vec<_>>(),
+        "markers": stats
+            .markers
+            .iter()
+            .filter(|(t, _)| *t > since)
+            .map(|(t, label)| json!([t, label]))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Render the chart of the samples so far as a PNG.
+fn render_chart(state: &LiveState) -> Result<Vec<u8>> {
+    // Copy the series so sampling is not held up while the chart is drawn
+    let stats = {
+        let shared = state.stats.lock().unwrap();
+        if shared.samples.is_empty() {
+            anyhow::bail!("no samples yet");
+        }
+        let mut stats = MemoryStats::new();
+        stats.samples = shared.samples.clone();
+        stats.markers = shared.markers.clone();
+        stats
+    };
+    let options = chart::ChartOptions {
+        markers: stats.markers.clone(),
+        ..state.chart_options.clone()
+    };
+    chart::render_png(&stats, &options)
+}
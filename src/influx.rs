@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::MemoryReading;
+
+/// Lines kept for the write endpoint while it cannot be reached; older ones are dropped
+/// first, so an endpoint that stays down costs bounded memory.
+const MAX_PENDING: usize = 100_000;
+
+/// Writes samples as InfluxDB line protocol to a file and/or an HTTP write endpoint.
+pub struct InfluxExporter {
+    measurement: String,
+    tags: String,
+    file: Option<BufWriter<File>>,
+    url: Option<String>,
+    token: Option<String>,
+    pending: VecDeque<String>,
+    /// Lines dropped from `pending` since the last successful write
+    dropped: u64,
+}
+
+impl InfluxExporter {
+    pub fn new(
+        measurement: &str,
+        tags: &[(String, String)],
+        file_path: Option<&str>,
+        url: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<Self> {
+        let file = match file_path {
+            Some(path) => {
+                Some(BufWriter::new(File::create(path).with_context(|| {
+                    format!("Failed to create InfluxDB output: {}", path)
+                })?))
+            }
+            None => None,
+        };
+
+        let tags = tags
+            .iter()
+            .map(|(k, v)| format!(",{}={}", escape(k), escape(v)))
+            .collect();
+
+        Ok(Self {
+            measurement: escape(measurement),
+            tags,
+            file,
+            url: url.map(str::to_string),
+            token: token.map(str::to_string),
+            pending: VecDeque::new(),
+            dropped: 0,
+        })
+    }
+
+    pub fn add_sample(&mut self, reading: &MemoryReading) -> Result<()> {
+        let timestamp_ns = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let line = format!(
+            "{}{} rss_kb={}i,swap_kb={}i,hwm_kb={}i {}",
+            self.measurement,
+            self.tags,
+            reading.rss_kb,
+            reading.swap_kb,
+            reading.hwm_kb,
+            timestamp_ns
+        );
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{}", line).context("Failed to write InfluxDB line")?;
+        }
+        if self.url.is_some() {
+            if self.pending.len() >= MAX_PENDING {
+                if self.dropped == 0 {
+                    warn!(
+                        "InfluxDB write endpoint is behind by {} lines, dropping the oldest",
+                        MAX_PENDING
+                    );
+                }
+                self.pending.pop_front();
+                self.dropped += 1;
+            }
+            self.pending.push_back(line);
+        }
+        Ok(())
+    }
+
+    /// Flush buffered lines to the file and send pending lines to the write endpoint.
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush().context("Failed to flush InfluxDB output")?;
+        }
+
+        let Some(url) = &self.url else {
+            return Ok(());
+        };
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut request = ureq::post(url).set("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Token {}", token));
+        }
+        let body = self
+            .pending
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\n");
+        request
+            .send_string(&body)
+            .with_context(|| format!("Failed to write samples to {}", url))?;
+        self.pending.clear();
+        if self.dropped > 0 {
+            warn!(
+                "InfluxDB write endpoint caught up; {} lines were dropped while it was unreachable",
+                self.dropped
+            );
+            self.dropped = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Escape a measurement name, tag key or tag value for line protocol.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+/// Parse a `key=value` tag argument.
+pub fn parse_tag(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected key=value, got '{}'", arg))
+}
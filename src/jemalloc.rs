@@ -0,0 +1,144 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+use std::time::SystemTime;
+
+use crate::metrics_plugins::{Collector, Metric};
+
+/// Environment variable telling a spawned child where to write its jemalloc stats.
+pub const STATS_PATH_ENV: &str = "MEMORY_TRACKER_JEMALLOC_STATS";
+
+/// Allocator-level counters from jemalloc's `stats.*` mallctls, in KB.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JemallocStats {
+    pub allocated_kb: u64,
+    pub active_kb: u64,
+    pub resident_kb: u64,
+    pub metadata_kb: u64,
+    pub mapped_kb: u64,
+    pub retained_kb: u64,
+}
+
+impl JemallocStats {
+    /// Parse `malloc_stats_print` JSON output (the `J` option) or a flat object with the
+    /// same keys. Values are in bytes, as jemalloc reports them.
+    pub fn parse(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text).context("Invalid jemalloc stats JSON")?;
+        let stats = value
+            .pointer("/jemalloc/stats")
+            .or_else(|| value.get("stats"))
+            .unwrap_or(&value);
+        let kb = |name: &str| stats.get(name).and_then(Value::as_u64).unwrap_or(0) / 1024;
+        let Some(allocated) = stats.get("allocated").and_then(Value::as_u64) else {
+            bail!("jemalloc stats have no \"allocated\" counter");
+        };
+        Ok(Self {
+            allocated_kb: allocated / 1024,
+            active_kb: kb("active"),
+            resident_kb: kb("resident"),
+            metadata_kb: kb("metadata"),
+            mapped_kb: kb("mapped"),
+            retained_kb: kb("retained"),
+        })
+    }
+
+    /// Series drawn alongside RSS.
+    pub fn series(&self) -> [(&'static str, u64); 3] {
+        [
+            ("jemalloc allocated", self.allocated_kb),
+            ("jemalloc active", self.active_kb),
+            ("jemalloc resident", self.resident_kb),
+        ]
+    }
+
+    /// RSS not backed by live allocations: fragmentation, allocator metadata and
+    /// anything allocated outside jemalloc.
+    pub fn fragmentation_kb(&self, rss_kb: u64) -> u64 {
+        rss_kb.saturating_sub(self.allocated_kb)
+    }
+
+    pub fn to_json(self, rss_kb: u64) -> Value {
+        let fragmentation_kb = self.fragmentation_kb(rss_kb);
+        json!({
+            "allocated_kb": self.allocated_kb,
+            "active_kb": self.active_kb,
+            "resident_kb": self.resident_kb,
+            "metadata_kb": self.metadata_kb,
+            "mapped_kb": self.mapped_kb,
+            "retained_kb": self.retained_kb,
+            "rss_kb": rss_kb,
+            "fragmentation_kb": fragmentation_kb,
+            "fragmentation_percent": percent_of(fragmentation_kb, rss_kb),
+        })
+    }
+}
+
+pub fn percent_of(part_kb: u64, whole_kb: u64) -> f64 {
+    if whole_kb == 0 {
+        0.0
+    } else {
+        part_kb as f64 * 100.0 / whole_kb as f64
+    }
+}
+
+/// Follows a stats file the target rewrites periodically, re-parsing it only when its
+/// modification time changes. jemalloc has no out-of-process interface, so the target
+/// has to dump its own stats, e.g. `malloc_stats_print` with the `J` option.
+pub struct JemallocReader {
+    path: String,
+    modified: Option<SystemTime>,
+    latest: Option<JemallocStats>,
+}
+
+impl JemallocReader {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            modified: None,
+            latest: None,
+        }
+    }
+
+    /// The most recent stats, or `None` until the file first parses. A file caught
+    /// mid-write keeps the previous reading.
+    pub fn read(&mut self) -> Result<Option<JemallocStats>> {
+        let modified = match fs::metadata(&self.path) {
+            Ok(meta) => meta.modified().ok(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(self.latest),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to stat {}", self.path));
+            }
+        };
+        if modified.is_some() && modified == self.modified {
+            return Ok(self.latest);
+        }
+        let text = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path))?;
+        // Leave `modified` alone on failure so the next sample retries
+        if let Ok(stats) = JemallocStats::parse(&text) {
+            self.modified = modified;
+            self.latest = Some(stats);
+        }
+        Ok(self.latest)
+    }
+
+    /// The stats of the last successful read.
+    pub fn latest(&self) -> Option<JemallocStats> {
+        self.latest
+    }
+}
+
+impl Collector for JemallocReader {
+    fn collect(&mut self, _pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        Ok(self
+            .read()?
+            .map(|stats| {
+                stats
+                    .series()
+                    .into_iter()
+                    .map(|(name, kb)| Metric::kb(name, kb))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
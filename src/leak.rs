@@ -0,0 +1,126 @@
+use crate::stats::MemoryStats;
+
+/// Mann-Kendall is O(n²), so long runs are first reduced to this many bucket means.
+/// Averaging also damps the sample-to-sample autocorrelation the test assumes away.
+const MAX_POINTS: usize = 500;
+
+/// Outcome of a Mann-Kendall monotonic-trend test on the steady-state samples.
+#[derive(Debug, Clone, Copy)]
+pub struct LeakVerdict {
+    /// Start of the analysed window in seconds
+    pub from_time: f64,
+    pub points: usize,
+    /// Kendall's tau: -1 (always falling) to 1 (always rising)
+    pub tau: f64,
+    pub z: f64,
+    /// One-sided p-value for an increasing trend
+    pub p_value: f64,
+    pub suspected: bool,
+}
+
+impl LeakVerdict {
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "from_time_s": self.from_time,
+            "points": self.points,
+            "tau": self.tau,
+            "z": self.z,
+            "p_value": self.p_value,
+            "suspected": self.suspected,
+        })
+    }
+}
+
+/// Test the samples after `warmup` seconds for a statistically significant upward trend.
+/// Without an explicit warmup the first 10% of the run is treated as start-up.
+pub fn detect(stats: &MemoryStats, warmup: Option<f64>, alpha: f64) -> Option<LeakVerdict> {
+    let (first, last) = (stats.samples.first()?.0, stats.samples.last()?.0);
+    let from_time = first + warmup.unwrap_or((last - first) * 0.1);
+    let steady: Vec<(f64, u64)> = stats
+        .samples
+        .iter()
+        .copied()
+        .filter(|(t, _)| *t >= from_time)
+        .collect();
+
+    let values = bucket_means(&steady, MAX_POINTS);
+    let n = values.len();
+    if n < 4 {
+        return None;
+    }
+
+    let mut s = 0i64;
+    for i in 0..n {
+        for j in i + 1..n {
+            s += match values[j].partial_cmp(&values[i]) {
+                Some(std::cmp::Ordering::Greater) => 1,
+                Some(std::cmp::Ordering::Less) => -1,
+                _ => 0,
+            };
+        }
+    }
+
+    // Variance of S with the usual correction for tied groups
+    let mut sorted = values.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mut tie_term = 0.0;
+    for group in sorted.chunk_by(|a, b| a == b) {
+        let t = group.len() as f64;
+        tie_term += t * (t - 1.0) * (2.0 * t + 5.0);
+    }
+    let nf = n as f64;
+    let var_s = (nf * (nf - 1.0) * (2.0 * nf + 5.0) - tie_term) / 18.0;
+
+    let z = if var_s <= 0.0 {
+        0.0
+    } else if s > 0 {
+        (s - 1) as f64 / var_s.sqrt()
+    } else if s < 0 {
+        (s + 1) as f64 / var_s.sqrt()
+    } else {
+        0.0
+    };
+    let p_value = 0.5 * erfc(z / std::f64::consts::SQRT_2);
+
+    Some(LeakVerdict {
+        from_time,
+        points: n,
+        tau: s as f64 / (nf * (nf - 1.0) / 2.0),
+        z,
+        p_value,
+        suspected: p_value < alpha,
+    })
+}
+
+/// Reduce samples to at most `max_points` means over equal-length runs of samples.
+fn bucket_means(samples: &[(f64, u64)], max_points: usize) -> Vec<f64> {
+    if samples.len() <= max_points {
+        return samples.iter().map(|(_, m)| *m as f64).collect();
+    }
+    let bucket = samples.len().div_ceil(max_points);
+    samples
+        .chunks(bucket)
+        .map(|chunk| chunk.iter().map(|(_, m)| *m as f64).sum::<f64>() / chunk.len() as f64)
+        .collect()
+}
+
+/// Complementary error function (Numerical Recipes erfcc, relative error < 1.2e-7).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.26551223
+            + t * (1.00002368
+                + t * (0.37409196
+                    + t * (0.09678418
+                        + t * (-0.18628806
+                            + t * (0.27886807
+                                + t * (-1.13520398
+                                    + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
@@ -0,0 +1,290 @@
+//! Records the memory usage of a process: the library behind the `memory_tracker`
+//! command. [`run_cli`] runs the command line; [`metrics_plugins`] is where a build adds
+//! collectors of its own before handing their registry to it.
+
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+use anyhow::Context;
+use anyhow::Result;
+use clap::{CommandFactory, Parser};
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+use std::fs;
+
+mod agent;
+mod alert;
+mod alloc_trace;
+mod bench;
+// FreeBSD and OpenBSD have no Linux-style /proc; samples come from sysctl(3) instead
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+mod bsd;
+mod cgroup;
+mod chart;
+mod chat;
+mod children;
+mod ci_report;
+mod collector;
+mod compare;
+mod config;
+mod csv_writer;
+mod daemon;
+#[cfg(feature = "ebpf")]
+mod ebpf;
+mod exec_watch;
+mod gaps;
+mod headroom;
+mod history;
+mod html_report;
+mod http_api;
+mod influx;
+mod jemalloc;
+mod leak;
+mod logging;
+mod markers;
+mod md_report;
+mod metadata;
+pub mod metrics_plugins;
+#[cfg(feature = "otel")]
+mod otel;
+mod peak_hold;
+mod phases;
+mod pidns;
+mod plot;
+mod prometheus;
+mod record;
+mod recording;
+mod segments;
+mod shutdown;
+mod smaps;
+mod spikes;
+mod statm;
+mod stats;
+mod statsd;
+mod summary;
+mod swap;
+mod systemd;
+mod threads;
+mod top;
+mod trace;
+mod units;
+mod wait;
+
+/// Process exit code when `--detect-leak` finds a significant upward trend.
+const EXIT_LEAK_SUSPECTED: i32 = 3;
+/// Process exit code when the peak exceeds `--max-peak`.
+const EXIT_PEAK_BUDGET: i32 = 4;
+/// Process exit code when the mean exceeds `--max-mean`.
+const EXIT_MEAN_BUDGET: i32 = 5;
+/// Process exit code when `compare` finds a regression beyond `--max-regression`.
+const EXIT_REGRESSION: i32 = 6;
+/// Process exit code when `history check` finds drift beyond `--max-drift`.
+const EXIT_DRIFT: i32 = 7;
+
+#[derive(Parser)]
+#[command(name = "memory_tracker")]
+#[command(about = "Track memory usage of a process and generate statistics")]
+#[command(
+    override_usage = "memory_tracker [record] [OPTIONS] --pid <PID>\n       memory_tracker [record] [OPTIONS] --wait-for-name <NAME>\n       memory_tracker [record] [OPTIONS] --unit <UNIT>\n       memory_tracker [record] [OPTIONS] --top <N>\n       memory_tracker run [OPTIONS] -- <COMMAND>...\n       memory_tracker compare [OPTIONS] <BASELINE> <CANDIDATE>\n       memory_tracker plot [OPTIONS] <CSV>...\n       memory_tracker stats [OPTIONS] <CSV>\n       memory_tracker bench [OPTIONS] -n <RUNS> -- <COMMAND>...\n       memory_tracker collector [OPTIONS] --agents <N>\n       memory_tracker history <record|check|list> [OPTIONS] <CSV>"
+)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+// Config file values are passed as leading flags, so a later command-line flag must win
+#[command(args_override_self = true)]
+struct Cli {
+    #[command(subcommand)]
+    subcommand: Option<Command>,
+
+    /// Recording options, accepted without a subcommand for compatibility
+    #[command(flatten)]
+    record: record::RecordArgs,
+
+    /// Only print warnings, errors and results
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print debug messages; repeat (-vv) to also trace every sample
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Record the memory usage of a running process (the default without a subcommand)
+    Record(record::RecordArgs),
+    /// Launch a command and record its memory usage until it exits
+    #[command(override_usage = "memory_tracker run [OPTIONS] -- <COMMAND>...")]
+    Run(record::RecordArgs),
+    /// Compare two recorded runs and fail on a memory regression
+    Compare(compare::CompareArgs),
+    /// Re-render charts from saved recordings, optionally overlaying several runs
+    Plot(plot::PlotArgs),
+    /// Compute the statistics summary of a saved recording
+    Stats(summary::StatsArgs),
+    /// Run a command several times and report the spread of its peak memory
+    #[command(override_usage = "memory_tracker bench [OPTIONS] -n <RUNS> -- <COMMAND>...")]
+    Bench(bench::BenchArgs),
+    /// Receive samples from agents (record/run with --collector) into one recording and chart
+    Collector(collector::CollectorArgs),
+    /// Track peak and mean memory across CI runs and fail on drift from recent history
+    History(history::HistoryArgs),
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct MemoryReading {
+    rss_kb: u64,
+    swap_kb: u64,
+    hwm_kb: u64,
+    rss_anon_kb: u64,
+    rss_file_kb: u64,
+    rss_shmem_kb: u64,
+}
+
+impl MemoryReading {
+    /// The reading as metrics named after the `/proc/<pid>/status` fields, the way the
+    /// memory sources return it through the `Collector` trait.
+    fn to_metrics(self) -> Vec<metrics_plugins::Metric> {
+        [
+            ("VmRSS", self.rss_kb),
+            ("VmSwap", self.swap_kb),
+            ("VmHWM", self.hwm_kb),
+            ("RssAnon", self.rss_anon_kb),
+            ("RssFile", self.rss_file_kb),
+            ("RssShmem", self.rss_shmem_kb),
+        ]
+        .into_iter()
+        .map(|(name, kb)| metrics_plugins::Metric::kb(name, kb))
+        .collect()
+    }
+
+    /// The reading back from `to_metrics`; the fields missing from `metrics` are zero.
+    fn from_metrics(metrics: &[metrics_plugins::Metric]) -> Result<Self> {
+        let mut reading = Self::default();
+        let mut found_rss = false;
+        for metric in metrics {
+            let kb = metric.value as u64;
+            match metric.name.as_str() {
+                "VmRSS" => {
+                    reading.rss_kb = kb;
+                    found_rss = true;
+                }
+                "VmSwap" => reading.swap_kb = kb,
+                "VmHWM" => reading.hwm_kb = kb,
+                "RssAnon" => reading.rss_anon_kb = kb,
+                "RssFile" => reading.rss_file_kb = kb,
+                "RssShmem" => reading.rss_shmem_kb = kb,
+                _ => {}
+            }
+        }
+        if !found_rss {
+            anyhow::bail!("The memory source returned no VmRSS");
+        }
+        Ok(reading)
+    }
+}
+
+#[cfg(any(target_os = "freebsd", target_os = "openbsd"))]
+use bsd::{hostname, read_cmdline, read_memory_usage};
+
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+fn read_memory_usage(pid: u32) -> Result<MemoryReading> {
+    let status_path = format!("/proc/{}/status", pid);
+    let content = fs::read_to_string(&status_path)
+        .with_context(|| format!("Failed to read {}", status_path))?;
+
+    let mut reading = MemoryReading::default();
+    let mut found_rss = false;
+    for line in content.lines() {
+        let field = match line.split(':').next() {
+            Some(field @ ("VmRSS" | "VmSwap" | "VmHWM" | "RssAnon" | "RssFile" | "RssShmem")) => {
+                field
+            }
+            _ => continue,
+        };
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() >= 2 {
+            let memory_kb = parts[1]
+                .parse::<u64>()
+                .with_context(|| format!("Failed to parse memory value: {}", parts[1]))?;
+            match field {
+                "VmRSS" => {
+                    reading.rss_kb = memory_kb;
+                    found_rss = true;
+                }
+                "VmSwap" => reading.swap_kb = memory_kb,
+                "VmHWM" => reading.hwm_kb = memory_kb,
+                "RssAnon" => reading.rss_anon_kb = memory_kb,
+                "RssFile" => reading.rss_file_kb = memory_kb,
+                _ => reading.rss_shmem_kb = memory_kb,
+            }
+        }
+    }
+
+    if !found_rss {
+        anyhow::bail!("VmRSS not found in /proc/{}/status", pid);
+    }
+    Ok(reading)
+}
+
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+fn read_cmdline(pid: u32) -> String {
+    fs::read(format!("/proc/{}/cmdline", pid))
+        .map(|raw| {
+            raw.split(|b| *b == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .unwrap_or_default()
+}
+
+/// Parse the command line and run it, with the collectors of `registry` available to
+/// `--collect`. Returns the exit code for the process, e.g. `EXIT_PEAK_BUDGET` when a
+/// budget is exceeded; errors are left to the caller to report.
+pub fn run_cli(registry: metrics_plugins::Registry) -> Result<i32> {
+    let command = Cli::command();
+    let record_options: Vec<String> = command
+        .find_subcommand("record")
+        .expect("record subcommand is defined")
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_id().as_str(), "config" | "profile"))
+        .filter_map(|arg| arg.get_long().map(str::to_string))
+        .collect();
+    let args = config::expand_args(std::env::args_os().collect(), &record_options)?;
+    metadata::record_invocation(&args);
+    let cli = Cli::parse_from(args);
+    logging::init(if cli.quiet {
+        -1
+    } else {
+        cli.verbose.min(2) as i8
+    });
+    match &cli.subcommand {
+        None => record::run(&cli.record, &registry),
+        Some(Command::Record(args)) => record::run(args, &registry),
+        Some(Command::Run(args)) => {
+            if args.command.is_empty() {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "`run` needs a command to launch after `--`",
+                    )
+                    .exit();
+            }
+            record::run(args, &registry)
+        }
+        Some(Command::Compare(args)) => Ok(if compare::run(args)? {
+            EXIT_REGRESSION
+        } else {
+            0
+        }),
+        Some(Command::Plot(args)) => plot::run(args).map(|()| 0),
+        Some(Command::Stats(args)) => summary::run(args).map(|()| 0),
+        Some(Command::Bench(args)) => bench::run(args).map(|()| 0),
+        Some(Command::Collector(args)) => collector::run(args).map(|()| 0),
+        Some(Command::History(args)) => Ok(if history::run(args)? { EXIT_DRIFT } else { 0 }),
+    }
+}
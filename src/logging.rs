@@ -0,0 +1,141 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Whether a progress display is on the current terminal line, so the next message must
+/// first clear it rather than being appended to it.
+static PROGRESS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether `-q` was given, which also turns the progress display off by default.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// How the live progress of a recording is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressStyle {
+    /// No progress display, for log-capturing CI systems
+    None,
+    /// A single status line rewritten in place
+    Line,
+    /// The status line behind a bar filling up over `--duration`, or against the peak
+    Bar,
+}
+
+/// Install the console logger. `verbosity` is -1 for `-q` (warnings and errors only),
+/// 0 by default, 1 for `-v` (debug) and 2 or more for `-vv` (trace).
+///
+/// Messages up to info level go to stdout and warnings and errors to stderr, as plain
+/// lines; debug and trace messages carry a timestamp and their level.
+pub fn init(verbosity: i8) {
+    let level = match verbosity {
+        i8::MIN..=-1 => Level::WARN,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    QUIET.store(verbosity < 0, Ordering::Relaxed);
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .event_format(ConsoleFormat)
+        .with_writer(
+            std::io::stderr
+                .with_max_level(Level::WARN)
+                .or_else(std::io::stdout),
+        )
+        .init();
+}
+
+struct ConsoleFormat;
+
+impl<S, N> FormatEvent<S, N> for ConsoleFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if PROGRESS_SHOWN.swap(false, Ordering::Relaxed) {
+            write!(writer, "\r\x1b[2K")?;
+        }
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            Level::INFO => {}
+            level => write!(
+                writer,
+                "[{} {}] ",
+                chrono::Local::now().format("%H:%M:%S%.3f"),
+                level.as_str().to_ascii_lowercase()
+            )?,
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Clear the progress display before writing output that bypasses the logger, such as
+/// the monitored command's own output.
+pub fn clear_progress() {
+    if PROGRESS_SHOWN.swap(false, Ordering::Relaxed) {
+        print!("\r\x1b[2K");
+    }
+}
+
+/// The live status display of a recording.
+pub struct Progress {
+    style: ProgressStyle,
+}
+
+impl Progress {
+    /// `style` as chosen with `--progress`; by default a status line on a terminal and
+    /// nothing when stdout is a pipe or file, or with `-q`.
+    pub fn new(style: Option<ProgressStyle>) -> Self {
+        let style = style.unwrap_or(
+            if std::io::stdout().is_terminal() && !QUIET.load(Ordering::Relaxed) {
+                ProgressStyle::Line
+            } else {
+                ProgressStyle::None
+            },
+        );
+        Self { style }
+    }
+
+    /// Redraw the display with `status`; `fraction` (0 to 1) fills the bar.
+    pub fn update(&self, status: &str, fraction: f64) {
+        let line = match self.style {
+            ProgressStyle::None => return,
+            ProgressStyle::Line => status.to_string(),
+            ProgressStyle::Bar => {
+                const WIDTH: usize = 30;
+                let filled =
+                    ((fraction.clamp(0.0, 1.0) * WIDTH as f64).round() as usize).min(WIDTH);
+                format!(
+                    "[{}{}] {}",
+                    "#".repeat(filled),
+                    "-".repeat(WIDTH - filled),
+                    status
+                )
+            }
+        };
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "\r{}\x1b[K", line);
+        let _ = stdout.flush();
+        PROGRESS_SHOWN.store(true, Ordering::Relaxed);
+    }
+
+    /// End the display, leaving the last status on its own line.
+    pub fn finish(&self) {
+        if PROGRESS_SHOWN.swap(false, Ordering::Relaxed) {
+            println!();
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::logging;
+
+/// A labeled point in time, in seconds since the start of monitoring.
+pub type Marker = (f64, String);
+
+/// Long log lines would run off the chart.
+const MAX_LABEL_CHARS: usize = 60;
+
+/// Collects markers from a marker file/FIFO and from monitored command output,
+/// timestamping each as it arrives.
+pub struct MarkerSource {
+    start: Instant,
+    sender: Sender<Marker>,
+    receiver: Receiver<Marker>,
+    created_fifo: Option<PathBuf>,
+}
+
+impl MarkerSource {
+    pub fn new(start: Instant) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            start,
+            sender,
+            receiver,
+            created_fifo: None,
+        }
+    }
+
+    /// Start following `path`. A missing path is created as a FIFO (and removed again
+    /// on drop); for an existing regular file only lines appended from now on count.
+    pub fn follow_path(&mut self, path: &str) -> Result<()> {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            let c_path = CString::new(path.as_os_str().as_bytes())
+                .context("Marker path contains a NUL byte")?;
+            // SAFETY: `c_path` is a valid NUL-terminated string
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to create marker FIFO {}", path.display()));
+            }
+            self.created_fifo = Some(path.clone());
+        }
+
+        let is_fifo = fs::metadata(&path)
+            .with_context(|| format!("Failed to stat marker path {}", path.display()))?
+            .file_type()
+            .is_fifo();
+
+        let (start, sender) = (self.start, self.sender.clone());
+        thread::spawn(move || {
+            let _ = if is_fifo {
+                follow_fifo(&path, start, &sender)
+            } else {
+                follow_file(&path, start, &sender)
+            };
+        });
+        Ok(())
+    }
+
+    /// Pass a child's output stream through to our own stdout/stderr, turning lines
+    /// that match `pattern` into markers.
+    pub fn follow_output<R: Read + Send + 'static>(
+        &self,
+        stream: R,
+        to_stderr: bool,
+        pattern: Option<Regex>,
+    ) {
+        let (start, sender) = (self.start, self.sender.clone());
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                logging::clear_progress();
+                if to_stderr {
+                    eprintln!("{}", line);
+                } else {
+                    println!("{}", line);
+                }
+                if pattern.as_ref().is_some_and(|p| p.is_match(&line)) {
+                    let label: String = line.trim().chars().take(MAX_LABEL_CHARS).collect();
+                    let _ = send(label, start, &sender);
+                }
+            }
+        });
+    }
+
+    /// Markers received since the last call.
+    pub fn drain(&self) -> Vec<Marker> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for MarkerSource {
+    fn drop(&mut self) {
+        if let Some(path) = &self.created_fifo {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Each writer session ends with EOF, so reopen (blocking until the next writer).
+fn follow_fifo(path: &Path, start: Instant, sender: &Sender<Marker>) -> Result<()> {
+    loop {
+        let reader = BufReader::new(File::open(path)?);
+        for line in reader.lines() {
+            send(line?, start, sender)?;
+        }
+    }
+}
+
+/// Tail a regular file, polling for appended lines.
+fn follow_file(path: &Path, start: Instant, sender: &Sender<Marker>) -> Result<()> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::End(0))?;
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    loop {
+        if reader.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+            // Partial lines stay buffered until the writer finishes them
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        send(std::mem::take(&mut line), start, sender)?;
+    }
+}
+
+fn send(line: String, start: Instant, sender: &Sender<Marker>) -> Result<()> {
+    let label = line.trim();
+    if !label.is_empty() {
+        sender.send((start.elapsed().as_secs_f64(), label.to_string()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::ci_report::Check;
+use crate::metadata::RunMetadata;
+use crate::spikes::Spike;
+use crate::stats::MemoryStats;
+use crate::units::Unit;
+
+/// Everything that goes into the Markdown summary of a run.
+pub struct MarkdownReport<'a> {
+    pub stats: &'a MemoryStats,
+    pub metadata: &'a RunMetadata,
+    pub duration: f64,
+    pub unit: Unit,
+    pub checks: &'a [Check],
+    pub spikes: &'a [Spike],
+    pub warnings: &'a [String],
+    /// Chart image to link, when one was rendered
+    pub chart: Option<&'a str>,
+}
+
+impl MarkdownReport<'_> {
+    /// Write the report to `path`: run description, warnings, chart, check verdicts,
+    /// statistics, spikes and metadata, in GitHub-flavored Markdown that renders in PR
+    /// comments and wiki pages.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let stats = self.stats;
+        let show = |kb: f64| self.unit.format(kb);
+        let mut md = String::from("# Memory usage report\n\n");
+        md.push_str(&format!(
+            "`{}` (pid {}) on {}, started {}, monitored for {:.1}s every {} ms.\n\n",
+            self.metadata.command_line.replace('`', "'"),
+            self.metadata.pid,
+            self.metadata.host,
+            self.metadata.started.format("%Y-%m-%d %H:%M:%S %Z"),
+            self.duration,
+            self.metadata.interval_ms
+        ));
+        for warning in self.warnings {
+            md.push_str(&format!("> **Warning:** {}\n\n", warning));
+        }
+        if let Some(chart) = self.chart {
+            md.push_str(&format!("![Memory usage chart]({})\n\n", link(path, chart)));
+        }
+
+        if !self.checks.is_empty() {
+            md.push_str("## Checks\n\n| Check | Result | Details |\n|---|---|---|\n");
+            for check in self.checks {
+                let (result, details) = match &check.failure {
+                    Some(failure) => ("❌ failed", failure),
+                    None => ("✅ passed", &check.output),
+                };
+                md.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    cell(&check.name),
+                    result,
+                    cell(details)
+                ));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("## Statistics\n\n| Statistic | Value |\n|---|---:|\n");
+        let mut rows = vec![
+            ("Samples", stats.sample_count().to_string()),
+            ("Peak", show(stats.max() as f64)),
+            ("Mean", show(stats.mean())),
+            ("Median", show(stats.median())),
+            ("Min", show(stats.min() as f64)),
+            ("p90", show(stats.percentile(90.0))),
+            ("p95", show(stats.percentile(95.0))),
+            ("p99", show(stats.percentile(99.0))),
+            ("Std dev", show(stats.std_dev())),
+            ("Time-weighted mean", show(stats.time_weighted_mean())),
+        ];
+        if let Some(fit) = stats.linear_fit(f64::NEG_INFINITY) {
+            rows.push((
+                "Growth rate",
+                format!(
+                    "{:.2} MB/hour (R² = {:.3})",
+                    fit.mb_per_hour(),
+                    fit.r_squared
+                ),
+            ));
+        }
+        if !stats.swap_samples.is_empty() {
+            rows.push(("Max swap", show(stats.max_swap() as f64)));
+        }
+        for (name, value) in rows {
+            md.push_str(&format!("| {} | {} |\n", name, value));
+        }
+        md.push('\n');
+
+        if !self.spikes.is_empty() {
+            md.push_str(
+                "## Spikes\n\n| Time (s) | Peak | Above baseline | Duration (s) | z-score |\n",
+            );
+            md.push_str("|---:|---:|---:|---:|---:|\n");
+            for spike in self.spikes {
+                md.push_str(&format!(
+                    "| {:.1} | {} | {} | {:.1} | {:.1} |\n",
+                    spike.time,
+                    show(spike.peak_kb as f64),
+                    show(spike.magnitude_kb()),
+                    spike.duration,
+                    spike.z_score
+                ));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("<details><summary>Run metadata</summary>\n\n| Key | Value |\n|---|---|\n");
+        for (key, value) in self.metadata.fields() {
+            let value = match value {
+                serde_json::Value::String(text) => text.clone(),
+                serde_json::Value::Null => "-".to_string(),
+                other => other.to_string(),
+            };
+            md.push_str(&format!("| {} | {} |\n", key, cell(&value)));
+        }
+        md.push_str("\n</details>\n");
+
+        fs::write(path, md).with_context(|| format!("Failed to write Markdown report: {}", path))
+    }
+}
+
+/// Make `text` safe inside a table cell, where a pipe would start a new column.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Path of `target` as seen from the directory of the report at `report`, so the
+/// image link keeps working when both are committed or uploaded together.
+fn link(report: &str, target: &str) -> String {
+    let dir = Path::new(report).parent().unwrap_or(Path::new(""));
+    let (Ok(dir), Ok(target_abs)) = (
+        fs::canonicalize(if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        }),
+        fs::canonicalize(target),
+    ) else {
+        return target.to_string();
+    };
+    let common = dir
+        .components()
+        .zip(target_abs.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative: PathBuf = dir
+        .components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .collect();
+    relative.extend(target_abs.components().skip(common));
+    relative.to_string_lossy().replace(' ', "%20")
+}
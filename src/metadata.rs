@@ -0,0 +1,174 @@
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::sync::OnceLock;
+
+use crate::{cgroup, hostname};
+
+/// Longest command line shown in the chart subtitle before it is cut short.
+const SUBTITLE_COMMAND_CHARS: usize = 60;
+
+/// Options whose values are credentials or carry them in the URL, written as `***`.
+const SECRET_OPTIONS: [&str; 4] = [
+    "--slack-webhook",
+    "--discord-webhook",
+    "--webhook-url",
+    "--influx-token",
+];
+
+/// The tracker's arguments after the config file was expanded into them.
+static INVOCATION: OnceLock<String> = OnceLock::new();
+
+/// Remember the tracker's full argument list, options from `--config` and `--profile`
+/// included, for the metadata of every output. Secret values are redacted.
+pub fn record_invocation(args: &[OsString]) {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    // The monitored command after `--` is recorded as given
+    let options_end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let mut redacted = args.clone();
+    for (idx, arg) in args[..options_end].iter().enumerate() {
+        for option in SECRET_OPTIONS {
+            if arg == option && idx + 1 < options_end {
+                redacted[idx + 1] = "***".to_string();
+            } else if arg
+                .strip_prefix(option)
+                .is_some_and(|rest| rest.starts_with('='))
+            {
+                redacted[idx] = format!("{}=***", option);
+            }
+        }
+    }
+    let _ = INVOCATION.set(redacted.join(" "));
+}
+
+/// What was measured, where and how: stored with every output so a file can be
+/// identified long after the run.
+#[derive(Debug, Clone)]
+pub struct RunMetadata {
+    pub pid: u32,
+    pub command_line: String,
+    /// Resolved executable of the target, when it could still be read
+    pub exe: Option<String>,
+    pub started: chrono::DateTime<chrono::Local>,
+    pub host: String,
+    pub kernel: Option<String>,
+    pub cgroup_limit_kb: Option<u64>,
+    pub run_id: String,
+    pub interval_ms: u64,
+    /// The tracker's own command line with the config file's options expanded and
+    /// secrets redacted
+    pub tracker_args: String,
+}
+
+impl RunMetadata {
+    pub fn collect(
+        pid: u32,
+        command_line: &str,
+        started: chrono::DateTime<chrono::Local>,
+        run_id: &str,
+        interval_ms: u64,
+    ) -> Self {
+        Self {
+            pid,
+            command_line: command_line.to_string(),
+            exe: fs::read_link(format!("/proc/{}/exe", pid))
+                .ok()
+                .map(|path| path.display().to_string()),
+            started,
+            host: hostname(),
+            kernel: fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|release| release.trim().to_string()),
+            cgroup_limit_kb: cgroup::MemoryCgroup::of_pid(pid).and_then(|cg| cg.limit_kb()),
+            run_id: run_id.to_string(),
+            interval_ms,
+            tracker_args: INVOCATION.get().cloned().unwrap_or_default(),
+        }
+    }
+
+    /// The fields as `(key, value)` pairs, in the order they are written.
+    pub fn fields(&self) -> Vec<(&'static str, Value)> {
+        vec![
+            ("pid", json!(self.pid)),
+            ("command_line", json!(self.command_line)),
+            ("exe", json!(self.exe)),
+            ("host", json!(self.host)),
+            ("kernel", json!(self.kernel)),
+            ("cgroup_limit_kb", json!(self.cgroup_limit_kb)),
+            ("run_id", json!(self.run_id)),
+            ("started", json!(self.started.to_rfc3339())),
+            ("interval_ms", json!(self.interval_ms)),
+            ("tracker_version", json!(env!("CARGO_PKG_VERSION"))),
+            ("tracker_args", json!(self.tracker_args)),
+        ]
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::Object(
+            self.fields()
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// `key: value` lines for the comment block at the top of a CSV recording. Unknown
+    /// values are left out.
+    pub fn csv_comments(&self) -> Vec<String> {
+        self.fields()
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(text) => text,
+                    other => other.to_string(),
+                };
+                format!("{}: {}", key, value.replace(['\r', '\n'], " "))
+            })
+            .collect()
+    }
+
+    /// Name and pid of the target, heading the chart's stats box.
+    pub fn target(&self) -> String {
+        target(&self.command_line, self.pid)
+    }
+
+    /// One line identifying the run under the chart title.
+    pub fn subtitle(&self) -> String {
+        let command = if self.command_line.chars().count() > SUBTITLE_COMMAND_CHARS {
+            let cut: String = self
+                .command_line
+                .chars()
+                .take(SUBTITLE_COMMAND_CHARS - 3)
+                .collect();
+            format!("{}...", cut)
+        } else {
+            self.command_line.clone()
+        };
+        format!(
+            "{} (pid {}) on {}, started {}, every {} ms",
+            command,
+            self.pid,
+            self.host,
+            self.started.format("%Y-%m-%d %H:%M:%S %Z"),
+            self.interval_ms
+        )
+    }
+}
+
+/// `name (pid N)`, with the program name taken from the command line without its path.
+pub fn target(command_line: &str, pid: u32) -> String {
+    let program = command_line.split_whitespace().next().unwrap_or_default();
+    let name = program.rsplit('/').next().unwrap_or(program);
+    if name.is_empty() {
+        format!("pid {}", pid)
+    } else {
+        format!("{} (pid {})", name, pid)
+    }
+}
@@ -0,0 +1,725 @@
+//! Metrics read at every sample next to the RSS, from `--collect` and `--collector-exec`,
+//! and the [`Collector`] trait the tracker's own readers implement too. A build of the
+//! tracker adds collectors of its own with [`Registry::register`] and passes the registry
+//! to [`crate::run_cli`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::json;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::cgroup::MemoryCgroup;
+use crate::chart;
+use crate::read_memory_usage;
+use crate::stats::{BoundedSeries, MemoryStats, SeriesLimit};
+use crate::units::{self, Unit};
+
+/// How long a `--collector-exec` command may take before its reading is given up.
+const EXEC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// System-wide pressure stall information, used when the cgroup has none of its own.
+const SYSTEM_PRESSURE: &str = "/proc/pressure/memory";
+
+/// What a collected value measures.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricUnit {
+    /// A memory size in KB, charted next to RSS
+    Kb,
+    /// Anything else, e.g. `%`, charted on an axis of its own with --output-dir
+    Other(String),
+}
+
+/// One value read by a collector.
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    pub unit: MetricUnit,
+}
+
+impl Metric {
+    pub fn kb(name: &str, kb: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            value: kb as f64,
+            unit: MetricUnit::Kb,
+        }
+    }
+
+    pub fn other(name: &str, value: f64, unit: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value,
+            unit: MetricUnit::Other(unit.to_string()),
+        }
+    }
+}
+
+/// A source of metrics read at every sample. Besides the `--collect` and
+/// `--collector-exec` collectors, the RSS sources (`/proc/<pid>/status`, statm, a unit's
+/// cgroup, a process tree), the cgroup headroom, `--track-mapping`, `--per-thread` and
+/// `--jemalloc-stats` are read through it.
+pub trait Collector: Send {
+    /// The current values for the process `pid`, sampled `time` seconds into the run.
+    fn collect(&mut self, pid: u32, time: f64) -> Result<Vec<Metric>>;
+
+    /// Whether a reading can take long enough to hold up the sample, e.g. because it runs
+    /// a command. Slow collectors are read on a thread of their own and the sample waits
+    /// for them no longer than `--collector-budget`.
+    fn is_slow(&self) -> bool {
+        false
+    }
+}
+
+/// Creates a collector for the process being recorded.
+pub type Factory = fn(pid: u32) -> Result<Box<dyn Collector>>;
+
+/// The collectors `--collect` can name.
+pub struct Registry {
+    /// (name, description, factory)
+    entries: Vec<(&'static str, &'static str, Factory)>,
+}
+
+impl Registry {
+    /// The collectors built into the tracker.
+    pub fn builtin() -> Self {
+        let mut registry = Self {
+            entries: Vec::new(),
+        };
+        registry.register(
+            "procfs",
+            "virtual size and data segment (VmSize, VmData) from /proc/<pid>/status",
+            |_| Ok(Box::new(Procfs)),
+        );
+        registry.register(
+            "smaps",
+            "proportional and unique set size (PSS, USS) from /proc/<pid>/smaps_rollup",
+            |_| Ok(Box::new(Smaps)),
+        );
+        registry.register(
+            "cgroup",
+            "memory charged to the process's cgroup, page cache included",
+            CgroupUsage::create,
+        );
+        registry.register(
+            "psi",
+            "memory pressure stall of the process's cgroup, or of the system (avg10)",
+            Pressure::create,
+        );
+        #[cfg(feature = "nvml")]
+        registry.register(
+            "nvml",
+            "GPU memory used by the process on each NVIDIA GPU, from NVML",
+            Nvml::create,
+        );
+        registry
+    }
+
+    /// Add a collector under `name`, replacing any registered under the same name.
+    pub fn register(&mut self, name: &'static str, description: &'static str, factory: Factory) {
+        self.entries.retain(|(existing, _, _)| *existing != name);
+        self.entries.push((name, description, factory));
+    }
+
+    /// Create the collector called `name` for `pid`.
+    pub fn create(&self, name: &str, pid: u32) -> Result<Box<dyn Collector>> {
+        match self
+            .entries
+            .iter()
+            .find(|(existing, _, _)| *existing == name)
+        {
+            Some((_, _, factory)) => {
+                factory(pid).with_context(|| format!("Cannot collect {}", name))
+            }
+            None => {
+                let available: Vec<_> = self
+                    .entries
+                    .iter()
+                    .map(|(name, description, _)| format!("  {:<8} {}", name, description))
+                    .collect();
+                bail!(
+                    "Unknown collector '{}'; available:\n{}",
+                    name,
+                    available.join("\n")
+                )
+            }
+        }
+    }
+}
+
+/// `name: 1234 kB` fields of a /proc file in KB.
+fn kb_field(text: &str, name: &str) -> Option<u64> {
+    text.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix(':')?;
+        value.trim().trim_end_matches("kB").trim().parse().ok()
+    })
+}
+
+/// The RSS and the rest of the reading from `/proc/<pid>/status` (sysctl on the BSDs), the
+/// memory source when no other applies.
+pub(crate) struct Status;
+
+impl Collector for Status {
+    fn collect(&mut self, pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        Ok(read_memory_usage(pid)?.to_metrics())
+    }
+}
+
+struct Procfs;
+
+impl Collector for Procfs {
+    fn collect(&mut self, pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid))
+            .with_context(|| format!("Failed to read /proc/{}/status", pid))?;
+        Ok(["VmSize", "VmData"]
+            .into_iter()
+            .filter_map(|name| Some(Metric::kb(name, kb_field(&status, name)?)))
+            .collect())
+    }
+}
+
+struct Smaps;
+
+impl Collector for Smaps {
+    fn collect(&mut self, pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        let rollup = fs::read_to_string(format!("/proc/{}/smaps_rollup", pid))
+            .with_context(|| format!("Failed to read /proc/{}/smaps_rollup", pid))?;
+        let pss = kb_field(&rollup, "Pss").context("smaps_rollup has no Pss")?;
+        let uss = kb_field(&rollup, "Private_Clean").unwrap_or(0)
+            + kb_field(&rollup, "Private_Dirty").unwrap_or(0);
+        Ok(vec![Metric::kb("PSS", pss), Metric::kb("USS", uss)])
+    }
+}
+
+struct CgroupUsage(MemoryCgroup);
+
+impl CgroupUsage {
+    fn create(pid: u32) -> Result<Box<dyn Collector>> {
+        let cgroup = MemoryCgroup::of_pid(pid)
+            .with_context(|| format!("Process {} has no memory cgroup", pid))?;
+        Ok(Box::new(Self(cgroup)))
+    }
+}
+
+impl Collector for CgroupUsage {
+    fn collect(&mut self, _pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        let usage_kb = self
+            .0
+            .usage_kb()
+            .context("Failed to read the cgroup's memory usage")?;
+        Ok(vec![Metric::kb("Cgroup usage", usage_kb)])
+    }
+}
+
+/// Share of time tasks stalled waiting for memory, over the last 10 seconds.
+struct Pressure(Option<MemoryCgroup>);
+
+impl Pressure {
+    fn create(pid: u32) -> Result<Box<dyn Collector>> {
+        let cgroup = MemoryCgroup::of_pid(pid).filter(|cgroup| cgroup.pressure().is_some());
+        if cgroup.is_none() && !Path::new(SYSTEM_PRESSURE).exists() {
+            bail!("No memory pressure information; PSI needs Linux 4.20 or later built with CONFIG_PSI");
+        }
+        Ok(Box::new(Self(cgroup)))
+    }
+}
+
+impl Collector for Pressure {
+    fn collect(&mut self, _pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        let text = match &self.0 {
+            Some(cgroup) => cgroup
+                .pressure()
+                .context("Failed to read the cgroup's memory.pressure")?,
+            None => fs::read_to_string(SYSTEM_PRESSURE)
+                .with_context(|| format!("Failed to read {}", SYSTEM_PRESSURE))?,
+        };
+        // e.g. `some avg10=0.12 avg60=0.05 avg300=0.01 total=123456`
+        Ok(text
+            .lines()
+            .filter_map(|line| {
+                let (kind, rest) = line.split_once(' ')?;
+                let avg10 = rest
+                    .split_whitespace()
+                    .find_map(|field| field.strip_prefix("avg10="))?;
+                Some(Metric::other(
+                    &format!("Memory pressure ({})", kind),
+                    avg10.parse().ok()?,
+                    "%",
+                ))
+            })
+            .collect())
+    }
+}
+
+/// Memory the process holds on each NVIDIA GPU, as the driver accounts it.
+#[cfg(feature = "nvml")]
+struct Nvml(nvml_wrapper::Nvml);
+
+#[cfg(feature = "nvml")]
+impl Nvml {
+    fn create(_pid: u32) -> Result<Box<dyn Collector>> {
+        let nvml = nvml_wrapper::Nvml::init()
+            .context("Failed to load NVML; is the NVIDIA driver installed?")?;
+        Ok(Box::new(Self(nvml)))
+    }
+}
+
+#[cfg(feature = "nvml")]
+impl Collector for Nvml {
+    fn collect(&mut self, pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        use nvml_wrapper::enums::device::UsedGpuMemory;
+        use nvml_wrapper::error::NvmlError;
+
+        let count = self.0.device_count().context("Failed to count the GPUs")?;
+        let mut metrics = Vec::new();
+        for index in 0..count {
+            let device = self
+                .0
+                .device_by_index(index)
+                .with_context(|| format!("Failed to open GPU {}", index))?;
+            // A process can be listed as both; each entry is its whole use of the GPU
+            let mut used_bytes = None;
+            for processes in [
+                device.running_compute_processes(),
+                device.running_graphics_processes(),
+            ] {
+                let processes = match processes {
+                    Ok(processes) => processes,
+                    Err(NvmlError::NotSupported) => continue,
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!("Failed to list the processes on GPU {}", index)
+                        })
+                    }
+                };
+                for process in processes.iter().filter(|process| process.pid == pid) {
+                    if let UsedGpuMemory::Used(bytes) = process.used_gpu_memory {
+                        used_bytes = used_bytes.max(Some(bytes));
+                    }
+                }
+            }
+            if let Some(bytes) = used_bytes {
+                metrics.push(Metric::kb(&format!("GPU {} memory", index), bytes / 1024));
+            }
+        }
+        Ok(metrics)
+    }
+
+    fn is_slow(&self) -> bool {
+        // NVML calls go through the driver and can block while the GPU is busy
+        true
+    }
+}
+
+/// Values printed by a `--collector-exec` command, one `NAME VALUE [UNIT]` per line.
+struct Exec {
+    command: String,
+}
+
+impl Collector for Exec {
+    fn collect(&mut self, pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        let command = self.command.replace("{pid}", &pid.to_string());
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run `{}`", command))?;
+        // Read on a thread so a command printing more than a pipe holds cannot stall
+        let mut stdout = child.stdout.take().context("The command has no stdout")?;
+        let reader = std::thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+        let deadline = Instant::now() + EXEC_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("`{}` took longer than {}s", command, EXEC_TIMEOUT.as_secs());
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        if !status.success() {
+            bail!("`{}` failed with {}", command, status);
+        }
+        let output = reader
+            .join()
+            .map_err(|_| anyhow!("Failed to read the output of `{}`", command))??;
+        output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                parse_metric(line).map_err(|e| anyhow!("`{}` printed '{}': {}", command, line, e))
+            })
+            .collect()
+    }
+
+    fn is_slow(&self) -> bool {
+        true
+    }
+}
+
+/// `NAME VALUE [UNIT]`: a memory size when VALUE and UNIT read as one (`rss 12MB`,
+/// `cache 3 GiB`, a bare number is KB), otherwise a number in UNIT (`load 0.5 %`).
+fn parse_metric(line: &str) -> Result<Metric, String> {
+    let mut fields = line.split_whitespace();
+    let (Some(name), Some(value)) = (fields.next(), fields.next()) else {
+        return Err("expected NAME VALUE [UNIT]".to_string());
+    };
+    let unit = fields.next();
+    match (
+        units::parse_memory_kb(&format!("{}{}", value, unit.unwrap_or(""))),
+        unit,
+    ) {
+        (Ok(kb), _) => Ok(Metric::kb(name, kb)),
+        (Err(_), Some(unit)) => value
+            .parse()
+            .map(|value| Metric::other(name, value, unit))
+            .map_err(|_| format!("invalid value '{}'", value)),
+        (Err(e), None) => Err(e),
+    }
+}
+
+/// A recorded metric and its samples. The points are bounded like the samples of the
+/// stats; the summary covers every reading.
+struct Series {
+    name: String,
+    unit: MetricUnit,
+    points: BoundedSeries<(f64, f64)>,
+    min: f64,
+    max: f64,
+    sum: f64,
+    last: f64,
+}
+
+impl Series {
+    fn new(name: String, unit: MetricUnit, limit: SeriesLimit) -> Self {
+        Self {
+            name,
+            unit,
+            points: BoundedSeries::new(limit),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            last: 0.0,
+        }
+    }
+
+    fn push(&mut self, time: f64, value: f64) {
+        self.points.push((time, value));
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+        self.last = value;
+    }
+
+    /// (min, mean, max, last)
+    fn summary(&self) -> (f64, f64, f64, f64) {
+        let mean = self.sum / self.points.count().max(1) as f64;
+        (self.min, mean, self.max, self.last)
+    }
+}
+
+/// A slow collector read on a thread of its own: the sampling loop sends the time of a
+/// sample and takes the reading back once it is ready.
+struct Background {
+    requests: mpsc::Sender<f64>,
+    results: mpsc::Receiver<(f64, Result<Vec<Metric>>)>,
+    /// Whether a requested reading has not come back yet
+    pending: bool,
+}
+
+impl Background {
+    fn start(mut collector: Box<dyn Collector>, pid: u32) -> Result<Self> {
+        let (requests, requested) = mpsc::channel::<f64>();
+        let (read, results) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("collector".to_string())
+            .spawn(move || {
+                for time in requested {
+                    if read.send((time, collector.collect(pid, time))).is_err() {
+                        break;
+                    }
+                }
+            })
+            .context("Failed to start a collector thread")?;
+        Ok(Self {
+            requests,
+            results,
+            pending: false,
+        })
+    }
+
+    /// Ask for a reading at `time`; false when the thread is gone.
+    fn request(&mut self, time: f64) -> bool {
+        self.pending = self.requests.send(time).is_ok();
+        self.pending
+    }
+
+    /// The pending reading and the time it was requested at, waiting until `deadline`.
+    fn take(&mut self, deadline: Instant) -> Result<(f64, Result<Vec<Metric>>), RecvTimeoutError> {
+        let result = self
+            .results
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        self.pending = matches!(result, Err(RecvTimeoutError::Timeout));
+        result
+    }
+}
+
+enum Reader {
+    /// Read on the sampling thread
+    Inline(Box<dyn Collector>),
+    Background(Background),
+}
+
+/// One collector of the run.
+struct Entry {
+    name: String,
+    reader: Reader,
+    /// Whether its last reading failed
+    failing: bool,
+    /// Whether it was warned about for outlasting the budget
+    overran: bool,
+}
+
+impl Entry {
+    /// Record a reading taken at `time`; memory sizes also become extra series of `stats`
+    /// to chart with the RSS.
+    fn record(
+        &mut self,
+        time: f64,
+        reading: Result<Vec<Metric>>,
+        series: &mut Vec<Series>,
+        limit: SeriesLimit,
+        stats: &mut MemoryStats,
+    ) {
+        let metrics = match reading {
+            Ok(metrics) => metrics,
+            // Warn once per run of failures, not at every sample
+            Err(e) if !self.failing => {
+                warn!("Collector {} failed: {:#}", self.name, e);
+                self.failing = true;
+                return;
+            }
+            Err(e) => {
+                debug!("Collector {} failed: {:#}", self.name, e);
+                return;
+            }
+        };
+        self.failing = false;
+        for metric in metrics {
+            if metric.unit == MetricUnit::Kb {
+                stats.add_extra_sample(&metric.name, time, metric.value as u64);
+            }
+            match series
+                .iter_mut()
+                .find(|s| s.name == metric.name && s.unit == metric.unit)
+            {
+                Some(series) => series.push(time, metric.value),
+                None => {
+                    let mut new = Series::new(metric.name, metric.unit, limit);
+                    new.push(time, metric.value);
+                    series.push(new);
+                }
+            }
+        }
+    }
+}
+
+/// The collectors of a run, from `--collect` and `--collector-exec`, and what they read.
+#[derive(Default)]
+pub(crate) struct CollectorSet {
+    entries: Vec<Entry>,
+    series: Vec<Series>,
+    /// --max-samples and --retention, for the series
+    limit: SeriesLimit,
+    /// How long a sample waits for the slow collectors
+    budget: Duration,
+}
+
+impl CollectorSet {
+    pub fn new(
+        registry: &Registry,
+        names: &[String],
+        commands: &[String],
+        pid: u32,
+        budget: Duration,
+        limit: SeriesLimit,
+    ) -> Result<Self> {
+        let mut collectors = Vec::new();
+        for name in names {
+            collectors.push((name.clone(), registry.create(name, pid)?));
+        }
+        for command in commands {
+            let collector: Box<dyn Collector> = Box::new(Exec {
+                command: command.clone(),
+            });
+            collectors.push((format!("`{}`", command), collector));
+        }
+        let mut set = Self {
+            budget,
+            limit,
+            ..Default::default()
+        };
+        for (name, collector) in collectors {
+            let reader = if collector.is_slow() {
+                Reader::Background(Background::start(collector, pid)?)
+            } else {
+                Reader::Inline(collector)
+            };
+            set.entries.push(Entry {
+                name,
+                reader,
+                failing: false,
+                overran: false,
+            });
+        }
+        Ok(set)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Read every collector at `time`. The slow ones are started first and waited for
+    /// until the budget is spent; a reading that comes later is recorded at the time it
+    /// was requested, and none is requested again before it is in.
+    pub fn on_sample(&mut self, pid: u32, time: f64, stats: &mut MemoryStats) {
+        let deadline = Instant::now() + self.budget;
+        let Self {
+            entries,
+            series,
+            limit,
+            budget,
+        } = self;
+        let limit = *limit;
+        for entry in entries.iter_mut() {
+            if let Reader::Background(background) = &mut entry.reader {
+                if !background.pending && !background.request(time) {
+                    entry.record(
+                        time,
+                        Err(anyhow!("Its thread has stopped")),
+                        series,
+                        limit,
+                        stats,
+                    );
+                }
+            }
+        }
+        for entry in entries.iter_mut() {
+            if let Reader::Inline(collector) = &mut entry.reader {
+                let reading = collector.collect(pid, time);
+                entry.record(time, reading, series, limit, stats);
+            }
+        }
+        for entry in entries.iter_mut() {
+            let Reader::Background(background) = &mut entry.reader else {
+                continue;
+            };
+            if !background.pending {
+                continue;
+            }
+            match background.take(deadline) {
+                Ok((requested, reading)) => entry.record(requested, reading, series, limit, stats),
+                Err(RecvTimeoutError::Timeout) if !entry.overran => {
+                    warn!(
+                        "Collector {} takes longer than the {} ms budget; its readings are recorded when they arrive",
+                        entry.name,
+                        budget.as_millis()
+                    );
+                    entry.overran = true;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    debug!("Collector {} is still reading", entry.name)
+                }
+                Err(RecvTimeoutError::Disconnected) => entry.record(
+                    time,
+                    Err(anyhow!("Its thread has stopped")),
+                    series,
+                    limit,
+                    stats,
+                ),
+            }
+        }
+    }
+
+    /// Panels of the metrics that are not memory sizes, for --output-dir; the sizes are
+    /// already among the extra series of the stats.
+    pub fn panels(&self) -> Vec<chart::Panel> {
+        self.series
+            .iter()
+            .filter_map(|series| match &series.unit {
+                MetricUnit::Kb => None,
+                MetricUnit::Other(unit) => Some(chart::Panel {
+                    name: series.name.clone(),
+                    y_desc: format!("{} ({})", series.name, unit),
+                    points: series.points.to_vec(),
+                    thresholds: Vec::new(),
+                }),
+            })
+            .collect()
+    }
+
+    /// Print the range of every collected metric.
+    pub fn print(&self, units: Option<Unit>) {
+        if self.series.is_empty() {
+            return;
+        }
+        println!("\nCollected metrics:");
+        println!(
+            "  {:<32} {:>6} {:>12} {:>12} {:>12} {:>12}",
+            "Metric", "Unit", "Min", "Mean", "Max", "Last"
+        );
+        for series in &self.series {
+            let (min, mean, max, last) = series.summary();
+            let (label, scale) = match &series.unit {
+                MetricUnit::Kb => {
+                    let unit = units.unwrap_or_default().resolve(max);
+                    (unit.label().to_string(), unit.kb())
+                }
+                MetricUnit::Other(unit) => (unit.clone(), 1.0),
+            };
+            let name: String = series.name.chars().take(32).collect();
+            println!(
+                "  {:<32} {:>6} {:>12.2} {:>12.2} {:>12.2} {:>12.2}",
+                name,
+                label,
+                min / scale,
+                mean / scale,
+                max / scale,
+                last / scale
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.series
+            .iter()
+            .map(|series| {
+                let (min, mean, max, last) = series.summary();
+                json!({
+                    "name": series.name,
+                    "unit": match &series.unit {
+                        MetricUnit::Kb => "KB",
+                        MetricUnit::Other(unit) => unit,
+                    },
+                    "samples": series.points.count(),
+                    "min": min,
+                    "mean": mean,
+                    "max": max,
+                    "last": last,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::MemoryReading;
+
+/// Pushes samples as OTLP/HTTP (JSON encoding) gauge metrics to a collector.
+pub struct OtelExporter {
+    url: String,
+    resource: Value,
+    rss: Vec<Value>,
+    swap: Vec<Value>,
+    hwm: Vec<Value>,
+}
+
+impl OtelExporter {
+    pub fn new(endpoint: &str, pid: u32, cmdline: &str, host: &str) -> Self {
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", json!({ "stringValue": "memory_tracker" })),
+                attribute("process.pid", json!({ "intValue": pid.to_string() })),
+                attribute("process.command_line", json!({ "stringValue": cmdline })),
+                attribute("host.name", json!({ "stringValue": host })),
+            ]
+        });
+
+        Self {
+            url: format!("{}/v1/metrics", endpoint.trim_end_matches('/')),
+            resource,
+            rss: Vec::new(),
+            swap: Vec::new(),
+            hwm: Vec::new(),
+        }
+    }
+
+    pub fn add_sample(&mut self, reading: &MemoryReading) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0)
+            .to_string();
+        let point = |kb: u64| json!({ "timeUnixNano": time, "asInt": (kb * 1024).to_string() });
+
+        self.rss.push(point(reading.rss_kb));
+        self.swap.push(point(reading.swap_kb));
+        self.hwm.push(point(reading.hwm_kb));
+    }
+
+    /// Send all buffered data points in a single export request.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.rss.is_empty() {
+            return Ok(());
+        }
+
+        let gauge = |name: &str, description: &str, points: &mut Vec<Value>| {
+            json!({
+                "name": name,
+                "description": description,
+                "unit": "By",
+                "gauge": { "dataPoints": std::mem::take(points) }
+            })
+        };
+        let metrics = vec![
+            gauge("process.memory.usage", "Resident set size", &mut self.rss),
+            gauge("process.memory.swap", "Swapped-out memory", &mut self.swap),
+            gauge(
+                "process.memory.peak",
+                "Peak resident set size (VmHWM)",
+                &mut self.hwm,
+            ),
+        ];
+
+        let body = json!({
+            "resourceMetrics": [{
+                "resource": self.resource,
+                "scopeMetrics": [{
+                    "scope": { "name": "memory_tracker", "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": metrics
+                }]
+            }]
+        });
+
+        ureq::post(&self.url)
+            .send_json(body)
+            .with_context(|| format!("Failed to export metrics to {}", self.url))?;
+        Ok(())
+    }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
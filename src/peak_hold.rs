@@ -0,0 +1,91 @@
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::units::{self, Unit};
+use crate::MemoryReading;
+
+/// Share by which the high-water mark may exceed the sampled peak before the summary
+/// warns that a spike fell between samples.
+const DIVERGENCE: f64 = 0.10;
+
+/// Name of the `--peak-hold` chart series.
+pub const SERIES: &str = "High-water mark";
+
+/// The kernel's record of the highest RSS (VmHWM, `ru_maxrss` on the BSDs), which also
+/// covers spikes that rise and fall between two samples.
+#[derive(Default)]
+pub struct PeakHold {
+    hwm_kb: u64,
+    /// Mark at the first sample, which predates the run when attaching to a process
+    initial_kb: Option<u64>,
+    /// Times of the samples around the last rise of the mark that no sample caught
+    missed: Option<(f64, f64)>,
+    last_time: Option<f64>,
+}
+
+impl PeakHold {
+    pub fn on_sample(&mut self, time: f64, reading: &MemoryReading) {
+        let previous = self.last_time.replace(time);
+        // The statm fast path has no mark and reports zero
+        if reading.hwm_kb == 0 {
+            return;
+        }
+        if self.initial_kb.is_none() {
+            self.initial_kb = Some(reading.hwm_kb);
+        } else if reading.hwm_kb > self.hwm_kb && reading.hwm_kb > reading.rss_kb {
+            self.missed = Some((previous.unwrap_or(time), time));
+        }
+        self.hwm_kb = self.hwm_kb.max(reading.hwm_kb);
+    }
+
+    /// The highest mark seen, None when the reader does not provide one.
+    pub fn hwm_kb(&self) -> Option<u64> {
+        (self.hwm_kb > 0).then_some(self.hwm_kb)
+    }
+
+    /// Whether the mark never rose during the run, so its peak came before it.
+    fn before_run(&self) -> bool {
+        self.initial_kb == Some(self.hwm_kb)
+    }
+
+    fn diverges(&self, sampled_max_kb: u64) -> bool {
+        self.hwm_kb as f64 > sampled_max_kb as f64 * (1.0 + DIVERGENCE)
+    }
+
+    /// Print the mark under the sampled max and warn when samples missed the peak.
+    pub fn print(&self, sampled_max_kb: u64, units: Option<Unit>) {
+        let Some(hwm_kb) = self.hwm_kb() else {
+            return;
+        };
+        println!(
+            "High-water mark (kernel): {}",
+            units::describe(hwm_kb as f64, units)
+        );
+        if !self.diverges(sampled_max_kb) {
+            return;
+        }
+        if self.before_run() {
+            info!("The high-water mark was already reached when monitoring started");
+            return;
+        }
+        let between = match self.missed {
+            Some((from, to)) => format!("the samples at {:.1}s and {:.1}s", from, to),
+            None => "two samples".to_string(),
+        };
+        warn!(
+            "The process peaked {} above the sampled max between {}; a shorter --interval would show the spike",
+            units::describe((hwm_kb - sampled_max_kb) as f64, units),
+            between
+        );
+    }
+
+    pub fn to_json(&self, sampled_max_kb: u64) -> serde_json::Value {
+        json!({
+            "hwm_kb": self.hwm_kb,
+            "above_sampled_max_kb": self.hwm_kb.saturating_sub(sampled_max_kb),
+            "diverges": self.diverges(sampled_max_kb),
+            "reached_before_run": self.before_run(),
+            "missed_between_s": self.missed.map(|(from, to)| [from, to]),
+        })
+    }
+}
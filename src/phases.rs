@@ -0,0 +1,117 @@
+use serde_json::json;
+
+use crate::stats::MemoryStats;
+use crate::units::Unit;
+
+/// The samples between two consecutive markers.
+pub struct Phase {
+    /// Label of the marker that opened the phase; None for the part before the first marker
+    pub label: Option<String>,
+    pub start: f64,
+    pub end: f64,
+    pub stats: MemoryStats,
+}
+
+impl Phase {
+    pub fn name(&self) -> &str {
+        self.label.as_deref().unwrap_or("(start)")
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "label": self.label,
+            "start_s": self.start,
+            "end_s": self.end,
+            "samples": self.stats.sample_count(),
+            "max_kb": self.stats.max(),
+            "mean_kb": self.stats.mean(),
+            "median_kb": self.stats.median(),
+            "min_kb": self.stats.min(),
+            "p95_kb": self.stats.percentile(95.0),
+            "growth": self.stats.linear_fit(f64::NEG_INFINITY).map(|fit| fit.to_json()),
+        })
+    }
+}
+
+/// Split a run at its markers, leaving out the samples before `from` (the --warmup). A
+/// phase runs from its marker to the next one, the first from the first sample; markers
+/// before `from` still name the phase in progress there. Phases without samples, e.g.
+/// between two markers set within one interval, are left out. A run without markers has
+/// no phases.
+pub fn split(stats: &MemoryStats, from: f64) -> Vec<Phase> {
+    let mut markers: Vec<_> = stats.markers.iter().collect();
+    if markers.is_empty() || stats.samples.is_empty() {
+        return Vec::new();
+    }
+    markers.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut phases = Vec::new();
+    let mut current = Phase {
+        label: None,
+        start: stats.samples[0].0,
+        end: stats.samples[0].0,
+        stats: MemoryStats::new(),
+    };
+    let mut markers = markers.into_iter().peekable();
+    for &(time, kb) in &stats.samples {
+        while let Some((marker_time, label)) = markers.next_if(|(t, _)| *t <= time) {
+            let next = Phase {
+                label: Some(label.clone()),
+                start: *marker_time,
+                end: *marker_time,
+                stats: MemoryStats::new(),
+            };
+            let mut done = std::mem::replace(&mut current, next);
+            if !done.stats.samples.is_empty() {
+                done.end = *marker_time;
+                phases.push(done);
+            }
+        }
+        if time < from {
+            continue;
+        }
+        if current.stats.samples.is_empty() {
+            current.start = current.start.max(from);
+        }
+        current.stats.add_sample(time, kb);
+        current.end = time;
+    }
+    if !current.stats.samples.is_empty() {
+        phases.push(current);
+    }
+    phases
+}
+
+/// Print one line per phase with its peak, mean and growth.
+pub fn print(phases: &[Phase], units: Option<Unit>) {
+    let max_kb = phases.iter().map(|p| p.stats.max()).max().unwrap_or(0);
+    let unit = units.unwrap_or_default().resolve(max_kb as f64);
+    println!("\nPhases (between markers):");
+    println!(
+        "  {:<30} {:>9} {:>9} {:>8} {:>12} {:>12} {:>12}",
+        "Phase",
+        "Start",
+        "End",
+        "Samples",
+        format!("Peak {}", unit.label()),
+        format!("Mean {}", unit.label()),
+        "Growth MB/h"
+    );
+    for phase in phases {
+        let name: String = phase.name().chars().take(30).collect();
+        let growth = phase
+            .stats
+            .linear_fit(f64::NEG_INFINITY)
+            .map_or("-".to_string(), |fit| format!("{:+.2}", fit.mb_per_hour()));
+        println!(
+            "  {:<30} {:>8.1}s {:>8.1}s {:>8} {:>12.2} {:>12.2} {:>12}",
+            name,
+            phase.start,
+            phase.end,
+            phase.stats.sample_count(),
+            phase.stats.max() as f64 / unit.kb(),
+            phase.stats.mean() / unit.kb(),
+            growth
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+/// Host pid of the process numbered `pid` inside the PID namespace `namespace`.
+///
+/// `namespace` is the host pid of any process in that namespace, such as a container's
+/// init (`docker inspect -f '{{.State.Pid}}'`), or the path of a namespace file like
+/// `/proc/<pid>/ns/pid` or a bind mount of one. The processes in the namespace are found
+/// by comparing their `/proc/<pid>/ns/pid` with it, and the last entry of their `NSpid`
+/// status line is their pid as seen from inside.
+pub fn resolve(namespace: &str, pid: u32) -> Result<u32> {
+    let path = match namespace.parse::<u32>() {
+        Ok(member) => format!("/proc/{}/ns/pid", member),
+        Err(_) => namespace.to_string(),
+    };
+    let target =
+        fs::metadata(&path).with_context(|| format!("Failed to open PID namespace {}", path))?;
+    let target = (target.dev(), target.ino());
+
+    let mut members = 0;
+    for entry in fs::read_dir("/proc")? {
+        let Some(host_pid) = entry
+            .ok()
+            .and_then(|e| e.file_name().to_str()?.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // Processes can exit during the scan and others' namespaces may be unreadable
+        let Ok(ns) = fs::metadata(format!("/proc/{}/ns/pid", host_pid)) else {
+            continue;
+        };
+        if (ns.dev(), ns.ino()) != target {
+            continue;
+        }
+        members += 1;
+        let Ok(status) = fs::read_to_string(format!("/proc/{}/status", host_pid)) else {
+            continue;
+        };
+        let Some(nspid) = status.lines().find_map(|line| line.strip_prefix("NSpid:")) else {
+            bail!(
+                "/proc/{}/status has no NSpid line; --pidns needs Linux 4.1 or later",
+                host_pid
+            );
+        };
+        if nspid.split_whitespace().last().and_then(|p| p.parse().ok()) == Some(pid) {
+            return Ok(host_pid);
+        }
+    }
+    if members == 0 {
+        bail!("No process in PID namespace {} is visible from here; is the tracker running on the host?", path);
+    }
+    bail!(
+        "No process with pid {} in PID namespace {} ({} processes checked)",
+        pid,
+        path,
+        members
+    )
+}
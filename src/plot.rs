@@ -0,0 +1,157 @@
+use anyhow::{bail, Result};
+use tracing::info;
+
+use crate::chart;
+use crate::metadata;
+use crate::recording;
+use crate::units;
+
+#[derive(clap::Args)]
+pub struct PlotArgs {
+    /// Recordings to plot (CSV, SQLite or Parquet written with --csv-output)
+    #[arg(required = true, value_name = "CSV")]
+    files: Vec<String>,
+
+    /// Draw all recordings on one chart with a legend entry per run
+    #[arg(long)]
+    overlay: bool,
+
+    /// Stretch each run to 0-100% of its duration so runs of different length line up
+    #[arg(long, requires = "overlay")]
+    normalize_time: bool,
+
+    /// Output chart file path; the format is chosen by extension (.svg for vector, otherwise bitmap)
+    #[arg(short, long, default_value = "memory_usage.png")]
+    output: String,
+
+    #[command(flatten)]
+    chart: chart::ChartArgs,
+
+    /// Use a logarithmic memory axis
+    #[arg(long)]
+    log_scale: bool,
+
+    /// How to reduce long recordings before charting (see the record options)
+    #[arg(long, value_enum, default_value = "auto")]
+    downsample: chart::Downsample,
+
+    /// Target number of points for `--downsample lttb`
+    #[arg(long, default_value = "2000")]
+    downsample_points: usize,
+
+    /// Overlay a moving average over this many samples
+    #[arg(long, value_name = "WINDOW", value_parser = clap::value_parser!(u64).range(2..))]
+    smooth: Option<u64>,
+
+    /// Draw the allocation rate in a second panel below the memory chart
+    #[arg(long, conflicts_with = "overlay")]
+    rate_chart: bool,
+
+    /// Number of sample intervals the allocation rate is averaged over
+    #[arg(long, value_name = "N", default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_window: u64,
+
+    /// Unit of the memory axis
+    #[arg(long, value_enum, default_value = "mb")]
+    units: units::Unit,
+
+    /// Draw a labeled horizontal reference line at this memory size, e.g. 2GB (repeatable)
+    #[arg(long = "mark-threshold", value_name = "SIZE")]
+    mark_thresholds: Vec<String>,
+
+    /// Most ticks on the time axis (default: one per ~100 pixels of width)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..))]
+    x_ticks: Option<u32>,
+
+    /// Corner of the chart legend
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POSITION",
+        default_value = "upper-left"
+    )]
+    legend: chart::LegendPosition,
+
+    /// Do not draw the box with peak, mean, p95, duration and the target (single recording)
+    #[arg(long)]
+    no_stats_box: bool,
+
+    #[command(flatten)]
+    range: recording::TimeRange,
+}
+
+/// Render saved recordings without re-running the capture.
+pub fn run(args: &PlotArgs) -> Result<()> {
+    if args.files.len() > 1 && !args.overlay {
+        bail!("pass --overlay to draw several recordings on one chart");
+    }
+    let mut thresholds = Vec::new();
+    for arg in &args.mark_thresholds {
+        let kb =
+            units::parse_memory_kb(arg).map_err(|e| anyhow::anyhow!("--mark-threshold: {}", e))?;
+        thresholds.push((arg.clone(), kb as f64));
+    }
+    let options = chart::ChartOptions {
+        log_scale: args.log_scale,
+        downsample: args.downsample,
+        downsample_points: args.downsample_points,
+        smooth: args.smooth.map(|w| w as usize),
+        rate_window: args.rate_chart.then_some(args.rate_window as usize),
+        unit: args.units,
+        thresholds,
+        legend: args.legend,
+        x_ticks: args.x_ticks,
+        ..args.chart.options()
+    };
+
+    info!("Generating chart: {}", args.output);
+    if args.overlay {
+        let mut runs = Vec::new();
+        for path in &args.files {
+            let stats = args.range.load(path)?;
+            let stats = if args.normalize_time {
+                recording::normalized(stats)
+            } else {
+                recording::aligned(stats)
+            };
+            runs.push((path.clone(), stats));
+        }
+        let runs: Vec<_> = runs
+            .iter()
+            .map(|(label, stats)| (label.clone(), stats))
+            .collect();
+        let x_axis = if args.normalize_time {
+            chart::OverlayAxis::Other("Run progress (%)")
+        } else {
+            chart::OverlayAxis::Elapsed("Time")
+        };
+        chart::generate_overlay(&runs, x_axis, &args.output, &options)?;
+    } else {
+        let path = &args.files[0];
+        let stats = args.range.load(path)?;
+        // Recordings carry the target in their metadata comments
+        let comments = recording::comments(path)?;
+        let comment = |key: &str| {
+            comments
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        let target = match (
+            comment("command_line"),
+            comment("pid").and_then(|pid| pid.parse().ok()),
+        ) {
+            (Some(command_line), Some(pid)) => Some(metadata::target(command_line, pid)),
+            _ => None,
+        };
+        let options = chart::ChartOptions {
+            markers: stats.markers.clone(),
+            stats_box: !args.no_stats_box,
+            target,
+            ..options
+        };
+        chart::generate_chart(&stats, &args.output, &options)?;
+    }
+    info!("Chart saved successfully!");
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long a client may take to send its request or accept the response before its
+/// connection is dropped.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Latest values exposed on the `/metrics` endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct Gauges {
+    pub pid: u32,
+    pub rss_kb: u64,
+    pub swap_kb: u64,
+    pub hwm_kb: u64,
+    pub samples: u64,
+}
+
+pub type SharedGauges = Arc<Mutex<Gauges>>;
+
+/// Bind `addr` and serve `/metrics` from a task on the current tokio runtime.
+pub fn serve(addr: &str, gauges: SharedGauges) -> Result<()> {
+    let listener = std::net::TcpListener::bind(addr)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .with_context(|| format!("Failed to bind metrics listener on {}", addr))?;
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let gauges = Arc::clone(&gauges);
+            // A stalled scraper must not hold up the others, nor take down the tracker
+            tokio::spawn(async move {
+                let _ =
+                    tokio::time::timeout(CLIENT_TIMEOUT, handle_connection(stream, &gauges)).await;
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_connection(mut stream: TcpStream, gauges: &SharedGauges) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&mut stream)
+        .read_line(&mut request_line)
+        .await?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, content_type, body) = if path == "/metrics" {
+        let body = render(&gauges.lock().unwrap());
+        ("200 OK", "text/plain; version=0.0.4", body)
+    } else {
+        ("404 Not Found", "text/plain", "Not Found\n".to_string())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+/// Atomically write the gauges in node_exporter textfile-collector format.
+pub fn write_textfile(path: &str, gauges: &Gauges) -> Result<()> {
+    // node_exporter may read at any moment, so never expose a half-written file
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, render(gauges))
+        .with_context(|| format!("Failed to write textfile: {}", tmp_path))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to move {} to {}", tmp_path, path))?;
+    Ok(())
+}
+
+/// Push the gauges to a Prometheus Pushgateway, grouped by job and pid.
+pub fn push(gateway_url: &str, gauges: &Gauges) -> Result<()> {
+    let url = format!(
+        "{}/metrics/job/memory_tracker/pid/{}",
+        gateway_url.trim_end_matches('/'),
+        gauges.pid
+    );
+    ureq::put(&url)
+        .set("Content-Type", "text/plain; version=0.0.4")
+        .send_string(&render(gauges))
+        .with_context(|| format!("Failed to push metrics to {}", url))?;
+    Ok(())
+}
+
+fn render(gauges: &Gauges) -> String {
+    let mut out = String::new();
+    let pid = gauges.pid;
+
+    let mut gauge = |name: &str, help: &str, value_kb: u64| {
+        out.push_str(&format!("# HELP {} {}\n", name, help));
+        out.push_str(&format!("# TYPE {} gauge\n", name));
+        out.push_str(&format!(
+            "{}{{pid=\"{}\"}} {}\n",
+            name,
+            pid,
+            value_kb * 1024
+        ));
+    };
+    gauge(
+        "memory_tracker_rss_bytes",
+        "Resident set size of the monitored process.",
+        gauges.rss_kb,
+    );
+    gauge(
+        "memory_tracker_swap_bytes",
+        "Swapped-out memory of the monitored process.",
+        gauges.swap_kb,
+    );
+    gauge(
+        "memory_tracker_hwm_bytes",
+        "Peak resident set size (VmHWM) of the monitored process.",
+        gauges.hwm_kb,
+    );
+
+    out.push_str("# HELP memory_tracker_samples_total Number of samples collected so far.\n");
+    out.push_str("# TYPE memory_tracker_samples_total counter\n");
+    out.push_str(&format!(
+        "memory_tracker_samples_total{{pid=\"{}\"}} {}\n",
+        pid, gauges.samples
+    ));
+    out
+}
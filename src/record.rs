@@ -0,0 +1,3198 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, trace, warn};
+
+use crate::csv_writer::{Columns, CsvWriter};
+use crate::metrics_plugins::{self, Collector};
+use crate::stats::{MemoryStats, Retention, SeriesLimit};
+use crate::{
+    agent, alert, alloc_trace, chart, chat, children, ci_report, daemon, exec_watch, gaps,
+    headroom, hostname, html_report, http_api, influx, jemalloc, leak, logging, markers, md_report,
+    metadata, peak_hold, phases, pidns, prometheus, read_cmdline, recording, segments, shutdown,
+    smaps, spikes, statm, statsd, summary, swap, systemd, threads, top, trace, units, wait,
+    MemoryReading, EXIT_LEAK_SUSPECTED, EXIT_MEAN_BUDGET, EXIT_PEAK_BUDGET,
+};
+
+#[cfg(feature = "ebpf")]
+use crate::ebpf;
+#[cfg(feature = "otel")]
+use crate::otel;
+
+/// Options for recording a live process (the `record` and `run` subcommands, and the
+/// legacy top-level invocation).
+#[derive(clap::Args)]
+pub struct RecordArgs {
+    /// TOML file with default values for any of these options; command-line flags override it
+    #[arg(long, value_name = "FILE", env = crate::config::CONFIG_ENV)]
+    config: Option<String>,
+
+    /// Apply the `[profile.NAME]` section of the config file on top of its top-level values
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Process ID to monitor
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["command", "wait_for_name", "unit", "top"],
+        conflicts_with = "command"
+    )]
+    pid: Option<u32>,
+
+    /// Interpret --pid inside this PID namespace, e.g. a container's: the host pid of a
+    /// process in it (such as the container's init) or a namespace file like /proc/<pid>/ns/pid
+    #[arg(long, value_name = "PID|PATH", requires = "pid")]
+    pidns: Option<String>,
+
+    /// Wait for a process with this name (command name, executable or argv[0] file name)
+    /// to appear and monitor it from then on
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["pid", "command"])]
+    wait_for_name: Option<String>,
+
+    /// Monitor all processes of this systemd unit together through its cgroup, e.g. nginx.service
+    #[arg(long, value_name = "UNIT", conflicts_with_all = ["pid", "command", "wait_for_name"])]
+    unit: Option<String>,
+
+    /// Record the process and all its descendants (with --unit, every process of the unit)
+    /// as their sum, and each of them: print which made up the peak and draw them stacked
+    /// in a second chart named after -o (memory_usage-children.png)
+    #[arg(long)]
+    children: bool,
+
+    /// Track the N processes with the highest RSS on the system instead of one process,
+    /// drawing one line per process
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["pid", "command", "wait_for_name", "unit"]
+    )]
+    top: Option<u64>,
+
+    /// How often the --top set is re-evaluated
+    #[arg(long, value_name = "DURATION", default_value = "10", value_parser = units::parse_whole_seconds, requires = "top")]
+    top_refresh: u64,
+
+    /// Give up waiting for --wait-for-name after this long (0 = wait forever)
+    #[arg(long, value_name = "DURATION", default_value = "0", value_parser = units::parse_whole_seconds, requires = "wait_for_name")]
+    wait_timeout: u64,
+
+    /// Command to launch and monitor (run mode); its output is passed through
+    #[arg(last = true, value_name = "COMMAND")]
+    pub command: Vec<String>,
+
+    /// In run mode, turn command output lines matching this regex into chart annotations
+    #[arg(long, value_name = "REGEX", requires = "command")]
+    annotate_regex: Option<regex::Regex>,
+
+    /// Also sample swap usage (VmSwap) and chart it on a secondary axis
+    #[arg(long)]
+    swap: bool,
+
+    /// Draw the kernel's high-water mark of RSS (VmHWM) on the chart, which also catches
+    /// spikes between samples
+    #[arg(long)]
+    peak_hold: bool,
+
+    /// Sampling interval, e.g. 250ms or 5s (a bare number is milliseconds)
+    #[arg(short, long, value_name = "INTERVAL", default_value = "1000", value_parser = units::parse_millis)]
+    interval: u64,
+
+    /// Output chart file path; the format is chosen by extension (.svg for vector, otherwise bitmap)
+    #[arg(short, long, default_value = "memory_usage.png")]
+    output: String,
+
+    /// Skip chart rendering
+    #[arg(long)]
+    no_chart: bool,
+
+    #[command(flatten)]
+    chart: chart::ChartArgs,
+
+    /// Use a logarithmic memory axis
+    #[arg(long)]
+    log_scale: bool,
+
+    /// Chart style; `stacked` breaks RSS down into anonymous, file-backed and shared memory
+    #[arg(long, value_enum, default_value = "line")]
+    chart_style: chart::ChartStyle,
+
+    /// How to reduce long recordings before charting: `auto` draws a per-pixel min/max band
+    /// with a mean line once there are more samples than pixels, `lttb` keeps the most
+    /// visually significant points; the CSV always keeps every sample
+    #[arg(long, value_enum, default_value = "auto")]
+    downsample: chart::Downsample,
+
+    /// Target number of points for `--downsample lttb`
+    #[arg(long, default_value = "2000")]
+    downsample_points: usize,
+
+    /// Overlay a moving average over this many samples and report the smoothed peak
+    #[arg(long, value_name = "WINDOW", value_parser = clap::value_parser!(u64).range(2..))]
+    smooth: Option<u64>,
+
+    /// How live progress is shown: a status line rewritten in place, a progress bar, or
+    /// nothing (default: line on a terminal, none otherwise)
+    #[arg(long, value_enum)]
+    progress: Option<logging::ProgressStyle>,
+
+    /// Unit of memory values on the console, in the CSV and on the chart's y-axis
+    /// (default: KB and MB on the console, KB in the CSV, MiB on the chart)
+    #[arg(long, value_enum)]
+    units: Option<units::Unit>,
+
+    /// Add the allocation rate (KB/s) as a rate_kb_s column to the CSV and report its extremes
+    #[arg(long)]
+    rate: bool,
+
+    /// Draw the allocation rate in a second panel below the memory chart
+    #[arg(long)]
+    rate_chart: bool,
+
+    /// Number of sample intervals the allocation rate is averaged over
+    #[arg(long, value_name = "N", default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    rate_window: u64,
+
+    /// Draw a labeled horizontal reference line at this memory size, e.g. 2GB (repeatable)
+    #[arg(long = "mark-threshold", value_name = "SIZE")]
+    mark_thresholds: Vec<String>,
+
+    /// Do not draw the cgroup/container memory limit on the chart
+    #[arg(long)]
+    no_limit_line: bool,
+
+    /// Chart the room left under the cgroup memory limit (the limit minus the cgroup's usage)
+    #[arg(long)]
+    headroom: bool,
+
+    /// Read extra metrics at every sample from a built-in collector: procfs, smaps, cgroup, psi or nvml (with the nvml feature) (repeatable)
+    #[arg(long = "collect", value_name = "NAME", conflicts_with = "top")]
+    collect: Vec<String>,
+
+    /// Read extra metrics at every sample from the output of this shell command, one `NAME VALUE [UNIT]` per line; {pid} is replaced by the process id (repeatable)
+    #[arg(
+        long = "collector-exec",
+        value_name = "COMMAND",
+        conflicts_with = "top"
+    )]
+    collector_exec: Vec<String>,
+
+    /// How long each sample waits for slow collectors (--collector-exec, nvml), e.g. 200ms (default: half the interval); later readings are recorded when they arrive
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_millis)]
+    collector_budget: Option<u64>,
+
+    /// Corner of the chart legend, shown when there is more than the RSS series
+    #[arg(
+        long,
+        value_enum,
+        value_name = "POSITION",
+        default_value = "upper-left"
+    )]
+    legend: chart::LegendPosition,
+
+    /// Do not draw the box with peak, mean, p95, duration and the target on the chart
+    #[arg(long)]
+    no_stats_box: bool,
+
+    /// Duration to monitor, e.g. 90s or 2h30m (a bare number is seconds; 0 = until process exits)
+    #[arg(short, long, value_name = "DURATION", default_value = "0", value_parser = units::parse_whole_seconds)]
+    duration: u64,
+
+    /// In run mode, time the command gets to exit after a forwarded signal or the end
+    /// of --duration before it is killed
+    #[arg(long, value_name = "DURATION", default_value = "10", value_parser = units::parse_whole_seconds, requires = "command")]
+    grace_period: u64,
+
+    /// Always parse /proc/<pid>/status, even for intervals where the statm fast path applies
+    #[arg(long)]
+    no_fast_path: bool,
+
+    /// Retry a failed sample this many times before giving up on it, as long as the
+    /// process still exists
+    #[arg(long, value_name = "N", default_value = "3")]
+    retry: u32,
+
+    /// Record samples that still fail after --retry as gaps and keep monitoring, instead
+    /// of ending the run
+    #[arg(long)]
+    tolerate_gaps: bool,
+
+    /// Keep at most about N samples in memory so indefinite runs stay bounded
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(2..))]
+    max_samples: Option<u64>,
+
+    /// Which samples to keep once --max-samples is reached
+    #[arg(long, value_enum, default_value = "ring", requires = "max_samples")]
+    retention: Retention,
+
+    /// Optional file path to stream memory data to as CSV (time,memory_kb,marker), as an
+    /// SQLite database when it ends in .sqlite or .db, or as Parquet when it ends in .parquet
+    #[arg(short = 'c', long)]
+    csv_output: Option<String>,
+
+    /// Continue an earlier CSV recording: load its samples, carry on its time axis and append
+    /// new rows to it (or write the combined recording to --csv-output)
+    #[arg(long, value_name = "FILE")]
+    append: Option<String>,
+
+    /// Time representation for the chart axis and CSV: seconds since start or absolute RFC3339 timestamps
+    #[arg(long, value_enum, default_value = "relative")]
+    time_axis: TimeAxis,
+
+    /// Most ticks on the chart's time axis (default: one per ~100 pixels of width)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(2..))]
+    x_ticks: Option<u32>,
+
+    /// strftime format of the chart's time labels with --time-axis wallclock, e.g. '%d %b %H:%M'
+    #[arg(long, value_name = "FORMAT", value_parser = chart::parse_date_format)]
+    date_format: Option<String>,
+
+    /// File or FIFO to read event markers from, one label per line (created as a FIFO if missing)
+    #[arg(long, value_name = "PATH")]
+    markers: Option<String>,
+
+    /// Serve live gauges on a Prometheus `/metrics` endpoint at this address (e.g. 0.0.0.0:9200)
+    #[arg(long, value_name = "ADDR")]
+    listen: Option<String>,
+
+    /// Serve a live JSON/PNG query API (/current, /stats, /samples?since=, /chart.png) at this address
+    #[arg(long, value_name = "ADDR")]
+    http: Option<String>,
+
+    /// Push metrics to this Prometheus Pushgateway URL during and at the end of the run
+    #[arg(long, value_name = "URL")]
+    push_gateway: Option<String>,
+
+    /// Write metrics to this node_exporter textfile-collector file during and at the end of the run
+    #[arg(long, value_name = "FILE")]
+    textfile: Option<String>,
+
+    /// How often to push/write metrics
+    #[arg(long, value_name = "DURATION", default_value = "15", value_parser = units::parse_period_seconds)]
+    push_interval: u64,
+
+    /// Write samples in InfluxDB line protocol to this file
+    #[arg(long, value_name = "FILE")]
+    influx_output: Option<String>,
+
+    /// InfluxDB write endpoint (e.g. http://host:8086/api/v2/write?org=ORG&bucket=BUCKET&precision=ns)
+    #[arg(long, value_name = "URL")]
+    influx_url: Option<String>,
+
+    /// API token for the InfluxDB write endpoint
+    #[arg(long, env = "INFLUX_TOKEN", hide_env_values = true)]
+    influx_token: Option<String>,
+
+    /// InfluxDB measurement name
+    #[arg(long, default_value = "memory")]
+    influx_measurement: String,
+
+    /// Extra InfluxDB tag as KEY=VALUE (repeatable); pid, host and run_id are always added
+    #[arg(long = "influx-tag", value_name = "KEY=VALUE", value_parser = influx::parse_tag)]
+    influx_tags: Vec<(String, String)>,
+
+    /// Send per-sample gauges over UDP to this StatsD server (host:port)
+    #[arg(long, value_name = "HOST:PORT")]
+    statsd: Option<String>,
+
+    /// Metric name prefix for StatsD gauges
+    #[arg(long, default_value = "memory_tracker")]
+    statsd_prefix: String,
+
+    /// Agent mode: stream samples and markers to a `memory_tracker collector` at this address
+    #[arg(long, value_name = "HOST:PORT")]
+    collector: Option<String>,
+
+    /// Name this agent reports to the collector (default: the host name)
+    #[arg(long, value_name = "NAME", requires = "collector")]
+    agent_name: Option<String>,
+
+    /// Push samples as OTLP/HTTP gauge metrics to this collector endpoint (e.g. http://localhost:4318)
+    #[cfg(feature = "otel")]
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    otel_endpoint: Option<String>,
+
+    /// Write samples as counter events in Chrome trace event format (viewable in Perfetto)
+    #[arg(long, value_name = "FILE")]
+    trace_output: Option<String>,
+
+    /// Clock used for trace event timestamps; match the clock of the traces you want to align with
+    #[arg(long, value_enum, default_value = "relative")]
+    trace_clock: trace::TraceClock,
+
+    /// Fail with exit code 4 when peak RSS exceeds this size, e.g. 1.5GB
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_memory_kb)]
+    max_peak: Option<u64>,
+
+    /// Fail with exit code 5 when mean RSS exceeds this size, e.g. 800MB
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_memory_kb)]
+    max_mean: Option<u64>,
+
+    /// Alert once RSS reaches this size, e.g. 4GB; prints a warning and notifies --webhook-url
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_memory_kb)]
+    alert_at: Option<u64>,
+
+    /// Percentage of --alert-at that RSS must fall below the threshold before the alert can fire again
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value = "10",
+        requires = "alert_at"
+    )]
+    alert_hysteresis: f64,
+
+    /// POST a JSON payload to this URL when the --alert-at threshold is crossed
+    #[arg(long, value_name = "URL", requires = "alert_at")]
+    webhook_url: Option<String>,
+
+    /// Shell command to run when the --alert-at threshold is crossed, e.g. "gcore {pid}";
+    /// placeholders: {pid}, {rss_kb}, {rss_mb}, {threshold_kb}, {time}, {timestamp}
+    #[arg(long, value_name = "COMMAND", requires = "alert_at")]
+    on_threshold_exec: Option<String>,
+
+    /// Minimum seconds between two --on-threshold-exec runs
+    #[arg(long, value_name = "DURATION", default_value = "60", value_parser = units::parse_whole_seconds, requires = "on_threshold_exec")]
+    exec_min_interval: u64,
+
+    /// Save a parsed /proc/<pid>/smaps snapshot as JSON whenever RSS reaches a new peak
+    /// (debounced) or the --alert-at threshold is crossed
+    #[arg(long)]
+    snapshot_smaps: bool,
+
+    /// Directory for --snapshot-smaps files
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "smaps",
+        requires = "snapshot_smaps"
+    )]
+    smaps_dir: String,
+
+    /// Minimum seconds between two peak snapshots
+    #[arg(long, value_name = "DURATION", default_value = "30", value_parser = units::parse_whole_seconds, requires = "snapshot_smaps")]
+    smaps_debounce: u64,
+
+    /// Draw the combined RSS of the smaps mappings whose path matches this regex as its
+    /// own series, e.g. 'libfoo\.so' or '\[heap\]'; repeat for several series
+    #[arg(long, value_name = "REGEX")]
+    track_mapping: Vec<regex::Regex>,
+
+    /// Track the thread count, each thread's stack from smaps and glibc malloc arenas,
+    /// reporting threads whose stack grows
+    #[arg(long)]
+    per_thread: bool,
+
+    /// Seconds between two --per-thread snapshots
+    #[arg(long, value_name = "DURATION", default_value = "5", value_parser = units::parse_period_seconds, requires = "per_thread")]
+    per_thread_interval: u64,
+
+    /// Plot jemalloc's allocated/active/resident counters from this file, which the target
+    /// rewrites with malloc_stats_print JSON output; a spawned command gets the path in
+    /// MEMORY_TRACKER_JEMALLOC_STATS
+    #[arg(long, value_name = "FILE")]
+    jemalloc_stats: Option<String>,
+
+    /// In run mode, preload an allocation tracker into the command and report the call
+    /// sites holding the most live heap memory, and how much each grew
+    #[arg(long, requires = "command")]
+    alloc_trace: bool,
+
+    /// Preload library for --alloc-trace (default: libmemory_tracker_alloc_trace.so next
+    /// to this binary)
+    #[arg(long, value_name = "FILE", requires = "alloc_trace")]
+    alloc_trace_lib: Option<String>,
+
+    /// Number of call sites listed by --alloc-trace
+    #[arg(long, value_name = "N", default_value = "10", requires = "alloc_trace")]
+    alloc_trace_top: usize,
+
+    /// Attribute memory growth to user stacks with bpftrace: uprobes on libc malloc/free
+    /// or the kmem:mm_page_alloc tracepoint
+    #[cfg(feature = "ebpf")]
+    #[arg(long, value_enum, value_name = "PROBE")]
+    ebpf: Option<ebpf::EbpfProbe>,
+
+    /// Frames kept per stack by --ebpf
+    #[cfg(feature = "ebpf")]
+    #[arg(long, value_name = "N", default_value = "8", requires = "ebpf")]
+    ebpf_depth: u32,
+
+    /// Number of stacks listed per interval and in the summary by --ebpf
+    #[cfg(feature = "ebpf")]
+    #[arg(long, value_name = "N", default_value = "10", requires = "ebpf")]
+    ebpf_top: usize,
+
+    /// Send --kill-signal to the target once RSS reaches this size, then stop and write the outputs
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_memory_kb)]
+    kill_at: Option<u64>,
+
+    /// Signal sent by --kill-at
+    #[arg(
+        long,
+        value_enum,
+        value_name = "SIGNAL",
+        default_value = "term",
+        requires = "kill_at"
+    )]
+    kill_signal: KillSignal,
+
+    /// End the run when the target exec()s into another program instead of marking it
+    /// on the chart
+    #[arg(long)]
+    stop_on_exec: bool,
+
+    /// Post threshold alerts and an end-of-run summary to this Slack incoming webhook
+    #[arg(long, value_name = "URL")]
+    slack_webhook: Option<String>,
+
+    /// Post threshold alerts and an end-of-run summary to this Discord webhook
+    #[arg(long, value_name = "URL")]
+    discord_webhook: Option<String>,
+
+    /// Attach the chart to chat notifications (uploaded to Discord; Slack gets the file path)
+    #[arg(long, conflicts_with = "no_chart")]
+    notify_chart: bool,
+
+    /// Trailing window for the recent growth-rate estimate
+    #[arg(long, value_name = "DURATION", default_value = "300", value_parser = units::parse_whole_seconds)]
+    trend_window: u64,
+
+    /// Leave the first DURATION of the run, e.g. 60s, out of the statistics, budgets and
+    /// reports so start-up allocation does not dominate them; charts still show it
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_seconds, conflicts_with = "top")]
+    warmup: Option<f64>,
+
+    /// Test the steady-state samples for a monotonic upward trend (Mann-Kendall) and
+    /// exit with code 3 and a LEAK SUSPECTED verdict when it is significant
+    #[arg(long)]
+    detect_leak: bool,
+
+    /// Start-up period to exclude from leak detection (default: --warmup, otherwise the
+    /// first 10% of the run)
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_seconds, requires = "detect_leak")]
+    leak_warmup: Option<f64>,
+
+    /// Significance level for leak detection
+    #[arg(long, default_value = "0.01", requires = "detect_leak")]
+    leak_alpha: f64,
+
+    /// Detect change points and report the run as a sequence of plateaus and growth phases
+    #[arg(long)]
+    segments: bool,
+
+    /// Smallest level change that counts as a new segment, e.g. 50MB
+    /// (default: 5% of the observed range, at least 1MB)
+    #[arg(long, value_name = "SIZE", value_parser = units::parse_memory_kb, requires = "segments")]
+    segment_min_change: Option<u64>,
+
+    /// Draw the detected segments on the chart, colored by kind
+    #[arg(long, requires = "segments")]
+    color_segments: bool,
+
+    /// List the N largest memory spikes (rolling z-score outliers) and mark them on the chart
+    #[arg(long, value_name = "N")]
+    spikes: Option<usize>,
+
+    /// Number of preceding samples that form the baseline for spike detection
+    #[arg(long, default_value = "30", value_parser = clap::value_parser!(u64).range(2..))]
+    spike_window: u64,
+
+    /// Z-score above which a sample counts as part of a spike
+    #[arg(long, default_value = "4.0")]
+    spike_threshold: f64,
+
+    /// Also write one chart per recorded metric (RSS, swap, extra series, allocation rate,
+    /// thread count) and a combined multi-panel figure into this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "no_chart")]
+    output_dir: Option<String>,
+
+    /// Also render a histogram of the sampled memory values to this file, with the bucket
+    /// counts written next to it as CSV
+    #[arg(long, value_name = "FILE", conflicts_with = "no_chart")]
+    histogram: Option<String>,
+
+    /// Number of histogram buckets
+    #[arg(long, default_value = "30", value_parser = clap::value_parser!(u64).range(1..))]
+    histogram_bins: u64,
+
+    /// Write run metadata and summary statistics as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    json: Option<String>,
+
+    /// Write a self-contained interactive HTML report with chart, statistics and run metadata
+    #[arg(long, value_name = "FILE")]
+    html_report: Option<String>,
+
+    /// Write a Markdown summary with statistics, check verdicts, spikes and a link to
+    /// the chart, ready to paste into a PR comment or wiki page
+    #[arg(long, value_name = "FILE")]
+    md_report: Option<String>,
+
+    /// Write the budget and leak checks as a JUnit XML report, one test case per check
+    #[arg(long, value_name = "FILE")]
+    report: Option<String>,
+
+    /// Print failed budget and leak checks and warnings as GitHub Actions annotations
+    #[arg(long)]
+    gha_annotations: bool,
+
+    /// Identifier for this run used in exported metrics (default: <start time>-<pid>)
+    #[arg(long)]
+    run_id: Option<String>,
+
+    /// Detach from the terminal and keep recording in the background
+    #[arg(long)]
+    daemon: bool,
+
+    /// Write the daemon's pid to this file (removed on exit)
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    pidfile: Option<String>,
+
+    /// Send the daemon's console output to this file instead of discarding it
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    log_file: Option<String>,
+
+    /// Every DURATION, write the period's CSV and chart to timestamped files and start a new period
+    #[arg(long, value_name = "DURATION", value_parser = units::parse_period_seconds)]
+    rotate: Option<u64>,
+
+    /// Number of rotated files to keep per output, oldest deleted first
+    #[arg(long, value_name = "N", default_value = "24", requires = "rotate")]
+    keep_rotated: usize,
+}
+
+impl RecordArgs {
+    /// Where samples are written: --csv-output, or else the recording being appended to.
+    fn csv_path(&self) -> Option<&str> {
+        self.csv_output.as_deref().or(self.append.as_deref())
+    }
+}
+
+/// Signal sent to the target by --kill-at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KillSignal {
+    Term,
+    Kill,
+    Int,
+    Hup,
+    Quit,
+    Abrt,
+    Usr1,
+    Usr2,
+}
+
+impl KillSignal {
+    fn number(self) -> libc::c_int {
+        match self {
+            KillSignal::Term => libc::SIGTERM,
+            KillSignal::Kill => libc::SIGKILL,
+            KillSignal::Int => libc::SIGINT,
+            KillSignal::Hup => libc::SIGHUP,
+            KillSignal::Quit => libc::SIGQUIT,
+            KillSignal::Abrt => libc::SIGABRT,
+            KillSignal::Usr1 => libc::SIGUSR1,
+            KillSignal::Usr2 => libc::SIGUSR2,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            KillSignal::Term => "SIGTERM",
+            KillSignal::Kill => "SIGKILL",
+            KillSignal::Int => "SIGINT",
+            KillSignal::Hup => "SIGHUP",
+            KillSignal::Quit => "SIGQUIT",
+            KillSignal::Abrt => "SIGABRT",
+            KillSignal::Usr1 => "SIGUSR1",
+            KillSignal::Usr2 => "SIGUSR2",
+        }
+    }
+}
+
+/// When and why --kill-at signalled the target.
+#[derive(Debug, Clone, Copy)]
+struct KillEvent {
+    time: f64,
+    rss_kb: u64,
+    limit_kb: u64,
+    signal: KillSignal,
+}
+
+impl KillEvent {
+    fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "time_s": self.time,
+            "rss_kb": self.rss_kb,
+            "limit_kb": self.limit_kb,
+            "signal": self.signal.name(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimeAxis {
+    /// Seconds since monitoring started
+    Relative,
+    /// Absolute wall-clock time
+    Wallclock,
+}
+
+/// Chart options shared by the final chart and rotated period charts: everything except
+/// the analysis overlays.
+fn base_chart_options(
+    args: &RecordArgs,
+    stats: &MemoryStats,
+    run_metadata: &metadata::RunMetadata,
+    wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+) -> Result<chart::ChartOptions> {
+    let mut thresholds = Vec::new();
+    for arg in &args.mark_thresholds {
+        let kb =
+            units::parse_memory_kb(arg).map_err(|e| anyhow::anyhow!("--mark-threshold: {}", e))?;
+        thresholds.push((arg.clone(), kb as f64));
+    }
+    if !args.no_limit_line {
+        // Read at the start, as the process may be gone by the time charts are drawn
+        if let Some(limit_kb) = run_metadata.cgroup_limit_kb {
+            let limit = match args.units {
+                Some(unit) => unit.format(limit_kb as f64),
+                None => format!("{:.0} MB", limit_kb as f64 / 1024.0),
+            };
+            thresholds.push((format!("cgroup limit ({})", limit), limit_kb as f64));
+        }
+    }
+
+    Ok(chart::ChartOptions {
+        log_scale: args.log_scale,
+        style: args.chart_style,
+        downsample: args.downsample,
+        downsample_points: args.downsample_points,
+        smooth: args.smooth.map(|w| w as usize),
+        rate_window: args.rate_chart.then_some(args.rate_window as usize),
+        unit: args.units.unwrap_or_default(),
+        thresholds,
+        markers: stats.markers.clone(),
+        wallclock_start,
+        x_ticks: args.x_ticks,
+        date_format: args.date_format.clone(),
+        subtitle: Some(run_metadata.subtitle()),
+        legend: args.legend,
+        stats_box: !args.no_stats_box,
+        target: Some(run_metadata.target()),
+        ..args.chart.options()
+    })
+}
+
+/// Every metric recorded in this run as a chart panel with an axis of its own.
+fn metric_panels(
+    args: &RecordArgs,
+    stats: &MemoryStats,
+    threads: Option<&threads::ThreadTracker>,
+    collectors: &metrics_plugins::CollectorSet,
+    options: &chart::ChartOptions,
+) -> Vec<chart::Panel> {
+    let mut panels = vec![chart::Panel::memory(
+        "RSS",
+        &stats.samples,
+        options.unit,
+        &options.thresholds,
+    )];
+    if !stats.swap_samples.is_empty() {
+        panels.push(chart::Panel::memory(
+            "Swap",
+            &stats.swap_samples,
+            options.unit,
+            &[],
+        ));
+    }
+    for (name, series) in &stats.extra_series {
+        panels.push(chart::Panel::memory(name, series, options.unit, &[]));
+    }
+    if args.rate || args.rate_chart {
+        panels.push(chart::Panel {
+            name: "Allocation rate".to_string(),
+            y_desc: "Rate (KB/s)".to_string(),
+            points: stats.rate_series(args.rate_window as usize),
+            thresholds: Vec::new(),
+        });
+    }
+    if let Some(tracker) = threads.filter(|t| !t.counts.is_empty()) {
+        panels.push(chart::Panel {
+            name: "Threads".to_string(),
+            y_desc: "Threads".to_string(),
+            points: tracker
+                .counts
+                .iter()
+                .map(|(t, n)| (*t, *n as f64))
+                .collect(),
+            thresholds: Vec::new(),
+        });
+    }
+    panels.extend(collectors.panels());
+    panels
+}
+
+/// Write each panel as a chart of its own into `dir`, named after the metric, and all
+/// of them as `combined` in the image format of `output`.
+fn write_metric_charts(
+    dir: &str,
+    panels: &[chart::Panel],
+    output: &str,
+    options: &chart::ChartOptions,
+) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create chart directory: {}", dir))?;
+    let extension = std::path::Path::new(output)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("png");
+    for panel in panels {
+        let slug = panel
+            .name
+            .to_ascii_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        let path = format!("{}/{}.{}", dir, slug, extension);
+        info!("Generating chart: {}", path);
+        let title = format!("{}: {}", options.title, panel.name);
+        chart::generate_panels(std::slice::from_ref(panel), &title, &path, options)?;
+    }
+    let path = format!("{}/combined.{}", dir, extension);
+    info!("Generating combined chart: {}", path);
+    // Each panel keeps a readable height however many metrics there are
+    let (width, height) = options.size;
+    let options = chart::ChartOptions {
+        size: (
+            width,
+            height.max(panels.len() as u32 * 260 * options.dpi / 96),
+        ),
+        ..options.clone()
+    };
+    chart::generate_panels(panels, &options.title, &path, &options)?;
+    info!("Metric charts saved to {}/", dir);
+    Ok(())
+}
+
+/// How long the end of the run waits for the exporters' last push.
+const FINAL_PUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The exporters that push or write on a schedule: the Pushgateway, the textfile, InfluxDB
+/// and OTLP. They run as a task of their own that gets every reading, so a slow endpoint
+/// holds up the next push rather than the next sample.
+struct Pusher {
+    push_gateway: Option<String>,
+    textfile: Option<String>,
+    gauges: Option<prometheus::SharedGauges>,
+    influx: Option<influx::InfluxExporter>,
+    #[cfg(feature = "otel")]
+    otel: Option<otel::OtelExporter>,
+}
+
+impl Pusher {
+    /// Push after the first reading and then every `interval`, and once more when the
+    /// sampling loop hangs up.
+    async fn run(
+        mut self,
+        mut readings: mpsc::UnboundedReceiver<MemoryReading>,
+        interval: Duration,
+    ) {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut pushed = false;
+        loop {
+            tokio::select! {
+                reading = readings.recv() => match reading {
+                    Some(reading) => {
+                        self.add_sample(&reading);
+                        if !pushed {
+                            self = self.push().await;
+                            pushed = true;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticks.tick() => self = self.push().await,
+            }
+        }
+        self.push().await;
+    }
+
+    fn add_sample(&mut self, reading: &MemoryReading) {
+        if let Some(influx) = &mut self.influx {
+            if let Err(e) = influx.add_sample(reading) {
+                warn!("{:#}", e);
+            }
+        }
+        #[cfg(feature = "otel")]
+        if let Some(otel) = &mut self.otel {
+            otel.add_sample(reading);
+        }
+    }
+
+    /// Push or write what the exporters hold on a blocking thread, as they use blocking
+    /// HTTP and file I/O. Failures are reported but never end the run.
+    async fn push(mut self) -> Self {
+        let pushed = tokio::task::spawn_blocking(move || {
+            self.push_now();
+            self
+        });
+        match pushed.await {
+            Ok(pusher) => pusher,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    fn push_now(&mut self) {
+        if let Some(gauges) = &self.gauges {
+            // Pushing takes a while, so the live /metrics endpoint must not wait for it
+            let gauges = gauges.lock().unwrap().clone();
+            if let Some(url) = &self.push_gateway {
+                if let Err(e) = prometheus::push(url, &gauges) {
+                    warn!("{:#}", e);
+                }
+            }
+            if let Some(path) = &self.textfile {
+                if let Err(e) = prometheus::write_textfile(path, &gauges) {
+                    warn!("{:#}", e);
+                }
+            }
+        }
+        if let Some(influx) = &mut self.influx {
+            if let Err(e) = influx.flush() {
+                warn!("{:#}", e);
+            }
+        }
+        #[cfg(feature = "otel")]
+        if let Some(otel) = &mut self.otel {
+            if let Err(e) = otel.flush() {
+                warn!("{:#}", e);
+            }
+        }
+    }
+}
+
+/// Everything samples and markers go to as they are taken, besides the recording itself:
+/// the CSV, the metric exporters, the collector agent and the live API.
+struct Outputs {
+    csv_writer: Option<CsvWriter>,
+    /// Why the CSV stopped being written, reported once sampling is over
+    csv_failure: Option<anyhow::Error>,
+    gauges: Option<prometheus::SharedGauges>,
+    live: Option<http_api::SharedState>,
+    statsd: Option<statsd::StatsdClient>,
+    agent: Option<agent::AgentStream>,
+    trace_writer: Option<trace::TraceWriter>,
+    /// Readings for the [`Pusher`] task, and the task itself
+    pusher: Option<(
+        mpsc::UnboundedSender<MemoryReading>,
+        tokio::task::JoinHandle<()>,
+    )>,
+}
+
+impl Outputs {
+    /// Start every exporter and server the options ask for, as tasks on the current tokio
+    /// runtime. The live API reads `stats` as the sampling loop fills it.
+    fn start(
+        run: &Run,
+        stats: &http_api::SharedStats,
+        csv_writer: Option<CsvWriter>,
+    ) -> Result<Self> {
+        let (args, pid) = (run.args, run.target.pid);
+        let exporting =
+            args.listen.is_some() || args.push_gateway.is_some() || args.textfile.is_some();
+        let gauges = exporting.then(|| {
+            prometheus::SharedGauges::new(std::sync::Mutex::new(prometheus::Gauges {
+                pid,
+                ..Default::default()
+            }))
+        });
+        if let (Some(addr), Some(gauges)) = (&args.listen, &gauges) {
+            prometheus::serve(addr, gauges.clone())?;
+            info!("Serving Prometheus metrics on http://{}/metrics", addr);
+        }
+        let live = match &args.http {
+            Some(addr) => {
+                let chart_options = base_chart_options(
+                    args,
+                    &stats.lock().unwrap(),
+                    &run.run_metadata,
+                    run.wallclock_start,
+                )?;
+                let live = http_api::SharedState::new(http_api::LiveState {
+                    pid,
+                    latest: Default::default(),
+                    stats: stats.clone(),
+                    chart_options,
+                    trend_window: args.trend_window as f64,
+                });
+                http_api::serve(addr, live.clone())?;
+                info!("Serving the live API on http://{}/", addr);
+                Some(live)
+            }
+            None => None,
+        };
+
+        let influx = if args.influx_output.is_some() || args.influx_url.is_some() {
+            let mut tags = vec![
+                ("pid".to_string(), pid.to_string()),
+                ("host".to_string(), hostname()),
+                ("run_id".to_string(), run.target.run_id.clone()),
+            ];
+            tags.extend(args.influx_tags.iter().cloned());
+            Some(influx::InfluxExporter::new(
+                &args.influx_measurement,
+                &tags,
+                args.influx_output.as_deref(),
+                args.influx_url.as_deref(),
+                args.influx_token.as_deref(),
+            )?)
+        } else {
+            None
+        };
+
+        let statsd = match &args.statsd {
+            Some(addr) => Some(statsd::StatsdClient::new(addr, &args.statsd_prefix)?),
+            None => None,
+        };
+
+        let agent = match &args.collector {
+            Some(addr) => {
+                let name = args.agent_name.clone().unwrap_or_else(hostname);
+                info!("Streaming samples to the collector at {} as {}", addr, name);
+                Some(agent::AgentStream::connect(addr, &name, &run.run_metadata)?)
+            }
+            None => None,
+        };
+
+        let trace_writer = match &args.trace_output {
+            Some(path) => Some(trace::TraceWriter::new(path, args.trace_clock, pid)?),
+            None => None,
+        };
+
+        let pusher = Pusher {
+            push_gateway: args.push_gateway.clone(),
+            textfile: args.textfile.clone(),
+            gauges: (args.push_gateway.is_some() || args.textfile.is_some())
+                .then(|| gauges.clone())
+                .flatten(),
+            influx,
+            #[cfg(feature = "otel")]
+            otel: args.otel_endpoint.as_ref().map(|endpoint| {
+                otel::OtelExporter::new(endpoint, pid, &run.target.cmdline, &hostname())
+            }),
+        };
+        #[cfg(feature = "otel")]
+        let otel_enabled = pusher.otel.is_some();
+        #[cfg(not(feature = "otel"))]
+        let otel_enabled = false;
+        let pusher =
+            (pusher.gauges.is_some() || pusher.influx.is_some() || otel_enabled).then(|| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                let task = tokio::spawn(pusher.run(rx, Duration::from_secs(args.push_interval)));
+                (tx, task)
+            });
+
+        Ok(Self {
+            csv_writer,
+            csv_failure: None,
+            gauges,
+            live,
+            statsd,
+            agent,
+            trace_writer,
+            pusher,
+        })
+    }
+
+    /// Whether any output uses more of a reading than RSS, which rules out the statm
+    /// fast path.
+    fn need_full_reading(&self) -> bool {
+        self.gauges.is_some()
+            || self.live.is_some()
+            || self.pusher.is_some()
+            || self.statsd.is_some()
+            || self.trace_writer.is_some()
+    }
+
+    /// Drop the CSV output once its writer has stopped, warning right away; sampling goes
+    /// on and the run fails when it is over.
+    fn check_csv(&mut self, sent: Result<()>) {
+        let Err(failed) = sent else {
+            return;
+        };
+        let e = match self.csv_writer.take() {
+            Some(csv_writer) => csv_writer.stopped(failed),
+            None => failed,
+        };
+        warn!("Stopped writing the CSV: {:#}", e);
+        self.csv_failure = Some(e);
+    }
+
+    /// Add a marker to `stats` and pass it to the CSV, the collector agent and the live API.
+    fn emit_marker(&mut self, stats: &mut MemoryStats, label: String, time: f64) {
+        if let Some(csv_writer) = &self.csv_writer {
+            let sent = csv_writer.marker(time, &label);
+            self.check_csv(sent);
+        }
+        if let Some(agent) = &self.agent {
+            agent.marker(time, &label);
+        }
+        stats.markers.push((time, label));
+    }
+
+    /// Pass a reading just added to `stats` on to every output.
+    fn add_sample(
+        &mut self,
+        stats: &MemoryStats,
+        time_secs: f64,
+        reading: MemoryReading,
+    ) -> Result<()> {
+        if let Some(csv_writer) = &self.csv_writer {
+            let sent = csv_writer.sample(time_secs, reading.rss_kb);
+            self.check_csv(sent);
+        }
+        if let Some(agent) = &self.agent {
+            agent.sample(time_secs, reading.rss_kb);
+        }
+        if let Some(gauges) = &self.gauges {
+            let mut gauges = gauges.lock().unwrap();
+            gauges.rss_kb = reading.rss_kb;
+            gauges.swap_kb = reading.swap_kb;
+            gauges.hwm_kb = reading.hwm_kb;
+            gauges.samples = stats.sample_count();
+        }
+        if let Some(live) = &self.live {
+            *live.latest.lock().unwrap() = Some((time_secs, reading));
+        }
+        if let Some(statsd) = &self.statsd {
+            statsd.send(&reading);
+        }
+        if let Some(trace_writer) = &mut self.trace_writer {
+            trace_writer.add_sample(&reading)?;
+        }
+        if let Some((pusher, _)) = &self.pusher {
+            let _ = pusher.send(reading);
+        }
+        Ok(())
+    }
+
+    /// Write the finished period's CSV and chart to timestamped files, prune old ones and
+    /// start a new period. Failures are reported but never end the run.
+    fn rotate(
+        &mut self,
+        run: &Run,
+        stats: &mut MemoryStats,
+        period_started_at: chrono::DateTime<chrono::Local>,
+    ) {
+        let args = run.args;
+        if !stats.samples.is_empty() {
+            if let (Some(csv_path), Some(csv_writer)) = (args.csv_path(), &self.csv_writer) {
+                let sent = csv_writer.rotate(
+                    daemon::rotated_path(csv_path, period_started_at),
+                    args.keep_rotated,
+                );
+                self.check_csv(sent);
+            }
+            if !args.no_chart {
+                let rotated = daemon::rotated_path(&args.output, period_started_at);
+                let result =
+                    base_chart_options(args, stats, &run.run_metadata, run.wallclock_start)
+                        .and_then(|options| chart::generate_chart(stats, &rotated, &options))
+                        .and_then(|()| daemon::prune_rotated(&args.output, args.keep_rotated));
+                match result {
+                    Ok(()) => info!("Rotated chart to {}", rotated),
+                    Err(e) => warn!("{:#}", e),
+                }
+            }
+        }
+        stats.start_period();
+    }
+
+    /// Push once more, then close the collector stream, the trace and the CSV once
+    /// sampling is over.
+    async fn finish(self, args: &RecordArgs) -> Result<()> {
+        if let Some((pusher, task)) = self.pusher {
+            drop(pusher);
+            match tokio::time::timeout(FINAL_PUSH_TIMEOUT, task).await {
+                Ok(pushed) => pushed?,
+                Err(_) => warn!(
+                    "The last push did not finish within {}s, skipping it",
+                    FINAL_PUSH_TIMEOUT.as_secs()
+                ),
+            }
+        }
+        if let Some(agent) = self.agent {
+            agent.finish()?;
+        }
+        if let Some(trace_writer) = self.trace_writer {
+            trace_writer.finish()?;
+        }
+        if let (Some(csv_path), Some(csv_writer)) = (args.csv_path(), self.csv_writer) {
+            csv_writer.finish()?;
+            info!("Memory data saved to CSV: {}", csv_path);
+        }
+        Ok(())
+    }
+}
+
+/// The first tick after `now` on the grid `start + k * interval`. Sleeping until a
+/// deadline instead of for a fixed interval keeps sampling overhead from accumulating
+/// as drift; ticks missed while the loop overran are skipped, not made up.
+fn next_tick(start: Instant, interval: Duration, now: Instant) -> Instant {
+    let step = interval.as_nanos();
+    if step == 0 {
+        return now;
+    }
+    let ticks = now.saturating_duration_since(start).as_nanos() / step + 1;
+    start + Duration::from_nanos((ticks * step) as u64)
+}
+
+/// Read one of the tracker's own collectors at `time`, adding what it returns to the
+/// extra series of `stats`.
+fn collect_into(collector: &mut dyn Collector, pid: u32, time: f64, stats: &mut MemoryStats) {
+    match collector.collect(pid, time) {
+        Ok(metrics) => {
+            for metric in metrics {
+                stats.add_extra_sample(&metric.name, time, metric.value as u64);
+            }
+        }
+        Err(e) => warn!("{:#}", e),
+    }
+}
+
+/// The options given in `args` that only work on Linux. Elsewhere (the BSDs) only RSS
+/// and its high-water mark are sampled.
+fn linux_only_options(args: &RecordArgs) -> Vec<&'static str> {
+    let options = [
+        (args.top.is_some(), "--top"),
+        (args.wait_for_name.is_some(), "--wait-for-name"),
+        (args.unit.is_some(), "--unit"),
+        (args.children, "--children"),
+        (args.pidns.is_some(), "--pidns"),
+        (args.swap, "--swap"),
+        (
+            args.chart_style == chart::ChartStyle::Stacked,
+            "--chart-style stacked",
+        ),
+        (args.snapshot_smaps, "--snapshot-smaps"),
+        (!args.track_mapping.is_empty(), "--track-mapping"),
+        (args.per_thread, "--per-thread"),
+        (args.alloc_trace, "--alloc-trace"),
+        (!args.collect.is_empty(), "--collect"),
+        #[cfg(feature = "ebpf")]
+        (args.ebpf.is_some(), "--ebpf"),
+    ];
+    options
+        .into_iter()
+        .filter(|(given, _)| *given)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// The process being recorded.
+struct Target {
+    pid: u32,
+    unit: Option<systemd::Unit>,
+    cmdline: String,
+    run_id: String,
+}
+
+/// What the sampling loop and the end-of-run outputs know about the run.
+struct Run<'a> {
+    args: &'a RecordArgs,
+    target: Target,
+    run_metadata: metadata::RunMetadata,
+    start_time: Instant,
+    started_at: chrono::DateTime<chrono::Local>,
+    /// Added to every sample time, so an appended run continues the earlier time axis
+    time_offset: f64,
+    wallclock_start: Option<chrono::DateTime<chrono::Local>>,
+}
+
+/// Sample the target until it exits or the duration runs out, then write every
+/// requested output. Returns the exit code of the run: a budget's reserved code, else
+/// the command's own failure, else 0.
+pub fn run(args: &RecordArgs, registry: &metrics_plugins::Registry) -> Result<i32> {
+    // Forking must happen before any thread or child process exists
+    let _pidfile = if args.daemon {
+        daemon::daemonize(args.pidfile.as_deref(), args.log_file.as_deref())?
+    } else {
+        None
+    };
+    shutdown::install();
+    if let Some(path) = &args.config {
+        match &args.profile {
+            Some(profile) => info!("Using configuration from {} (profile {})", path, profile),
+            None => info!("Using configuration from {}", path),
+        }
+    }
+    if !cfg!(target_os = "linux") {
+        let options = linux_only_options(args);
+        if !options.is_empty() {
+            bail!(
+                "{} read Linux's /proc or cgroups and are not available on this system",
+                options.join(", ")
+            );
+        }
+    }
+    if let Some(n) = args.top {
+        return run_top(args, n as usize).map(|()| 0);
+    }
+    // Sampling starts the moment the process is found, so time zero is its appearance
+    let (unit, found_pid) = find_target(args)?;
+    let start_time = Instant::now();
+    let started_at = chrono::Local::now();
+
+    let prior = load_prior(args, started_at)?;
+    let time_offset = prior.as_ref().map_or(0.0, |(_, offset)| *offset);
+    let time_origin = started_at - chrono::TimeDelta::milliseconds((time_offset * 1000.0) as i64);
+    let wallclock_start = (args.time_axis == TimeAxis::Wallclock).then_some(time_origin);
+
+    let mut marker_source = markers::MarkerSource::new(start_time);
+    let (mut child, alloc_trace) = launch_command(args, &mut marker_source)?;
+    let target = resolve_target(args, unit, found_pid, child.as_ref(), started_at)?;
+    let run_metadata = metadata::RunMetadata::collect(
+        target.pid,
+        &target.cmdline,
+        started_at,
+        &target.run_id,
+        args.interval,
+    );
+    let run = Run {
+        args,
+        target,
+        run_metadata,
+        start_time,
+        started_at,
+        time_offset,
+        wallclock_start,
+    };
+    let pid = run.target.pid;
+    let mut csv_writer = open_csv_writer(&run)?;
+
+    match &run.target.unit {
+        Some(unit) => info!(
+            "Monitoring unit {} (cgroup {}, main process {}) with interval {}ms",
+            unit.name, unit.control_group, pid, args.interval
+        ),
+        None if args.children => info!(
+            "Monitoring process {} and its descendants with interval {}ms",
+            pid, args.interval
+        ),
+        None => info!(
+            "Monitoring process {} with interval {}ms",
+            pid, args.interval
+        ),
+    }
+    if let Some(path) = args.csv_path() {
+        info!("Writing samples to CSV: {}", path);
+    }
+    if args.duration > 0 {
+        info!("Duration: {} seconds", args.duration);
+    } else {
+        info!("Duration: until process exits");
+    }
+
+    let mut stats = match args.max_samples {
+        Some(max) => MemoryStats::bounded(max as usize, args.retention),
+        None => MemoryStats::new(),
+    };
+    if args.chart_style == chart::ChartStyle::Stacked {
+        stats.breakdown_labels = ["Anonymous", "File-backed", "Shared memory"]
+            .map(String::from)
+            .to_vec();
+    }
+    if let Some((prior, _)) = prior {
+        // A separate --csv-output gets the earlier rows too, so it holds the combined run
+        if let (Some(writer), Some(_)) = (&csv_writer, &args.csv_output) {
+            let written = (|| -> Result<()> {
+                let mut markers = prior.markers.iter().peekable();
+                for (time, memory_kb) in &prior.samples {
+                    while let Some((marker_time, label)) = markers.next_if(|(t, _)| t <= time) {
+                        writer.marker(*marker_time, label)?;
+                    }
+                    writer.sample(*time, *memory_kb)?;
+                }
+                for (marker_time, label) in markers {
+                    writer.marker(*marker_time, label)?;
+                }
+                Ok(())
+            })();
+            if let Err(e) = written {
+                return Err(match csv_writer.take() {
+                    Some(writer) => writer.stopped(e),
+                    None => e,
+                });
+            }
+        }
+        for (time, memory_kb) in prior.samples {
+            stats.add_sample(time, memory_kb);
+        }
+        stats.markers = prior.markers;
+    }
+
+    // Sampling runs on the main thread, the servers and exporters on the runtime's workers
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .context("Failed to start the async runtime")?;
+    let runtime_context = runtime.enter();
+    // Shared with the live API, which reads it between samples
+    let stats = http_api::SharedStats::new(std::sync::Mutex::new(stats));
+    let mut outputs = Outputs::start(&run, &stats, csv_writer)?;
+    if let Some(path) = &args.markers {
+        marker_source.follow_path(path)?;
+        info!("Reading event markers from {}", path);
+    }
+    let mut sampler = Sampler::new(&run, &outputs, registry)?;
+
+    let interval = Duration::from_millis(args.interval);
+    let max_duration = (args.duration > 0).then(|| Duration::from_secs(args.duration));
+    let rotate_interval = args.rotate.map(Duration::from_secs);
+    let mut period_start = start_time;
+    let grace_period = Duration::from_secs(args.grace_period);
+    // Set once the tracker is interrupted in run mode: keep sampling while the command
+    // shuts down, until this deadline
+    let mut stop_deadline: Option<Instant> = None;
+    let mut interrupted = std::pin::pin!(shutdown::wait());
+    runtime.block_on(async {
+        loop {
+            if shutdown::requested() && stop_deadline.is_none() {
+                if child.is_none() {
+                    info!("Interrupted, stopping");
+                    break;
+                }
+                match shutdown::signal_to_forward() {
+                    Some(signal) => {
+                        info!(
+                            "Interrupted, forwarding signal {} to process {}",
+                            signal, pid
+                        );
+                        unsafe { libc::kill(pid as libc::pid_t, signal) };
+                    }
+                    None => info!("Interrupted, waiting for the command to exit"),
+                }
+                stop_deadline = Some(Instant::now() + grace_period);
+            }
+            if stop_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                info!(
+                    "Command did not exit within {}s, stopping",
+                    args.grace_period
+                );
+                break;
+            }
+            if max_duration.is_some_and(|max_dur| start_time.elapsed() >= max_dur) {
+                info!("Reached maximum duration");
+                break;
+            }
+
+            for (time, label) in marker_source.drain() {
+                let time = time_offset + time;
+                info!("Marker at {:.1}s: {}", time, label);
+                outputs.emit_marker(&mut stats.lock().unwrap(), label, time);
+            }
+
+            // The exit status is reported once the loop is done
+            if child
+                .as_mut()
+                .map(|c| c.try_wait())
+                .transpose()?
+                .flatten()
+                .is_some()
+            {
+                break;
+            }
+
+            if sampler
+                .sample(&run, &mut stats.lock().unwrap(), &mut outputs)?
+                .is_break()
+            {
+                break;
+            }
+
+            if rotate_interval
+                .is_some_and(|rotate_interval| period_start.elapsed() >= rotate_interval)
+            {
+                outputs.rotate(
+                    &run,
+                    &mut stats.lock().unwrap(),
+                    started_at + (period_start - start_time),
+                );
+                period_start = Instant::now();
+            }
+
+            let next = next_tick(start_time, interval, Instant::now());
+            tokio::select! {
+                () = tokio::time::sleep_until(next.into()) => {}
+                // Only until the shutdown request is handled
+                () = &mut interrupted, if stop_deadline.is_none() => {}
+            }
+        }
+        anyhow::Ok(())
+    })?;
+    sampler.progress.finish();
+
+    for (time, label) in marker_source.drain() {
+        outputs.emit_marker(&mut stats.lock().unwrap(), label, time_offset + time);
+    }
+    let csv_failure = outputs.csv_failure.take();
+    runtime.block_on(outputs.finish(args))?;
+    // A push stuck on an unresponsive endpoint must not keep the run from ending
+    drop(runtime_context);
+    runtime.shutdown_background();
+    let stats = std::mem::take(&mut *stats.lock().unwrap());
+    let child_exit_code = match &mut child {
+        Some(child) => stop_command(
+            child,
+            pid,
+            sampler.kill.is_some(),
+            stop_deadline.is_some(),
+            grace_period,
+        )?,
+        None => None,
+    };
+
+    let analysis = summarize(&run, &stats, &mut sampler, alloc_trace.as_ref())?;
+    write_charts(&run, &stats, &sampler, &analysis)?;
+    if let Some(json_path) = &args.json {
+        write_json(&run, &stats, &sampler, &analysis, json_path)?;
+    }
+    if let Some(html_path) = &args.html_report {
+        write_html(&run, &stats, &analysis, html_path)?;
+    }
+    let budgets = check_budgets(args, analysis.measured(&stats), analysis.leak_verdict);
+    for violation in &budgets.violations {
+        eprintln!("\n{}", violation);
+    }
+    write_ci_reports(&run, &stats, &analysis, &budgets)?;
+    notify_finished(&run, &stats, &mut sampler, &analysis, &budgets);
+    alert::wait(sampler.pending_notifications)?;
+    if let Some(e) = csv_failure {
+        return Err(e.context("The CSV is incomplete"));
+    }
+
+    // Budget violations keep their reserved codes; otherwise the command's failure is ours
+    Ok(budgets.exit_code.or(child_exit_code).unwrap_or(0))
+}
+
+/// The unit and the pid the options name, waiting for --wait-for-name to appear first.
+fn find_target(args: &RecordArgs) -> Result<(Option<systemd::Unit>, Option<u32>)> {
+    let waited_pid = match &args.wait_for_name {
+        Some(name) => {
+            info!("Waiting for a process named {}", name);
+            let timeout = (args.wait_timeout > 0).then(|| Duration::from_secs(args.wait_timeout));
+            Some(wait::wait_for_name(name, timeout)?)
+        }
+        None => None,
+    };
+    let unit = match &args.unit {
+        Some(name) => Some(systemd::Unit::resolve(name)?),
+        None => None,
+    };
+    let given_pid = match (args.pid, &args.pidns) {
+        (Some(pid), Some(namespace)) => {
+            let host_pid = pidns::resolve(namespace, pid)?;
+            info!(
+                "Pid {} in PID namespace {} is pid {} on the host",
+                pid, namespace, host_pid
+            );
+            Some(host_pid)
+        }
+        (pid, _) => pid,
+    };
+    Ok((unit, waited_pid.or(given_pid)))
+}
+
+/// The recording given to --append and the time its continuation starts at.
+fn load_prior(
+    args: &RecordArgs,
+    started_at: chrono::DateTime<chrono::Local>,
+) -> Result<Option<(MemoryStats, f64)>> {
+    let Some(path) = &args.append else {
+        return Ok(None);
+    };
+    let (prior, origin) = recording::load_csv_with_origin(path)?;
+    if origin.is_some() != (args.time_axis == TimeAxis::Wallclock) {
+        bail!(
+            "{} uses {} times; pass --time-axis {} to append to it",
+            path,
+            if origin.is_some() {
+                "wall-clock"
+            } else {
+                "relative"
+            },
+            if origin.is_some() {
+                "wallclock"
+            } else {
+                "relative"
+            }
+        );
+    }
+    let time_offset = match origin {
+        Some(origin) => (started_at.fixed_offset() - origin).num_milliseconds() as f64 / 1000.0,
+        None => {
+            let last_sample = prior.samples.last().map_or(0.0, |(t, _)| *t);
+            let last_marker = prior.markers.last().map_or(0.0, |(t, _)| *t);
+            last_sample.max(last_marker) + args.interval as f64 / 1000.0
+        }
+    };
+    info!(
+        "Appending to {} ({} samples), continuing at {:.1}s",
+        path,
+        prior.samples.len(),
+        time_offset
+    );
+    Ok(Some((prior, time_offset)))
+}
+
+/// Launch the command given to `run`, following its output for markers and preloading
+/// the allocation tracer with --alloc-trace.
+fn launch_command(
+    args: &RecordArgs,
+    marker_source: &mut markers::MarkerSource,
+) -> Result<(Option<std::process::Child>, Option<alloc_trace::AllocTrace>)> {
+    if args.command.is_empty() {
+        return Ok((None, None));
+    }
+    let mut command = std::process::Command::new(&args.command[0]);
+    command
+        .args(&args.command[1..])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    if let Some(path) = &args.jemalloc_stats {
+        command.env(jemalloc::STATS_PATH_ENV, path);
+    }
+    let mut alloc_trace = None;
+    if args.alloc_trace {
+        let library = alloc_trace::find_library(args.alloc_trace_lib.as_deref())?;
+        info!("Tracing allocations with {}", library.display());
+        alloc_trace = Some(alloc_trace::AllocTrace::prepare(
+            &mut command,
+            &library,
+            args.interval.max(100),
+        )?);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to launch {}", args.command[0]))?;
+    debug!(
+        "Launched `{}` as pid {}",
+        args.command.join(" "),
+        child.id()
+    );
+    if let Some(stdout) = child.stdout.take() {
+        marker_source.follow_output(stdout, false, args.annotate_regex.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        marker_source.follow_output(stderr, true, args.annotate_regex.clone());
+    }
+    Ok((Some(child), alloc_trace))
+}
+
+/// The process to sample: the launched command, the unit's main process or the pid that
+/// was given or waited for.
+fn resolve_target(
+    args: &RecordArgs,
+    unit: Option<systemd::Unit>,
+    found_pid: Option<u32>,
+    child: Option<&std::process::Child>,
+    started_at: chrono::DateTime<chrono::Local>,
+) -> Result<Target> {
+    let pid = match (child, &unit) {
+        (Some(child), _) => child.id(),
+        (None, Some(unit)) => unit.main_pid()?,
+        (None, None) => {
+            found_pid.expect("clap requires --pid, --wait-for-name or --unit without a command")
+        }
+    };
+    let run_id = args
+        .run_id
+        .clone()
+        .unwrap_or_else(|| format!("{}-{}", started_at.timestamp(), pid));
+    // A just-spawned child may not have exec'd yet, so its /proc cmdline can still be empty
+    let cmdline = match &unit {
+        Some(unit) => format!("{} ({})", unit.name, read_cmdline(pid)),
+        None if args.command.is_empty() => read_cmdline(pid),
+        None => args.command.join(" "),
+    };
+    Ok(Target {
+        pid,
+        unit,
+        cmdline,
+        run_id,
+    })
+}
+
+/// The CSV writer for --csv-output, or for the recording given to --append.
+fn open_csv_writer(run: &Run) -> Result<Option<CsvWriter>> {
+    let args = run.args;
+    match (&args.csv_output, &args.append) {
+        (Some(path), _) => Ok(Some(CsvWriter::create(
+            path,
+            run.wallclock_start,
+            &run.run_metadata.csv_comments(),
+            Columns {
+                unit: args.units.unwrap_or(units::Unit::Kb),
+                rate_window: args.rate.then_some(args.rate_window as usize),
+                agents: false,
+            },
+        )?)),
+        // Appended rows keep the columns of the existing recording
+        (None, Some(path)) => {
+            let (unit, has_rate) = recording::layout(path)?;
+            let columns = Columns {
+                unit,
+                rate_window: has_rate.then_some(args.rate_window as usize),
+                agents: false,
+            };
+            Ok(Some(CsvWriter::append(path, run.wallclock_start, columns)?))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Wait for the launched command once sampling is over, stopping it if it still runs.
+/// Returns its exit code, unless the tracker had to stop it.
+fn stop_command(
+    child: &mut std::process::Child,
+    pid: u32,
+    killed: bool,
+    interrupted: bool,
+    grace_period: Duration,
+) -> Result<Option<i32>> {
+    // Give a killed command a moment to exit so its status is reported
+    let mut status = child.try_wait()?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while status.is_none() && killed && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+        status = child.try_wait()?;
+    }
+    let stopped = status.is_none();
+    // The command must not outlive the tracker, which would leave nobody to reap it
+    if stopped && !interrupted {
+        info!("Stopping command (pid {}) with SIGTERM", pid);
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+        let deadline = Instant::now() + grace_period;
+        while status.is_none() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+            status = child.try_wait()?;
+        }
+    }
+    let status = match status {
+        Some(status) => status,
+        None => {
+            info!("Killing command (pid {})", pid);
+            child.kill()?;
+            child.wait()?
+        }
+    };
+    info!("Command exited with {}", status);
+    Ok((!stopped).then(|| exit_code_of(status)))
+}
+
+/// The readers, trackers and alerting state each sample passes through.
+struct Sampler {
+    /// The statm fast path, the unit's cgroup or `/proc/<pid>/status`; unused with
+    /// --children, where the breakdown reads the process tree or the unit
+    memory: Box<dyn Collector>,
+    child_breakdown: Option<children::ChildBreakdown>,
+    peak_hold: Option<peak_hold::PeakHold>,
+    headroom: Option<headroom::Headroom>,
+    collectors: metrics_plugins::CollectorSet,
+    swap_watch: Option<swap::SwapWatch>,
+    exec_watch: Option<exec_watch::ExecWatch>,
+    smaps_snapshotter: Option<smaps::Snapshotter>,
+    thread_tracker: Option<threads::ThreadTracker>,
+    mapping_tracker: Option<smaps::MappingTracker>,
+    jemalloc_reader: Option<jemalloc::JemallocReader>,
+    last_jemalloc: Option<(jemalloc::JemallocStats, u64)>,
+    #[cfg(feature = "ebpf")]
+    ebpf_collector: Option<ebpf::EbpfCollector>,
+    alert: Option<alert::ThresholdAlert>,
+    threshold_command: Option<alert::ThresholdCommand>,
+    chat_notifiers: Vec<chat::ChatNotifier>,
+    /// Webhook and chat posts still being sent, waited for before exiting
+    pending_notifications: Vec<std::thread::JoinHandle<()>>,
+    gap_tracker: gaps::GapTracker,
+    /// Set once --kill-at signalled the target
+    kill: Option<KillEvent>,
+    /// The unit's main process as last looked up, which per-process actions apply to
+    main_pid: u32,
+    progress: logging::Progress,
+    /// Retries are spread over a fraction of the interval so they do not delay the next tick
+    retry_delay: Duration,
+}
+
+impl Sampler {
+    fn new(run: &Run, outputs: &Outputs, registry: &metrics_plugins::Registry) -> Result<Self> {
+        let (args, pid, unit) = (run.args, run.target.pid, run.target.unit.as_ref());
+        // statm only carries RSS; anything needing swap, HWM or the breakdown reads status
+        let fast_path = cfg!(target_os = "linux")
+            && args.interval < statm::FAST_PATH_BELOW_MS
+            && !args.no_fast_path
+            && !args.swap
+            && !args.peak_hold
+            && args.chart_style != chart::ChartStyle::Stacked
+            && unit.is_none()
+            && !args.children
+            && !outputs.need_full_reading();
+        let headroom = headroom::Headroom::detect(pid, args.headroom);
+        match &headroom {
+            Some(headroom) => debug!(
+                "Tracking headroom under the cgroup limit of {}",
+                units::describe(headroom.limit_kb() as f64, args.units)
+            ),
+            None if args.headroom => warn!(
+                "No cgroup memory limit applies to process {}, --headroom has nothing to chart",
+                pid
+            ),
+            None => {}
+        }
+        let limit = SeriesLimit::new(args.max_samples, args.retention);
+        let budget = Duration::from_millis(args.collector_budget.unwrap_or(args.interval / 2));
+        let collectors = metrics_plugins::CollectorSet::new(
+            registry,
+            &args.collect,
+            &args.collector_exec,
+            pid,
+            budget,
+            limit,
+        )?;
+        let memory: Box<dyn Collector> = match unit {
+            _ if fast_path => {
+                info!("Sampling RSS from /proc/{}/statm", pid);
+                Box::new(statm::StatmReader::open(pid)?)
+            }
+            Some(unit) => Box::new(unit.clone()),
+            None => Box::new(metrics_plugins::Status),
+        };
+        let smaps_snapshotter = if args.snapshot_smaps {
+            info!("Saving smaps snapshots to {}/", args.smaps_dir);
+            Some(smaps::Snapshotter::new(
+                &args.smaps_dir,
+                Duration::from_secs(args.smaps_debounce),
+            )?)
+        } else {
+            None
+        };
+        #[cfg(feature = "ebpf")]
+        let ebpf_collector = match args.ebpf {
+            Some(probe) => {
+                info!("Tracing {} growth per stack with bpftrace", probe.name());
+                Some(ebpf::EbpfCollector::start(
+                    probe,
+                    pid,
+                    args.interval.max(100),
+                    args.ebpf_depth,
+                    args.ebpf_top,
+                    run.start_time,
+                    run.time_offset,
+                )?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            memory,
+            child_breakdown: args
+                .children
+                .then(|| children::ChildBreakdown::new(unit.cloned(), limit)),
+            // A unit's or tree's mark would be the sum of its processes' own peaks, not a
+            // peak of the whole
+            peak_hold: (unit.is_none() && !args.children).then(peak_hold::PeakHold::default),
+            headroom,
+            collectors,
+            // VmSwap is not in statm, so the fast path cannot tell when the process is swapped
+            swap_watch: (!fast_path).then(swap::SwapWatch::start),
+            // A unit is followed through its cgroup, whatever its main process runs
+            exec_watch: unit.is_none().then(|| exec_watch::ExecWatch::start(pid)),
+            smaps_snapshotter,
+            thread_tracker: args.per_thread.then(|| {
+                threads::ThreadTracker::new(Duration::from_secs(args.per_thread_interval), limit)
+            }),
+            mapping_tracker: (!args.track_mapping.is_empty())
+                .then(|| smaps::MappingTracker::new(&args.track_mapping)),
+            jemalloc_reader: args.jemalloc_stats.as_deref().map(|path| {
+                info!("Reading jemalloc stats from {}", path);
+                jemalloc::JemallocReader::new(path)
+            }),
+            last_jemalloc: None,
+            #[cfg(feature = "ebpf")]
+            ebpf_collector,
+            alert: args.alert_at.map(|threshold_kb| {
+                alert::ThresholdAlert::new(threshold_kb, args.alert_hysteresis)
+            }),
+            threshold_command: args.on_threshold_exec.as_ref().map(|template| {
+                alert::ThresholdCommand::new(template, Duration::from_secs(args.exec_min_interval))
+            }),
+            chat_notifiers: [
+                (chat::Service::Slack, &args.slack_webhook),
+                (chat::Service::Discord, &args.discord_webhook),
+            ]
+            .into_iter()
+            .filter_map(|(service, url)| {
+                url.as_ref()
+                    .map(|url| chat::ChatNotifier::new(service, url))
+            })
+            .collect(),
+            pending_notifications: Vec::new(),
+            gap_tracker: gaps::GapTracker::default(),
+            kill: None,
+            main_pid: pid,
+            progress: logging::Progress::new(args.progress),
+            retry_delay: Duration::from_millis((args.interval / 10).clamp(1, 100)),
+        })
+    }
+
+    /// Take one sample and pass it to `stats`, the trackers and the outputs. Breaks when
+    /// the run has to stop: the target is gone or cannot be read, exec'd with
+    /// --stop-on-exec, or was signalled by --kill-at.
+    fn sample(
+        &mut self,
+        run: &Run,
+        stats: &mut MemoryStats,
+        outputs: &mut Outputs,
+    ) -> Result<ControlFlow<()>> {
+        let (args, pid, unit) = (run.args, run.target.pid, run.target.unit.as_ref());
+        // Stamp the sample with when it was actually taken, not when the tick was due
+        let time_secs = run.time_offset + run.start_time.elapsed().as_secs_f64();
+        let mut reading = self.read_memory(pid, time_secs);
+        let mut attempts = 0;
+        while let Err(e) = &reading {
+            // A unit's reading only fails once its cgroup is gone
+            if attempts >= args.retry || unit.is_some() || gaps::process_gone(pid, e) {
+                break;
+            }
+            attempts += 1;
+            debug!(
+                "Sampling process {} failed, retry {} of {}: {:#}",
+                pid, attempts, args.retry, e
+            );
+            std::thread::sleep(self.retry_delay);
+            reading = self.read_memory(pid, time_secs);
+        }
+        match reading {
+            Ok(reading) => self.on_reading(run, stats, outputs, time_secs, reading),
+            Err(e) => Ok(self.on_failure(run, stats, outputs, time_secs, e)),
+        }
+    }
+
+    /// One reading of the target from its memory source.
+    fn read_memory(&mut self, pid: u32, time: f64) -> Result<MemoryReading> {
+        let source: &mut dyn Collector = match &mut self.child_breakdown {
+            Some(breakdown) => breakdown,
+            None => self.memory.as_mut(),
+        };
+        MemoryReading::from_metrics(&source.collect(pid, time)?)
+    }
+
+    fn on_reading(
+        &mut self,
+        run: &Run,
+        stats: &mut MemoryStats,
+        outputs: &mut Outputs,
+        time_secs: f64,
+        reading: MemoryReading,
+    ) -> Result<ControlFlow<()>> {
+        let (args, pid) = (run.args, run.target.pid);
+        let memory_kb = reading.rss_kb;
+        if let Some(gap) = self.gap_tracker.on_sample(time_secs) {
+            info!(
+                "Sampling resumed at {:.1}s after {} missed sample(s)",
+                time_secs, gap.missed
+            );
+        }
+        stats.add_sample(time_secs, memory_kb);
+        outputs.add_sample(stats, time_secs, reading)?;
+        let process = self
+            .process_pid(run, false)
+            .map_err(|e| debug!("Skipping the per-process readings: {:#}", e))
+            .ok();
+        if let Some(peak_hold) = &mut self.peak_hold {
+            peak_hold.on_sample(time_secs, &reading);
+            if args.peak_hold && reading.hwm_kb > 0 {
+                stats.add_extra_sample(peak_hold::SERIES, time_secs, reading.hwm_kb);
+            }
+        }
+        if let Some(headroom) = &mut self.headroom {
+            collect_into(headroom, pid, time_secs, stats);
+        }
+        if let Some(pid) = process {
+            self.collectors.on_sample(pid, time_secs, stats);
+            if let Some(snapshotter) = &mut self.smaps_snapshotter {
+                if let Err(e) = snapshotter.on_sample(pid, time_secs, memory_kb) {
+                    warn!("{:#}", e);
+                }
+            }
+            if let Some(tracker) = &mut self.mapping_tracker {
+                collect_into(tracker, pid, time_secs, stats);
+            }
+            if let Some(tracker) = &mut self.thread_tracker {
+                collect_into(tracker, pid, time_secs, stats);
+            }
+        }
+        if self.alert.as_mut().is_some_and(|a| a.check(memory_kb)) {
+            self.on_alert(run, stats, time_secs, memory_kb);
+        }
+        if args.swap {
+            stats.add_swap_sample(time_secs, reading.swap_kb);
+        }
+        if let Some(start) = self
+            .swap_watch
+            .as_mut()
+            .and_then(|w| w.on_sample(time_secs, reading.swap_kb))
+        {
+            warn!(
+                "process {} is being swapped at {:.1}s: VmSwap {:+.2} MB to {:.2} MB \
+                 (system: {} pages swapped in, {} out since the last sample)",
+                pid,
+                start.time,
+                start.delta_kb as f64 / 1024.0,
+                start.swap_kb as f64 / 1024.0,
+                start.pages_in,
+                start.pages_out
+            );
+            let label = format!("swapping ({:.0} MB swapped)", start.swap_kb as f64 / 1024.0);
+            outputs.emit_marker(stats, label, time_secs);
+        }
+        if let Some(watch) = &mut self.exec_watch {
+            if let Some(exec) = watch.on_sample(time_secs) {
+                warn!(
+                    "process {} exec'd at {:.1}s: '{}' was replaced by '{}'; earlier samples \
+                     belong to the previous program",
+                    pid, exec.time, exec.from, exec.to
+                );
+                outputs.emit_marker(stats, format!("exec: {}", exec.program()), time_secs);
+                if args.stop_on_exec {
+                    info!("Stopping: process {} exec'd (--stop-on-exec)", pid);
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+        }
+        if args.chart_style == chart::ChartStyle::Stacked {
+            stats.add_breakdown(
+                time_secs,
+                vec![
+                    reading.rss_anon_kb,
+                    reading.rss_file_kb,
+                    reading.rss_shmem_kb,
+                ],
+            );
+        }
+        if let Some(reader) = &mut self.jemalloc_reader {
+            collect_into(reader, pid, time_secs, stats);
+            if let Some(jemalloc_stats) = reader.latest() {
+                self.last_jemalloc = Some((jemalloc_stats, memory_kb));
+            }
+        }
+        trace!("Sample at {:.3}s: {} KB", time_secs, memory_kb);
+        self.show_progress(run, stats, time_secs, &reading);
+
+        if let Some(limit_kb) = args.kill_at.filter(|limit| memory_kb >= *limit) {
+            let signal = args.kill_signal;
+            let pid = match self.process_pid(run, true) {
+                Ok(pid) => pid,
+                Err(e) => {
+                    warn!(
+                        "Memory limit of {:.2} MB reached, not sending {}: {:#}",
+                        limit_kb as f64 / 1024.0,
+                        signal.name(),
+                        e
+                    );
+                    return Ok(ControlFlow::Continue(()));
+                }
+            };
+            warn!(
+                "Memory limit of {:.2} MB reached, sending {} to process {}",
+                limit_kb as f64 / 1024.0,
+                signal.name(),
+                pid
+            );
+            if unsafe { libc::kill(pid as libc::pid_t, signal.number()) } != 0 {
+                warn!(
+                    "failed to signal process {}: {}",
+                    pid,
+                    std::io::Error::last_os_error()
+                );
+            }
+            let label = format!("{} at {:.0} MB", signal.name(), memory_kb as f64 / 1024.0);
+            outputs.emit_marker(stats, label, time_secs);
+            self.kill = Some(KillEvent {
+                time: time_secs,
+                rss_kb: memory_kb,
+                limit_kb,
+                signal,
+            });
+            return Ok(ControlFlow::Break(()));
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// The pid per-process actions apply to: the target's, or the unit's main process.
+    /// A unit's main process is replaced on reload or restart and its old pid may be
+    /// reused by an unrelated process, so the pid is only trusted while it is still in the
+    /// unit's cgroup, and looked up again otherwise or when `fresh`, e.g. before a signal.
+    /// Fails when the unit has no main process left to act on.
+    fn process_pid(&mut self, run: &Run, fresh: bool) -> Result<u32> {
+        let Some(unit) = &run.target.unit else {
+            return Ok(run.target.pid);
+        };
+        if !fresh && unit.contains(self.main_pid) {
+            return Ok(self.main_pid);
+        }
+        let pid = unit.main_pid()?;
+        if !unit.contains(pid) {
+            bail!(
+                "process {} left unit {} while it was looked up",
+                pid,
+                unit.name
+            );
+        }
+        if pid != self.main_pid {
+            info!("Unit {} now runs as process {}", unit.name, pid);
+            self.main_pid = pid;
+        }
+        Ok(pid)
+    }
+
+    /// Stop on a failed sample, unless the process still exists and --tolerate-gaps
+    /// records the gap instead.
+    fn on_failure(
+        &mut self,
+        run: &Run,
+        stats: &mut MemoryStats,
+        outputs: &mut Outputs,
+        time_secs: f64,
+        e: anyhow::Error,
+    ) -> ControlFlow<()> {
+        let (args, pid) = (run.args, run.target.pid);
+        if run.target.unit.is_some() || gaps::process_gone(pid, &e) {
+            info!("Process {} no longer exists: {:#}", pid, e);
+            return ControlFlow::Break(());
+        }
+        if !args.tolerate_gaps {
+            warn!(
+                "stopping: process {} still exists but could not be sampled after {} retries: {:#} \
+                 (--tolerate-gaps keeps monitoring)",
+                pid, args.retry, e
+            );
+            return ControlFlow::Break(());
+        }
+        if self.gap_tracker.on_failure(time_secs, &e) {
+            warn!("sampling gap at {:.1}s: {:#}", time_secs, e);
+            outputs.emit_marker(stats, "sampling gap".to_string(), time_secs);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Act on memory crossing --alert-at: snapshot smaps and the chart, run
+    /// --on-threshold-exec and post to the webhook and chat services.
+    fn on_alert(&mut self, run: &Run, stats: &MemoryStats, time_secs: f64, memory_kb: u64) {
+        let (args, target) = (run.args, &run.target);
+        let process = self.process_pid(run, true);
+        let threshold_kb = args.alert_at.unwrap_or_default();
+        warn!(
+            "ALERT: memory reached {:.2} MB (threshold {:.2} MB) at {:.1}s",
+            memory_kb as f64 / 1024.0,
+            threshold_kb as f64 / 1024.0,
+            time_secs
+        );
+        if let Err(e) = &process {
+            warn!(
+                "Skipping the smaps snapshot and --on-threshold-exec: {:#}",
+                e
+            );
+        }
+        let pid = process.as_ref().ok().copied();
+        if let (Some(snapshotter), Some(pid)) = (&mut self.smaps_snapshotter, pid) {
+            if let Err(e) = snapshotter.on_threshold(pid, time_secs, memory_kb) {
+                warn!("{:#}", e);
+            }
+        }
+        let now = chrono::Local::now();
+        if let (Some(command), Some(pid)) = (&mut self.threshold_command, pid) {
+            let vars = [
+                ("pid", pid.to_string()),
+                ("rss_kb", memory_kb.to_string()),
+                ("rss_mb", format!("{:.0}", memory_kb as f64 / 1024.0)),
+                ("threshold_kb", threshold_kb.to_string()),
+                ("time", format!("{:.1}", time_secs)),
+                ("timestamp", now.format("%Y%m%d-%H%M%S").to_string()),
+            ];
+            match command.trigger(time_secs, &vars) {
+                Some(expanded) => info!("Running: {}", expanded),
+                None => info!("Skipping --on-threshold-exec (rate limited)"),
+            }
+        }
+        let mut snapshot = (!args.no_chart).then(|| alert::snapshot_path(&args.output, now));
+        if let Some(path) = &snapshot {
+            let result = base_chart_options(args, stats, &run.run_metadata, run.wallclock_start)
+                .and_then(|options| chart::generate_chart(stats, path, &options));
+            if let Err(e) = result {
+                warn!("{:#}", e);
+                snapshot = None;
+            }
+        }
+        if let Some(url) = &args.webhook_url {
+            let payload = serde_json::json!({
+                "event": "threshold_exceeded",
+                "pid": pid,
+                "command": target.cmdline,
+                "host": hostname(),
+                "run_id": target.run_id,
+                "rss_kb": memory_kb,
+                "threshold_kb": threshold_kb,
+                "time_s": time_secs,
+                "timestamp": now.to_rfc3339(),
+                "chart_snapshot": snapshot,
+            });
+            self.pending_notifications
+                .push(alert::post_json(url, payload));
+        }
+        let text = format!(
+            "Memory alert on {}: `{}` (pid {}) reached {:.2} MB, threshold {:.2} MB, after {:.1}s",
+            hostname(),
+            target.cmdline,
+            pid.unwrap_or(target.pid),
+            memory_kb as f64 / 1024.0,
+            threshold_kb as f64 / 1024.0,
+            time_secs
+        );
+        for notifier in &self.chat_notifiers {
+            let image = snapshot.clone().filter(|_| args.notify_chart);
+            self.pending_notifications
+                .push(notifier.send(text.clone(), image));
+        }
+    }
+
+    fn show_progress(
+        &self,
+        run: &Run,
+        stats: &MemoryStats,
+        time_secs: f64,
+        reading: &MemoryReading,
+    ) {
+        let args = run.args;
+        let memory_kb = reading.rss_kb;
+        let mut status = match args.units {
+            Some(unit) => format!(
+                "Time: {:.1}s | Memory: {}",
+                time_secs,
+                unit.format(memory_kb as f64)
+            ),
+            None => format!(
+                "Time: {:.1}s | Memory: {} KB ({:.2} MB)",
+                time_secs,
+                memory_kb,
+                memory_kb as f64 / 1024.0
+            ),
+        };
+        if args.swap {
+            match args.units {
+                Some(unit) => {
+                    status.push_str(&format!(" | Swap: {}", unit.format(reading.swap_kb as f64)))
+                }
+                None => status.push_str(&format!(
+                    " | Swap: {:.2} MB",
+                    reading.swap_kb as f64 / 1024.0
+                )),
+            }
+        }
+        // With a duration the bar shows how much of the run is done, otherwise
+        // how close memory is to the peak so far
+        let fraction = match args.duration {
+            0 => memory_kb as f64 / stats.max().max(1) as f64,
+            duration => run.start_time.elapsed().as_secs_f64() / duration as f64,
+        };
+        self.progress.update(&status, fraction);
+    }
+}
+
+/// End-of-run results shared by the printed summary, the reports and the budgets.
+struct Analysis {
+    /// The samples after --warmup, when it leaves some out
+    steady: Option<MemoryStats>,
+    phases: Vec<phases::Phase>,
+    rate_extremes: Option<((f64, f64), (f64, f64))>,
+    alloc_sites: Option<Vec<alloc_trace::SiteReport>>,
+    #[cfg(feature = "ebpf")]
+    ebpf_report: Option<ebpf::EbpfReport>,
+    segments: Vec<segments::Segment>,
+    spikes: Vec<spikes::Spike>,
+    leak_verdict: Option<leak::LeakVerdict>,
+    smaps_snapshots: Vec<smaps::SnapshotRecord>,
+    threshold_runs: Vec<alert::ExecRecord>,
+    warnings: Vec<String>,
+}
+
+impl Analysis {
+    /// What statistics and budgets cover: the whole run, or the part after --warmup.
+    fn measured<'a>(&'a self, stats: &'a MemoryStats) -> &'a MemoryStats {
+        self.steady.as_ref().unwrap_or(stats)
+    }
+}
+
+/// Print the end-of-run summary and work out everything the reports include.
+fn summarize(
+    run: &Run,
+    stats: &MemoryStats,
+    sampler: &mut Sampler,
+    alloc_trace: Option<&alloc_trace::AllocTrace>,
+) -> Result<Analysis> {
+    let args = run.args;
+    let smaps_snapshots = sampler
+        .smaps_snapshotter
+        .take()
+        .map(|s| s.snapshots)
+        .unwrap_or_default();
+    if !smaps_snapshots.is_empty() {
+        println!("\nsmaps snapshots:");
+        for snapshot in &smaps_snapshots {
+            println!(
+                "  {:>8.1}s  {:>10.2} MB  {:<9}  {}",
+                snapshot.time,
+                snapshot.rss_kb as f64 / 1024.0,
+                snapshot.reason,
+                snapshot.path
+            );
+        }
+    }
+    let threshold_runs = sampler
+        .threshold_command
+        .take()
+        .map(alert::ThresholdCommand::finish)
+        .unwrap_or_default();
+    for exec in &threshold_runs {
+        match (exec.exit_code, &exec.error) {
+            (_, Some(error)) => {
+                warn!(
+                    "Threshold command `{}` failed to start: {}",
+                    exec.command, error
+                )
+            }
+            (Some(code), _) => info!(
+                "Threshold command `{}` (at {:.1}s) exited with code {} after {:.1}s",
+                exec.command, exec.time, code, exec.duration_s
+            ),
+            (None, _) => warn!(
+                "Threshold command `{}` (at {:.1}s) was terminated by a signal",
+                exec.command, exec.time
+            ),
+        }
+    }
+
+    info!("Generating statistics...");
+    // Statistics, budgets and reports leave out --warmup; charts still show the whole run
+    let mut steady_from = f64::NEG_INFINITY;
+    let steady = match (args.warmup, stats.first_time()) {
+        (Some(warmup), Some(first)) => {
+            let steady = stats.since(first + warmup);
+            if steady.samples.is_empty() {
+                warn!(
+                    "--warmup {}s covers the whole run; statistics include every sample",
+                    warmup
+                );
+                None
+            } else {
+                info!("Statistics exclude the first {}s (--warmup)", warmup);
+                steady_from = first + warmup;
+                Some(steady)
+            }
+        }
+        _ => None,
+    };
+    let measured = steady.as_ref().unwrap_or(stats);
+    summary::print_summary(measured, args.trend_window as f64, args.units);
+    if let Some(window) = args.smooth {
+        let peak = measured.smoothed_peak(window as usize);
+        println!(
+            "Smoothed peak ({}-sample average): {}",
+            window,
+            units::describe(peak, args.units)
+        );
+    }
+    if args.swap {
+        println!(
+            "Max swap: {}",
+            units::describe(measured.max_swap() as f64, args.units)
+        );
+    }
+    // The mark covers the whole run, warm-up included
+    if let Some(peak_hold) = &sampler.peak_hold {
+        peak_hold.print(stats.max(), args.units);
+    }
+    if let Some(headroom) = &sampler.headroom {
+        headroom.print(args.units);
+    }
+    sampler.collectors.print(args.units);
+    let rate_extremes = (args.rate || args.rate_chart)
+        .then(|| rate_extremes(&stats.rate_series(args.rate_window as usize)))
+        .flatten();
+    if let Some(((grow_t, grow), (shrink_t, shrink))) = rate_extremes {
+        println!(
+            "Allocation rate ({}-sample average): fastest growth {:+.1} KB/s at {:.1}s, fastest release {:+.1} KB/s at {:.1}s",
+            args.rate_window, grow, grow_t, shrink, shrink_t
+        );
+    }
+    match (&args.jemalloc_stats, &sampler.last_jemalloc) {
+        (Some(_), Some((jemalloc_stats, rss_kb))) => {
+            let fragmentation_kb = jemalloc_stats.fragmentation_kb(*rss_kb);
+            println!(
+                "jemalloc (last reading): allocated {:.2} MB, active {:.2} MB, resident {:.2} MB",
+                jemalloc_stats.allocated_kb as f64 / 1024.0,
+                jemalloc_stats.active_kb as f64 / 1024.0,
+                jemalloc_stats.resident_kb as f64 / 1024.0
+            );
+            println!(
+                "Fragmentation (RSS - allocated): {:.2} MB ({:.1}% of RSS)",
+                fragmentation_kb as f64 / 1024.0,
+                jemalloc::percent_of(fragmentation_kb, *rss_kb)
+            );
+        }
+        (Some(path), None) => println!("jemalloc: no stats were read from {}", path),
+        _ => {}
+    }
+    let phases = phases::split(stats, steady_from);
+    if !phases.is_empty() {
+        phases::print(&phases, args.units);
+    }
+    if let Some(breakdown) = &sampler.child_breakdown {
+        breakdown.print(args.units);
+    }
+    let alloc_sites = alloc_trace.map(|trace| trace.top_sites(args.alloc_trace_top));
+    match (alloc_trace, &alloc_sites) {
+        (Some(trace), Some(sites)) if trace.reports() > 0 => {
+            println!(
+                "\nAllocation sites by growth ({} reports):",
+                trace.reports()
+            );
+            for site in sites {
+                println!(
+                    "  {:>+10.2} MB growth  {:>10.2} MB live in {:>8} blocks  {}",
+                    site.growth_bytes as f64 / 1048576.0,
+                    site.live_bytes as f64 / 1048576.0,
+                    site.live_count,
+                    site.site
+                );
+            }
+        }
+        (Some(_), _) => println!(
+            "\nAllocation trace: no reports received (statically linked or too short a run?)"
+        ),
+        _ => {}
+    }
+    #[cfg(feature = "ebpf")]
+    let ebpf_report = match sampler.ebpf_collector.take() {
+        Some(collector) => Some(collector.finish()?),
+        None => None,
+    };
+    #[cfg(feature = "ebpf")]
+    if let Some(report) = &ebpf_report {
+        if let Some(error) = &report.error {
+            println!("\neBPF ({}): {}", report.probe.name(), error);
+        } else if !report.top.is_empty() {
+            println!(
+                "\neBPF {} stacks by growth ({} intervals):",
+                report.probe.name(),
+                report.intervals.len()
+            );
+            for stack in &report.top {
+                println!(
+                    "  {:>+10.2} MB  {}",
+                    stack.bytes as f64 / 1048576.0,
+                    stack.stack
+                );
+            }
+        } else {
+            println!(
+                "\neBPF ({}): no allocations attributed",
+                report.probe.name()
+            );
+        }
+    }
+    let mut warnings = Vec::new();
+    let gap_tracker = &sampler.gap_tracker;
+    if !gap_tracker.gaps.is_empty() {
+        let warning = format!(
+            "{} sample(s) could not be taken in {} gap(s), the longest {:.1}s; statistics cover the samples taken",
+            gap_tracker.missed(),
+            gap_tracker.gaps.len(),
+            gap_tracker.gaps.iter().map(|gap| gap.end - gap.start).fold(0.0, f64::max)
+        );
+        warn!("{}", warning);
+        warnings.push(warning);
+    }
+    if let Some(watch) = sampler
+        .swap_watch
+        .as_ref()
+        .filter(|w| !w.episodes.is_empty())
+    {
+        let (pages_in, pages_out) = watch.system_pages();
+        let warning = format!(
+            "The process was swapped: {} episode(s), {:.2} MB swapped out and {:.2} MB in, \
+             VmSwap up to {:.2} MB (system: {} pages swapped in, {} out); RSS understates its memory use",
+            watch.episodes.len(),
+            watch.swapped_out_kb as f64 / 1024.0,
+            watch.swapped_in_kb as f64 / 1024.0,
+            watch.max_swap_kb as f64 / 1024.0,
+            pages_in,
+            pages_out
+        );
+        warn!("{}", warning);
+        warnings.push(warning);
+    }
+    for track in sampler
+        .mapping_tracker
+        .iter()
+        .flat_map(|tracker| &tracker.tracks)
+    {
+        track.print();
+    }
+    if let Some(tracker) = &sampler.thread_tracker {
+        print_thread_summary(tracker);
+    }
+    let segments = if args.segments {
+        let min_change_kb = args
+            .segment_min_change
+            .map(|kb| kb as f64)
+            .unwrap_or_else(|| segments::default_min_change_kb(stats));
+        let segments = segments::segment(stats, min_change_kb);
+        println!("\nSegments (min change {:.2} MB):", min_change_kb / 1024.0);
+        for segment in &segments {
+            let range = format!("{:.1}s - {:.1}s", segment.start, segment.end);
+            match segment.kind {
+                segments::SegmentKind::Plateau => println!(
+                    "  {:<20} {:<8} {:.2} MB",
+                    range,
+                    segment.kind.name(),
+                    segment.level_kb / 1024.0
+                ),
+                _ => println!(
+                    "  {:<20} {:<8} {:.2} MB -> {:.2} MB ({:+.2} MB/s)",
+                    range,
+                    segment.kind.name(),
+                    segment.start_kb / 1024.0,
+                    segment.end_kb / 1024.0,
+                    segment.slope_kb_per_s / 1024.0
+                ),
+            }
+        }
+        segments
+    } else {
+        Vec::new()
+    };
+    let spikes = match args.spikes {
+        Some(top) => {
+            let spikes =
+                spikes::detect(stats, args.spike_window as usize, args.spike_threshold, top);
+            println!("\nTop {} spikes (z > {}):", top, args.spike_threshold);
+            if spikes.is_empty() {
+                println!("  none");
+            }
+            for spike in &spikes {
+                println!(
+                    "  {:.1}s: {:.2} MB ({:+.2} MB over {:.2} MB baseline, z = {:.1}, {:.1}s)",
+                    spike.time,
+                    spike.peak_kb as f64 / 1024.0,
+                    spike.magnitude_kb() / 1024.0,
+                    spike.baseline_kb / 1024.0,
+                    spike.z_score,
+                    spike.duration
+                );
+            }
+            spikes
+        }
+        None => Vec::new(),
+    };
+    let leak_verdict =
+        if args.detect_leak {
+            let verdict = leak::detect(stats, args.leak_warmup.or(args.warmup), args.leak_alpha);
+            match &verdict {
+                Some(v) => println!(
+                "\nLeak check (Mann-Kendall from {:.1}s, {} points): tau = {:.3}, p = {:.2e} => {}",
+                v.from_time,
+                v.points,
+                v.tau,
+                v.p_value,
+                if v.suspected { "LEAK SUSPECTED" } else { "no leak detected" }
+            ),
+                None => println!("\nLeak check: not enough steady-state samples"),
+            }
+            verdict
+        } else {
+            None
+        };
+
+    Ok(Analysis {
+        steady,
+        phases,
+        rate_extremes,
+        alloc_sites,
+        #[cfg(feature = "ebpf")]
+        ebpf_report,
+        segments,
+        spikes,
+        leak_verdict,
+        smaps_snapshots,
+        threshold_runs,
+        warnings,
+    })
+}
+
+/// The chart, with --histogram, --output-dir and --children the charts beside it.
+fn write_charts(
+    run: &Run,
+    stats: &MemoryStats,
+    sampler: &Sampler,
+    analysis: &Analysis,
+) -> Result<()> {
+    let args = run.args;
+    if args.no_chart {
+        info!("Chart rendering disabled");
+        return Ok(());
+    }
+    if stats.samples.is_empty() {
+        info!("No samples collected, skipping chart generation");
+        return Ok(());
+    }
+    info!("Generating chart: {}", args.output);
+    let chart_options = chart::ChartOptions {
+        segments: if args.color_segments {
+            analysis.segments.clone()
+        } else {
+            Vec::new()
+        },
+        spikes: analysis
+            .spikes
+            .iter()
+            .map(|s| {
+                let label = match args.units {
+                    Some(unit) => format!("+{}", unit.format(s.magnitude_kb())),
+                    None => format!("{:+.1} MB", s.magnitude_kb() / 1024.0),
+                };
+                (s.time, s.peak_kb as f64, label)
+            })
+            .collect(),
+        ..base_chart_options(args, stats, &run.run_metadata, run.wallclock_start)?
+    };
+    chart::generate_chart(stats, &args.output, &chart_options)?;
+    info!("Chart saved successfully!");
+
+    if let Some(histogram_path) = &args.histogram {
+        let buckets = stats.histogram(args.histogram_bins as usize);
+        info!("Generating histogram: {}", histogram_path);
+        chart::generate_histogram(&buckets, histogram_path, &chart_options)?;
+
+        let csv_path = std::path::Path::new(histogram_path).with_extension("csv");
+        let mut csv_content = String::from("lower_kb,upper_kb,count\n");
+        for (lower, upper, count) in &buckets {
+            csv_content.push_str(&format!("{:.0},{:.0},{}\n", lower, upper, count));
+        }
+        fs::write(&csv_path, csv_content)
+            .with_context(|| format!("Failed to write histogram CSV: {}", csv_path.display()))?;
+        info!(
+            "Histogram saved successfully! (bucket counts: {})",
+            csv_path.display()
+        );
+    }
+
+    if let Some(dir) = &args.output_dir {
+        let panels = metric_panels(
+            args,
+            stats,
+            sampler.thread_tracker.as_ref(),
+            &sampler.collectors,
+            &chart_options,
+        );
+        write_metric_charts(dir, &panels, &args.output, &chart_options)?;
+    }
+
+    if let Some(breakdown) = &sampler.child_breakdown {
+        let path = children::chart_path(&args.output);
+        info!("Generating per-process chart: {}", path);
+        let options = chart::ChartOptions {
+            title: format!("{} by process", chart_options.title),
+            style: chart::ChartStyle::Stacked,
+            ..chart_options.clone()
+        };
+        chart::generate_chart(&breakdown.stacked(), &path, &options)?;
+        info!("Chart saved successfully!");
+    }
+    Ok(())
+}
+
+fn write_json(
+    run: &Run,
+    stats: &MemoryStats,
+    sampler: &Sampler,
+    analysis: &Analysis,
+    json_path: &str,
+) -> Result<()> {
+    let args = run.args;
+    info!("Writing JSON summary: {}", json_path);
+    let measured = analysis.measured(stats);
+    let mut statistics = measured.summary_json(args.trend_window as f64);
+    if analysis.steady.is_some() {
+        statistics["warmup_s"] = serde_json::json!(args.warmup);
+    }
+    if !analysis.phases.is_empty() {
+        statistics["phases"] = analysis.phases.iter().map(phases::Phase::to_json).collect();
+    }
+    if let Some(window) = args.smooth {
+        statistics["smoothed_peak_kb"] = serde_json::json!(measured.smoothed_peak(window as usize));
+    }
+    if args.swap {
+        statistics["max_swap_kb"] = serde_json::json!(measured.max_swap());
+    }
+    if let Some(peak_hold) = sampler.peak_hold.as_ref().filter(|p| p.hwm_kb().is_some()) {
+        statistics["high_water_mark"] = peak_hold.to_json(stats.max());
+    }
+    if let Some(headroom) = &sampler.headroom {
+        statistics["headroom"] = headroom.to_json();
+    }
+    if !sampler.collectors.is_empty() {
+        statistics["collected"] = sampler.collectors.to_json();
+    }
+    if let Some((jemalloc_stats, rss_kb)) = &sampler.last_jemalloc {
+        statistics["jemalloc"] = jemalloc_stats.to_json(*rss_kb);
+    }
+    if let Some(sites) = &analysis.alloc_sites {
+        statistics["alloc_sites"] = sites.iter().map(|s| s.to_json()).collect();
+    }
+    #[cfg(feature = "ebpf")]
+    if let Some(report) = &analysis.ebpf_report {
+        statistics["ebpf"] = report.to_json();
+    }
+    if let Some(((grow_t, grow), (shrink_t, shrink))) = analysis.rate_extremes {
+        statistics["rate"] = serde_json::json!({
+            "window_samples": args.rate_window,
+            "max_kb_per_s": grow,
+            "max_at_s": grow_t,
+            "min_kb_per_s": shrink,
+            "min_at_s": shrink_t,
+        });
+    }
+    if let Some(watch) = &sampler.swap_watch {
+        statistics["swap_activity"] = watch.to_json();
+    }
+    if args.tolerate_gaps {
+        statistics["gaps"] = sampler
+            .gap_tracker
+            .gaps
+            .iter()
+            .map(|gap| gap.to_json())
+            .collect();
+    }
+    if let Some(tracker) = &sampler.mapping_tracker {
+        statistics["mappings"] = tracker
+            .tracks
+            .iter()
+            .map(smaps::MappingTrack::to_json)
+            .collect();
+    }
+    if let Some(tracker) = &sampler.thread_tracker {
+        statistics["threads"] = tracker.to_json();
+    }
+    if let Some(breakdown) = &sampler.child_breakdown {
+        statistics["children"] = breakdown.to_json();
+    }
+    if args.segments {
+        statistics["segments"] = analysis.segments.iter().map(|s| s.to_json()).collect();
+    }
+    if args.spikes.is_some() {
+        statistics["spikes"] = analysis.spikes.iter().map(|s| s.to_json()).collect();
+    }
+    if args.detect_leak {
+        statistics["leak"] =
+            serde_json::json!(analysis.leak_verdict.map(leak::LeakVerdict::to_json));
+    }
+    let mut metadata = run.run_metadata.to_json();
+    metadata["duration_s"] = serde_json::json!(run.start_time.elapsed().as_secs_f64());
+    metadata["killed"] = serde_json::json!(sampler.kill.map(KillEvent::to_json));
+    if let Some(watch) = &sampler.exec_watch {
+        metadata["execs"] = watch.execs.iter().map(|e| e.to_json()).collect();
+    }
+    metadata["threshold_commands"] = analysis
+        .threshold_runs
+        .iter()
+        .map(|r| r.to_json())
+        .collect();
+    metadata["smaps_snapshots"] = analysis
+        .smaps_snapshots
+        .iter()
+        .map(|s| s.to_json())
+        .collect();
+    let summary = serde_json::json!({
+        "metadata": metadata,
+        "statistics": statistics,
+    });
+    fs::write(json_path, serde_json::to_string_pretty(&summary)?)
+        .with_context(|| format!("Failed to write JSON summary: {}", json_path))?;
+    info!("JSON summary saved successfully!");
+    Ok(())
+}
+
+fn write_html(run: &Run, stats: &MemoryStats, analysis: &Analysis, html_path: &str) -> Result<()> {
+    let (args, target, run_metadata) = (run.args, &run.target, &run.run_metadata);
+    info!("Writing HTML report: {}", html_path);
+    let mut metadata = serde_json::json!({
+        "PID": target.pid,
+        "Command line": target.cmdline,
+        "Executable": run_metadata.exe,
+        "Host": hostname(),
+        "Kernel": run_metadata.kernel,
+        "cgroup limit (MB)": run_metadata.cgroup_limit_kb.map(|kb| kb as f64 / 1024.0),
+        "Run ID": target.run_id,
+        "Started": run.started_at.to_rfc3339(),
+        "Interval (ms)": args.interval,
+        "Duration (s)": run.start_time.elapsed().as_secs_f64(),
+        "Tracker": format!("memory_tracker {}: {}", env!("CARGO_PKG_VERSION"), run_metadata.tracker_args),
+    });
+    if !analysis.smaps_snapshots.is_empty() {
+        metadata["smaps snapshots"] = analysis
+            .smaps_snapshots
+            .iter()
+            .map(|s| format!("{} ({}, {:.1}s)", s.path, s.reason, s.time))
+            .collect::<Vec<_>>()
+            .join(", ")
+            .into();
+    }
+    html_report::write(html_path, stats, &metadata, &analysis.warnings)?;
+    info!("HTML report saved successfully!");
+    Ok(())
+}
+
+/// The outcome of --detect-leak, --max-mean and --max-peak.
+struct Budgets {
+    checks: Vec<ci_report::Check>,
+    violations: Vec<String>,
+    /// The reserved code of the most severe failed check
+    exit_code: Option<i32>,
+}
+
+/// Failed checks' exit codes, most severe first: a peak over budget outranks a mean over
+/// budget, which outranks a suspected leak.
+const BUDGET_SEVERITY: [i32; 3] = [EXIT_PEAK_BUDGET, EXIT_MEAN_BUDGET, EXIT_LEAK_SUSPECTED];
+
+/// Check `measured` against every budget given, reporting all of them.
+fn check_budgets(
+    args: &RecordArgs,
+    measured: &MemoryStats,
+    leak_verdict: Option<leak::LeakVerdict>,
+) -> Budgets {
+    let mut failed = Vec::new();
+    let mut violations = Vec::new();
+    let mut checks = Vec::new();
+    if args.detect_leak {
+        if leak_verdict.is_some_and(|v| v.suspected) {
+            failed.push(EXIT_LEAK_SUSPECTED);
+        }
+        checks.push(ci_report::Check {
+            name: "leak check".to_string(),
+            failure: leak_verdict.filter(|v| v.suspected).map(|v| {
+                format!(
+                    "Leak suspected: upward trend with tau = {:.3}, p = {:.2e}",
+                    v.tau, v.p_value
+                )
+            }),
+            output: match leak_verdict {
+                Some(v) => format!(
+                    "Mann-Kendall from {:.1}s, {} points: tau = {:.3}, p = {:.2e}, alpha = {}",
+                    v.from_time, v.points, v.tau, v.p_value, args.leak_alpha
+                ),
+                None => "not enough steady-state samples".to_string(),
+            },
+        });
+    }
+    if let Some(budget_kb) = args.max_mean {
+        let mean = measured.mean();
+        let failure = (mean > budget_kb as f64).then(|| {
+            format!(
+                "Budget exceeded: mean {:.2} MB > --max-mean {:.2} MB",
+                mean / 1024.0,
+                budget_kb as f64 / 1024.0
+            )
+        });
+        if let Some(violation) = &failure {
+            violations.push(violation.clone());
+            failed.push(EXIT_MEAN_BUDGET);
+        }
+        checks.push(ci_report::Check {
+            name: "mean budget".to_string(),
+            failure,
+            output: format!(
+                "mean {:.2} MB, budget {:.2} MB",
+                mean / 1024.0,
+                budget_kb as f64 / 1024.0
+            ),
+        });
+    }
+    if let Some(budget_kb) = args.max_peak {
+        let peak = measured.max();
+        let failure = (peak > budget_kb).then(|| {
+            format!(
+                "Budget exceeded: peak {:.2} MB > --max-peak {:.2} MB",
+                peak as f64 / 1024.0,
+                budget_kb as f64 / 1024.0
+            )
+        });
+        if let Some(violation) = &failure {
+            violations.push(violation.clone());
+            failed.push(EXIT_PEAK_BUDGET);
+        }
+        checks.push(ci_report::Check {
+            name: "peak budget".to_string(),
+            failure,
+            output: format!(
+                "peak {:.2} MB, budget {:.2} MB",
+                peak as f64 / 1024.0,
+                budget_kb as f64 / 1024.0
+            ),
+        });
+    }
+    Budgets {
+        checks,
+        violations,
+        exit_code: BUDGET_SEVERITY
+            .into_iter()
+            .find(|code| failed.contains(code)),
+    }
+}
+
+/// The JUnit report, GitHub Actions annotations and Markdown report for CI.
+fn write_ci_reports(
+    run: &Run,
+    stats: &MemoryStats,
+    analysis: &Analysis,
+    budgets: &Budgets,
+) -> Result<()> {
+    let args = run.args;
+    if let Some(report_path) = &args.report {
+        info!("Writing JUnit report: {}", report_path);
+        ci_report::write_junit(
+            report_path,
+            &format!("memory_tracker: {}", run.target.cmdline),
+            &budgets.checks,
+            run.started_at,
+            run.start_time.elapsed().as_secs_f64(),
+        )?;
+        info!("JUnit report saved successfully!");
+    }
+    if args.gha_annotations {
+        ci_report::print_annotations(&budgets.checks, &analysis.warnings);
+    }
+    if let Some(md_path) = &args.md_report {
+        info!("Writing Markdown report: {}", md_path);
+        // Without --spikes the report still lists the few most notable ones
+        let notable = match args.spikes {
+            Some(_) => analysis.spikes.clone(),
+            None => spikes::detect(stats, args.spike_window as usize, args.spike_threshold, 5),
+        };
+        md_report::MarkdownReport {
+            stats: analysis.measured(stats),
+            metadata: &run.run_metadata,
+            duration: run.start_time.elapsed().as_secs_f64(),
+            unit: args.units.unwrap_or_default(),
+            checks: &budgets.checks,
+            spikes: &notable,
+            warnings: &analysis.warnings,
+            chart: (!args.no_chart && !stats.samples.is_empty()).then_some(args.output.as_str()),
+        }
+        .write(md_path)?;
+        info!("Markdown report saved successfully!");
+    }
+    Ok(())
+}
+
+/// Post the end-of-run summary to the chat services.
+fn notify_finished(
+    run: &Run,
+    stats: &MemoryStats,
+    sampler: &mut Sampler,
+    analysis: &Analysis,
+    budgets: &Budgets,
+) {
+    let (args, target) = (run.args, &run.target);
+    if sampler.chat_notifiers.is_empty() {
+        return;
+    }
+    let measured = analysis.measured(stats);
+    let mut text = format!(
+        "Memory tracking finished on {}: `{}` (pid {})\n\
+         Duration: {:.1}s, samples: {}\n\
+         Peak: {:.2} MB, mean: {:.2} MB, p95: {:.2} MB",
+        hostname(),
+        target.cmdline,
+        target.pid,
+        run.start_time.elapsed().as_secs_f64(),
+        measured.sample_count(),
+        measured.max() as f64 / 1024.0,
+        measured.mean() / 1024.0,
+        measured.percentile(95.0) / 1024.0
+    );
+    if let Some(fit) = measured.linear_fit(f64::NEG_INFINITY) {
+        text.push_str(&format!(
+            "\nGrowth: {:.2} MB/hour (R² = {:.3})",
+            fit.mb_per_hour(),
+            fit.r_squared
+        ));
+    }
+    if let Some(verdict) = analysis.leak_verdict {
+        text.push_str(&format!(
+            "\nLeak check: {} (p = {:.2e})",
+            if verdict.suspected {
+                "LEAK SUSPECTED"
+            } else {
+                "no leak detected"
+            },
+            verdict.p_value
+        ));
+    }
+    if let Some(kill) = sampler.kill {
+        text.push_str(&format!(
+            "\nSent {} at {:.2} MB after {:.1}s (--kill-at {:.2} MB)",
+            kill.signal.name(),
+            kill.rss_kb as f64 / 1024.0,
+            kill.time,
+            kill.limit_kb as f64 / 1024.0
+        ));
+    }
+    for violation in &budgets.violations {
+        text.push_str(&format!("\n{}", violation));
+    }
+    let chart = (args.notify_chart && !stats.samples.is_empty()).then(|| args.output.clone());
+    for notifier in &sampler.chat_notifiers {
+        sampler
+            .pending_notifications
+            .push(notifier.send(text.clone(), chart.clone()));
+    }
+}
+
+/// The exit code a shell would report for `status`: the command's own code, or 128 plus
+/// the number of the signal that terminated it.
+fn exit_code_of(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// The (time, KB/s) points of fastest growth and fastest release.
+fn rate_extremes(rate: &[(f64, f64)]) -> Option<((f64, f64), (f64, f64))> {
+    let max = rate.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let min = rate.iter().copied().min_by(|a, b| a.1.total_cmp(&b.1))?;
+    Some((max, min))
+}
+
+fn print_thread_summary(tracker: &threads::ThreadTracker) {
+    let Some((first, last, max)) = tracker.count_range else {
+        return;
+    };
+    println!(
+        "\nThreads: {} at start, {} at end, {} at most",
+        first, last, max
+    );
+    if last > first {
+        println!("  Thread count grew by {} over the run", last - first);
+    }
+    if let Some((count, rss_kb)) = tracker.last_arenas {
+        println!(
+            "  malloc arenas: {} ({:.2} MB resident)",
+            count,
+            rss_kb as f64 / 1024.0
+        );
+    }
+    let stacks_kb: u64 = tracker
+        .threads()
+        .filter(|t| !t.exited)
+        .filter_map(|t| t.last_stack_kb)
+        .sum();
+    println!(
+        "  Resident thread stacks: {:.2} MB",
+        stacks_kb as f64 / 1024.0
+    );
+    let growing = tracker.growing();
+    if !growing.is_empty() {
+        println!("  Threads whose stack grew:");
+        for thread in growing.iter().take(10) {
+            println!(
+                "    {:>8} {:<16} {:>+10.2} MB (now {:.2} MB){}",
+                thread.tid,
+                thread.name,
+                thread.growth_kb() as f64 / 1024.0,
+                thread.last_stack_kb.unwrap_or_default() as f64 / 1024.0,
+                if thread.exited { ", exited" } else { "" }
+            );
+        }
+    }
+}
+
+/// Track the processes with the highest RSS until interrupted or `--duration` runs out,
+/// then write one line per process that was ever in the top set.
+fn run_top(args: &RecordArgs, n: usize) -> Result<()> {
+    let start_time = Instant::now();
+    let started_at = chrono::Local::now();
+    let interval = Duration::from_millis(args.interval);
+    let refresh = Duration::from_secs(args.top_refresh.max(1));
+    let duration = (args.duration > 0).then(|| Duration::from_secs(args.duration));
+    info!(
+        "Tracking the top {} processes by RSS, re-ranked every {}s",
+        n,
+        refresh.as_secs()
+    );
+    info!("Sampling interval: {}ms", args.interval);
+    info!("Press Ctrl+C to stop and generate chart\n");
+
+    let mut tracker = top::TopTracker::new(n);
+    let progress = logging::Progress::new(args.progress);
+    let mut peak_total_kb = 0;
+    let mut next_refresh = start_time;
+    let mut next_sample = start_time;
+    while !shutdown::requested() && duration.is_none_or(|d| start_time.elapsed() < d) {
+        let now = Instant::now();
+        let time = start_time.elapsed().as_secs_f64();
+        // A refresh samples every member itself
+        if now >= next_refresh {
+            for change in tracker.refresh(time) {
+                let line = match change {
+                    top::TopChange::Entered(label) => format!("{} entered the top {}", label, n),
+                    top::TopChange::Left(label) => format!("{} left the top {}", label, n),
+                    top::TopChange::Exited(label) => format!("{} exited", label),
+                };
+                info!("[{:.1}s] {}", time, line);
+            }
+            next_refresh += refresh;
+        } else if now >= next_sample {
+            tracker.sample(time);
+        }
+        if now >= next_sample {
+            next_sample += interval;
+        }
+        let total_kb = tracker.total_kb();
+        peak_total_kb = peak_total_kb.max(total_kb);
+        let fraction = match duration {
+            Some(d) => start_time.elapsed().as_secs_f64() / d.as_secs_f64(),
+            None => total_kb as f64 / peak_total_kb.max(1) as f64,
+        };
+        progress.update(
+            &format!(
+                "Time: {:.1}s | Top {} total: {:.2} MB",
+                time,
+                n,
+                total_kb as f64 / 1024.0
+            ),
+            fraction,
+        );
+        shutdown::sleep_until(next_sample.min(next_refresh));
+    }
+    progress.finish();
+    info!("Stopping monitoring...");
+
+    let mut processes: Vec<&top::TopProcess> = tracker
+        .processes()
+        .iter()
+        .filter(|p| !p.stats.samples.is_empty())
+        .collect();
+    if processes.is_empty() {
+        bail!("No process was sampled");
+    }
+    processes.sort_by_key(|p| std::cmp::Reverse(p.stats.max()));
+
+    println!(
+        "\n{:<32} {:>8} {:>12} {:>12} {:>10}",
+        "Process", "Samples", "Peak MB", "Mean MB", "Status"
+    );
+    for process in &processes {
+        println!(
+            "{:<32} {:>8} {:>12.2} {:>12.2} {:>10}",
+            process.label(),
+            process.stats.samples.len(),
+            process.stats.max() as f64 / 1024.0,
+            process.stats.mean() / 1024.0,
+            match (process.in_top, process.exited) {
+                (_, true) => "exited",
+                (true, _) => "top",
+                _ => "dropped",
+            }
+        );
+    }
+
+    if !args.no_chart {
+        info!("Generating chart: {}", args.output);
+        let lines: Vec<(String, &MemoryStats)> =
+            processes.iter().map(|p| (p.label(), &p.stats)).collect();
+        let options = chart::ChartOptions {
+            downsample: args.downsample,
+            downsample_points: args.downsample_points,
+            unit: args.units.unwrap_or_default(),
+            x_ticks: args.x_ticks,
+            ..args.chart.options()
+        };
+        chart::generate_overlay(
+            &lines,
+            chart::OverlayAxis::Elapsed("Time"),
+            &args.output,
+            &options,
+        )?;
+        info!("Chart saved successfully!");
+    }
+
+    if let Some(csv_path) = &args.csv_output {
+        if recording::is_sqlite(csv_path) || recording::is_parquet(csv_path) {
+            bail!(
+                "--top writes its recording as CSV only, not to {}",
+                csv_path
+            );
+        }
+        let mut csv = String::from("pid,name,time,memory_kb\n");
+        for process in &processes {
+            for (time, memory_kb) in &process.stats.samples {
+                csv.push_str(&format!(
+                    "{},{},{:.3},{}\n",
+                    process.pid,
+                    crate::csv_writer::csv_field(&process.name),
+                    time,
+                    memory_kb
+                ));
+            }
+        }
+        fs::write(csv_path, csv)
+            .with_context(|| format!("Failed to write CSV file: {}", csv_path))?;
+        info!("Recording saved to CSV: {}", csv_path);
+    }
+
+    if let Some(json_path) = &args.json {
+        let summary = serde_json::json!({
+            "metadata": {
+                "host": hostname(),
+                "started": started_at.to_rfc3339(),
+                "top": n,
+                "refresh_s": refresh.as_secs(),
+                "interval_ms": args.interval,
+            },
+            "processes": processes
+                .iter()
+                .map(|p| serde_json::json!({
+                    "pid": p.pid,
+                    "name": p.name,
+                    "in_top": p.in_top,
+                    "exited": p.exited,
+                    "first_seen_s": p.stats.samples.first().map(|(t, _)| *t),
+                    "last_seen_s": p.stats.samples.last().map(|(t, _)| *t),
+                    "statistics": p.stats.summary_json(args.trend_window as f64),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        fs::write(json_path, serde_json::to_string_pretty(&summary)?)
+            .with_context(|| format!("Failed to write JSON summary: {}", json_path))?;
+        info!("JSON summary saved to {}", json_path);
+    }
+    Ok(())
+}
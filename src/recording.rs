@@ -0,0 +1,545 @@
+use anyhow::{bail, Context, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::fs;
+use std::io::Read;
+use tracing::warn;
+
+use crate::stats::MemoryStats;
+use crate::units::{self, Unit};
+
+/// Tables of a recording written to an SQLite file with `--csv-output run.sqlite`. Memory
+/// is always in KB, `time` in seconds on the run's axis, and `timestamp` the RFC3339 time
+/// of `--time-axis wallclock` recordings. `metadata` holds the run metadata that heads a
+/// CSV as comments.
+pub const SQLITE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS metadata (
+         key TEXT NOT NULL,
+         value TEXT NOT NULL
+     );
+     CREATE TABLE IF NOT EXISTS samples (
+         time REAL NOT NULL,
+         timestamp TEXT,
+         memory_kb INTEGER NOT NULL,
+         rate_kb_s REAL
+     );
+     CREATE TABLE IF NOT EXISTS markers (
+         time REAL NOT NULL,
+         timestamp TEXT,
+         label TEXT NOT NULL
+     );";
+
+/// Schema of a recording written to a Parquet file with `--csv-output run.parquet`: the
+/// rows of a CSV recording, memory always in KB. Marker rows have a `marker` and no
+/// memory, and `timestamp` is the RFC3339 time of `--time-axis wallclock` recordings. The
+/// run metadata is the file's key-value metadata.
+pub const PARQUET_SCHEMA: &str = "message recording {
+         REQUIRED DOUBLE time;
+         OPTIONAL BYTE_ARRAY timestamp (UTF8);
+         OPTIONAL INT64 memory_kb;
+         OPTIONAL DOUBLE rate_kb_s;
+         OPTIONAL BYTE_ARRAY marker (UTF8);
+     }";
+
+/// One row of a Parquet recording (see [`PARQUET_SCHEMA`]).
+#[derive(Debug, Clone, Default)]
+pub struct ParquetRow {
+    pub time: f64,
+    pub timestamp: Option<String>,
+    pub memory_kb: Option<u64>,
+    pub rate_kb_s: Option<f64>,
+    pub marker: Option<String>,
+}
+
+/// Whether `path` names an SQLite recording (`.sqlite` or `.db`) rather than a CSV.
+pub fn is_sqlite(path: &str) -> bool {
+    has_extension(path, &["sqlite", "db"])
+}
+
+/// Whether `path` names a Parquet recording (`.parquet`) rather than a CSV.
+pub fn is_parquet(path: &str) -> bool {
+    has_extension(path, &["parquet"])
+}
+
+fn has_extension(path: &str, extensions: &[&str]) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// The run metadata of a Parquet recording, as `key: value` comments head a CSV.
+pub type ParquetMetadata = Vec<(String, String)>;
+
+/// The contents of a Parquet recording.
+pub struct ParquetRecording {
+    pub metadata: ParquetMetadata,
+    pub rows: Vec<ParquetRow>,
+}
+
+/// Read the Parquet recording at `path`.
+pub fn read_parquet(path: &str) -> Result<ParquetRecording> {
+    let (metadata, rows) = open_parquet(path)?;
+    Ok(ParquetRecording {
+        metadata,
+        rows: rows.collect::<Result<_>>()?,
+    })
+}
+
+/// The run metadata of the Parquet recording at `path`, and its rows parsed as they are
+/// read, so a long recording is never held in memory as a whole.
+pub fn open_parquet(
+    path: &str,
+) -> Result<(ParquetMetadata, impl Iterator<Item = Result<ParquetRow>>)> {
+    let reader = SerializedFileReader::new(fs::File::open(path)?)?;
+    let metadata = reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .into_iter()
+        .flatten()
+        .map(|kv| (kv.key.clone(), kv.value.clone().unwrap_or_default()))
+        .collect();
+    let rows = reader.into_iter().map(|row| {
+        let mut parsed = ParquetRow::default();
+        for (name, field) in row?.get_column_iter() {
+            match (name.as_str(), field) {
+                ("time", Field::Double(time)) => parsed.time = *time,
+                ("timestamp", Field::Str(timestamp)) => parsed.timestamp = Some(timestamp.clone()),
+                ("memory_kb", Field::Long(memory_kb)) => parsed.memory_kb = Some(*memory_kb as u64),
+                ("rate_kb_s", Field::Double(rate)) => parsed.rate_kb_s = Some(*rate),
+                ("marker", Field::Str(label)) => parsed.marker = Some(label.clone()),
+                _ => {}
+            }
+        }
+        Ok(parsed)
+    });
+    Ok((metadata, rows))
+}
+
+/// Load a recording written with `--csv-output` (`time,memory_kb,marker`, with a
+/// `rate_kb_s` column before the marker when written with `--rate`), optionally
+/// gzip- or zstd-compressed, or its SQLite or Parquet equivalent. `#` comment lines
+/// describing the run are skipped. Memory written in MiB or GiB (`memory_mib`,
+/// `memory_gib`) is converted back to KB.
+///
+/// Times may be seconds since start or RFC3339 timestamps (`--time-axis wallclock`);
+/// the latter are converted to seconds since the first row.
+pub fn load_csv(path: &str) -> Result<MemoryStats> {
+    load_csv_with_origin(path).map(|(stats, _)| stats)
+}
+
+/// [`load_csv`], also returning the timestamp of the first row for wall-clock recordings.
+pub fn load_csv_with_origin(
+    path: &str,
+) -> Result<(MemoryStats, Option<chrono::DateTime<chrono::FixedOffset>>)> {
+    if is_parquet(path) {
+        return load_parquet(path).with_context(|| format!("Failed to read recording: {}", path));
+    }
+    if is_sqlite(path) {
+        return load_sqlite(path).with_context(|| format!("Failed to read recording: {}", path));
+    }
+    let content = read_text(path).with_context(|| format!("Failed to read recording: {}", path))?;
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .skip_while(|(_, line)| line.starts_with('#'));
+    let header = lines.next().map(|(_, line)| line).unwrap_or_default();
+    let Some((unit, has_rate)) = parse_header(header) else {
+        bail!(
+            "{}: expected a time,memory_kb[,marker] header, got '{}'",
+            path,
+            header
+        );
+    };
+
+    let mut stats = MemoryStats::new();
+    let mut origin: Option<chrono::DateTime<chrono::FixedOffset>> = None;
+    for (idx, line) in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let line_no = idx + 1;
+        let mut fields = line.splitn(if has_rate { 4 } else { 3 }, ',');
+        let (time, memory) = (
+            fields.next().unwrap_or_default(),
+            fields.next().unwrap_or_default(),
+        );
+        if has_rate {
+            fields.next();
+        }
+        let marker = fields.next().unwrap_or_default();
+
+        let time = match time.parse::<f64>() {
+            Ok(seconds) => seconds,
+            Err(_) => {
+                let timestamp = chrono::DateTime::parse_from_rfc3339(time)
+                    .with_context(|| format!("{}:{}: invalid time '{}'", path, line_no, time))?;
+                let origin = *origin.get_or_insert(timestamp);
+                (timestamp - origin).num_milliseconds() as f64 / 1000.0
+            }
+        };
+
+        if memory.is_empty() {
+            stats.markers.push((time, unquote(marker)));
+        } else {
+            let memory_kb = match unit {
+                Unit::Kb => memory.parse::<u64>().ok(),
+                _ => memory
+                    .parse::<f64>()
+                    .ok()
+                    .map(|value| (value * unit.kb()).round() as u64),
+            }
+            .with_context(|| format!("{}:{}: invalid memory value '{}'", path, line_no, memory))?;
+            stats.add_sample(time, memory_kb);
+        }
+    }
+
+    if stats.samples.is_empty() {
+        bail!("{}: recording contains no samples", path);
+    }
+    Ok((stats, origin))
+}
+
+/// [`load_csv_with_origin`] for an SQLite recording. Wall-clock times are taken from
+/// the timestamps, relative to the earliest one.
+fn load_sqlite(path: &str) -> Result<(MemoryStats, Option<chrono::DateTime<chrono::FixedOffset>>)> {
+    let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let origin = db
+        .query_row(
+            "SELECT timestamp FROM (SELECT time, timestamp FROM samples UNION ALL SELECT time, timestamp FROM markers)
+             WHERE timestamp IS NOT NULL ORDER BY time LIMIT 1",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(|timestamp| chrono::DateTime::parse_from_rfc3339(&timestamp))
+        .transpose()?;
+    let time_of = |time: f64, timestamp: Option<String>| -> Result<f64> {
+        match (origin, timestamp) {
+            (Some(origin), Some(timestamp)) => {
+                let timestamp = chrono::DateTime::parse_from_rfc3339(&timestamp)
+                    .with_context(|| format!("invalid time '{}'", timestamp))?;
+                Ok((timestamp - origin).num_milliseconds() as f64 / 1000.0)
+            }
+            _ => Ok(time),
+        }
+    };
+
+    let mut stats = MemoryStats::new();
+    let mut samples =
+        db.prepare("SELECT time, timestamp, memory_kb FROM samples ORDER BY rowid")?;
+    let rows = samples.query_map([], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)?))
+    })?;
+    for row in rows {
+        let (time, timestamp, memory_kb) = row?;
+        stats.add_sample(time_of(time, timestamp)?, memory_kb as u64);
+    }
+    let mut markers = db.prepare("SELECT time, timestamp, label FROM markers ORDER BY rowid")?;
+    let rows = markers.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    for row in rows {
+        let (time, timestamp, label) = row?;
+        stats.markers.push((time_of(time, timestamp)?, label));
+    }
+
+    if stats.samples.is_empty() {
+        bail!("recording contains no samples");
+    }
+    Ok((stats, origin))
+}
+
+/// [`load_csv_with_origin`] for a Parquet recording.
+fn load_parquet(
+    path: &str,
+) -> Result<(MemoryStats, Option<chrono::DateTime<chrono::FixedOffset>>)> {
+    let mut stats = MemoryStats::new();
+    let mut origin: Option<chrono::DateTime<chrono::FixedOffset>> = None;
+    for row in read_parquet(path)?.rows {
+        let time = match row.timestamp {
+            Some(timestamp) => {
+                let timestamp = chrono::DateTime::parse_from_rfc3339(&timestamp)
+                    .with_context(|| format!("invalid time '{}'", timestamp))?;
+                let origin = *origin.get_or_insert(timestamp);
+                (timestamp - origin).num_milliseconds() as f64 / 1000.0
+            }
+            None => row.time,
+        };
+        match (row.memory_kb, row.marker) {
+            (Some(memory_kb), _) => stats.add_sample(time, memory_kb),
+            (None, Some(label)) => stats.markers.push((time, label)),
+            (None, None) => {}
+        }
+    }
+
+    if stats.samples.is_empty() {
+        bail!("recording contains no samples");
+    }
+    Ok((stats, origin))
+}
+
+/// The memory unit of the recording at `path` and whether it has the `rate_kb_s`
+/// column of `--rate`, so rows appended to it can match.
+pub fn layout(path: &str) -> Result<(Unit, bool)> {
+    if is_sqlite(path) {
+        let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to read recording: {}", path))?;
+        let has_rate = db
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM samples WHERE rate_kb_s IS NOT NULL)",
+                [],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("Failed to read recording: {}", path))?;
+        return Ok((Unit::Kb, has_rate));
+    }
+    if is_parquet(path) {
+        let recording =
+            read_parquet(path).with_context(|| format!("Failed to read recording: {}", path))?;
+        return Ok((
+            Unit::Kb,
+            recording.rows.iter().any(|row| row.rate_kb_s.is_some()),
+        ));
+    }
+    let content = read_text(path).with_context(|| format!("Failed to read recording: {}", path))?;
+    let header = content
+        .lines()
+        .find(|line| !line.starts_with('#'))
+        .unwrap_or_default();
+    parse_header(header).with_context(|| {
+        format!(
+            "{}: expected a time,memory_kb[,marker] header, got '{}'",
+            path, header
+        )
+    })
+}
+
+/// The `key: value` metadata comments at the top of the recording at `path`.
+pub fn comments(path: &str) -> Result<Vec<(String, String)>> {
+    if is_sqlite(path) {
+        let read = || -> rusqlite::Result<Vec<(String, String)>> {
+            let db = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            let mut metadata = db.prepare("SELECT key, value FROM metadata ORDER BY rowid")?;
+            let rows = metadata.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect()
+        };
+        return read().with_context(|| format!("Failed to read recording: {}", path));
+    }
+    if is_parquet(path) {
+        return read_parquet(path)
+            .map(|recording| recording.metadata)
+            .with_context(|| format!("Failed to read recording: {}", path));
+    }
+    let content = read_text(path).with_context(|| format!("Failed to read recording: {}", path))?;
+    Ok(content
+        .lines()
+        .map_while(|line| line.strip_prefix('#'))
+        .filter_map(|comment| comment.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect())
+}
+
+/// Memory unit and rate column of a `time,memory_<unit>[,rate_kb_s][,marker]` header.
+/// The rate is derived from the samples, so loading recomputes rather than reads it.
+fn parse_header(header: &str) -> Option<(Unit, bool)> {
+    let mut columns = header.split(',');
+    if columns.next()? != "time" {
+        return None;
+    }
+    let unit = Unit::from_csv_column(columns.next()?)?;
+    Some((unit, columns.next() == Some("rate_kb_s")))
+}
+
+/// Read a file, decompressing it first when its name ends in `.gz` or `.zst`. A
+/// compressed recording cut short, as by a crash mid-run, is read up to its last
+/// complete line.
+fn read_text(path: &str) -> Result<String> {
+    let mut decoder: Box<dyn Read> = if path.ends_with(".gz") {
+        Box::new(flate2::read::MultiGzDecoder::new(fs::File::open(path)?))
+    } else if path.ends_with(".zst") {
+        Box::new(zstd::Decoder::new(fs::File::open(path)?)?)
+    } else {
+        return Ok(fs::read_to_string(path)?);
+    };
+    let mut content = Vec::new();
+    if let Err(e) = decoder.read_to_end(&mut content) {
+        if e.kind() != std::io::ErrorKind::UnexpectedEof || content.is_empty() {
+            return Err(e.into());
+        }
+        warn!(
+            "{} is cut short ({}), reading the rows before the cut",
+            path, e
+        );
+        content.truncate(
+            content
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |idx| idx + 1),
+        );
+    }
+    Ok(String::from_utf8(content)?)
+}
+
+/// The samples and markers of `stats` at the times `keep` accepts, moved to `time(t)`,
+/// as a recording of their own. Adding the samples afresh recomputes the whole-run
+/// totals (sample count, first time, mean, trend) from what is left.
+fn rebuilt(
+    stats: MemoryStats,
+    keep: impl Fn(f64) -> bool,
+    time: impl Fn(f64) -> f64,
+) -> MemoryStats {
+    fn moved<T>(
+        points: Vec<(f64, T)>,
+        keep: &impl Fn(f64) -> bool,
+        time: &impl Fn(f64) -> f64,
+    ) -> Vec<(f64, T)> {
+        points
+            .into_iter()
+            .filter(|(t, _)| keep(*t))
+            .map(|(t, value)| (time(t), value))
+            .collect()
+    }
+    let mut rebuilt = MemoryStats::new();
+    for (t, kb) in moved(stats.samples, &keep, &time) {
+        rebuilt.add_sample(t, kb);
+    }
+    rebuilt.swap_samples = moved(stats.swap_samples, &keep, &time);
+    rebuilt.breakdown = moved(stats.breakdown, &keep, &time);
+    rebuilt.breakdown_labels = stats.breakdown_labels;
+    rebuilt.extra_series = stats
+        .extra_series
+        .into_iter()
+        .map(|(name, series)| (name, moved(series, &keep, &time)))
+        .collect();
+    rebuilt.markers = moved(stats.markers, &keep, &time);
+    rebuilt
+}
+
+/// Shift a recording so its first sample is at time zero.
+pub fn aligned(stats: MemoryStats) -> MemoryStats {
+    let origin = stats.samples.first().map(|(t, _)| *t).unwrap_or(0.0);
+    rebuilt(stats, |_| true, |t| t - origin)
+}
+
+/// Keep only samples and markers between `from` and `to` seconds (inclusive).
+pub fn crop(stats: MemoryStats, from: Option<f64>, to: Option<f64>) -> MemoryStats {
+    let range = from.unwrap_or(f64::NEG_INFINITY)..=to.unwrap_or(f64::INFINITY);
+    rebuilt(stats, |t| range.contains(&t), |t| t)
+}
+
+/// `--from`/`--to` of the commands that analyse saved recordings.
+#[derive(clap::Args)]
+pub struct TimeRange {
+    /// Only use samples from this far into the recording, e.g. 10m (a bare number is seconds)
+    #[arg(long, value_name = "TIME", value_parser = units::parse_seconds)]
+    pub from: Option<f64>,
+
+    /// Only use samples up to this far into the recording, e.g. 45m
+    #[arg(long, value_name = "TIME", value_parser = units::parse_seconds)]
+    pub to: Option<f64>,
+}
+
+impl TimeRange {
+    /// Load a recording and [`crop`] it to the range; fails when no sample is left.
+    pub fn load(&self, path: &str) -> Result<MemoryStats> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from >= to {
+                bail!(
+                    "--from ({}) must be before --to ({})",
+                    units::format_duration(from),
+                    units::format_duration(to)
+                );
+            }
+        }
+        let stats = crop(load_csv(path)?, self.from, self.to);
+        if stats.samples.is_empty() {
+            bail!(
+                "{}: no samples {}",
+                path,
+                self.describe().unwrap_or_default()
+            );
+        }
+        Ok(stats)
+    }
+
+    /// `from 10m 00s to 45m 00s`, or None for the whole recording.
+    pub fn describe(&self) -> Option<String> {
+        match (self.from, self.to) {
+            (None, None) => None,
+            (Some(from), None) => Some(format!("from {}", units::format_duration(from))),
+            (None, Some(to)) => Some(format!("up to {}", units::format_duration(to))),
+            (Some(from), Some(to)) => Some(format!(
+                "from {} to {}",
+                units::format_duration(from),
+                units::format_duration(to)
+            )),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({ "from_s": self.from, "to_s": self.to })
+    }
+}
+
+/// Rescale a recording's time axis to 0-100% of its duration.
+pub fn normalized(stats: MemoryStats) -> MemoryStats {
+    let stats = aligned(stats);
+    let duration = stats.samples.last().map(|(t, _)| *t).unwrap_or(0.0);
+    if duration <= 0.0 {
+        return stats;
+    }
+    rebuilt(stats, |_| true, |t| t / duration * 100.0)
+}
+
+/// Undo the quoting applied to marker labels containing separators or quotes.
+fn unquote(field: &str) -> String {
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(inner) => inner.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 100 samples, one a second, of 1000 + t KB, with a marker every 10 seconds.
+    fn recording() -> MemoryStats {
+        let mut stats = MemoryStats::new();
+        for t in 0..100 {
+            stats.add_sample(t as f64, 1000 + t);
+        }
+        stats.markers = (0..10)
+            .map(|i| (i as f64 * 10.0, format!("m{}", i)))
+            .collect();
+        stats
+    }
+
+    #[test]
+    fn crop_recomputes_totals() {
+        let cropped = crop(recording(), Some(50.0), Some(59.0));
+        assert_eq!(cropped.samples.len(), 10);
+        assert_eq!(cropped.sample_count(), 10);
+        assert_eq!(cropped.first_time(), Some(50.0));
+        assert_eq!(cropped.mean(), 1054.5);
+        assert_eq!((cropped.min(), cropped.max()), (1050, 1059));
+        assert_eq!(cropped.markers, vec![(50.0, "m5".to_string())]);
+    }
+
+    #[test]
+    fn aligned_starts_totals_at_zero() {
+        let aligned = aligned(crop(recording(), Some(20.0), None));
+        assert_eq!(aligned.first_time(), Some(0.0));
+        assert_eq!(aligned.sample_count(), 80);
+        assert_eq!(aligned.samples.last(), Some(&(79.0, 1099)));
+        assert_eq!(aligned.markers.first(), Some(&(0.0, "m2".to_string())));
+    }
+
+    #[test]
+    fn normalized_rescales_totals() {
+        let normalized = normalized(crop(recording(), Some(10.0), Some(60.0)));
+        assert_eq!(normalized.first_time(), Some(0.0));
+        assert_eq!(normalized.sample_count(), 51);
+        assert_eq!(normalized.samples.last(), Some(&(100.0, 1060)));
+        assert_eq!(normalized.markers.last(), Some(&(100.0, "m6".to_string())));
+    }
+}
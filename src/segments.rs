@@ -0,0 +1,229 @@
+use crate::stats::MemoryStats;
+
+/// Upper bound on change points, so pathological input cannot explode the report.
+const MAX_SEGMENTS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Plateau,
+    Growth,
+    Decline,
+}
+
+impl SegmentKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SegmentKind::Plateau => "plateau",
+            SegmentKind::Growth => "growth",
+            SegmentKind::Decline => "decline",
+        }
+    }
+}
+
+/// A stretch of the run with a roughly constant level or a steady trend.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub kind: SegmentKind,
+    /// Times of the first and last sample, in seconds
+    pub start: f64,
+    pub end: f64,
+    /// Mean RSS over the segment
+    pub level_kb: f64,
+    /// Fitted RSS at the segment's start and end
+    pub start_kb: f64,
+    pub end_kb: f64,
+    pub slope_kb_per_s: f64,
+}
+
+impl Segment {
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "kind": self.kind.name(),
+            "start_s": self.start,
+            "end_s": self.end,
+            "level_kb": self.level_kb,
+            "start_kb": self.start_kb,
+            "end_kb": self.end_kb,
+            "slope_kb_per_s": self.slope_kb_per_s,
+        })
+    }
+}
+
+/// Default `min_change_kb`: 5% of the observed range, but never below 1 MB.
+pub fn default_min_change_kb(stats: &MemoryStats) -> f64 {
+    ((stats.max() - stats.min()) as f64 * 0.05).max(1024.0)
+}
+
+/// Split the run into plateaus and growth/decline phases.
+///
+/// Binary segmentation with a piecewise-linear least-squares cost finds the change
+/// points; a piece is a plateau when its fitted rise or fall stays below `min_change_kb`.
+/// Adjacent plateaus closer than `min_change_kb` and consecutive rises or falls are
+/// then merged.
+pub fn segment(stats: &MemoryStats, min_change_kb: f64) -> Vec<Segment> {
+    let n = stats.samples.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let sums = PrefixSums::new(&stats.samples);
+
+    // Noise level from the spread of first differences (robust to the steps themselves),
+    // floored so perfectly quantized data does not make every wobble significant
+    let mut diffs: Vec<f64> = stats
+        .samples
+        .windows(2)
+        .map(|w| w[1].1 as f64 - w[0].1 as f64)
+        .collect();
+    let sigma = (median_abs_deviation(&mut diffs) * 1.4826 / std::f64::consts::SQRT_2)
+        .max(min_change_kb / 4.0);
+    let penalty = 3.0 * (n as f64).ln().max(1.0) * sigma * sigma;
+    let min_len = (n / 200).max(3);
+
+    let mut bounds = vec![0, n];
+    let mut pending = vec![(0, n)];
+    while let Some((lo, hi)) = pending.pop() {
+        if hi - lo < 2 * min_len || bounds.len() > MAX_SEGMENTS {
+            continue;
+        }
+        let whole = sums.cost(lo, hi);
+        let best = (lo + min_len..=hi - min_len)
+            .map(|k| (k, sums.cost(lo, k) + sums.cost(k, hi)))
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((k, cost)) = best {
+            if whole - cost > penalty {
+                bounds.push(k);
+                pending.push((lo, k));
+                pending.push((k, hi));
+            }
+        }
+    }
+    bounds.sort_unstable();
+
+    let mut segments: Vec<(usize, usize)> = bounds.windows(2).map(|w| (w[0], w[1])).collect();
+    // Merge neighbours that describe the same thing at this resolution
+    let mut idx = 0;
+    while idx + 1 < segments.len() {
+        let (a, b) = (segments[idx], segments[idx + 1]);
+        let left = describe(stats, &sums, a, min_change_kb);
+        let right = describe(stats, &sums, b, min_change_kb);
+        // Consecutive rises (or falls) form one phase even when their slopes differ
+        let same = left.kind == right.kind
+            && (left.kind != SegmentKind::Plateau
+                || (left.level_kb - right.level_kb).abs() < min_change_kb);
+        if same {
+            segments[idx] = (a.0, b.1);
+            segments.remove(idx + 1);
+        } else {
+            idx += 1;
+        }
+    }
+
+    segments
+        .into_iter()
+        .map(|range| describe(stats, &sums, range, min_change_kb))
+        .collect()
+}
+
+fn describe(
+    stats: &MemoryStats,
+    sums: &PrefixSums,
+    (lo, hi): (usize, usize),
+    min_change_kb: f64,
+) -> Segment {
+    let (start, end) = (stats.samples[lo].0, stats.samples[hi - 1].0);
+    let (slope, level) = sums.fit(lo, hi);
+    let mean_t = sums.range(lo, hi)[1] / (hi - lo) as f64 + sums.t0;
+    let start_kb = level + slope * (start - mean_t);
+    let end_kb = level + slope * (end - mean_t);
+    let kind = match end_kb - start_kb {
+        d if d >= min_change_kb => SegmentKind::Growth,
+        d if d <= -min_change_kb => SegmentKind::Decline,
+        _ => SegmentKind::Plateau,
+    };
+    Segment {
+        kind,
+        start,
+        end,
+        level_kb: level,
+        start_kb,
+        end_kb,
+        slope_kb_per_s: slope,
+    }
+}
+
+/// Running sums for O(1) least-squares fits over any sample range.
+/// Values are centered first to keep the squared sums well-conditioned.
+struct PrefixSums {
+    t0: f64,
+    m0: f64,
+    // n, Σt, Σm, Σt², Σm², Σtm
+    acc: Vec<[f64; 6]>,
+}
+
+impl PrefixSums {
+    fn new(samples: &[(f64, u64)]) -> Self {
+        let n = samples.len() as f64;
+        let t0 = samples.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let m0 = samples.iter().map(|(_, m)| *m as f64).sum::<f64>() / n;
+        let mut acc = Vec::with_capacity(samples.len() + 1);
+        let mut running = [0.0; 6];
+        acc.push(running);
+        for (t, m) in samples {
+            let (t, m) = (t - t0, *m as f64 - m0);
+            for (slot, value) in running.iter_mut().zip([1.0, t, m, t * t, m * m, t * m]) {
+                *slot += value;
+            }
+            acc.push(running);
+        }
+        Self { t0, m0, acc }
+    }
+
+    fn range(&self, lo: usize, hi: usize) -> [f64; 6] {
+        let mut out = self.acc[hi];
+        for (slot, value) in out.iter_mut().zip(self.acc[lo]) {
+            *slot -= value;
+        }
+        out
+    }
+
+    /// Residual sum of squares of the best line through samples `lo..hi`.
+    fn cost(&self, lo: usize, hi: usize) -> f64 {
+        let [n, st, sm, stt, smm, stm] = self.range(lo, hi);
+        let var_t = stt - st * st / n;
+        let var_m = smm - sm * sm / n;
+        let cov = stm - st * sm / n;
+        let sse = if var_t > 0.0 {
+            var_m - cov * cov / var_t
+        } else {
+            var_m
+        };
+        sse.max(0.0)
+    }
+
+    /// (slope, mean) of samples `lo..hi`, in KB/s and KB.
+    fn fit(&self, lo: usize, hi: usize) -> (f64, f64) {
+        let [n, st, sm, stt, _, stm] = self.range(lo, hi);
+        let var_t = stt - st * st / n;
+        let cov = stm - st * sm / n;
+        let slope = if var_t > 0.0 { cov / var_t } else { 0.0 };
+        (slope, sm / n + self.m0)
+    }
+}
+
+fn median_abs_deviation(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let median = |v: &mut [f64]| {
+        v.sort_by(|a, b| a.total_cmp(b));
+        let mid = v.len() / 2;
+        if v.len().is_multiple_of(2) {
+            (v[mid - 1] + v[mid]) / 2.0
+        } else {
+            v[mid]
+        }
+    };
+    let center = median(values);
+    let mut deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    median(&mut deviations)
+}
@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// The signal that requested the shutdown.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+/// Whether the signal was sent by a process with kill(2) rather than by the kernel,
+/// e.g. for Ctrl-C on the terminal.
+static SENT_BY_PROCESS: AtomicBool = AtomicBool::new(false);
+/// Ends of a pipe the handler writes a byte to, so async code can wait for a shutdown
+/// request rather than poll for it.
+static WAKE_READ: AtomicI32 = AtomicI32::new(-1);
+static WAKE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+// si_code values for signals sent with kill(2) and sigqueue(3), which the libc crate
+// does not define for every platform
+#[cfg(target_os = "freebsd")]
+const SI_USER: libc::c_int = 0x10001;
+#[cfg(target_os = "freebsd")]
+const SI_QUEUE: libc::c_int = 0x10002;
+#[cfg(target_os = "openbsd")]
+const SI_USER: libc::c_int = 0;
+#[cfg(target_os = "openbsd")]
+const SI_QUEUE: libc::c_int = -2;
+#[cfg(not(any(target_os = "freebsd", target_os = "openbsd")))]
+use libc::{SI_QUEUE, SI_USER};
+
+/// Longest uninterrupted sleep, bounding how late a shutdown request is noticed.
+const POLL: Duration = Duration::from_millis(50);
+
+extern "C" fn on_signal(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    SIGNAL.store(signal, Ordering::SeqCst);
+    // SAFETY: the kernel passes a valid siginfo_t to SA_SIGINFO handlers
+    let code = unsafe { (*info).si_code };
+    SENT_BY_PROCESS.store(code == SI_USER || code == SI_QUEUE, Ordering::SeqCst);
+    REQUESTED.store(true, Ordering::SeqCst);
+    let wake = WAKE_WRITE.load(Ordering::SeqCst);
+    if wake >= 0 {
+        // write(2) is async-signal-safe; a full pipe already wakes the reader
+        unsafe { libc::write(wake, [1u8].as_ptr().cast(), 1) };
+    }
+}
+
+/// Turn the first SIGINT or SIGTERM into a shutdown request so the run can stop
+/// sampling and still write its outputs. A second signal gets the default action.
+pub fn install() {
+    unsafe {
+        let mut fds = [-1; 2];
+        if WAKE_READ.load(Ordering::SeqCst) < 0 && libc::pipe(fds.as_mut_ptr()) == 0 {
+            for fd in fds {
+                libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+            WAKE_READ.store(fds[0], Ordering::SeqCst);
+            WAKE_WRITE.store(fds[1], Ordering::SeqCst);
+        }
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal
+            as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+            as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND | libc::SA_RESTART | libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// The signal to pass on to a supervised command, if it needs one. A signal raised by
+/// the terminal already reached the command through its process group, so only one
+/// sent with kill(2), e.g. by a CI runner or `timeout`, is returned.
+pub fn signal_to_forward() -> Option<libc::c_int> {
+    (requested() && SENT_BY_PROCESS.load(Ordering::SeqCst)).then(|| SIGNAL.load(Ordering::SeqCst))
+}
+
+/// Wait until a shutdown is requested, from a task on the tokio runtime. Never returns
+/// when [`install`] could not set up the wake-up pipe.
+pub async fn wait() {
+    let wake = WAKE_READ.load(Ordering::SeqCst);
+    let Ok(wake) = AsyncFd::with_interest(wake, Interest::READABLE) else {
+        return std::future::pending().await;
+    };
+    while !requested() {
+        match wake.readable().await {
+            Ok(mut guard) => guard.clear_ready(),
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+/// Sleep until `deadline`, returning early once a shutdown is requested.
+pub fn sleep_until(deadline: Instant) {
+    loop {
+        let now = Instant::now();
+        if requested() || now >= deadline {
+            return;
+        }
+        thread::sleep((deadline - now).min(POLL));
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::metrics_plugins::{Collector, Metric};
+
+/// One mapping from `/proc/<pid>/smaps` with its `kB` counters (Rss, Pss, Swap, ...).
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub range: String,
+    pub perms: String,
+    pub path: String,
+    pub fields: BTreeMap<String, u64>,
+}
+
+impl Mapping {
+    pub fn field(&self, name: &str) -> u64 {
+        self.fields.get(name).copied().unwrap_or(0)
+    }
+
+    /// Start and end address of the mapping.
+    pub fn bounds(&self) -> Option<(u64, u64)> {
+        let (start, end) = self.range.split_once('-')?;
+        Some((
+            u64::from_str_radix(start, 16).ok()?,
+            u64::from_str_radix(end, 16).ok()?,
+        ))
+    }
+}
+
+/// Parse `/proc/<pid>/smaps`.
+pub fn read(pid: u32) -> Result<Vec<Mapping>> {
+    let path = format!("/proc/{}/smaps", pid);
+    let content = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+    let mut mappings: Vec<Mapping> = Vec::new();
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        let Some(first) = parts.next() else {
+            continue;
+        };
+        // Header lines start with the address range, counters with "Name:"
+        if let Some(name) = first.strip_suffix(':') {
+            if let (Some(mapping), Some(Ok(value)), Some("kB")) = (
+                mappings.last_mut(),
+                parts.next().map(str::parse::<u64>),
+                parts.next(),
+            ) {
+                mapping.fields.insert(name.to_string(), value);
+            }
+            continue;
+        }
+        let perms = parts.next().unwrap_or_default().to_string();
+        // offset, device and inode come before the optional path
+        let path = parts.skip(3).collect::<Vec<_>>().join(" ");
+        mappings.push(Mapping {
+            range: first.to_string(),
+            perms,
+            path: if path.is_empty() {
+                "[anon]".to_string()
+            } else {
+                path
+            },
+            fields: BTreeMap::new(),
+        });
+    }
+    Ok(mappings)
+}
+
+/// Combined RSS of the mappings whose path matches `pattern`, in total and per path.
+pub fn matching_rss_kb(
+    mappings: &[Mapping],
+    pattern: &regex::Regex,
+) -> (u64, BTreeMap<String, u64>) {
+    let mut by_path: BTreeMap<String, u64> = BTreeMap::new();
+    for mapping in mappings.iter().filter(|m| pattern.is_match(&m.path)) {
+        *by_path.entry(mapping.path.clone()).or_default() += mapping.field("Rss");
+    }
+    (by_path.values().sum(), by_path)
+}
+
+/// The footprint of the mappings selected by one --track-mapping pattern.
+pub struct MappingTrack {
+    pub name: String,
+    first_kb: Option<u64>,
+    peak_kb: u64,
+    last_kb: u64,
+    /// RSS per matching path at the last reading
+    by_path: std::collections::BTreeMap<String, u64>,
+}
+
+impl MappingTrack {
+    fn growth_kb(&self) -> i64 {
+        self.last_kb as i64 - self.first_kb.unwrap_or(self.last_kb) as i64
+    }
+
+    pub fn print(&self) {
+        println!(
+            "\n{}: {:.2} MB at the end, {:.2} MB peak, {:+.2} MB over the run",
+            self.name,
+            self.last_kb as f64 / 1024.0,
+            self.peak_kb as f64 / 1024.0,
+            self.growth_kb() as f64 / 1024.0
+        );
+        let mut paths: Vec<_> = self.by_path.iter().collect();
+        paths.sort_by_key(|(_, kb)| std::cmp::Reverse(**kb));
+        for (path, kb) in paths.iter().take(10) {
+            println!("  {:>10.2} MB  {}", **kb as f64 / 1024.0, path);
+        }
+        if self.by_path.is_empty() {
+            println!("  no mapping matched");
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "first_kb": self.first_kb,
+            "peak_kb": self.peak_kb,
+            "last_kb": self.last_kb,
+            "growth_kb": self.growth_kb(),
+            "by_path": self.by_path,
+        })
+    }
+}
+
+/// The footprint of the mappings selected by each --track-mapping pattern.
+pub struct MappingTracker {
+    patterns: Vec<regex::Regex>,
+    /// Per pattern: the path totals of the last reading and the first, peak and last total
+    pub tracks: Vec<MappingTrack>,
+}
+
+impl MappingTracker {
+    pub fn new(patterns: &[regex::Regex]) -> Self {
+        Self {
+            patterns: patterns.to_vec(),
+            tracks: patterns
+                .iter()
+                .map(|pattern| MappingTrack {
+                    name: format!("mapped: {}", pattern),
+                    first_kb: None,
+                    peak_kb: 0,
+                    last_kb: 0,
+                    by_path: Default::default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Collector for MappingTracker {
+    /// Each pattern's combined RSS, as a series named after it.
+    fn collect(&mut self, pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        let mappings = read(pid)?;
+        let mut metrics = Vec::new();
+        for (track, pattern) in self.tracks.iter_mut().zip(&self.patterns) {
+            let (kb, by_path) = matching_rss_kb(&mappings, pattern);
+            track.first_kb.get_or_insert(kb);
+            track.peak_kb = track.peak_kb.max(kb);
+            track.last_kb = kb;
+            track.by_path = by_path;
+            metrics.push(Metric::kb(&track.name, kb));
+        }
+        Ok(metrics)
+    }
+}
+
+/// A snapshot as JSON: per-path totals and every mapping, largest RSS first.
+pub fn to_json(mappings: &[Mapping], time: f64, rss_kb: u64, reason: &str) -> serde_json::Value {
+    let mut by_path: BTreeMap<&str, BTreeMap<&str, u64>> = BTreeMap::new();
+    for mapping in mappings {
+        let totals = by_path.entry(&mapping.path).or_default();
+        for (name, value) in &mapping.fields {
+            *totals.entry(name).or_default() += value;
+        }
+    }
+    let mut by_path: Vec<_> = by_path.into_iter().collect();
+    by_path.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.get("Rss").copied().unwrap_or(0)));
+
+    let mut sorted: Vec<&Mapping> = mappings.iter().collect();
+    sorted.sort_by_key(|m| std::cmp::Reverse(m.field("Rss")));
+    json!({
+        "time_s": time,
+        "rss_kb": rss_kb,
+        "reason": reason,
+        "by_path": by_path
+            .iter()
+            .map(|(path, totals)| json!({ "path": path, "kb": totals }))
+            .collect::<Vec<_>>(),
+        "mappings": sorted
+            .iter()
+            .map(|m| json!({
+                "range": m.range,
+                "perms": m.perms,
+                "path": m.path,
+                "kb": m.fields,
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// A snapshot written during the run.
+#[derive(Debug, Clone)]
+pub struct SnapshotRecord {
+    pub time: f64,
+    pub rss_kb: u64,
+    pub reason: &'static str,
+    pub path: String,
+}
+
+impl SnapshotRecord {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "time_s": self.time,
+            "rss_kb": self.rss_kb,
+            "reason": self.reason,
+            "path": self.path,
+        })
+    }
+}
+
+/// Saves smaps snapshots when RSS reaches a new peak, at most once per `debounce`,
+/// and whenever a threshold is crossed.
+pub struct Snapshotter {
+    dir: String,
+    debounce: Duration,
+    peak_kb: u64,
+    last_peak_snapshot: Option<Instant>,
+    pub snapshots: Vec<SnapshotRecord>,
+}
+
+impl Snapshotter {
+    pub fn new(dir: &str, debounce: Duration) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir))?;
+        Ok(Self {
+            dir: dir.to_string(),
+            debounce,
+            peak_kb: 0,
+            last_peak_snapshot: None,
+            snapshots: Vec::new(),
+        })
+    }
+
+    /// Snapshot if `rss_kb` is a new peak and the last peak snapshot is old enough.
+    /// Peaks reached during the debounce window are caught by the next sample above them.
+    pub fn on_sample(&mut self, pid: u32, time: f64, rss_kb: u64) -> Result<()> {
+        if rss_kb <= self.peak_kb
+            || self
+                .last_peak_snapshot
+                .is_some_and(|t| t.elapsed() < self.debounce)
+        {
+            return Ok(());
+        }
+        self.peak_kb = rss_kb;
+        self.last_peak_snapshot = Some(Instant::now());
+        self.save(pid, time, rss_kb, "peak")
+    }
+
+    pub fn on_threshold(&mut self, pid: u32, time: f64, rss_kb: u64) -> Result<()> {
+        self.save(pid, time, rss_kb, "threshold")
+    }
+
+    fn save(&mut self, pid: u32, time: f64, rss_kb: u64, reason: &'static str) -> Result<()> {
+        let mappings = read(pid)?;
+        let path = Path::new(&self.dir)
+            .join(format!("smaps-{:08.1}s-{}.json", time, reason))
+            .to_string_lossy()
+            .into_owned();
+        let snapshot = to_json(&mappings, time, rss_kb, reason);
+        fs::write(&path, serde_json::to_string_pretty(&snapshot)?)
+            .with_context(|| format!("Failed to write smaps snapshot: {}", path))?;
+        self.snapshots.push(SnapshotRecord {
+            time,
+            rss_kb,
+            reason,
+            path,
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use crate::stats::MemoryStats;
+
+/// A short excursion above the recent baseline.
+#[derive(Debug, Clone, Copy)]
+pub struct Spike {
+    /// Time of the highest sample in the excursion, in seconds
+    pub time: f64,
+    pub peak_kb: u64,
+    /// Mean of the trailing window before the excursion started
+    pub baseline_kb: f64,
+    pub z_score: f64,
+    pub duration: f64,
+}
+
+impl Spike {
+    pub fn magnitude_kb(&self) -> f64 {
+        self.peak_kb as f64 - self.baseline_kb
+    }
+
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "time_s": self.time,
+            "peak_kb": self.peak_kb,
+            "baseline_kb": self.baseline_kb,
+            "magnitude_kb": self.magnitude_kb(),
+            "z_score": self.z_score,
+            "duration_s": self.duration,
+        })
+    }
+}
+
+/// Flag samples whose z-score against the preceding `window` samples exceeds
+/// `threshold`, group consecutive flagged samples into one spike, and return the
+/// `top` largest spikes by magnitude in time order.
+pub fn detect(stats: &MemoryStats, window: usize, threshold: f64, top: usize) -> Vec<Spike> {
+    let samples = &stats.samples;
+    if samples.len() <= window || window < 2 {
+        return Vec::new();
+    }
+
+    // Prefix sums of centered values for O(1) trailing mean/variance
+    let center = stats.mean();
+    let mut sum = vec![0.0; samples.len() + 1];
+    let mut sum_sq = vec![0.0; samples.len() + 1];
+    for (idx, (_, mem)) in samples.iter().enumerate() {
+        let x = *mem as f64 - center;
+        sum[idx + 1] = sum[idx] + x;
+        sum_sq[idx + 1] = sum_sq[idx] + x * x;
+    }
+
+    let mut spikes: Vec<Spike> = Vec::new();
+    let mut current: Option<(Spike, f64)> = None; // (spike so far, start time)
+    for idx in window..samples.len() {
+        let (time, mem) = samples[idx];
+        let n = window as f64;
+        let window_sum = sum[idx] - sum[idx - window];
+        let mean = window_sum / n;
+        let variance = ((sum_sq[idx] - sum_sq[idx - window]) / n - mean * mean).max(0.0);
+        let baseline = mean + center;
+        // Perfectly flat stretches have no spread; require at least 1% of the level (and
+        // 64 KB) so page-sized wobbles after a flat stretch do not register
+        let std_dev = variance.sqrt().max(baseline.abs() * 0.01).max(64.0);
+        let z = (mem as f64 - baseline) / std_dev;
+
+        match (&mut current, z > threshold) {
+            (Some((spike, start)), true) => {
+                if mem > spike.peak_kb {
+                    spike.peak_kb = mem;
+                    spike.time = time;
+                }
+                spike.z_score = spike.z_score.max(z);
+                spike.duration = time - *start;
+            }
+            (None, true) => {
+                current = Some((
+                    Spike {
+                        time,
+                        peak_kb: mem,
+                        baseline_kb: baseline,
+                        z_score: z,
+                        duration: 0.0,
+                    },
+                    time,
+                ));
+            }
+            (Some(_), false) => spikes.extend(current.take().map(|(spike, _)| spike)),
+            (None, false) => {}
+        }
+    }
+    spikes.extend(current.map(|(spike, _)| spike));
+
+    spikes.sort_by(|a, b| b.magnitude_kb().total_cmp(&a.magnitude_kb()));
+    spikes.truncate(top);
+    spikes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    spikes
+}
@@ -0,0 +1,698 @@
+use std::collections::VecDeque;
+
+use crate::markers::Marker;
+
+/// Least-squares line through (time, RSS) samples.
+#[derive(Debug, Clone, Copy)]
+pub struct LinearFit {
+    pub slope_kb_per_s: f64,
+    pub r_squared: f64,
+}
+
+impl LinearFit {
+    pub fn mb_per_hour(&self) -> f64 {
+        self.slope_kb_per_s * 3600.0 / 1024.0
+    }
+
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "slope_kb_per_s": self.slope_kb_per_s,
+            "slope_mb_per_hour": self.mb_per_hour(),
+            "r_squared": self.r_squared,
+        })
+    }
+}
+
+/// Trailing allocation rate: the memory change in KB/s over the last `window` sample
+/// intervals. Taking the slope across several intervals smooths the derivative of
+/// jittery samples without lagging more than the window behind.
+pub struct RateEstimator {
+    window: usize,
+    recent: VecDeque<(f64, u64)>,
+}
+
+impl RateEstimator {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Add a sample and return the rate ending at it; the first sample has none.
+    pub fn push(&mut self, time: f64, kb: u64) -> Option<f64> {
+        self.recent.push_back((time, kb));
+        if self.recent.len() > self.window + 1 {
+            self.recent.pop_front();
+        }
+        let (t0, kb0) = *self.recent.front()?;
+        (time > t0).then(|| (kb as f64 - kb0 as f64) / (time - t0))
+    }
+}
+
+/// Which samples a bounded recording keeps once `--max-samples` is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Retention {
+    /// Keep the most recent samples
+    Ring,
+    /// Keep evenly spaced samples covering the whole run, halving the density when full
+    Decimate,
+}
+
+/// Sums over every sample ever added, so mean, extremes, spread and the overall trend
+/// stay exact when old samples are dropped. Values are offset by the first sample to
+/// keep the sums of squares well conditioned.
+#[derive(Debug, Clone, Default)]
+struct RunningTotals {
+    count: u64,
+    origin: (f64, f64),
+    sum_t: f64,
+    sum_m: f64,
+    sum_tt: f64,
+    sum_tm: f64,
+    sum_mm: f64,
+    min: u64,
+    max: u64,
+}
+
+impl RunningTotals {
+    fn add(&mut self, time: f64, memory_kb: u64) {
+        if self.count == 0 {
+            self.origin = (time, memory_kb as f64);
+            self.min = memory_kb;
+            self.max = memory_kb;
+        }
+        let (t, m) = (time - self.origin.0, memory_kb as f64 - self.origin.1);
+        self.count += 1;
+        self.sum_t += t;
+        self.sum_m += m;
+        self.sum_tt += t * t;
+        self.sum_tm += t * m;
+        self.sum_mm += m * m;
+        self.min = self.min.min(memory_kb);
+        self.max = self.max.max(memory_kb);
+    }
+
+    fn mean(&self) -> f64 {
+        self.origin.1 + self.sum_m / self.count as f64
+    }
+
+    fn variance(&self) -> f64 {
+        let n = self.count as f64;
+        (self.sum_mm / n - (self.sum_m / n).powi(2)).max(0.0)
+    }
+
+    fn linear_fit(&self) -> Option<LinearFit> {
+        let n = self.count as f64;
+        let s_tt = self.sum_tt - self.sum_t * self.sum_t / n;
+        let s_tm = self.sum_tm - self.sum_t * self.sum_m / n;
+        let s_mm = self.sum_mm - self.sum_m * self.sum_m / n;
+        if self.count < 2 || s_tt <= 0.0 {
+            return None;
+        }
+        Some(LinearFit {
+            slope_kb_per_s: s_tm / s_tt,
+            r_squared: if s_mm <= 0.0 {
+                1.0
+            } else {
+                (s_tm * s_tm) / (s_tt * s_mm)
+            },
+        })
+    }
+}
+
+/// Sample limit and the bookkeeping needed to enforce it.
+#[derive(Debug, Clone)]
+struct Bound {
+    max_samples: usize,
+    retention: Retention,
+    /// Decimation keeps every `stride`-th sample; always 1 for ring buffers
+    stride: u64,
+    /// Whether the latest `add_sample` was kept; swap, breakdown and extra samples follow it
+    kept: bool,
+}
+
+#[derive(Debug)]
+pub struct MemoryStats {
+    pub samples: Vec<(f64, u64)>,        // (time_seconds, memory_kb)
+    pub swap_samples: Vec<(f64, u64)>,   // (time_seconds, swap_kb), only with --swap
+    pub breakdown: Vec<(f64, Vec<u64>)>, // (time_seconds, kb per breakdown label), only when stacked
+    pub breakdown_labels: Vec<String>,
+    /// Named series in KB drawn alongside RSS, e.g. allocator statistics
+    pub extra_series: Vec<(String, Vec<(f64, u64)>)>,
+    pub markers: Vec<Marker>,
+    bound: Option<Bound>,
+    totals: RunningTotals,
+    /// Whether samples have been dropped, by the bound or a new period, so whole-run
+    /// figures must come from `totals`
+    partial: bool,
+}
+
+impl Default for MemoryStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStats {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            swap_samples: Vec::new(),
+            breakdown: Vec::new(),
+            breakdown_labels: Vec::new(),
+            extra_series: Vec::new(),
+            markers: Vec::new(),
+            bound: None,
+            totals: RunningTotals::default(),
+            partial: false,
+        }
+    }
+
+    /// Stats that keep at most about `max_samples` samples per series. Mean, min, max,
+    /// spread, sample count and the whole-run trend still cover every sample; median,
+    /// percentiles and charts use the retained ones.
+    pub fn bounded(max_samples: usize, retention: Retention) -> Self {
+        Self {
+            bound: Some(Bound {
+                max_samples: max_samples.max(2),
+                retention,
+                stride: 1,
+                kept: true,
+            }),
+            partial: true,
+            ..Self::new()
+        }
+    }
+
+    /// Drop all samples and markers, keeping the configuration, to start a new period.
+    /// Mean, min, max, spread, sample count and the whole-run trend still cover every
+    /// period, as with a bounded recording.
+    pub fn start_period(&mut self) {
+        self.samples.clear();
+        self.swap_samples.clear();
+        self.breakdown.clear();
+        for (_, series) in &mut self.extra_series {
+            series.clear();
+        }
+        self.markers.clear();
+        if let Some(bound) = &mut self.bound {
+            bound.stride = 1;
+            bound.kept = true;
+        }
+        self.partial = true;
+    }
+
+    /// Totals over every sample, when the retained samples no longer cover the run.
+    fn run_totals(&self) -> Option<&RunningTotals> {
+        Some(&self.totals).filter(|totals| self.partial && totals.count > 0)
+    }
+
+    /// Time of the first sample taken, even if no longer retained.
+    pub fn first_time(&self) -> Option<f64> {
+        (self.totals.count > 0).then_some(self.totals.origin.0)
+    }
+
+    /// Number of samples taken, including any no longer retained.
+    pub fn sample_count(&self) -> u64 {
+        self.totals.count
+    }
+
+    pub fn add_breakdown(&mut self, time: f64, values: Vec<u64>) {
+        if self.bound.as_ref().is_none_or(|b| b.kept) {
+            self.breakdown.push((time, values));
+            self.enforce_bound();
+        }
+    }
+
+    /// Add a sample to the extra series called `name`, creating it on first use.
+    pub fn add_extra_sample(&mut self, name: &str, time: f64, kb: u64) {
+        if self.bound.as_ref().is_some_and(|b| !b.kept) {
+            return;
+        }
+        let idx = match self.extra_series.iter().position(|(n, _)| n == name) {
+            Some(idx) => idx,
+            None => {
+                self.extra_series.push((name.to_string(), Vec::new()));
+                self.extra_series.len() - 1
+            }
+        };
+        self.extra_series[idx].1.push((time, kb));
+        self.enforce_bound();
+    }
+
+    pub fn add_swap_sample(&mut self, time: f64, swap_kb: u64) {
+        if self.bound.as_ref().is_none_or(|b| b.kept) {
+            self.swap_samples.push((time, swap_kb));
+            self.enforce_bound();
+        }
+    }
+
+    pub fn max_swap(&self) -> u64 {
+        self.swap_samples
+            .iter()
+            .map(|(_, swap)| swap)
+            .max()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The samples, swap samples and markers from `from` seconds on, as a recording of
+    /// their own. A bounded recording only has its retained samples to offer.
+    pub fn since(&self, from: f64) -> MemoryStats {
+        let mut since = MemoryStats::new();
+        for &(time, kb) in self.samples.iter().filter(|(t, _)| *t >= from) {
+            since.add_sample(time, kb);
+        }
+        since.swap_samples = self
+            .swap_samples
+            .iter()
+            .filter(|(t, _)| *t >= from)
+            .copied()
+            .collect();
+        since.markers = self
+            .markers
+            .iter()
+            .filter(|(t, _)| *t >= from)
+            .cloned()
+            .collect();
+        since
+    }
+
+    pub fn add_sample(&mut self, time: f64, memory_kb: u64) {
+        if let Some(bound) = &mut self.bound {
+            // Decimation halves every series and doubles the stride once full, before the
+            // new tick is added so swap and breakdown samples of this tick stay aligned
+            if bound.retention == Retention::Decimate && self.samples.len() >= bound.max_samples {
+                halve(&mut self.samples);
+                halve(&mut self.swap_samples);
+                halve(&mut self.breakdown);
+                for (_, series) in &mut self.extra_series {
+                    halve(series);
+                }
+                bound.stride *= 2;
+            }
+            bound.kept = self.totals.count.is_multiple_of(bound.stride);
+        }
+        self.totals.add(time, memory_kb);
+        if self.bound.as_ref().is_some_and(|b| !b.kept) {
+            return;
+        }
+        self.samples.push((time, memory_kb));
+        self.enforce_bound();
+    }
+
+    /// Drop the oldest samples of a ring buffer. Compacting in chunks keeps this cheap.
+    fn enforce_bound(&mut self) {
+        if let Some(bound) = self
+            .bound
+            .as_ref()
+            .filter(|b| b.retention == Retention::Ring)
+        {
+            let (max, slack) = (bound.max_samples, (bound.max_samples / 16).max(1));
+            trim_front(&mut self.samples, max, slack);
+            trim_front(&mut self.swap_samples, max, slack);
+            trim_front(&mut self.breakdown, max, slack);
+            for (_, series) in &mut self.extra_series {
+                trim_front(series, max, slack);
+            }
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        if let Some(totals) = self.run_totals() {
+            return totals.mean();
+        }
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let sum: u64 = self.samples.iter().map(|(_, mem)| mem).sum();
+        sum as f64 / self.samples.len() as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let mut values: Vec<u64> = self.samples.iter().map(|(_, mem)| *mem).collect();
+        values.sort_unstable();
+        let mid = values.len() / 2;
+        if values.len().is_multiple_of(2) {
+            (values[mid - 1] + values[mid]) as f64 / 2.0
+        } else {
+            values[mid] as f64
+        }
+    }
+
+    /// Centered moving average of RSS over `window` samples, as (time, kb).
+    pub fn rolling_mean(&self, window: usize) -> Vec<(f64, f64)> {
+        let half = window.max(1) / 2;
+        let mut prefix = Vec::with_capacity(self.samples.len() + 1);
+        prefix.push(0u64);
+        for (_, mem) in &self.samples {
+            prefix.push(prefix.last().unwrap() + mem);
+        }
+        (0..self.samples.len())
+            .map(|i| {
+                let lo = i.saturating_sub(half);
+                let hi = (i + half + 1).min(self.samples.len());
+                let mean = (prefix[hi] - prefix[lo]) as f64 / (hi - lo) as f64;
+                (self.samples[i].0, mean)
+            })
+            .collect()
+    }
+
+    /// Allocation rate in KB/s at every sample after the first, see [`RateEstimator`].
+    pub fn rate_series(&self, window: usize) -> Vec<(f64, f64)> {
+        let mut estimator = RateEstimator::new(window);
+        self.samples
+            .iter()
+            .filter_map(|(t, kb)| Some((*t, estimator.push(*t, *kb)?)))
+            .collect()
+    }
+
+    pub fn smoothed_peak(&self, window: usize) -> f64 {
+        self.rolling_mean(window)
+            .into_iter()
+            .map(|(_, mem)| mem)
+            .fold(0.0, f64::max)
+    }
+
+    /// Percentile `p` (0-100) of RSS in KB, linearly interpolated between samples.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let mut values: Vec<u64> = self.samples.iter().map(|(_, mem)| *mem).collect();
+        values.sort_unstable();
+        let rank = (p / 100.0).clamp(0.0, 1.0) * (values.len() - 1) as f64;
+        let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+        values[lo] as f64 + (values[hi] as f64 - values[lo] as f64) * (rank - lo as f64)
+    }
+
+    /// Population variance of RSS in KB².
+    pub fn variance(&self) -> f64 {
+        if let Some(totals) = self.run_totals() {
+            return totals.variance();
+        }
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let mean = self.mean();
+        self.samples
+            .iter()
+            .map(|(_, mem)| (*mem as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.samples.len() as f64
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    pub fn iqr(&self) -> f64 {
+        self.percentile(75.0) - self.percentile(25.0)
+    }
+
+    /// How long each sample's value is assumed to hold: until the next sample, with the
+    /// last one holding for the median gap. Uniform sampling gives equal weights.
+    fn time_weights(&self) -> Vec<f64> {
+        let mut gaps: Vec<f64> = self
+            .samples
+            .windows(2)
+            .map(|w| (w[1].0 - w[0].0).max(0.0))
+            .collect();
+        let mut sorted = gaps.clone();
+        sorted.sort_by(f64::total_cmp);
+        let last = sorted.get(sorted.len() / 2).copied().unwrap_or(1.0);
+        gaps.push(last);
+        gaps
+    }
+
+    /// Mean RSS in KB, weighted by the time each sample represents.
+    pub fn time_weighted_mean(&self) -> f64 {
+        let weights = self.time_weights();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return self.mean();
+        }
+        self.samples
+            .iter()
+            .zip(&weights)
+            .map(|((_, mem), w)| *mem as f64 * w)
+            .sum::<f64>()
+            / total
+    }
+
+    /// Percentile `p` (0-100) of RSS in KB over time rather than over samples: the
+    /// value below which memory stayed for `p`% of the run.
+    pub fn time_weighted_percentile(&self, p: f64) -> f64 {
+        let weights = self.time_weights();
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return self.percentile(p);
+        }
+        let mut weighted: Vec<(u64, f64)> = self
+            .samples
+            .iter()
+            .map(|(_, mem)| *mem)
+            .zip(weights)
+            .collect();
+        weighted.sort_unstable_by_key(|(mem, _)| *mem);
+
+        let target = (p / 100.0).clamp(0.0, 1.0) * total;
+        let mut cumulative = 0.0;
+        for (mem, w) in &weighted {
+            cumulative += w;
+            if cumulative >= target {
+                return *mem as f64;
+            }
+        }
+        weighted.last().map(|(mem, _)| *mem as f64).unwrap_or(0.0)
+    }
+
+    /// Linear regression of RSS over time for samples at or after `from_time` seconds.
+    /// Needs at least two samples at distinct times.
+    pub fn linear_fit(&self, from_time: f64) -> Option<LinearFit> {
+        if let Some(totals) = self.run_totals() {
+            if from_time <= totals.origin.0 {
+                return totals.linear_fit();
+            }
+        }
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .filter(|(t, _)| *t >= from_time)
+            .map(|(t, mem)| (*t, *mem as f64))
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_m = points.iter().map(|(_, m)| m).sum::<f64>() / n;
+        let (mut s_tt, mut s_tm, mut s_mm) = (0.0, 0.0, 0.0);
+        for (t, m) in &points {
+            s_tt += (t - mean_t).powi(2);
+            s_tm += (t - mean_t) * (m - mean_m);
+            s_mm += (m - mean_m).powi(2);
+        }
+        if s_tt == 0.0 {
+            return None;
+        }
+
+        let slope = s_tm / s_tt;
+        // A perfectly flat series is perfectly explained by a flat line
+        let r_squared = if s_mm == 0.0 {
+            1.0
+        } else {
+            (s_tm * s_tm) / (s_tt * s_mm)
+        };
+        Some(LinearFit {
+            slope_kb_per_s: slope,
+            r_squared,
+        })
+    }
+
+    /// Fit over the last `window` seconds of the run.
+    pub fn trailing_fit(&self, window: f64) -> Option<LinearFit> {
+        let end = self.samples.last()?.0;
+        self.linear_fit(end - window)
+    }
+
+    /// Summary statistics (KB) for JSON output.
+    pub fn summary_json(&self, trend_window: f64) -> serde_json::Value {
+        serde_json::json!({
+            "samples": self.sample_count(),
+            "mean_kb": self.mean(),
+            "median_kb": self.median(),
+            "min_kb": self.min(),
+            "max_kb": self.max(),
+            "p50_kb": self.percentile(50.0),
+            "p90_kb": self.percentile(90.0),
+            "p95_kb": self.percentile(95.0),
+            "p99_kb": self.percentile(99.0),
+            "std_dev_kb": self.std_dev(),
+            "variance_kb2": self.variance(),
+            "iqr_kb": self.iqr(),
+            "time_weighted": {
+                "mean_kb": self.time_weighted_mean(),
+                "p50_kb": self.time_weighted_percentile(50.0),
+                "p90_kb": self.time_weighted_percentile(90.0),
+                "p95_kb": self.time_weighted_percentile(95.0),
+                "p99_kb": self.time_weighted_percentile(99.0),
+            },
+            "growth": {
+                "overall": self.linear_fit(f64::NEG_INFINITY).map(LinearFit::to_json),
+                "trailing": self.trailing_fit(trend_window).map(LinearFit::to_json),
+                "trailing_window_s": trend_window,
+            },
+        })
+    }
+
+    /// Split the sampled range into `bins` equal-width buckets and count the samples in
+    /// each, as (lower_kb, upper_kb, count). The last bucket includes the maximum.
+    pub fn histogram(&self, bins: usize) -> Vec<(f64, f64, usize)> {
+        if self.samples.is_empty() || bins == 0 {
+            return Vec::new();
+        }
+        let (min, max) = (self.min() as f64, self.max() as f64);
+        // A flat run still gets one visible bucket around its only value
+        let width = if max > min {
+            (max - min) / bins as f64
+        } else {
+            1.0
+        };
+        let bins = if max > min { bins } else { 1 };
+
+        let mut counts = vec![0usize; bins];
+        for (_, mem) in &self.samples {
+            let idx = (((*mem as f64 - min) / width) as usize).min(bins - 1);
+            counts[idx] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(idx, count)| {
+                let lower = min + idx as f64 * width;
+                (lower, lower + width, count)
+            })
+            .collect()
+    }
+
+    pub fn max(&self) -> u64 {
+        if let Some(totals) = self.run_totals() {
+            return totals.max;
+        }
+        self.samples
+            .iter()
+            .map(|(_, mem)| mem)
+            .max()
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn min(&self) -> u64 {
+        if let Some(totals) = self.run_totals() {
+            return totals.min;
+        }
+        self.samples
+            .iter()
+            .map(|(_, mem)| mem)
+            .min()
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// `--max-samples` and `--retention`, for the series recorded next to `MemoryStats`.
+/// The default keeps everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeriesLimit(Option<(usize, Retention)>);
+
+impl SeriesLimit {
+    pub fn new(max_samples: Option<u64>, retention: Retention) -> Self {
+        Self(max_samples.map(|max| ((max as usize).max(2), retention)))
+    }
+}
+
+/// A series recorded next to the samples, such as a collected metric, kept within
+/// `--max-samples` the way `MemoryStats` keeps its own: a ring buffer drops the oldest
+/// points, decimation halves the series when full and keeps fewer points from then on.
+#[derive(Debug, Clone)]
+pub struct BoundedSeries<T> {
+    points: Vec<T>,
+    bound: Option<Bound>,
+    /// Points pushed, including those not kept
+    count: u64,
+}
+
+impl<T> BoundedSeries<T> {
+    pub fn new(limit: SeriesLimit) -> Self {
+        Self {
+            points: Vec::new(),
+            bound: limit.0.map(|(max_samples, retention)| Bound {
+                max_samples,
+                retention,
+                stride: 1,
+                kept: true,
+            }),
+            count: 0,
+        }
+    }
+
+    pub fn push(&mut self, point: T) {
+        let seen = self.count;
+        self.count += 1;
+        let Some(bound) = &mut self.bound else {
+            self.points.push(point);
+            return;
+        };
+        if bound.retention == Retention::Decimate && self.points.len() >= bound.max_samples {
+            halve(&mut self.points);
+            bound.stride *= 2;
+        }
+        bound.kept = seen.is_multiple_of(bound.stride);
+        if !bound.kept {
+            return;
+        }
+        self.points.push(point);
+        if bound.retention == Retention::Ring {
+            trim_front(
+                &mut self.points,
+                bound.max_samples,
+                (bound.max_samples / 16).max(1),
+            );
+        }
+    }
+
+    /// Number of points pushed, including any no longer retained.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<T> std::ops::Deref for BoundedSeries<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.points
+    }
+}
+
+/// Drop the oldest entries down to `max` once `max + slack` are held.
+fn trim_front<T>(series: &mut Vec<T>, max: usize, slack: usize) {
+    if series.len() >= max + slack {
+        series.drain(..series.len() - max);
+    }
+}
+
+/// Keep every other entry, starting with the first.
+fn halve<T>(series: &mut Vec<T>) {
+    let mut idx = 0;
+    series.retain(|_| {
+        idx += 1;
+        idx % 2 == 1
+    });
+}
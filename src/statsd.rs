@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use std::net::UdpSocket;
+
+use crate::MemoryReading;
+
+/// Fire-and-forget StatsD gauge emitter.
+pub struct StatsdClient {
+    socket: UdpSocket,
+    prefix: String,
+}
+
+impl StatsdClient {
+    pub fn new(addr: &str, prefix: &str) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to bind StatsD socket")?;
+        socket
+            .connect(addr)
+            .with_context(|| format!("Failed to resolve StatsD address {}", addr))?;
+        Ok(Self {
+            socket,
+            prefix: prefix.trim_end_matches('.').to_string(),
+        })
+    }
+
+    /// Send one gauge per metric in a single datagram.
+    pub fn send(&self, reading: &MemoryReading) {
+        let payload = format!(
+            "{p}.rss_kb:{}|g\n{p}.swap_kb:{}|g\n{p}.hwm_kb:{}|g",
+            reading.rss_kb,
+            reading.swap_kb,
+            reading.hwm_kb,
+            p = self.prefix
+        );
+        // UDP is lossy by design; a missing collector must not disturb sampling
+        let _ = self.socket.send(payload.as_bytes());
+    }
+}
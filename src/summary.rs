@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+
+use crate::phases;
+use crate::recording;
+use crate::stats::MemoryStats;
+use crate::units::{self, Unit};
+
+#[derive(clap::Args)]
+pub struct StatsArgs {
+    /// Recording to analyse (CSV written with --csv-output)
+    #[arg(value_name = "CSV")]
+    file: String,
+
+    /// Print the statistics as JSON instead of text
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    range: recording::TimeRange,
+
+    /// Trailing window for the recent growth-rate estimate
+    #[arg(long, value_name = "DURATION", default_value = "300", value_parser = units::parse_whole_seconds)]
+    trend_window: u64,
+
+    /// Show memory values in this unit instead of KB and MB
+    #[arg(long, value_enum)]
+    units: Option<Unit>,
+}
+
+/// Compute statistics from a saved recording, e.g. one captured on another host.
+pub fn run(args: &StatsArgs) -> Result<()> {
+    let stats = args.range.load(&args.file)?;
+    let phases = phases::split(&stats, f64::NEG_INFINITY);
+    if args.json {
+        let mut statistics = stats.summary_json(args.trend_window as f64);
+        if !phases.is_empty() {
+            statistics["phases"] = phases.iter().map(phases::Phase::to_json).collect();
+        }
+        let summary = serde_json::json!({
+            "source": args.file,
+            "range": args.range.to_json(),
+            "statistics": statistics,
+        });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&summary).context("Failed to encode statistics")?
+        );
+    } else {
+        match args.range.describe() {
+            Some(range) => println!("Statistics for {}, {}", args.file, range),
+            None => println!("Statistics for {}", args.file),
+        }
+        print_summary(&stats, args.trend_window as f64, args.units);
+        if !phases.is_empty() {
+            phases::print(&phases, args.units);
+        }
+    }
+    Ok(())
+}
+
+/// Print the statistics summary shared by live runs and saved recordings.
+pub fn print_summary(stats: &MemoryStats, trend_window: f64, units: Option<Unit>) {
+    let show = |kb: f64| units::describe(kb, units);
+    // Min and max are whole KB
+    let show_kb = |kb: u64| match units {
+        Some(unit) => unit.format(kb as f64),
+        None => format!("{} KB ({:.2} MB)", kb, kb as f64 / 1024.0),
+    };
+    println!("Total samples: {}", stats.sample_count());
+    println!("Mean memory: {}", show(stats.mean()));
+    println!("Median memory: {}", show(stats.median()));
+    println!("Max memory: {}", show_kb(stats.max()));
+    println!("Min memory: {}", show_kb(stats.min()));
+    for p in [90.0, 95.0, 99.0] {
+        println!("p{} memory: {}", p, show(stats.percentile(p)));
+    }
+    println!("Std dev: {}", show(stats.std_dev()));
+    println!("Interquartile range: {}", show(stats.iqr()));
+    println!("Time-weighted mean: {}", show(stats.time_weighted_mean()));
+    for p in [50.0, 95.0] {
+        println!(
+            "Time-weighted p{}: {}",
+            p,
+            show(stats.time_weighted_percentile(p))
+        );
+    }
+    let growth = [
+        (
+            "Growth rate (whole run)".to_string(),
+            stats.linear_fit(f64::NEG_INFINITY),
+        ),
+        (
+            format!("Growth rate (last {}s)", trend_window),
+            stats.trailing_fit(trend_window),
+        ),
+    ];
+    for (label, fit) in growth {
+        if let Some(fit) = fit {
+            println!(
+                "{}: {:.2} KB/s ({:.2} MB/hour), R² = {:.3}",
+                label,
+                fit.slope_kb_per_s,
+                fit.mb_per_hour(),
+                fit.r_squared
+            );
+        }
+    }
+}
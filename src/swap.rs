@@ -0,0 +1,133 @@
+use serde_json::json;
+use std::fs;
+
+/// Samples without any VmSwap change after which swapping is considered over, so the
+/// next change is reported as a new episode.
+const QUIET_SAMPLES: u32 = 10;
+
+/// The process started being swapped, reported once per episode.
+pub struct SwapStart {
+    pub time: f64,
+    pub swap_kb: u64,
+    pub delta_kb: i64,
+    /// System-wide pages swapped in and out since the previous sample
+    pub pages_in: u64,
+    pub pages_out: u64,
+}
+
+/// Watches VmSwap of the process and the system's `pswpin`/`pswpout` counters.
+///
+/// A flat RSS line can hide a process whose pages are being pushed to swap; VmSwap
+/// growing means pages were swapped out, shrinking means they were read back in or
+/// freed while swapped.
+pub struct SwapWatch {
+    last_swap_kb: Option<u64>,
+    last_vmstat: Option<(u64, u64)>,
+    first_vmstat: Option<(u64, u64)>,
+    quiet: u32,
+    swapping: bool,
+    pub episodes: Vec<f64>,
+    pub swapped_out_kb: u64,
+    pub swapped_in_kb: u64,
+    pub max_swap_kb: u64,
+}
+
+impl SwapWatch {
+    pub fn start() -> Self {
+        let vmstat = read_vmstat();
+        Self {
+            last_swap_kb: None,
+            last_vmstat: vmstat,
+            first_vmstat: vmstat,
+            quiet: 0,
+            swapping: false,
+            episodes: Vec::new(),
+            swapped_out_kb: 0,
+            swapped_in_kb: 0,
+            max_swap_kb: 0,
+        }
+    }
+
+    /// Record the VmSwap of a sample; returns the start of a new swapping episode.
+    pub fn on_sample(&mut self, time: f64, swap_kb: u64) -> Option<SwapStart> {
+        let vmstat = read_vmstat();
+        let (pages_in, pages_out) = match (self.last_vmstat, vmstat) {
+            (Some((in0, out0)), Some((in1, out1))) => {
+                (in1.saturating_sub(in0), out1.saturating_sub(out0))
+            }
+            _ => (0, 0),
+        };
+        self.last_vmstat = vmstat.or(self.last_vmstat);
+        self.max_swap_kb = self.max_swap_kb.max(swap_kb);
+
+        // Swap already in use when monitoring starts is reported as an episode at once,
+        // but not counted as swapped out during the run
+        let delta_kb = swap_kb as i64 - self.last_swap_kb.unwrap_or(0) as i64;
+        if self.last_swap_kb.is_some() {
+            if delta_kb > 0 {
+                self.swapped_out_kb += delta_kb as u64;
+            } else {
+                self.swapped_in_kb += delta_kb.unsigned_abs();
+            }
+        }
+        self.last_swap_kb = Some(swap_kb);
+
+        if delta_kb == 0 {
+            self.quiet += 1;
+            if self.quiet >= QUIET_SAMPLES {
+                self.swapping = false;
+            }
+            return None;
+        }
+        self.quiet = 0;
+        if self.swapping {
+            return None;
+        }
+        self.swapping = true;
+        self.episodes.push(time);
+        Some(SwapStart {
+            time,
+            swap_kb,
+            delta_kb,
+            pages_in,
+            pages_out,
+        })
+    }
+
+    /// System-wide pages swapped in and out since monitoring started.
+    pub fn system_pages(&self) -> (u64, u64) {
+        match (self.first_vmstat, self.last_vmstat) {
+            (Some((in0, out0)), Some((in1, out1))) => {
+                (in1.saturating_sub(in0), out1.saturating_sub(out0))
+            }
+            _ => (0, 0),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let (pages_in, pages_out) = self.system_pages();
+        json!({
+            "episodes_s": self.episodes,
+            "swapped_out_kb": self.swapped_out_kb,
+            "swapped_in_kb": self.swapped_in_kb,
+            "max_swap_kb": self.max_swap_kb,
+            "system_pswpin": pages_in,
+            "system_pswpout": pages_out,
+        })
+    }
+}
+
+/// `pswpin` and `pswpout` from `/proc/vmstat`, in pages.
+fn read_vmstat() -> Option<(u64, u64)> {
+    let vmstat = fs::read_to_string("/proc/vmstat").ok()?;
+    let counter = |name: &str| {
+        vmstat.lines().find_map(|line| {
+            line.strip_prefix(name)?
+                .strip_prefix(' ')?
+                .trim()
+                .parse()
+                .ok()
+        })
+    };
+    Some((counter("pswpin")?, counter("pswpout")?))
+}
@@ -0,0 +1,114 @@
+use anyhow::{bail, Context, Result};
+use std::process::Command;
+
+use crate::cgroup::MemoryCgroup;
+use crate::metrics_plugins::{Collector, Metric};
+use crate::{read_memory_usage, MemoryReading};
+
+/// A systemd unit whose processes are tracked together through its cgroup, so the
+/// recording survives the main process being replaced on reload or restart.
+#[derive(Clone)]
+pub struct Unit {
+    pub name: String,
+    /// The unit's cgroup path as systemd reports it, e.g. `/system.slice/nginx.service`
+    pub control_group: String,
+    cgroup: MemoryCgroup,
+}
+
+impl Unit {
+    /// Look the unit's cgroup up with `systemctl show`.
+    pub fn resolve(name: &str) -> Result<Self> {
+        let control_group = show(name, "ControlGroup")?;
+        if control_group.is_empty() {
+            bail!("Unit {} has no cgroup; is it running?", name);
+        }
+        let Some(cgroup) = MemoryCgroup::at_path(&control_group) else {
+            bail!(
+                "cgroup {} of unit {} not found under /sys/fs/cgroup",
+                control_group,
+                name
+            );
+        };
+        Ok(Self {
+            name: name.to_string(),
+            control_group,
+            cgroup,
+        })
+    }
+
+    /// The unit's current main process, or its first process when systemd tracks none
+    /// (e.g. `Type=forking` without a PID file).
+    pub fn main_pid(&self) -> Result<u32> {
+        let main_pid = show(&self.name, "MainPID")?.parse::<u32>().unwrap_or(0);
+        if main_pid != 0 {
+            return Ok(main_pid);
+        }
+        match self.cgroup.pids().into_iter().min() {
+            Some(pid) => Ok(pid),
+            None => bail!("Unit {} has no running processes", self.name),
+        }
+    }
+
+    /// Whether `pid` is still one of the unit's processes.
+    pub fn contains(&self, pid: u32) -> bool {
+        self.cgroup.pids().contains(&pid)
+    }
+
+    /// Memory of all processes in the unit's cgroup, summed.
+    pub fn read(&self) -> Result<MemoryReading> {
+        self.read_processes().map(|processes| total(&processes))
+    }
+
+    /// Memory of each process in the unit's cgroup. A process exiting while it is read
+    /// is skipped; a unit whose cgroup is gone has stopped.
+    pub fn read_processes(&self) -> Result<Vec<(u32, MemoryReading)>> {
+        if !self.cgroup.exists() {
+            bail!(
+                "cgroup {} of unit {} is gone",
+                self.control_group,
+                self.name
+            );
+        }
+        Ok(self
+            .cgroup
+            .pids()
+            .into_iter()
+            .filter_map(|pid| Some((pid, read_memory_usage(pid).ok()?)))
+            .collect())
+    }
+}
+
+impl Collector for Unit {
+    fn collect(&mut self, _pid: u32, _time: f64) -> Result<Vec<Metric>> {
+        Ok(self.read()?.to_metrics())
+    }
+}
+
+/// The readings of several processes, summed.
+pub fn total(processes: &[(u32, MemoryReading)]) -> MemoryReading {
+    let mut total = MemoryReading::default();
+    for (_, reading) in processes {
+        total.rss_kb += reading.rss_kb;
+        total.swap_kb += reading.swap_kb;
+        total.hwm_kb += reading.hwm_kb;
+        total.rss_anon_kb += reading.rss_anon_kb;
+        total.rss_file_kb += reading.rss_file_kb;
+        total.rss_shmem_kb += reading.rss_shmem_kb;
+    }
+    total
+}
+
+fn show(unit: &str, property: &str) -> Result<String> {
+    let output = Command::new("systemctl")
+        .args(["show", "--property", property, "--value", unit])
+        .output()
+        .context("Failed to run systemctl to resolve --unit")?;
+    if !output.status.success() {
+        bail!(
+            "systemctl show {} failed: {}",
+            unit,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::metrics_plugins::{Collector, Metric};
+use crate::smaps::{self, Mapping};
+use crate::stats::{BoundedSeries, SeriesLimit};
+
+/// glibc reserves each secondary malloc arena as a region of this size, aligned to it,
+/// of which only the part in use is readable and writable (HEAP_MAX_SIZE on 64-bit).
+const ARENA_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Stack growth below this is page-level noise rather than a growing thread.
+const GROWTH_NOTICE_KB: u64 = 64;
+
+/// What is known about one thread across the run.
+#[derive(Debug, Clone)]
+pub struct ThreadSeries {
+    pub tid: u32,
+    pub name: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    /// Resident size of the stack mapping when first and last located
+    pub first_stack_kb: Option<u64>,
+    pub last_stack_kb: Option<u64>,
+    pub peak_stack_kb: u64,
+    pub exited: bool,
+}
+
+impl ThreadSeries {
+    pub fn growth_kb(&self) -> i64 {
+        match (self.first_stack_kb, self.last_stack_kb) {
+            (Some(first), Some(last)) => last as i64 - first as i64,
+            _ => 0,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "tid": self.tid,
+            "name": self.name,
+            "first_seen_s": self.first_seen,
+            "last_seen_s": self.last_seen,
+            "first_stack_kb": self.first_stack_kb,
+            "last_stack_kb": self.last_stack_kb,
+            "peak_stack_kb": self.peak_stack_kb,
+            "growth_kb": self.growth_kb(),
+            "exited": self.exited,
+        })
+    }
+}
+
+/// Tracks the threads of a process from `/proc/<pid>/task/*`, at most once per `every`.
+///
+/// A thread's stack is the smaps mapping containing its stack pointer, which is read
+/// from `/proc/<pid>/task/<tid>/syscall`; the kernel only shows it to processes allowed
+/// to ptrace the target, and not while the thread is running on a CPU, in which case
+/// the mapping found last time is kept. Secondary glibc malloc arenas are counted from
+/// their 64 MB aligned reservations.
+pub struct ThreadTracker {
+    every: Duration,
+    last: Option<Instant>,
+    threads: BTreeMap<u32, ThreadSeries>,
+    /// Stack mapping start address per thread, reused while the thread is running
+    stack_starts: BTreeMap<u32, u64>,
+    /// (time, live threads), bounded like the samples of the stats
+    pub counts: BoundedSeries<(f64, usize)>,
+    /// Live threads at the first and last snapshot and at most, over every snapshot
+    pub count_range: Option<(usize, usize, usize)>,
+    /// (time, arena count, arena RSS in KB), bounded like the counts
+    pub arenas: BoundedSeries<(f64, usize, u64)>,
+    /// Arena count and RSS in KB at the last snapshot
+    pub last_arenas: Option<(usize, u64)>,
+}
+
+impl ThreadTracker {
+    pub fn new(every: Duration, limit: SeriesLimit) -> Self {
+        Self {
+            every,
+            last: None,
+            threads: BTreeMap::new(),
+            stack_starts: BTreeMap::new(),
+            counts: BoundedSeries::new(limit),
+            count_range: None,
+            arenas: BoundedSeries::new(limit),
+            last_arenas: None,
+        }
+    }
+
+    /// Take a snapshot if the last one is old enough. Returns the threads whose stack
+    /// grew past their previous peak by a noticeable amount.
+    pub fn on_sample(&mut self, pid: u32, time: f64) -> Result<Vec<ThreadSeries>> {
+        if self.last.is_some_and(|t| t.elapsed() < self.every) {
+            return Ok(Vec::new());
+        }
+        self.last = Some(Instant::now());
+
+        let task_dir = format!("/proc/{}/task", pid);
+        let tids: Vec<u32> = fs::read_dir(&task_dir)
+            .with_context(|| format!("Failed to read {}", task_dir))?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        let mappings = smaps::read(pid)?;
+
+        let mut grown = Vec::new();
+        for &tid in &tids {
+            let name = fs::read_to_string(format!("{}/{}/comm", task_dir, tid))
+                .map(|comm| comm.trim_end().to_string())
+                .unwrap_or_default();
+            let stack = stack_pointer(pid, tid)
+                .and_then(|sp| containing(&mappings, sp))
+                .or_else(|| {
+                    let start = *self.stack_starts.get(&tid)?;
+                    mappings
+                        .iter()
+                        .find(|m| m.bounds().is_some_and(|(s, _)| s == start))
+                });
+            if let Some((start, _)) = stack.and_then(Mapping::bounds) {
+                self.stack_starts.insert(tid, start);
+            }
+            let stack_kb = stack.map(|m| m.field("Rss"));
+
+            let thread = self.threads.entry(tid).or_insert_with(|| ThreadSeries {
+                tid,
+                name: name.clone(),
+                first_seen: time,
+                last_seen: time,
+                first_stack_kb: None,
+                last_stack_kb: None,
+                peak_stack_kb: 0,
+                exited: false,
+            });
+            thread.last_seen = time;
+            if !name.is_empty() {
+                thread.name = name;
+            }
+            if let Some(kb) = stack_kb {
+                thread.first_stack_kb.get_or_insert(kb);
+                thread.last_stack_kb = Some(kb);
+                if kb >= thread.peak_stack_kb + GROWTH_NOTICE_KB && thread.peak_stack_kb > 0 {
+                    grown.push(thread.clone());
+                }
+                thread.peak_stack_kb = thread.peak_stack_kb.max(kb);
+            }
+        }
+        for (tid, thread) in &mut self.threads {
+            if !thread.exited && !tids.contains(tid) {
+                thread.exited = true;
+                self.stack_starts.remove(tid);
+            }
+        }
+
+        let count = tids.len();
+        self.counts.push((time, count));
+        let (first, max) = self
+            .count_range
+            .map_or((count, count), |(first, _, max)| (first, max.max(count)));
+        self.count_range = Some((first, count, max));
+        let arenas = arenas(&mappings);
+        let arena_rss_kb = arenas.iter().map(|m| m.field("Rss")).sum();
+        self.arenas.push((time, arenas.len(), arena_rss_kb));
+        self.last_arenas = Some((arenas.len(), arena_rss_kb));
+        Ok(grown)
+    }
+
+    pub fn threads(&self) -> impl Iterator<Item = &ThreadSeries> {
+        self.threads.values()
+    }
+
+    /// Threads whose stack grew by a noticeable amount over the run, most growth first.
+    pub fn growing(&self) -> Vec<&ThreadSeries> {
+        let mut growing: Vec<&ThreadSeries> = self
+            .threads
+            .values()
+            .filter(|t| t.growth_kb() >= GROWTH_NOTICE_KB as i64)
+            .collect();
+        growing.sort_by_key(|t| std::cmp::Reverse(t.growth_kb()));
+        growing
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "counts": self.counts.iter().map(|(t, n)| json!([t, n])).collect::<Vec<_>>(),
+            "arenas": self
+                .arenas
+                .iter()
+                .map(|(t, n, kb)| json!({ "time_s": t, "count": n, "rss_kb": kb }))
+                .collect::<Vec<_>>(),
+            "threads": self.threads.values().map(ThreadSeries::to_json).collect::<Vec<_>>(),
+            "growing": self.growing().iter().map(|t| t.tid).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Collector for ThreadTracker {
+    /// Take a snapshot, logging the threads whose stack grew. The per-thread series are
+    /// charted from the tracker at the end of the run, not as extra series.
+    fn collect(&mut self, pid: u32, time: f64) -> Result<Vec<Metric>> {
+        for thread in self.on_sample(pid, time)? {
+            info!(
+                "Thread {} ({}) stack grew to {:.2} MB at {:.1}s",
+                thread.tid,
+                thread.name,
+                thread.last_stack_kb.unwrap_or_default() as f64 / 1024.0,
+                time
+            );
+        }
+        Ok(Vec::new())
+    }
+}
+
+/// Stack pointer of a thread blocked in or outside a system call; `None` while it is
+/// running or when the kernel refuses to show it.
+fn stack_pointer(pid: u32, tid: u32) -> Option<u64> {
+    let syscall = fs::read_to_string(format!("/proc/{}/task/{}/syscall", pid, tid)).ok()?;
+    // "nr args... sp pc" or "-1 sp pc"; the stack pointer is always second to last
+    let fields: Vec<&str> = syscall.split_whitespace().collect();
+    let sp = fields
+        .len()
+        .checked_sub(2)
+        .and_then(|idx| fields.get(idx))?;
+    u64::from_str_radix(sp.strip_prefix("0x")?, 16).ok()
+}
+
+fn containing(mappings: &[Mapping], address: u64) -> Option<&Mapping> {
+    mappings.iter().find(|m| {
+        m.bounds()
+            .is_some_and(|(start, end)| start <= address && address < end)
+    })
+}
+
+/// Readable parts of secondary malloc arenas: anonymous read-write mappings at a 64 MB
+/// boundary that, together with the inaccessible reservation after them, span 64 MB.
+fn arenas(mappings: &[Mapping]) -> Vec<&Mapping> {
+    mappings
+        .windows(2)
+        .filter_map(|pair| {
+            let (used, reserved) = (&pair[0], &pair[1]);
+            let (start, used_end) = used.bounds()?;
+            let (reserved_start, end) = reserved.bounds()?;
+            let is_arena = used.path == "[anon]"
+                && used.perms.starts_with("rw")
+                && start % ARENA_SIZE == 0
+                && reserved.perms.starts_with("---")
+                && reserved_start == used_end
+                && end - start == ARENA_SIZE;
+            is_arena.then_some(used)
+        })
+        .collect()
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+use std::fs;
+
+use crate::stats::MemoryStats;
+use crate::wait;
+
+/// One process that has been in the top set at some point during the run.
+pub struct TopProcess {
+    pub pid: u32,
+    pub name: String,
+    /// Start time in clock ticks since boot, to tell a reused pid from the original process
+    start_ticks: u64,
+    pub stats: MemoryStats,
+    pub in_top: bool,
+    pub exited: bool,
+}
+
+impl TopProcess {
+    pub fn label(&self) -> String {
+        format!("{} ({})", self.name, self.pid)
+    }
+}
+
+/// A change of the top set found by [`TopTracker::refresh`].
+pub enum TopChange {
+    Entered(String),
+    Left(String),
+    Exited(String),
+}
+
+/// Tracks the N processes with the highest RSS on the system.
+///
+/// Every refresh ranks all processes by their `/proc/<pid>/statm` RSS; processes that
+/// climb into the top N get a series of their own, which is kept when they drop out
+/// again. Between refreshes only the current members are sampled, while every process
+/// seen so far gets a sample at each refresh, so its line stays continuous at the
+/// coarser refresh rate after it left the top set.
+pub struct TopTracker {
+    n: usize,
+    page_kb: u64,
+    processes: Vec<TopProcess>,
+}
+
+impl TopTracker {
+    pub fn new(n: usize) -> Self {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        Self {
+            n,
+            page_kb: (page_size.max(4096) as u64) / 1024,
+            processes: Vec::new(),
+        }
+    }
+
+    /// Re-rank all processes at `time`, updating the top set.
+    pub fn refresh(&mut self, time: f64) -> Vec<TopChange> {
+        let own_pid = std::process::id();
+        let mut all: Vec<(u64, u32)> = fs::read_dir("/proc")
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter(|pid| *pid != own_pid)
+            .filter_map(|pid| Some((self.rss_kb(pid)?, pid)))
+            // Kernel threads have no resident memory of their own
+            .filter(|(rss_kb, _)| *rss_kb > 0)
+            .collect();
+        all.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut changes = Vec::new();
+        let mut top = HashSet::new();
+        for &(rss_kb, pid) in all.iter().take(self.n) {
+            let Some(start_ticks) = wait::start_ticks(pid) else {
+                continue;
+            };
+            let idx = match self.position(pid, start_ticks) {
+                Some(idx) => idx,
+                None => {
+                    let name = fs::read_to_string(format!("/proc/{}/comm", pid))
+                        .map(|comm| comm.trim_end().to_string())
+                        .unwrap_or_else(|_| "?".to_string());
+                    self.processes.push(TopProcess {
+                        pid,
+                        name,
+                        start_ticks,
+                        stats: MemoryStats::new(),
+                        in_top: false,
+                        exited: false,
+                    });
+                    self.processes.len() - 1
+                }
+            };
+            let process = &mut self.processes[idx];
+            if !process.in_top {
+                process.in_top = true;
+                changes.push(TopChange::Entered(process.label()));
+            }
+            process.stats.add_sample(time, rss_kb);
+            top.insert(idx);
+        }
+
+        for (idx, process) in self.processes.iter_mut().enumerate() {
+            if top.contains(&idx) || process.exited {
+                continue;
+            }
+            let current = all
+                .iter()
+                .find(|(_, pid)| *pid == process.pid)
+                .filter(|_| wait::start_ticks(process.pid) == Some(process.start_ticks));
+            match current {
+                Some((rss_kb, _)) => {
+                    process.stats.add_sample(time, *rss_kb);
+                    if process.in_top {
+                        process.in_top = false;
+                        changes.push(TopChange::Left(process.label()));
+                    }
+                }
+                None => {
+                    process.in_top = false;
+                    process.exited = true;
+                    changes.push(TopChange::Exited(process.label()));
+                }
+            }
+        }
+        changes
+    }
+
+    /// Sample the members of the current top set at `time`.
+    pub fn sample(&mut self, time: f64) {
+        let page_kb = self.page_kb;
+        for process in self.processes.iter_mut().filter(|p| p.in_top) {
+            // An exited member is noticed and reported at the next refresh
+            if let Some(rss_kb) = statm_rss_kb(process.pid, page_kb) {
+                process.stats.add_sample(time, rss_kb);
+            }
+        }
+    }
+
+    /// Sum of the RSS of the current top set at its latest samples.
+    pub fn total_kb(&self) -> u64 {
+        self.processes
+            .iter()
+            .filter(|p| p.in_top)
+            .filter_map(|p| p.stats.samples.last().map(|(_, kb)| *kb))
+            .sum()
+    }
+
+    pub fn processes(&self) -> &[TopProcess] {
+        &self.processes
+    }
+
+    fn position(&self, pid: u32, start_ticks: u64) -> Option<usize> {
+        self.processes
+            .iter()
+            .position(|p| p.pid == pid && p.start_ticks == start_ticks)
+    }
+
+    fn rss_kb(&self, pid: u32) -> Option<u64> {
+        statm_rss_kb(pid, self.page_kb)
+    }
+}
+
+/// Resident set size from the second field of `/proc/<pid>/statm`.
+fn statm_rss_kb(pid: u32, page_kb: u64) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let resident: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(resident * page_kb)
+}
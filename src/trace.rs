@@ -0,0 +1,89 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::MemoryReading;
+
+/// Time base used for the `ts` field of trace events.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum TraceClock {
+    /// Microseconds since the tracker started
+    Relative,
+    /// Microseconds since the Unix epoch
+    Unix,
+    /// CLOCK_MONOTONIC microseconds, as used by Chrome and Perfetto on Linux
+    Monotonic,
+}
+
+/// Streams samples as counter events in Chrome trace event format.
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    clock: TraceClock,
+    start: Instant,
+    pid: u32,
+    first: bool,
+}
+
+impl TraceWriter {
+    pub fn new(path: &str, clock: TraceClock, pid: u32) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create trace file: {}", path))?;
+        let mut writer = BufWriter::new(file);
+        write!(writer, "{{\"displayTimeUnit\":\"ms\",\"traceEvents\":[")?;
+        Ok(Self {
+            writer,
+            clock,
+            start: Instant::now(),
+            pid,
+            first: true,
+        })
+    }
+
+    pub fn add_sample(&mut self, reading: &MemoryReading) -> Result<()> {
+        let event = json!({
+            "name": "Memory (MB)",
+            "ph": "C",
+            "ts": self.timestamp_us(),
+            "pid": self.pid,
+            "args": {
+                "rss": reading.rss_kb as f64 / 1024.0,
+                "swap": reading.swap_kb as f64 / 1024.0,
+            }
+        });
+        if !self.first {
+            write!(self.writer, ",")?;
+        }
+        self.first = false;
+        write!(self.writer, "\n{}", event).context("Failed to write trace event")?;
+        Ok(())
+    }
+
+    /// Close the event array; the file is not valid JSON until this is called.
+    pub fn finish(mut self) -> Result<()> {
+        write!(self.writer, "\n]}}\n")?;
+        self.writer.flush().context("Failed to flush trace file")?;
+        Ok(())
+    }
+
+    fn timestamp_us(&self) -> f64 {
+        match self.clock {
+            TraceClock::Relative => self.start.elapsed().as_secs_f64() * 1e6,
+            TraceClock::Unix => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs_f64() * 1e6)
+                .unwrap_or(0.0),
+            TraceClock::Monotonic => {
+                let mut ts = libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: 0,
+                };
+                // SAFETY: `ts` is a valid, writable timespec
+                unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+                ts.tv_sec as f64 * 1e6 + ts.tv_nsec as f64 / 1e3
+            }
+        }
+    }
+}
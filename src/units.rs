@@ -0,0 +1,226 @@
+/// Parse a memory size such as `512MB`, `1.5GiB` or `2g` into KB.
+///
+/// Suffixes are case-insensitive and always binary (K = 1024 bytes); a bare number
+/// is taken to be KB, the unit used throughout the tool.
+pub fn parse_memory_kb(arg: &str) -> Result<u64, String> {
+    let arg = arg.trim();
+    let split = arg
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(arg.len());
+    let (number, suffix) = arg.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("expected a size like 512MB or 1.5GB, got '{}'", arg))?;
+
+    let suffix = suffix.trim().to_ascii_lowercase();
+    let unit = suffix.trim_end_matches('b').trim_end_matches('i');
+    let multiplier_kb = match (unit, suffix.as_str()) {
+        (_, "") | ("k", _) => 1.0,
+        (_, "b") => 1.0 / 1024.0,
+        ("m", _) => 1024.0,
+        ("g", _) => 1024.0 * 1024.0,
+        ("t", _) => 1024.0 * 1024.0 * 1024.0,
+        _ => {
+            return Err(format!(
+                "unknown size unit '{}' in '{}' (expected B, KB, MB, GB or TB)",
+                suffix, arg
+            ))
+        }
+    };
+    Ok((number * multiplier_kb).round() as u64)
+}
+
+/// Parse a duration such as `250ms`, `5s`, `1.5m`, `2h30m` or `1d` into seconds.
+///
+/// Units (`ms`, `s`, `m`/`min`, `h`, `d`) are case-insensitive and can be combined
+/// largest first; a bare number is taken to be in `bare_s` seconds, so flags keep
+/// accepting the plain values they always did.
+fn parse_duration(arg: &str, bare_s: f64) -> Result<f64, String> {
+    let expected = || format!("expected a duration like 250ms, 5s or 2h30m, got '{}'", arg);
+    let text: String = arg
+        .split_whitespace()
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if let Ok(number) = text.parse::<f64>() {
+        if number.is_finite() && number >= 0.0 {
+            return Ok(number * bare_s);
+        }
+        return Err(expected());
+    }
+
+    let mut rest = text.as_str();
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..split].parse().map_err(|_| expected())?;
+        rest = &rest[split..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let unit_s = match &rest[..end] {
+            "ms" => 0.001,
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 3600.0,
+            "d" => 86400.0,
+            "" => {
+                return Err(format!(
+                    "missing time unit after '{}' in '{}' (expected ms, s, m, h or d)",
+                    number, arg
+                ))
+            }
+            unit => {
+                return Err(format!(
+                    "unknown time unit '{}' in '{}' (expected ms, s, m, h or d)",
+                    unit, arg
+                ))
+            }
+        };
+        seconds += number * unit_s;
+        rest = &rest[end..];
+    }
+    if text.is_empty() {
+        return Err(expected());
+    }
+    Ok(seconds)
+}
+
+/// Parse a duration into seconds; a bare number is seconds.
+pub fn parse_seconds(arg: &str) -> Result<f64, String> {
+    parse_duration(arg, 1.0)
+}
+
+/// Parse a duration into whole seconds; a bare number is seconds. Decimal fractions
+/// like `0.1h` are not exact in binary, hence the tolerance.
+pub fn parse_whole_seconds(arg: &str) -> Result<u64, String> {
+    let seconds = parse_duration(arg, 1.0)?;
+    if (seconds - seconds.round()).abs() > 1e-6 {
+        return Err(format!("expected a whole number of seconds, got '{}'", arg));
+    }
+    Ok(seconds.round() as u64)
+}
+
+/// Like [`parse_whole_seconds`], for periods that cannot be zero.
+pub fn parse_period_seconds(arg: &str) -> Result<u64, String> {
+    match parse_whole_seconds(arg)? {
+        0 => Err(format!("expected a period of at least 1s, got '{}'", arg)),
+        seconds => Ok(seconds),
+    }
+}
+
+/// Parse a duration into whole milliseconds; a bare number is milliseconds.
+pub fn parse_millis(arg: &str) -> Result<u64, String> {
+    let millis = parse_duration(arg, 0.001)? * 1000.0;
+    if (millis - millis.round()).abs() > 1e-6 {
+        return Err(format!(
+            "expected a whole number of milliseconds, got '{}'",
+            arg
+        ));
+    }
+    Ok(millis.round() as u64)
+}
+
+/// `12.3s`, `4m 05s` or `2h 03m`, for the chart stats box and
+/// console messages.
+pub fn format_duration(seconds: f64) -> String {
+    let whole = seconds.round() as u64;
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else if whole < 3600 {
+        format!("{}m {:02}s", whole / 60, whole % 60)
+    } else {
+        format!("{}h {:02}m", whole / 3600, whole % 3600 / 60)
+    }
+}
+
+/// Unit memory values are shown in, set with `--units`. Always binary: a KiB is 1024
+/// bytes, a MiB 1024 KiB and a GiB 1024 MiB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Unit {
+    /// Kibibytes
+    Kb,
+    /// Mebibytes
+    #[default]
+    Mb,
+    /// Gibibytes
+    Gb,
+    /// KiB, MiB or GiB, whichever keeps the value between 1 and 1024
+    Auto,
+}
+
+impl Unit {
+    /// The fixed unit for values up to `max_kb`; only changes `Auto`.
+    pub fn resolve(self, max_kb: f64) -> Unit {
+        match self {
+            Unit::Auto if max_kb >= 1024.0 * 1024.0 => Unit::Gb,
+            Unit::Auto if max_kb >= 1024.0 => Unit::Mb,
+            Unit::Auto => Unit::Kb,
+            unit => unit,
+        }
+    }
+
+    /// KB in one of this unit; `Auto` counts as MiB, resolve it first.
+    pub fn kb(self) -> f64 {
+        match self {
+            Unit::Kb => 1.0,
+            Unit::Mb | Unit::Auto => 1024.0,
+            Unit::Gb => 1024.0 * 1024.0,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Unit::Kb => "KiB",
+            Unit::Mb | Unit::Auto => "MiB",
+            Unit::Gb => "GiB",
+        }
+    }
+
+    /// `kb` in this unit with its label, e.g. `1.50 GiB`; `Auto` scales each value.
+    pub fn format(self, kb: f64) -> String {
+        let unit = self.resolve(kb.abs());
+        match unit {
+            Unit::Kb => format!("{:.0} {}", kb, unit.label()),
+            _ => format!("{:.2} {}", kb / unit.kb(), unit.label()),
+        }
+    }
+
+    /// Memory column of a CSV recording in this unit. Rows are written as they are
+    /// sampled, so `Auto` cannot pick by size and keeps exact KiB.
+    pub fn csv_column(self) -> &'static str {
+        match self {
+            Unit::Kb | Unit::Auto => "memory_kb",
+            Unit::Mb => "memory_mib",
+            Unit::Gb => "memory_gib",
+        }
+    }
+
+    /// A CSV value in this unit, with enough decimals to keep KiB resolution.
+    pub fn csv_value(self, kb: u64) -> String {
+        match self {
+            Unit::Kb | Unit::Auto => kb.to_string(),
+            Unit::Mb => format!("{:.3}", kb as f64 / 1024.0),
+            Unit::Gb => format!("{:.6}", kb as f64 / (1024.0 * 1024.0)),
+        }
+    }
+
+    /// The unit of a CSV memory column written by [`Unit::csv_column`].
+    pub fn from_csv_column(column: &str) -> Option<Unit> {
+        match column {
+            "memory_kb" => Some(Unit::Kb),
+            "memory_mib" => Some(Unit::Mb),
+            "memory_gib" => Some(Unit::Gb),
+            _ => None,
+        }
+    }
+}
+
+/// A value for the console: `--units` if given, otherwise KB followed by MB as always.
+pub fn describe(kb: f64, units: Option<Unit>) -> String {
+    match units {
+        Some(unit) => unit.format(kb),
+        None => format!("{:.2} KB ({:.2} MB)", kb, kb / 1024.0),
+    }
+}
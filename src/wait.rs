@@ -0,0 +1,66 @@
+use anyhow::{bail, Result};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::shutdown;
+
+/// How often `/proc` is scanned; short, so sampling starts before the startup peak.
+const POLL: Duration = Duration::from_millis(20);
+
+/// Block until a process named `name` exists and return its pid. With several matches
+/// the most recently started one wins.
+///
+/// A process matches when its command name (`/proc/<pid>/comm`, cut to 15 characters
+/// by the kernel), the file name of its executable or of its first argument equals
+/// `name`.
+pub fn wait_for_name(name: &str, timeout: Option<Duration>) -> Result<u32> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let own_pid = std::process::id();
+    loop {
+        let newest = fs::read_dir("/proc")?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter(|pid| *pid != own_pid && matches(*pid, name))
+            .filter_map(|pid| Some((start_ticks(pid)?, pid)))
+            .max();
+        if let Some((_, pid)) = newest {
+            return Ok(pid);
+        }
+        if shutdown::requested() {
+            bail!("Interrupted while waiting for a process named {}", name);
+        }
+        if let (Some(deadline), Some(timeout)) = (deadline, timeout) {
+            if Instant::now() >= deadline {
+                bail!(
+                    "No process named {} appeared within {}s",
+                    name,
+                    timeout.as_secs()
+                );
+            }
+        }
+        shutdown::sleep_until(Instant::now() + POLL);
+    }
+}
+
+fn matches(pid: u32, name: &str) -> bool {
+    let named = |path: &Path| path.file_name().is_some_and(|file_name| file_name == name);
+    let comm = fs::read_to_string(format!("/proc/{}/comm", pid));
+    if comm.is_ok_and(|comm| comm.trim_end() == name) {
+        return true;
+    }
+    if fs::read_link(format!("/proc/{}/exe", pid)).is_ok_and(|exe| named(&exe)) {
+        return true;
+    }
+    fs::read(format!("/proc/{}/cmdline", pid)).is_ok_and(|cmdline| {
+        let argv0 = cmdline.split(|b| *b == 0).next().unwrap_or_default();
+        named(Path::new(&*String::from_utf8_lossy(argv0)))
+    })
+}
+
+/// Start time of the process in clock ticks since boot (field 22 of `/proc/<pid>/stat`).
+pub fn start_ticks(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces and parentheses, so count from the last ')'
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19)?.parse().ok()
+}